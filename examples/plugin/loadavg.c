@@ -0,0 +1,49 @@
+/* loadavg.c
+ *
+ * Example perf-vumeter plugin showing the load average.
+ * The argument selects the average: 1 (default), 5 or 15 minutes.
+ *
+ *   cc -shared -fPIC -O2 -o libloadavg.so loadavg.c
+ *   perf_vumeter --channel 4=plugin:./libloadavg.so:8:5
+ */
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <math.h>
+
+struct ctx {
+    int field;
+};
+
+void *perf_vumeter_init(const char *arg)
+{
+    struct ctx *c = malloc(sizeof(*c));
+    if (!c)
+        return NULL;
+    switch (*arg ? atoi(arg) : 1) {
+    case 1: c->field = 0; break;
+    case 5: c->field = 1; break;
+    case 15: c->field = 2; break;
+    default: free(c); return NULL;
+    }
+    return c;
+}
+
+double perf_vumeter_sample(void *ctx)
+{
+    struct ctx *c = ctx;
+    double avg[3];
+    FILE *f = fopen("/proc/loadavg", "r");
+    if (!f)
+        return NAN;
+    int n = fscanf(f, "%lf %lf %lf", &avg[0], &avg[1], &avg[2]);
+    fclose(f);
+    return n == 3 ? avg[c->field] : NAN;
+}
+
+void perf_vumeter_free(void *ctx)
+{
+    free(ctx);
+}
+
+/* EOF */
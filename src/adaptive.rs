@@ -0,0 +1,70 @@
+// adaptive.rs
+
+use std::{collections::VecDeque, time};
+
+// Scales a source by where its value falls in its own recent history:
+// the gauge is the share of the samples within the window that were lower.
+// The history is a rolling histogram in a ring of time slots, with log spaced
+// buckets so that bit rates and percentages fit the same way.
+#[derive(Debug)]
+pub struct AdaptiveScale {
+    slot_len: time::Duration,
+    n_slots: usize,
+    slots: VecDeque<(time::Instant, Vec<u32>)>,
+    total: Vec<u64>,
+}
+
+// at most, a window shorter than this many seconds gets one second slots
+const SLOTS: usize = 60;
+// four per octave, up to 2^64
+const BUCKETS: usize = 256;
+
+impl AdaptiveScale {
+    pub fn new(window: time::Duration) -> Self {
+        let n_slots = (window.as_secs() as usize).clamp(1, SLOTS);
+        Self {
+            slot_len: window / n_slots as u32,
+            n_slots,
+            slots: VecDeque::with_capacity(n_slots + 1),
+            total: vec![0; BUCKETS],
+        }
+    }
+
+    fn bucket(value: f64) -> usize {
+        match value > 0.0 {
+            true => ((value + 1.0).log2() * 4.0).min((BUCKETS - 1) as f64) as usize,
+            // NaN too
+            false => 0,
+        }
+    }
+
+    // adds the value to the history and returns its gauge, 0-256
+    pub fn gauge(&mut self, value: f64) -> f64 {
+        let now = time::Instant::now();
+        if self
+            .slots
+            .back()
+            .is_none_or(|(start, _)| now - *start >= self.slot_len)
+        {
+            self.slots.push_back((now, vec![0; BUCKETS]));
+        }
+        while self.slots.len() > self.n_slots {
+            if let Some((_, old)) = self.slots.pop_front() {
+                for (t, c) in self.total.iter_mut().zip(old) {
+                    *t -= c as u64;
+                }
+            }
+        }
+        let b = Self::bucket(value);
+        if let Some((_, counts)) = self.slots.back_mut() {
+            counts[b] += 1;
+        }
+        self.total[b] += 1;
+
+        let n = self.total.iter().sum::<u64>();
+        let below = self.total[..b].iter().sum::<u64>();
+        256.0 * below as f64 / n as f64
+    }
+}
+
+// EOF
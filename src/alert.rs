@@ -0,0 +1,94 @@
+// alert.rs
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::{fmt, str::FromStr, time};
+
+use anyhow::anyhow;
+
+use crate::*;
+
+// the needle swings to full scale and back in this period while alerting,
+// slow enough for the default slew rate to follow
+const ALERT_FLASH: time::Duration = time::Duration::from_secs(2);
+
+// A gauge threshold and how many seconds it must be exceeded, "230" or "230:10"
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AlertSpec {
+    pub threshold: f64,
+    pub after: f64,
+}
+
+impl fmt::Display for AlertSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.threshold, self.after)
+    }
+}
+
+impl FromStr for AlertSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (threshold, after) = match s.split_once(':') {
+            Some((t, a)) => (t.parse::<f64>()?, a.parse::<f64>()?),
+            None => (s.parse::<f64>()?, 0.0),
+        };
+        if !(threshold.is_finite() && after >= 0.0 && after.is_finite()) {
+            return Err(anyhow!("Invalid alert {s}"));
+        }
+        Ok(Self { threshold, after })
+    }
+}
+
+// Turns the meter into a passive alarm: a channel above its threshold for long
+// enough flashes between full scale and its value until it falls below again
+#[derive(Debug, Default)]
+pub struct Alerts {
+    specs: Vec<(u8, AlertSpec)>,
+    above: BTreeMap<u8, time::Instant>,
+    alerting: BTreeSet<u8>,
+}
+
+impl Alerts {
+    pub fn new(specs: &[(u8, AlertSpec)]) -> Self {
+        Self {
+            specs: specs.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    // new thresholds on reload, the running alerts start over
+    pub fn set_specs(&mut self, specs: &[(u8, AlertSpec)]) {
+        if self.specs != specs {
+            *self = Self::new(specs);
+        }
+    }
+
+    pub fn apply(&mut self, frame: &mut Frame) {
+        let now = time::Instant::now();
+        for (ch, sample) in frame.iter_mut() {
+            let Some((_, spec)) = self.specs.iter().rev().find(|(c, _)| c == ch) else {
+                continue;
+            };
+            if sample.value <= spec.threshold {
+                self.above.remove(ch);
+                if self.alerting.remove(ch) {
+                    info!("Channel {ch} is back below {}", spec.threshold);
+                }
+                continue;
+            }
+            let since = *self.above.entry(*ch).or_insert(now);
+            if (now - since).as_secs_f64() < spec.after {
+                continue;
+            }
+            if self.alerting.insert(*ch) {
+                warn!("Channel {ch} is above {} alerting", spec.threshold);
+            }
+            let phase = (now - since).as_secs_f64() % ALERT_FLASH.as_secs_f64();
+            if phase < ALERT_FLASH.as_secs_f64() / 2.0 {
+                sample.value = 255.0;
+            }
+        }
+    }
+}
+
+// EOF
@@ -0,0 +1,129 @@
+// audio.rs
+
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::{io::Read, thread, time};
+
+use anyhow::anyhow;
+
+use crate::*;
+
+const AUDIO_RATE: usize = 48_000;
+// 50ms blocks of interleaved stereo s16le
+const AUDIO_BLOCK: usize = AUDIO_RATE / 20 * 2;
+
+#[derive(Clone, Copy, Debug)]
+pub struct AudioLevel {
+    pub rms_db: f64,
+    pub peak_db: f64,
+    pub ts: time::Instant,
+}
+
+// Loudness of the system output, captured from the monitor source
+// with parec (works with both PulseAudio and pipewire-pulse).
+#[derive(Debug)]
+pub struct AudioMeter {
+    // peak instead of rms
+    pub peak: bool,
+    level: Arc<Mutex<AudioLevel>>,
+}
+
+impl AudioMeter {
+    pub fn new<S: AsRef<str>>(device: S, peak: bool) -> anyhow::Result<Self> {
+        let device = device.as_ref().to_string();
+        let level = Arc::new(Mutex::new(AudioLevel {
+            rms_db: f64::NEG_INFINITY,
+            peak_db: f64::NEG_INFINITY,
+            ts: time::Instant::now(),
+        }));
+        let ret = Self {
+            peak,
+            level: level.clone(),
+        };
+
+        // capture ends after the AudioMeter is dropped
+        thread::spawn(move || loop {
+            match Self::capture(&device, &level) {
+                Ok(()) => return,
+                Err(e) => {
+                    error!("Audio capture: {e}");
+                    count_error("audio");
+                }
+            }
+            thread::sleep(time::Duration::from_secs(5));
+        });
+        Ok(ret)
+    }
+
+    pub fn level(&self) -> AudioLevel {
+        *self.level.lock().unwrap()
+    }
+
+    fn capture(device: &str, level: &Arc<Mutex<AudioLevel>>) -> anyhow::Result<()> {
+        let mut child = Command::new("parec")
+            .args([
+                "--raw",
+                "--format=s16le",
+                "--channels=2",
+                "--latency-msec=50",
+            ])
+            .arg(format!("--rate={AUDIO_RATE}"))
+            .arg(format!("--device={device}"))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let mut stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| anyhow!("No stdout from parec"))?;
+
+        let mut buf = vec![0u8; AUDIO_BLOCK * 2];
+        loop {
+            if Arc::strong_count(level) == 1 {
+                child.kill().ok();
+                child.wait()?;
+                return Ok(());
+            }
+            if let Err(e) = stdout.read_exact(&mut buf) {
+                child.kill().ok();
+                child.wait()?;
+                return Err(e.into());
+            }
+            let mut sum_sq = 0.0;
+            let mut peak = 0.0f64;
+            for s in buf.chunks_exact(2) {
+                let v = i16::from_le_bytes([s[0], s[1]]) as f64 / 32768.0;
+                sum_sq += v * v;
+                peak = peak.max(v.abs());
+            }
+            let rms = (sum_sq / AUDIO_BLOCK as f64).sqrt();
+            *level.lock().unwrap() = AudioLevel {
+                rms_db: 20.0 * rms.log10(),
+                peak_db: 20.0 * peak.log10(),
+                ts: time::Instant::now(),
+            };
+        }
+    }
+}
+
+// level in dBFS, silence is minus infinity
+impl StatSource for AudioMeter {
+    fn sample(&mut self) -> anyhow::Result<f64> {
+        Ok(self.sample_at()?.0)
+    }
+    fn name(&self) -> &'static str {
+        "audio"
+    }
+    fn unit(&self) -> &'static str {
+        "dB"
+    }
+    fn sample_at(&mut self) -> anyhow::Result<(f64, time::Instant)> {
+        let level = self.level();
+        Ok(match self.peak {
+            true => (level.peak_db, level.ts),
+            false => (level.rms_db, level.ts),
+        })
+    }
+}
+
+// EOF
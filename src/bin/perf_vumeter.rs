@@ -1,130 +1,426 @@
 // bin/perf-vumeter.rs
 
-use std::{cmp, thread, time};
-use std::fs::{File, OpenOptions};
-use std::io::Write;
+use std::{collections::BTreeSet, io, thread, time};
 
-use anyhow::bail;
+use anyhow::{anyhow, bail};
 
 use perf_vumeter::*;
 
-const MAX_DELTA: i16 = 96;
+// let the needles drop when the agent has gone quiet
+const DISPLAY_TIMEOUT: time::Duration = time::Duration::from_secs(5);
 
 fn main() -> anyhow::Result<()> {
-    let opts = OptsCommon::parse();
+    let mut opts = OptsCommon::load()?;
+    if let Some(Cmd::Completions { shell }) = opts.cmd {
+        let mut cmd = OptsCommon::command();
+        clap_complete::generate(shell, &mut cmd, env!("CARGO_BIN_NAME"), &mut io::stdout());
+        return Ok(());
+    }
     opts.start_pgm(env!("CARGO_BIN_NAME"));
+    set_fs_roots(&opts.proc_root, &opts.sys_root);
 
-    info!("Opening serial port {}", &opts.port);
-    let mut ser = OpenOptions::new().read(true).write(true).open(&opts.port)?;
-
-    info!("Vu sez hi (:");
-    hello(&mut ser)?;
-
-    let mut cpustats = CpuStats::new()?;
-    let n_cpu = cpustats.n_cpu();
-    let mut rx = IfStats::new(&opts.interface, IfCounter::Rx)?;
-    let mut tx = IfStats::new(&opts.interface, IfCounter::Tx)?;
-    let mut diskstats = DiskStats::new()?;
+    match &opts.cmd {
+        Some(Cmd::Display { listen }) => return display(&opts, listen),
+        Some(Cmd::Replay {
+            file,
+            speed,
+            repeat,
+        }) => return replay(&opts, file, *speed, *repeat),
+        Some(Cmd::Check) => return check(&opts),
+        Some(Cmd::ListPorts) => return list_ports(),
+        Some(Cmd::Set { channel, value }) => return set_channel(&opts, *channel, *value),
+        Some(Cmd::Sweep) => return sweep(&opts),
+        Some(Cmd::History { channel, since }) => {
+            let db = opts
+                .history_db
+                .as_deref()
+                .ok_or_else(|| anyhow!("History needs --history-db"))?;
+            return history_query(db, *channel, since);
+        }
+        _ => {}
+    }
+    let agent = match &opts.cmd {
+        Some(Cmd::Agent { connect }) => {
+            info!("Streaming gauges to {connect}");
+            Some(FrameSender::new(connect)?)
+        }
+        _ => None,
+    };
+    let mut meters = match agent {
+        Some(_) => Vec::new(),
+        None => open_meters(&opts)?,
+    };
+    let mut recorder = match &opts.cmd {
+        Some(Cmd::Record { file }) => {
+            info!("Recording the gauges to {file}");
+            Some(FrameRecorder::create(file)?)
+        }
+        _ => None,
+    };
 
-    let mut elapsed_ns = 0;
-    let sleep_ns: u32 = 1_000_000_000 / (opts.samplerate as u32);
+    let mut ticker = Ticker::new(time::Duration::from_secs(1) / opts.samplerate.max(1) as u32);
+    let mut pipeline = Pipeline::from_opts(&opts)?;
+    let mut sinks = Vec::new();
+    if opts.tui {
+        sinks.push(TuiSink::spawn()?);
+    }
+    if opts.sparklines {
+        sinks.push(SparklineSink::spawn()?);
+    }
+    if let Some(format) = opts.statusbar {
+        sinks.push(StatusBarSink::spawn(format)?);
+    }
+    if let Some(listen) = &opts.ws_listen {
+        sinks.push(WebSocketSink::spawn(listen)?);
+    }
+    if let Some(listen) = &opts.grpc_listen {
+        sinks.push(GrpcSink::spawn(listen)?);
+    }
+    if let Some(listen) = &opts.status_listen {
+        sinks.push(StatusSink::spawn(listen)?);
+    }
+    if let Some(target) = &opts.osc_target {
+        sinks.push(OscSink::spawn(target, &opts.osc_address)?);
+    }
+    if let Some(proto) = opts.dmx_proto {
+        sinks.push(DmxSink::spawn(DmxConfig {
+            proto,
+            target: opts.dmx_target.clone(),
+            universe: opts.dmx_universe,
+            start: opts.dmx_start,
+        })?);
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(spi) = &opts.ws2812_spi {
+        sinks.push(Ws2812Sink::spawn(Ws2812Config {
+            spi: spi.clone(),
+            channels: opts.ws2812_channels.clone(),
+            leds: opts.ws2812_leds.max(1),
+            colors: opts.ws2812_colors.clone(),
+            peak_color: opts.ws2812_peak_color,
+            brightness: opts.ws2812_brightness,
+        })?);
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(bus) = &opts.pca9685_bus {
+        sinks.push(Pca9685Sink::spawn(Pca9685Config {
+            bus: bus.clone(),
+            addr: opts.pca9685_addr,
+            freq: opts.pca9685_freq,
+            map: opts.pca9685_map.clone(),
+            max_duty: opts.pca9685_max.min(4095),
+        })?);
+    }
+    #[cfg(target_os = "linux")]
+    if !opts.gpio_pwm.is_empty() {
+        sinks.push(GpioPwmSink::spawn(
+            opts.gpio_pwm.clone(),
+            opts.gpio_pwm_freq,
+            &opts.gpio_chip,
+        )?);
+    }
+    #[cfg(unix)]
+    if let Some(bus) = opts.dbus {
+        sinks.push(DbusSink::spawn(bus)?);
+    }
+    #[cfg(unix)]
+    if let Some(path) = &opts.fifo {
+        sinks.push(FifoSink::spawn(path)?);
+    }
+    if let Some(target) = &opts.broadcast {
+        sinks.push(BroadcastSink::spawn(target, opts.broadcast_format)?);
+    }
+    if let Some(path) = &opts.csv_file {
+        sinks.push(CsvSink::spawn(path, opts.csv_rotate)?);
+    }
+    if let Some(db) = &opts.history_db {
+        sinks.push(HistorySink::spawn(db, opts.history_retention)?);
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(device) = &opts.streamdeck {
+        sinks.push(StreamDeckSink::spawn(StreamDeckConfig {
+            device: device.clone(),
+            model: opts.streamdeck_model,
+            style: opts.streamdeck_style,
+            keys: opts.streamdeck_keys.clone(),
+            brightness: opts.streamdeck_brightness,
+        })?);
+    }
+    if let Some(target) = &opts.openrgb {
+        sinks.push(OpenRgbSink::spawn(OpenRgbConfig {
+            target: target.clone(),
+            device: opts.openrgb_device,
+            channels: opts.openrgb_channels.clone(),
+            style: opts.openrgb_style,
+        })?);
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(bus) = &opts.ssd1306_bus {
+        sinks.push(Ssd1306Sink::spawn(Ssd1306Config {
+            bus: bus.clone(),
+            addr: opts.ssd1306_addr,
+            height: opts.ssd1306_height,
+            channels: opts.ssd1306_channels.clone(),
+        })?);
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(spi) = &opts.eink_spi {
+        sinks.push(EinkSink::spawn(EinkConfig {
+            spi: spi.clone(),
+            gpiochip: opts.eink_gpiochip.clone(),
+            dc: opts.eink_dc,
+            rst: opts.eink_rst,
+            busy: opts.eink_busy,
+            width: opts.eink_width,
+            height: opts.eink_height,
+            minutes: opts.eink_minutes.max(1),
+            channels: opts.eink_channels.clone(),
+        })?);
+    }
+    if let Some(listen) = &opts.modbus_listen {
+        sinks.push(ModbusServerSink::spawn(listen, opts.modbus_scale)?);
+    }
+    if let Some(target) = &opts.modbus_target {
+        sinks.push(ModbusSink::spawn(ModbusConfig {
+            target: target.clone(),
+            unit: opts.modbus_unit,
+            baud: opts.modbus_baud,
+            base: opts.modbus_base,
+            scale: opts.modbus_scale,
+        })?);
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(interface) = &opts.can_interface {
+        sinks.push(CanSink::spawn(CanConfig {
+            interface: interface.clone(),
+            map: opts.can_map.clone(),
+            base_id: opts.can_base_id,
+            format: opts.can_format,
+            scale: opts.can_scale,
+        })?);
+    }
+    #[cfg(feature = "sonify")]
+    if opts.sonify {
+        sinks.push(SonifySink::spawn(SonifyConfig {
+            device: opts.sonify_device.clone(),
+            channels: opts.sonify_channels.clone(),
+            low_hz: opts.sonify_low.max(1.0),
+            high_hz: opts.sonify_high.max(1.0),
+            volume: opts.sonify_volume,
+        })?);
+    }
+    #[cfg(unix)]
+    if opts.tray {
+        sinks.push(TraySink::spawn(opts.tray_channels.clone())?);
+    }
+    if let Some(broker) = &opts.mqtt_broker {
+        sinks.push(MqttSink::spawn(MqttConfig {
+            broker: broker.clone(),
+            client_id: opts
+                .mqtt_client_id
+                .clone()
+                .unwrap_or_else(|| format!("perf-vumeter-{}", hostname())),
+            topic: opts.mqtt_topic.clone(),
+            username: opts.mqtt_username.clone(),
+            password: opts.mqtt_password.clone(),
+            keepalive: 60,
+            command_topic: opts.mqtt_command_topic.clone(),
+            discovery: opts
+                .mqtt_discovery
+                .then(|| opts.mqtt_discovery_prefix.clone()),
+        })?);
+    }
+    if let Some(url) = &opts.influx_url {
+        sinks.push(InfluxSink::spawn(InfluxConfig {
+            url: url.clone(),
+            org: opts.influx_org.clone(),
+            bucket: opts.influx_bucket.clone(),
+            token: opts.influx_token.clone(),
+            flush: time::Duration::from_secs_f64(opts.influx_flush.max(0.1)),
+        })?);
+    }
+    if let Some(target) = &opts.graphite {
+        sinks.push(GraphiteSink::spawn(GraphiteConfig {
+            target: target.clone(),
+            proto: opts.graphite_proto,
+            prefix: opts.graphite_prefix.clone(),
+            flush: time::Duration::from_secs_f64(opts.graphite_flush.max(0.1)),
+        })?);
+    }
+    // one list, the meters take the needles and the other sinks the frames
+    meters.extend(
+        sinks
+            .into_iter()
+            .map(|sink| Meter::new(MeterPort::default(), Box::new(sink))),
+    );
+    let mut latency_comp = LatencyComp::new(ticker.period() * 2);
 
+    catch_signals()?;
+    let mut notifier = Notifier::from_env();
     info!("Starting measure loop");
+    notifier.ready();
     loop {
-        thread::sleep(time::Duration::new(0, sleep_ns - elapsed_ns));
-        let start = time::Instant::now();
-
-        // CPU stats + gauge
-        // Note: cpu_rates[0] is total/summary, the rest are sorted largest first
-        let cpu_rates = cpustats.cpurates()?;
-        let mut cpu_gauge = if n_cpu >= 2 {
-            (cpu_rates[1] + cpu_rates[2]) / 2.0
-        } else {
-            cpu_rates[1]
-        };
-
-        if n_cpu >= 6 {
-            cpu_gauge += (cpu_rates[3] + cpu_rates[4]) / 2.0;
-            cpu_gauge += (cpu_rates[5] + cpu_rates[6]) / 3.0;
-        } else if n_cpu >= 4 {
-            cpu_gauge += (cpu_rates[3] + cpu_rates[4]) * 0.80;
-        } else {
-            cpu_gauge *= 2.56;
+        notifier.ping();
+        ticker.wait();
+        if take_reload_request() {
+            pipeline.reload(&mut opts);
         }
-        debug!(
-            "CPU gauge: {cpu_gauge:.1} sum: {sum:.1} -- {list}",
-            sum = cpu_rates[0],
-            list = cpu_rates[1..]
-                .iter()
-                .map(|a| format!("{a:.1}"))
-                .collect::<Vec<String>>()
-                .join(" ")
-                .as_str()
-        );
-        set_vu(&mut ser, 1, cpu_gauge as i16)?;
-
-        // DISK stats + gauge
-        let disk_rates = diskstats.diskrates()?;
-        let disk_gauge = 256.0 * disk_rates[0] / 200_000.0;
-        debug!("DISK gauge: {disk_gauge:.1} rates: {disk_rates:?}");
-        set_vu(&mut ser, 2, disk_gauge as i16)?;
-
-        // NET stats + gauge
-        let rx_rate = rx.bitrate()?;
-        let tx_rate = tx.bitrate()?;
-        let rate = cmp::max(rx_rate, tx_rate);
-        let net_gauge = 256.0 * (((rate as f64) / 1_000_000.0) / (opts.max_mbps as f64));
-        debug!(
-            "NET gauge: {net_gauge:.1} rx: {rx} kbps, tx: {tx} kbps",
-            rx = rx_rate / 1000,
-            tx = tx_rate / 1000
-        );
-        set_vu(&mut ser, 3, net_gauge as i16)?;
-
-        // keep the sample rate from drifting
-        elapsed_ns = start.elapsed().as_nanos() as u32;
+        let frame = pipeline.tick(Frame::new(), |frame| {
+            feed_sinks(&mut meters, frame);
+            if let Some(display) = &agent {
+                if let Err(e) = display.send(frame) {
+                    info!("Sending to display failed: {e}");
+                    count_error("agent");
+                }
+            }
+            if let Some(rec) = &mut recorder {
+                if let Err(e) = rec.write(frame) {
+                    error!("Recording failed: {e}");
+                    count_error("record");
+                }
+            }
+        });
+        if take_hello_request() {
+            for meter in &mut meters {
+                meter.hello()?;
+            }
+        }
+        write_frame(&mut meters, &opts, &mut latency_comp, frame)?;
     }
 }
 
-const CHANNELS_NUM: usize = 192; // Remember: channel cmd byte has offset 0x30
+fn display(opts: &OptsCommon, listen: &str) -> anyhow::Result<()> {
+    let mut meters = open_meters(opts)?;
+    let receiver = FrameReceiver::new(listen)?;
+    info!("Listening for agents on {listen}");
 
-fn set_vu(ser: &mut File, channel: u8, mut gauge: i16) -> anyhow::Result<()> {
-    static mut LAST_VAL: [i16; CHANNELS_NUM] = [0; CHANNELS_NUM];
+    let mut latency_comp = LatencyComp::new(time::Duration::from_secs(1));
+    let mut channels = BTreeSet::new();
+    let mut last_rx = time::Instant::now();
+    let mut alerts = Alerts::new(&opts.alert);
+    let mut notifier = Notifier::from_env();
+    notifier.ready();
+    loop {
+        notifier.ping();
+        match receiver.recv(time::Duration::from_secs(1)) {
+            Ok(Some(mut frame)) => {
+                last_rx = time::Instant::now();
+                channels.extend(frame.keys().copied());
+                alerts.apply(&mut frame);
+                write_frame(&mut meters, opts, &mut latency_comp, frame)?;
+            }
+            Ok(None) => {}
+            Err(e) => info!("{e}"),
+        }
+        if last_rx.elapsed() > DISPLAY_TIMEOUT {
+            for meter in meters.iter_mut() {
+                for ch in &channels {
+                    if let Some(dev_channel) = meter.port.device_channel(*ch) {
+                        meter.set_vu(dev_channel, 0.0, 1, opts.ballistics(*ch))?;
+                    }
+                }
+                meter.flush()?;
+            }
+        }
+    }
+}
 
-    let ch_i = channel as usize;
-    if ch_i >= CHANNELS_NUM {
-        bail!(
-            "Channel number too large: {ch_i} (maximum {}",
-            CHANNELS_NUM - 1
-        );
+fn replay(opts: &OptsCommon, file: &str, speed: f64, repeat: bool) -> anyhow::Result<()> {
+    if speed.is_nan() || speed <= 0.0 {
+        bail!("Replay speed must be positive");
     }
+    let mut meters = open_meters(opts)?;
+    let mut latency_comp = LatencyComp::new(time::Duration::from_secs(1));
+    loop {
+        info!("Replaying {file}");
+        let mut replay = FrameReplay::open(file)?;
+        let start = time::Instant::now();
+        while let Some((at, frame)) = replay.next_frame()? {
+            let at = at.div_f64(speed);
+            if let Some(wait) = at.checked_sub(start.elapsed()) {
+                thread::sleep(wait);
+            }
+            write_frame(&mut meters, opts, &mut latency_comp, frame)?;
+        }
+        if !repeat {
+            return Ok(());
+        }
+    }
+}
 
-    gauge = gauge.clamp(0, 255);
+fn sweep(opts: &OptsCommon) -> anyhow::Result<()> {
+    for mut meter in attached_meters(opts)? {
+        info!("Vu sez hi on {} (:", meter.port.path);
+        meter.hello()?;
+    }
+    Ok(())
+}
 
-    // do some smoothing -- only move the gauge MAX_DELTA at once
-    let delta = unsafe { gauge - LAST_VAL[ch_i] };
-    let delta_sig = delta.signum();
-    let delta_trunc = delta.abs().min(MAX_DELTA);
-    let new_value = unsafe { LAST_VAL[ch_i] + delta_sig * delta_trunc };
-    unsafe {
-        LAST_VAL[ch_i] = new_value;
+// straight to the needle, no smoothing
+fn set_channel(opts: &OptsCommon, channel: u8, value: u8) -> anyhow::Result<()> {
+    channel_index(channel)?;
+    for mut meter in attached_meters(opts)? {
+        if let Some(dev_channel) = meter.port.device_channel(channel) {
+            meter.write_vu(dev_channel, value as i16, 1)?;
+            meter.out.flush()?;
+        }
     }
+    Ok(())
+}
 
-    let cmd_buf: [u8; 4] = [0xFD, 0x02, 0x30 + channel, new_value as u8];
-    Ok(ser.write_all(&cmd_buf)?)
+fn list_ports() -> anyhow::Result<()> {
+    for port in serial_ports() {
+        match port.usb {
+            Some(id) => println!(
+                "{} usb:{id} {}",
+                port.path,
+                port.product.as_deref().unwrap_or_default()
+            ),
+            None => println!("{}", port.path),
+        }
+    }
+    Ok(())
 }
 
-fn hello(ser: &mut File) -> anyhow::Result<()> {
-    for i in (0i16..=255)
-        .chain((128..=255).rev())
-        .chain(128..=255)
-        .chain((0..=255).rev())
-    {
-        for c in 1u8..=3 {
-            set_vu(ser, c, i)?;
+// one sample of each channel and a look at the meters, fails if anything is off
+fn check(opts: &OptsCommon) -> anyhow::Result<()> {
+    let mapping_cfg = opts.mapping_config();
+    let mut failed = 0;
+    for (ch, spec) in opts.mapping() {
+        let result = ChannelSource::new(ch, spec.clone(), &mapping_cfg).and_then(|mut src| {
+            // the rate sources need two readings
+            src.sample()?;
+            thread::sleep(time::Duration::from_millis(200));
+            src.sample()
+        });
+        match result {
+            Ok(s) => println!("channel {ch}: {spec} gauge {:.1}", s.value),
+            Err(e) => {
+                println!("channel {ch}: {spec} FAILED: {e}");
+                failed += 1;
+            }
         }
-        thread::sleep(time::Duration::new(0, 3_000_000));
+    }
+    if opts.sink.is_none() && !opts.dry_run {
+        for port in opts.meter_ports() {
+            // a meter that does not answer who it is fails the check
+            let cfg = SerialConfig {
+                handshake: Handshake::Require,
+                ..opts.serial_config(port)
+            };
+            match SerialSink::open(&port.path, cfg) {
+                Ok(_) => println!("meter {}: ok", port.path),
+                Err(e) => {
+                    println!("meter {}: FAILED: {e}", port.path);
+                    failed += 1;
+                }
+            }
+        }
+    }
+    if failed > 0 {
+        bail!("{failed} check(s) failed");
     }
     Ok(())
 }
+
 // EOF
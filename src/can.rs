@@ -0,0 +1,185 @@
+// can.rs
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::Write,
+    os::fd::AsRawFd,
+    str::FromStr,
+    sync::mpsc,
+};
+
+use anyhow::{anyhow, bail};
+
+use crate::*;
+
+const CAN_RAW: i32 = 1;
+const CAN_EFF_FLAG: u32 = 0x8000_0000;
+const CAN_SFF_MAX: u32 = 0x7ff;
+const CAN_EFF_MAX: u32 = 0x1fff_ffff;
+
+#[repr(C)]
+struct SockAddrCan {
+    can_family: u16,
+    can_ifindex: i32,
+    // the transport protocol addresses, unused for CAN_RAW
+    rx_id: u32,
+    tx_id: u32,
+    _pad: [u8; 8],
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CanFormat {
+    #[default]
+    U8,
+    U16Be,
+    U16Le,
+}
+
+impl CanFormat {
+    fn width(self) -> usize {
+        match self {
+            CanFormat::U8 => 1,
+            _ => 2,
+        }
+    }
+}
+
+impl FromStr for CanFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "u8" => Ok(CanFormat::U8),
+            "u16be" | "u16" => Ok(CanFormat::U16Be),
+            "u16le" => Ok(CanFormat::U16Le),
+            _ => Err(anyhow!("Unknown CAN value format: {s}")),
+        }
+    }
+}
+
+// "0x316" or "0x316:2", the frame ID and the byte offset of the value in it.
+// IDs above 0x7ff are sent as extended frames.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CanSlot {
+    pub id: u32,
+    pub offset: usize,
+}
+
+impl FromStr for CanSlot {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (id, offset) = match s.split_once(':') {
+            Some((id, off)) => (id, off.parse::<usize>()?),
+            None => (s, 0),
+        };
+        let id = parse_int::<u32>(id).map_err(|e| anyhow!("CAN id {id}: {e}"))?;
+        if id > CAN_EFF_MAX {
+            bail!("CAN id 0x{id:x} is out of range");
+        }
+        if offset >= 8 {
+            bail!("CAN byte offset {offset} is out of range");
+        }
+        Ok(CanSlot { id, offset })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CanConfig {
+    pub interface: String,
+    // (channel, slot), empty sends channel N alone in frame base_id + N
+    pub map: Vec<(u8, CanSlot)>,
+    pub base_id: u32,
+    pub format: CanFormat,
+    // value of a full scale gauge
+    pub scale: u16,
+}
+
+// Sends the gauges as 8 byte CAN frames over SocketCAN, channels sharing
+// an ID are packed into the same frame
+pub struct CanSink;
+
+impl CanSink {
+    pub fn spawn(cfg: CanConfig) -> anyhow::Result<ThreadedSink> {
+        for (ch, slot) in &cfg.map {
+            if slot.offset + cfg.format.width() > 8 {
+                bail!("Channel {ch} does not fit in CAN frame 0x{:x}", slot.id);
+            }
+        }
+        let ifindex = fs::read_to_string(format!("/sys/class/net/{}/ifindex", cfg.interface))
+            .map_err(|e| anyhow!("No CAN interface {}: {e}", cfg.interface))?
+            .trim()
+            .parse::<i32>()?;
+        let sock = sys::sys_socket(sys::AF_CAN, sys::SOCK_RAW, CAN_RAW)?;
+        let addr = SockAddrCan {
+            can_family: sys::AF_CAN as u16,
+            can_ifindex: ifindex,
+            rx_id: 0,
+            tx_id: 0,
+            _pad: [0; 8],
+        };
+        sys::sys_bind(sock.as_raw_fd(), &addr)?;
+        info!("Sending {:?} gauges on {}", cfg.format, cfg.interface);
+        let sock = File::from(sock);
+        spawn_sink("can", move |rx| Self::run(cfg, sock, rx))
+    }
+
+    fn run(cfg: CanConfig, mut sock: File, rx: mpsc::Receiver<Frame>) {
+        // last payload of each ID, so that channels missing from a frame keep their value
+        let mut payloads: BTreeMap<u32, [u8; 8]> = BTreeMap::new();
+
+        while let Ok(frame) = rx.recv() {
+            let mut dirty = Vec::new();
+            for (ch, sample) in &frame {
+                let slots = match cfg.map.is_empty() {
+                    true => vec![CanSlot {
+                        id: cfg.base_id + *ch as u32,
+                        offset: 0,
+                    }],
+                    false => cfg
+                        .map
+                        .iter()
+                        .filter(|(c, _)| c == ch)
+                        .map(|(_, slot)| *slot)
+                        .collect(),
+                };
+                let v = (sample.value.clamp(0.0, 255.0) / 255.0 * cfg.scale as f64).round() as u16;
+                let bytes = match cfg.format {
+                    CanFormat::U8 => vec![v.min(255) as u8],
+                    CanFormat::U16Be => v.to_be_bytes().to_vec(),
+                    CanFormat::U16Le => v.to_le_bytes().to_vec(),
+                };
+                for slot in slots {
+                    let payload = payloads.entry(slot.id).or_default();
+                    payload[slot.offset..slot.offset + bytes.len()].copy_from_slice(&bytes);
+                    if !dirty.contains(&slot.id) {
+                        dirty.push(slot.id);
+                    }
+                }
+            }
+            for id in dirty {
+                if let Err(e) = sock.write_all(&can_frame(id, &payloads[&id])) {
+                    debug!("CAN: {e}");
+                    count_error("can");
+                }
+            }
+        }
+    }
+}
+
+// struct can_frame: id, dlc, 3 bytes of padding, data
+fn can_frame(id: u32, data: &[u8; 8]) -> [u8; 16] {
+    let id = if id > CAN_SFF_MAX {
+        id | CAN_EFF_FLAG
+    } else {
+        id
+    };
+    let mut buf = [0u8; 16];
+    buf[..4].copy_from_slice(&id.to_ne_bytes());
+    buf[4] = 8;
+    buf[8..].copy_from_slice(data);
+    buf
+}
+
+// EOF
@@ -0,0 +1,106 @@
+// clock.rs
+
+use std::sync::{Arc, Mutex};
+use std::{process::Command, thread, time};
+
+use anyhow::{anyhow, bail};
+
+use crate::*;
+
+// Tracks the absolute system clock offset as reported by chronyd,
+// falling back to ntpd via ntpq. Queried in a background thread,
+// which ends after the ClockOffset is dropped.
+#[derive(Debug)]
+pub struct ClockOffset {
+    offset: Arc<Mutex<(Option<f64>, time::Instant)>>,
+}
+
+impl ClockOffset {
+    pub fn new(interval: time::Duration) -> anyhow::Result<Self> {
+        let offset = Arc::new(Mutex::new((None, time::Instant::now())));
+        let ret = Self {
+            offset: offset.clone(),
+        };
+
+        thread::spawn(move || loop {
+            let start = time::Instant::now();
+            let res = Self::query_chrony().or_else(|e| {
+                trace!("chronyc: {e}");
+                Self::query_ntpd()
+            });
+            match &res {
+                Ok(secs) => trace!("Clock offset: {:.1} us", secs * 1_000_000.0),
+                Err(e) => {
+                    info!("Clock offset query failed: {e}");
+                    count_error("clock");
+                }
+            }
+            *offset.lock().unwrap() = (res.ok(), time::Instant::now());
+            if let Some(left) = interval.checked_sub(start.elapsed()) {
+                thread::sleep(left);
+            }
+            if Arc::strong_count(&offset) == 1 {
+                return;
+            }
+        });
+        Ok(ret)
+    }
+
+    // absolute offset in seconds, None if the latest query failed
+    pub fn offset(&self) -> Option<f64> {
+        self.offset.lock().unwrap().0
+    }
+
+    pub fn captured(&self) -> time::Instant {
+        self.offset.lock().unwrap().1
+    }
+
+    // Example output of "chronyc -c tracking":
+    // 50505300,PPS,1,1700000000.123456789,0.000000123,-0.000000045,0.000000210,...
+    // The fifth field is the current system time offset in seconds.
+    fn query_chrony() -> anyhow::Result<f64> {
+        let out = Command::new("chronyc").args(["-c", "tracking"]).output()?;
+        if !out.status.success() {
+            bail!("chronyc exited with {}", out.status);
+        }
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        let field = stdout
+            .split(',')
+            .nth(4)
+            .ok_or_else(|| anyhow!("Unexpected chronyc output: {stdout}"))?;
+        Ok(field.trim().parse::<f64>()?.abs())
+    }
+
+    // ntpq reports the offset in milliseconds, e.g. "offset=-0.123"
+    fn query_ntpd() -> anyhow::Result<f64> {
+        let out = Command::new("ntpq").args(["-c", "rv 0 offset"]).output()?;
+        if !out.status.success() {
+            bail!("ntpq exited with {}", out.status);
+        }
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        let ms = stdout
+            .split(',')
+            .find_map(|kv| kv.trim().strip_prefix("offset="))
+            .ok_or_else(|| anyhow!("Unexpected ntpq output: {stdout}"))?;
+        Ok(ms.trim().parse::<f64>()?.abs() / 1000.0)
+    }
+}
+
+// offset in microseconds, an unknown one is infinite and pegs the needle
+impl StatSource for ClockOffset {
+    fn sample(&mut self) -> anyhow::Result<f64> {
+        Ok(self.sample_at()?.0)
+    }
+    fn name(&self) -> &'static str {
+        "ntp"
+    }
+    fn unit(&self) -> &'static str {
+        "us"
+    }
+    fn sample_at(&mut self) -> anyhow::Result<(f64, time::Instant)> {
+        let (offset, ts) = *self.offset.lock().unwrap();
+        Ok((offset.map_or(f64::INFINITY, |secs| secs * 1_000_000.0), ts))
+    }
+}
+
+// EOF
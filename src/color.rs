@@ -0,0 +1,38 @@
+// color.rs
+
+use std::str::FromStr;
+
+use anyhow::anyhow;
+
+// A color of the LED strips, the tray icon and the status bar, e.g. --ws2812-colors
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl Rgb {
+    // 0.0 green, 0.5 yellow, 1.0 red
+    pub fn load(level: f64) -> Self {
+        let r = (2.0 * level).clamp(0.0, 1.0);
+        let g = (2.0 * (1.0 - level)).clamp(0.0, 1.0);
+        Rgb((r * 255.0) as u8, (g * 255.0) as u8, 0)
+    }
+    pub fn scale(self, brightness: u8) -> Self {
+        let s = |c: u8| (c as u16 * brightness as u16 / 255) as u8;
+        Rgb(s(self.0), s(self.1), s(self.2))
+    }
+}
+
+impl FromStr for Rgb {
+    type Err = anyhow::Error;
+
+    // "ff8000" or "#ff8000"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.trim_start_matches('#');
+        if hex.len() != 6 {
+            return Err(anyhow!("Invalid color {s}, expected rrggbb"));
+        }
+        let c = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16);
+        Ok(Rgb(c(0)?, c(2)?, c(4)?))
+    }
+}
+
+// EOF
@@ -1,9 +1,27 @@
 // startup.rs
 
+use std::{env, fs, str::FromStr, time};
+
+use anyhow::{anyhow, bail};
+
 use crate::*;
 
+// the udev symlink of the README, Windows has no such names
+#[cfg(not(windows))]
+const DEFAULT_PORT: &str = "/dev/VUmeter";
+#[cfg(windows)]
+const DEFAULT_PORT: &str = "COM3";
+
 #[derive(Debug, Default, Parser)]
+#[command(args_override_self = true)]
 pub struct OptsCommon {
+    #[command(subcommand)]
+    pub cmd: Option<Cmd>,
+
+    // file of "option = value" lines, read before the command line which can override them
+    #[arg(long)]
+    pub config: Option<String>,
+
     #[arg(short, long)]
     pub verbose: bool,
     #[arg(short, long)]
@@ -11,17 +29,1149 @@ pub struct OptsCommon {
     #[arg(short, long)]
     pub trace: bool,
 
-    #[arg(short, long, default_value = "/dev/VUmeter")]
-    pub port: String,
+    // serial meter device, repeat for several devices, each optionally followed by
+    // the channels it shows: /dev/ttyUSB1@4-6 puts channels 4-6 on its meters 1-3
+    // and /dev/ttyUSB1@4=1,7=2 maps them one by one
+    #[arg(short, long, default_value = DEFAULT_PORT)]
+    pub port: Vec<MeterPort>,
+    // find the meter by the USB vendor and product id of its serial adapter instead,
+    // optionally with the serial number and channels: 1a86:7523:A9K3@4-6
+    #[arg(long, value_parser = parse_usb_port)]
+    pub usb_id: Vec<MeterPort>,
+    // ask the meter for its firmware version when it is opened: off, warn logs
+    // when it does not answer and require leaves such a port unused
+    #[arg(long, default_value = "warn")]
+    pub handshake: Handshake,
+    // port=protocol, plain or crc framing with checksums for noisy lines,
+    // e.g. --protocol /dev/ttyS0=crc
+    #[arg(long, value_parser = parse_port_protocol)]
+    pub protocol: Vec<(String, Protocol)>,
+    // replace the serial meters with a virtual one for development,
+    // null discards the gauges and stdout prints what the meter would be sent
+    #[arg(long)]
+    pub sink: Option<VirtualMeter>,
+    // print the frames each meter port would be sent, "port channel value bytes",
+    // instead of opening the ports
+    #[arg(long)]
+    pub dry_run: bool,
+    // read the stats under other roots than /proc and /sys, e.g. from a snapshot
+    // copied off another machine
+    #[arg(long, default_value = "/proc")]
+    pub proc_root: String,
+    #[arg(long, default_value = "/sys")]
+    pub sys_root: String,
+    // several interfaces are summed up, e.g. --interface bond0,wg0
+    // and globs are matched dynamically, e.g. --interface 'en*,!veth*'
     #[arg(short, long, default_value = "br0")]
     pub interface: String,
-    #[arg(short, long, default_value_t = 5)]
+    // the disks the disk source looks at, globs with ! excluding, e.g. 'sd*,!sdc'
+    // or '!dm-*'. By default and with only exclusions all the whole disks count:
+    // sd*, vd*, xvd*, hd*, nvme*n*, mmcblk*, md* and dm-*, no partitions.
+    #[arg(long, default_value = "")]
+    pub disks: String,
+    // the gauge of several disks: busiest, sum of the disks under md and dm devices,
+    // or a percentile like p50
+    #[arg(long, default_value = "busiest")]
+    pub disk_gauge: DiskGauge,
+    #[arg(short, long, default_value_t = 5, value_parser = clap::value_parser!(u16).range(1..))]
     pub samplerate: u16,
     #[arg(short, long, default_value_t = 100)]
     pub max_mbps: u16,
+    // what the CPU gauge shows: busy, iowait, irq or steal
+    #[arg(long, default_value = "busy")]
+    pub cpu_mode: CpuMode,
+    #[arg(long)]
+    pub latency_comp: bool,
+    // channel=step, e.g. --quantize 2=4
+    #[arg(long, value_parser = parse_channel_arg::<u8>)]
+    pub quantize: Vec<(u8, u8)>,
+    // channel=source, repeat for each channel, e.g. --channel 1=cpu --channel 2=net:eth0:rx
+    // --channel 3=disk:nvme0n1, by default 1=cpu 2=disk 3=net
+    #[arg(long, value_parser = parse_channel_arg::<SourceSpec>)]
+    pub channel: Vec<(u8, SourceSpec)>,
+    // synthetic gauges on the first three channels instead of the real ones,
+    // for showing off the meters
+    #[arg(long)]
+    pub demo: bool,
+    // channel=curve from the gauge to the needle: lin, log[:decades], exp[:decades]
+    // or gamma:g, e.g. --curve 3=log for network traffic
+    #[arg(long, value_parser = parse_channel_arg::<Curve>)]
+    pub curve: Vec<(u8, Curve)>,
+    // needle speed limit in gauge units per second, 96 per sample at the default rate
+    #[arg(long, default_value_t = 480.0)]
+    pub slew_rate: f64,
+    // channel=units per second up and down, e.g. --attack 1=2000 --release 2=100
+    #[arg(long, value_parser = parse_channel_arg::<f64>)]
+    pub attack: Vec<(u8, f64)>,
+    #[arg(long, value_parser = parse_channel_arg::<f64>)]
+    pub release: Vec<(u8, f64)>,
+    // channel=seconds the needle holds the recent maximum before falling, e.g. --peak-hold 3=2
+    #[arg(long, value_parser = parse_channel_arg::<f64>)]
+    pub peak_hold: Vec<(u8, f64)>,
+    // channel=window, the gauge of a mapped source shows how its value ranks in the
+    // recent history instead of its full scale, e.g. --adaptive 3=24h
+    #[arg(long, value_parser = parse_channel_age)]
+    pub adaptive: Vec<(u8, f64)>,
+    // channel=seconds between the samples of a mapped source, e.g. --interval 2=0.5,
+    // the needle glides between them. By default every source is read each round
+    #[arg(long, value_parser = parse_channel_arg::<f64>)]
+    pub interval: Vec<(u8, f64)>,
+
+    #[arg(long)]
+    pub http_url: Option<String>,
+    #[arg(long, default_value_t = 4, value_parser = parse_channel)]
+    pub http_channel: u8,
+    #[arg(long, default_value_t = 1000)]
+    pub http_max_ms: u32,
+
+    #[arg(long, value_parser = parse_channel)]
+    pub ntp_channel: Option<u8>,
+    #[arg(long, default_value_t = 1000)]
+    pub ntp_max_us: u32,
+
+    // channel=gauge[:seconds], the needle flashes between full scale and the value
+    // when the gauge stays above the threshold that long, e.g. --alert 1=230:10
+    #[arg(long, value_parser = parse_channel_arg::<AlertSpec>)]
+    pub alert: Vec<(u8, AlertSpec)>,
+
+    #[arg(long, value_parser = parse_channel)]
+    pub heartbeat_channel: Option<u8>,
+    #[arg(long, default_value_t = 4.0)]
+    pub heartbeat_period: f64,
+    #[arg(long, default_value_t = 160)]
+    pub heartbeat_level: u8,
+
+    // channel=counter, e.g. --if-counter 4=rx_dropped
+    #[arg(long, value_parser = parse_channel_arg::<IfCounter>)]
+    pub if_counter: Vec<(u8, IfCounter)>,
+    #[arg(long, default_value_t = 100)]
+    pub if_events_max: u32,
+
+    #[arg(long, value_parser = parse_channel)]
+    pub conntrack_channel: Option<u8>,
+
+    // family/table/name, e.g. --nft-counter inet/filter/wan_up
+    #[arg(long)]
+    pub nft_counter: Option<String>,
+    #[arg(long, default_value_t = 4, value_parser = parse_channel)]
+    pub nft_channel: u8,
+    #[arg(long, default_value_t = 100)]
+    pub nft_max_mbps: u16,
+
+    #[arg(long, value_parser = parse_channel)]
+    pub failed_units_channel: Option<u8>,
+    // number of failed units giving full scale, 0 pegs the needle on any failure
+    #[arg(long, default_value_t = 0)]
+    pub failed_units_full: u32,
+
+    #[arg(long, value_parser = parse_channel)]
+    pub journal_channel: Option<u8>,
+    // count entries of this priority and more severe, 4=warning 3=err
+    #[arg(long, default_value_t = 4)]
+    pub journal_priority: u8,
+    #[arg(long, default_value_t = 60)]
+    pub journal_max_per_min: u32,
+
+    #[arg(long, value_parser = parse_channel)]
+    pub procs_running_channel: Option<u8>,
+    #[arg(long, value_parser = parse_channel)]
+    pub procs_blocked_channel: Option<u8>,
+    // process count giving full scale, 0 means the number of cpus
+    #[arg(long, default_value_t = 0)]
+    pub procs_max: u32,
+
+    #[arg(long, value_parser = parse_channel)]
+    pub steal_channel: Option<u8>,
+    // steal percentage giving full scale
+    #[arg(long, default_value_t = 100.0)]
+    pub steal_max_pct: f64,
+
+    #[arg(long, value_parser = parse_channel)]
+    pub irq_channel: Option<u8>,
+    // irq+softirq percentage of the busiest cpu giving full scale
+    #[arg(long, default_value_t = 100.0)]
+    pub irq_max_pct: f64,
+
+    #[arg(long, value_parser = parse_channel)]
+    pub perf_channel: Option<u8>,
+    // ipc or llc-miss
+    #[arg(long, default_value = "ipc")]
+    pub perf_metric: PerfMetric,
+    #[arg(long, default_value_t = 4.0)]
+    pub perf_ipc_max: f64,
+
+    #[arg(long, value_parser = parse_channel)]
+    pub pgfault_channel: Option<u8>,
+    #[arg(long, default_value_t = 100_000)]
+    pub pgfault_max: u32,
+    #[arg(long, value_parser = parse_channel)]
+    pub pgmajfault_channel: Option<u8>,
+    #[arg(long, default_value_t = 1000)]
+    pub pgmajfault_max: u32,
+
+    #[arg(long, value_parser = parse_channel)]
+    pub dirty_channel: Option<u8>,
+    #[arg(long, value_parser = parse_channel)]
+    pub writeback_channel: Option<u8>,
+
+    #[arg(long, value_parser = parse_channel)]
+    pub hugepages_channel: Option<u8>,
+
+    #[arg(long, value_parser = parse_channel)]
+    pub audio_channel: Option<u8>,
+    #[arg(long, default_value = "@DEFAULT_MONITOR@")]
+    pub audio_device: String,
+    // show peak level instead of rms
+    #[arg(long)]
+    pub audio_peak: bool,
+    // level in dBFS giving zero on the meter
+    #[arg(long, default_value_t = -60.0, allow_negative_numbers = true)]
+    pub audio_floor_db: f64,
+
+    #[arg(long, value_parser = parse_channel)]
+    pub cpufreq_channel: Option<u8>,
+
+    #[arg(long, value_parser = parse_channel)]
+    pub throttle_channel: Option<u8>,
+
+    #[arg(long, value_parser = parse_channel)]
+    pub gpu_temp_channel: Option<u8>,
+    // temperatures in Celsius giving zero and full scale
+    #[arg(long, default_value_t = 30.0)]
+    pub gpu_temp_idle: f64,
+    #[arg(long, default_value_t = 90.0)]
+    pub gpu_temp_redline: f64,
+
+    #[arg(long, value_parser = parse_channel)]
+    pub jitter_channel: Option<u8>,
+    #[arg(long, default_value = "rx")]
+    pub jitter_dir: IfCounter,
+    // number of samples in the jitter window
+    #[arg(long, default_value_t = 10)]
+    pub jitter_window: usize,
+
+    // public key, or a prefix of it matching only that peer
+    #[arg(long)]
+    pub wg_peer: Option<String>,
+    #[arg(long, default_value = "wg0")]
+    pub wg_interface: String,
+    #[arg(long, default_value_t = 4, value_parser = parse_channel)]
+    pub wg_channel: u8,
+    #[arg(long, default_value_t = 100)]
+    pub wg_max_mbps: u16,
+
+    // iface:stat as listed by ethtool -S, e.g. --ethtool-stat eth0:rx_missed_errors
+    #[arg(long)]
+    pub ethtool_stat: Option<String>,
+    #[arg(long, default_value_t = 4, value_parser = parse_channel)]
+    pub ethtool_channel: u8,
+    // counter increments per second giving full scale
+    #[arg(long, default_value_t = 1000)]
+    pub ethtool_max: u32,
+
+    // host[:port] of the SNMP agent, 2c or 3
+    #[arg(long)]
+    pub snmp_target: Option<String>,
+    #[arg(long, default_value = "2c")]
+    pub snmp_version: SnmpVersion,
+    #[arg(long, default_value = "public")]
+    pub snmp_community: String,
+    // v3 only: the USM user, with SHA authentication and AES privacy if the
+    // passwords are given, at least 8 characters
+    #[arg(long)]
+    pub snmp_user: Option<String>,
+    #[arg(long)]
+    pub snmp_auth_password: Option<String>,
+    #[arg(long)]
+    pub snmp_priv_password: Option<String>,
+    #[arg(long, default_value_t = 1)]
+    pub snmp_ifindex: u32,
+    #[arg(long, default_value_t = 5)]
+    pub snmp_interval: u32,
+    #[arg(long, default_value_t = 4, value_parser = parse_channel)]
+    pub snmp_channel: u8,
+    #[arg(long, default_value_t = 100)]
+    pub snmp_max_mbps: u16,
+
+    #[arg(long)]
+    pub libvirt_domain: Option<String>,
+    #[arg(long)]
+    pub libvirt_uri: Option<String>,
+    // cpu, block or net
+    #[arg(long, default_value = "cpu")]
+    pub libvirt_metric: VmMetric,
+    #[arg(long, default_value_t = 4, value_parser = parse_channel)]
+    pub libvirt_channel: u8,
+    // full scale in MB/s for block and Mbps for net
+    #[arg(long, default_value_t = 100)]
+    pub libvirt_max: u32,
+
+    // namespace/name-glob, e.g. --k8s-pods 'default/web-*'
+    #[arg(long)]
+    pub k8s_pods: Option<String>,
+    // cpu or net
+    #[arg(long, default_value = "cpu")]
+    pub k8s_metric: K8sMetric,
+    #[arg(long, default_value = "https://127.0.0.1:10250/stats/summary")]
+    pub k8s_url: String,
+    #[arg(
+        long,
+        default_value = "/var/run/secrets/kubernetes.io/serviceaccount/token"
+    )]
+    pub k8s_token_file: String,
+    #[arg(long, default_value_t = 10)]
+    pub k8s_interval: u32,
+    #[arg(long, default_value_t = 4, value_parser = parse_channel)]
+    pub k8s_channel: u8,
+    // full scale in cores for cpu (0 means all of them) and Mbps for net
+    #[arg(long, default_value_t = 0)]
+    pub k8s_max: u32,
+
+    // host:port of the MQTT broker
+    #[arg(long)]
+    pub mqtt_broker: Option<String>,
+    #[arg(long, default_value = "perf-vumeter/{channel}")]
+    pub mqtt_topic: String,
+    #[arg(long)]
+    pub mqtt_client_id: Option<String>,
+    #[arg(long)]
+    pub mqtt_username: Option<String>,
+    #[arg(long)]
+    pub mqtt_password: Option<String>,
+    // publishing "hello" here runs the sweep
+    #[arg(long, default_value = "perf-vumeter/cmd")]
+    pub mqtt_command_topic: String,
+    // announce the channels to Home Assistant
+    #[arg(long)]
+    pub mqtt_discovery: bool,
+    #[arg(long, default_value = "homeassistant")]
+    pub mqtt_discovery_prefix: String,
+
+    // InfluxDB v2 base URL, e.g. http://localhost:8086
+    #[arg(long)]
+    pub influx_url: Option<String>,
+    #[arg(long, default_value = "")]
+    pub influx_org: String,
+    #[arg(long, default_value = "perf-vumeter")]
+    pub influx_bucket: String,
+    #[arg(long)]
+    pub influx_token: Option<String>,
+    // seconds between batched writes
+    #[arg(long, default_value_t = 10.0)]
+    pub influx_flush: f64,
+
+    // host:port of a carbon plaintext listener or statsd server
+    #[arg(long)]
+    pub graphite: Option<String>,
+    // graphite or statsd
+    #[arg(long, default_value = "graphite")]
+    pub graphite_proto: GraphiteProto,
+    // "{host}" is replaced with the hostname
+    #[arg(long, default_value = "perf_vumeter.{host}")]
+    pub graphite_prefix: String,
+    // seconds between flushes
+    #[arg(long, default_value_t = 10.0)]
+    pub graphite_flush: f64,
+
+    // draw the gauges in the terminal, the meter becomes optional and logs go to stderr
+    #[arg(long)]
+    pub tui: bool,
+    // print braille sparklines of the gauges every second, like --tui the meter becomes optional
+    #[arg(long)]
+    pub sparklines: bool,
+    // print status bar JSON every second, waybar or i3bar, like --tui the meter becomes optional
+    #[arg(long)]
+    pub statusbar: Option<BarFormat>,
+
+    // serve the frames as JSON over WebSocket, e.g. 0.0.0.0:8765
+    #[arg(long)]
+    pub ws_listen: Option<String>,
+    // serve the gRPC Meter service of proto/perf_vumeter.proto, cleartext HTTP/2, e.g. 0.0.0.0:50051
+    #[arg(long)]
+    pub grpc_listen: Option<String>,
+    // serve GET /status with the current gauges as JSON, e.g. 127.0.0.1:8080
+    #[arg(long)]
+    pub status_listen: Option<String>,
+
+    // host:port to send Open Sound Control messages to
+    #[arg(long)]
+    pub osc_target: Option<String>,
+    // OSC address pattern, "{channel}" and "{source}" are replaced
+    #[arg(long, default_value = "/vumeter/{channel}")]
+    pub osc_address: String,
+
+    // artnet, sacn or enttec
+    #[arg(long)]
+    pub dmx_proto: Option<DmxProto>,
+    // host[:port] for artnet/sacn, serial device for enttec
+    #[arg(long)]
+    pub dmx_target: Option<String>,
+    #[arg(long, default_value_t = 1)]
+    pub dmx_universe: u16,
+    // DMX address of channel 1
+    #[arg(long, default_value_t = 1)]
+    pub dmx_start: u16,
+
+    // spidev device of a WS2812 LED strip, e.g. /dev/spidev0.0
+    #[cfg(target_os = "linux")]
+    #[arg(long)]
+    pub ws2812_spi: Option<String>,
+    // channels shown on the strip, in order
+    #[cfg(target_os = "linux")]
+    #[arg(long, value_delimiter = ',', default_value = "1,2,3")]
+    pub ws2812_channels: Vec<u8>,
+    // LEDs per channel
+    #[cfg(target_os = "linux")]
+    #[arg(long, default_value_t = 8)]
+    pub ws2812_leds: usize,
+    // bar colors from the bottom up, each gets an equal share of the LEDs
+    #[cfg(target_os = "linux")]
+    #[arg(long, value_delimiter = ',', default_value = "00ff00,ffff00,ff0000")]
+    pub ws2812_colors: Vec<Rgb>,
+    // color of the falling peak dot, none by default
+    #[cfg(target_os = "linux")]
+    #[arg(long)]
+    pub ws2812_peak_color: Option<Rgb>,
+    #[cfg(target_os = "linux")]
+    #[arg(long, default_value_t = 64)]
+    pub ws2812_brightness: u8,
+
+    // i2c-dev bus of a PCA9685 PWM board, e.g. /dev/i2c-1
+    #[cfg(target_os = "linux")]
+    #[arg(long)]
+    pub pca9685_bus: Option<String>,
+    // i2c address, decimal or 0x hex
+    #[cfg(target_os = "linux")]
+    #[arg(long, default_value = "0x40", value_parser = parse_int::<u16>)]
+    pub pca9685_addr: u16,
+    #[cfg(target_os = "linux")]
+    #[arg(long, default_value_t = 1000)]
+    pub pca9685_freq: u32,
+    // meter channel to PWM output (0-15), e.g. 1=0, default channels 1-16 to outputs 0-15
+    #[cfg(target_os = "linux")]
+    #[arg(long, value_parser = parse_channel_arg::<u8>)]
+    pub pca9685_map: Vec<(u8, u8)>,
+    // duty cycle of a full scale reading, out of 4095
+    #[cfg(target_os = "linux")]
+    #[arg(long, default_value_t = 4095)]
+    pub pca9685_max: u16,
+
+    // channel=pin[:min-max] with pin pwmCHIP.CHANNEL (hardware) or gpioLINE (software),
+    // the optional range is the duty cycle in percent, e.g. 1=pwm0.0 or 2=gpio17:0-80
+    #[cfg(target_os = "linux")]
+    #[arg(long, value_parser = parse_channel_arg::<GpioPwmSpec>)]
+    pub gpio_pwm: Vec<(u8, GpioPwmSpec)>,
+    // hardware PWM frequency
+    #[cfg(target_os = "linux")]
+    #[arg(long, default_value_t = 1000)]
+    pub gpio_pwm_freq: u32,
+    // gpiochip of the software PWM lines
+    #[cfg(target_os = "linux")]
+    #[arg(long, default_value = "/dev/gpiochip0")]
+    pub gpio_chip: String,
+
+    // serve the gauges on the session or system D-Bus
+    #[cfg(unix)]
+    #[arg(long)]
+    pub dbus: Option<DbusBus>,
+
+    // named pipe to write newline-delimited JSON frames to, created if missing
+    #[cfg(unix)]
+    #[arg(long)]
+    pub fifo: Option<String>,
+
+    // broadcast address:port for the frames, e.g. 255.255.255.255:4242
+    #[arg(long)]
+    pub broadcast: Option<String>,
+    // binary (as in agent mode) or json
+    #[arg(long, default_value = "binary")]
+    pub broadcast_format: BroadcastFormat,
+
+    // append the samples to this CSV file
+    #[arg(long)]
+    pub csv_file: Option<String>,
+    // none or daily, daily puts the date into the file name
+    #[arg(long, default_value = "none")]
+    pub csv_rotate: CsvRotate,
+
+    // SQLite database for the sample history
+    #[arg(long)]
+    pub history_db: Option<String>,
+    // days of history to keep
+    #[arg(long, default_value_t = 7.0)]
+    pub history_retention: f64,
+
+    // hidraw device of a Stream Deck, e.g. /dev/hidraw3
+    #[cfg(target_os = "linux")]
+    #[arg(long)]
+    pub streamdeck: Option<String>,
+    // mk2 (also Original V2) or xl
+    #[cfg(target_os = "linux")]
+    #[arg(long, default_value = "mk2")]
+    pub streamdeck_model: StreamDeckModel,
+    // bar or needle
+    #[cfg(target_os = "linux")]
+    #[arg(long, default_value = "bar")]
+    pub streamdeck_style: StreamDeckStyle,
+    // channel shown on each key, from the top left key on
+    #[cfg(target_os = "linux")]
+    #[arg(long, value_delimiter = ',', default_value = "1,2,3")]
+    pub streamdeck_keys: Vec<u8>,
+    // percent
+    #[cfg(target_os = "linux")]
+    #[arg(long, default_value_t = 70)]
+    pub streamdeck_brightness: u8,
+
+    // OpenRGB SDK server, host[:port], port 6742 by default
+    #[arg(long)]
+    pub openrgb: Option<String>,
+    // controller index on the server
+    #[arg(long, default_value_t = 0)]
+    pub openrgb_device: u32,
+    // channels shown, the device LEDs are split evenly between them
+    #[arg(long, value_delimiter = ',', default_value = "1,2,3")]
+    pub openrgb_channels: Vec<u8>,
+    // fill or bar
+    #[arg(long, default_value = "fill")]
+    pub openrgb_style: OpenRgbStyle,
+
+    // i2c-dev bus of an SSD1306 OLED, e.g. /dev/i2c-1
+    #[cfg(target_os = "linux")]
+    #[arg(long)]
+    pub ssd1306_bus: Option<String>,
+    // i2c address, decimal or 0x hex
+    #[cfg(target_os = "linux")]
+    #[arg(long, default_value = "0x3c", value_parser = parse_int::<u16>)]
+    pub ssd1306_addr: u16,
+    // 32 or 64 pixels, a channel takes 16
+    #[cfg(target_os = "linux")]
+    #[arg(long, default_value_t = 64)]
+    pub ssd1306_height: usize,
+    // channels from the top down
+    #[cfg(target_os = "linux")]
+    #[arg(long, value_delimiter = ',', default_value = "1,2,3")]
+    pub ssd1306_channels: Vec<u8>,
+
+    // spidev of an SSD1680 e-paper panel, e.g. /dev/spidev0.0
+    #[cfg(target_os = "linux")]
+    #[arg(long)]
+    pub eink_spi: Option<String>,
+    #[cfg(target_os = "linux")]
+    #[arg(long, default_value = "/dev/gpiochip0")]
+    pub eink_gpiochip: String,
+    // line offsets of the panel DC, RST and BUSY pins, Waveshare HAT wiring by default
+    #[cfg(target_os = "linux")]
+    #[arg(long, default_value_t = 25)]
+    pub eink_dc: u32,
+    #[cfg(target_os = "linux")]
+    #[arg(long, default_value_t = 17)]
+    pub eink_rst: u32,
+    #[cfg(target_os = "linux")]
+    #[arg(long, default_value_t = 24)]
+    pub eink_busy: u32,
+    // panel size in portrait, 128x296 for the 2.9" or 122x250 for the 2.13" (use 128)
+    #[cfg(target_os = "linux")]
+    #[arg(long, default_value_t = 128)]
+    pub eink_width: usize,
+    #[cfg(target_os = "linux")]
+    #[arg(long, default_value_t = 296)]
+    pub eink_height: usize,
+    // minutes summarized as min/avg/max
+    #[cfg(target_os = "linux")]
+    #[arg(long, default_value_t = 15)]
+    pub eink_minutes: usize,
+    #[cfg(target_os = "linux")]
+    #[arg(long, value_delimiter = ',', default_value = "1,2,3")]
+    pub eink_channels: Vec<u8>,
+
+    // serve the gauges as Modbus TCP registers, e.g. 0.0.0.0:502
+    #[arg(long)]
+    pub modbus_listen: Option<String>,
+    // write the gauges to a Modbus device, host:port for TCP or a serial device for RTU
+    #[arg(long)]
+    pub modbus_target: Option<String>,
+    #[arg(long, default_value_t = 1)]
+    pub modbus_unit: u8,
+    // RTU line speed, 8N1
+    #[arg(long, default_value_t = 9600)]
+    pub modbus_baud: u32,
+    // channel N is written to register base + N
+    #[arg(long, default_value_t = 0)]
+    pub modbus_base: u16,
+    // register value of a full scale gauge
+    #[arg(long, default_value_t = 1000)]
+    pub modbus_scale: u16,
+
+    // SocketCAN interface to send the gauges on, e.g. can0
+    #[cfg(target_os = "linux")]
+    #[arg(long)]
+    pub can_interface: Option<String>,
+    // channel=id[:byte], e.g. 1=0x316:2, the default sends channel N in frame base + N
+    #[cfg(target_os = "linux")]
+    #[arg(long, value_parser = parse_channel_arg::<CanSlot>)]
+    pub can_map: Vec<(u8, CanSlot)>,
+    #[cfg(target_os = "linux")]
+    #[arg(long, default_value = "0x100", value_parser = parse_int::<u32>)]
+    pub can_base_id: u32,
+    // u8, u16be or u16le
+    #[cfg(target_os = "linux")]
+    #[arg(long, default_value = "u8")]
+    pub can_format: CanFormat,
+    // value of a full scale gauge, e.g. 8000 for a tachometer
+    #[cfg(target_os = "linux")]
+    #[arg(long, default_value_t = 255)]
+    pub can_scale: u16,
+
+    // play the gauges as tones on an audio output, built with the sonify feature
+    #[cfg(feature = "sonify")]
+    #[arg(long)]
+    pub sonify: bool,
+    // the output device by name
+    #[cfg(feature = "sonify")]
+    #[arg(long, default_value = "default")]
+    pub sonify_device: String,
+    #[cfg(feature = "sonify")]
+    #[arg(long, value_delimiter = ',', default_value = "1,2,3")]
+    pub sonify_channels: Vec<u8>,
+    // pitch of an idle and a full scale gauge in Hz
+    #[cfg(feature = "sonify")]
+    #[arg(long, default_value_t = 220.0)]
+    pub sonify_low: f64,
+    #[cfg(feature = "sonify")]
+    #[arg(long, default_value_t = 880.0)]
+    pub sonify_high: f64,
+    // percent
+    #[cfg(feature = "sonify")]
+    #[arg(long, default_value_t = 30.0)]
+    pub sonify_volume: f64,
+
+    // show a tray icon with the meters over StatusNotifierItem
+    #[cfg(unix)]
+    #[arg(long)]
+    pub tray: bool,
+    #[cfg(unix)]
+    #[arg(long, value_delimiter = ',', default_value = "1,2,3")]
+    pub tray_channels: Vec<u8>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Cmd {
+    // Measure locally and stream the gauges to a display instance
+    Agent {
+        // host:port of the display
+        #[arg(long)]
+        connect: String,
+    },
+    // Sample every mapped source once, then try the meter ports and their handshake
+    Check,
+    // Print the completion script of bash, zsh, fish, elvish or powershell
+    Completions {
+        shell: clap_complete::Shell,
+    },
+    // Drive the meter from gauges streamed by an agent
+    Display {
+        #[arg(long, default_value = "0.0.0.0:4242")]
+        listen: String,
+    },
+    // Show per-minute averages from the --history-db database
+    History {
+        #[arg(long, value_parser = parse_channel)]
+        channel: Option<u8>,
+        // how far back, e.g. 30m, 2h or 1d
+        #[arg(long, default_value = "1h")]
+        since: String,
+    },
+    // List the serial ports with their USB ids, for --port and --usb-id
+    ListPorts,
+    // Measure and drive the meters as usual, and record the gauges to a file
+    Record {
+        file: String,
+    },
+    // Drive the meters from a recording
+    Replay {
+        file: String,
+        // playback speed, 2 plays twice as fast
+        #[arg(long, default_value_t = 1.0)]
+        speed: f64,
+        // start over at the end
+        #[arg(long = "loop")]
+        repeat: bool,
+    },
+    // Measure and drive the meters, the same as without a command
+    Run,
+    // Send one value to a channel of the meters and exit, e.g. set --channel 2 --value 180
+    Set {
+        #[arg(long, value_parser = parse_channel)]
+        channel: u8,
+        #[arg(long)]
+        value: u8,
+    },
+    // Run the hello sweep on the meters and exit
+    Sweep,
+}
+
+// Parse a decimal or 0x-prefixed hex number
+pub fn parse_int<T>(s: &str) -> Result<T, String>
+where
+    T: TryFrom<u64>,
+{
+    let n = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse::<u64>(),
+    }
+    .map_err(|e| e.to_string())?;
+    T::try_from(n).map_err(|_| format!("{s} is out of range"))
+}
+
+// A meter channel, checked here so that a bad one fails at startup and not on the first tick
+pub fn parse_channel(s: &str) -> Result<u8, String> {
+    let ch = s.parse::<u8>().map_err(|e| e.to_string())?;
+    channel_index(ch).map_err(|e| e.to_string())?;
+    Ok(ch)
+}
+
+// Parse "channel=value" style arguments
+pub fn parse_channel_arg<T>(s: &str) -> Result<(u8, T), String>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    let (ch, val) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected channel=value, got \"{s}\""))?;
+    let ch = parse_channel(ch.trim()).map_err(|e| format!("channel: {e}"))?;
+    let val = val.trim().parse::<T>().map_err(|e| format!("value: {e}"))?;
+    Ok((ch, val))
+}
+
+// Parse "channel=age" arguments like 3=24h into seconds
+pub fn parse_channel_age(s: &str) -> Result<(u8, f64), String> {
+    let (ch, age) = parse_channel_arg::<String>(s)?;
+    match parse_age(&age) {
+        Ok(secs) if secs > 0.0 => Ok((ch, secs)),
+        Ok(_) => Err(format!("{age} is not positive")),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// A serial meter device and the channels shown on it,
+// (channel, device channel) pairs, empty passes every channel through as is
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MeterPort {
+    pub path: String,
+    pub map: Vec<(u8, u8)>,
+}
+
+impl MeterPort {
+    pub fn device_channel(&self, channel: u8) -> Option<u8> {
+        if self.map.is_empty() {
+            return Some(channel);
+        }
+        self.map
+            .iter()
+            .find(|(ch, _)| *ch == channel)
+            .map(|(_, dev)| *dev)
+    }
+}
+
+impl FromStr for MeterPort {
+    type Err = anyhow::Error;
+
+    // device channels are handed out from 1 up unless given explicitly
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((path, channels)) = s.split_once('@') else {
+            return Ok(MeterPort {
+                path: s.into(),
+                map: Vec::new(),
+            });
+        };
+        let mut map = Vec::new();
+        let mut next = 1u8;
+        for item in channels.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+            let (first, last, dev) = match (item.split_once('-'), item.split_once('=')) {
+                (Some((a, b)), None) => (a.parse::<u8>()?, b.parse::<u8>()?, next),
+                (None, Some((a, d))) => (a.parse::<u8>()?, a.parse::<u8>()?, d.parse::<u8>()?),
+                (None, None) => (item.parse::<u8>()?, item.parse::<u8>()?, next),
+                _ => bail!("Invalid channel mapping {item} for {path}"),
+            };
+            if last < first {
+                bail!("Invalid channel range {item} for {path}");
+            }
+            for (i, ch) in (first..=last).enumerate() {
+                let dev = dev
+                    .checked_add(i as u8)
+                    .ok_or_else(|| anyhow!("Too many channels for {path}"))?;
+                channel_index(ch)?;
+                channel_index(dev)?;
+                map.push((ch, dev));
+                next = dev.saturating_add(1);
+            }
+        }
+        Ok(MeterPort {
+            path: path.into(),
+            map,
+        })
+    }
+}
+
+pub fn parse_port_protocol(s: &str) -> anyhow::Result<(String, Protocol)> {
+    let (port, proto) = s
+        .rsplit_once('=')
+        .ok_or_else(|| anyhow!("expected port=protocol, got \"{s}\""))?;
+    Ok((port.into(), proto.parse()?))
+}
+
+// VID:PID[:SERIAL][@channels] becomes the port usb:VID:PID[:SERIAL][@channels]
+pub fn parse_usb_port(s: &str) -> anyhow::Result<MeterPort> {
+    let id = s.split_once('@').map_or(s, |(id, _)| id);
+    id.parse::<UsbId>()?;
+    format!("usb:{s}").parse()
+}
+
+// How fast a needle may move, in gauge units per second, and how many
+// seconds it holds a peak
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ballistics {
+    pub attack: f64,
+    pub release: f64,
+    pub hold: f64,
+}
+
+// the defaults of --slew-rate and --peak-hold
+impl Default for Ballistics {
+    fn default() -> Self {
+        Self {
+            attack: 480.0,
+            release: 480.0,
+            hold: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Handshake {
+    Off,
+    #[default]
+    Warn,
+    Require,
+}
+
+impl FromStr for Handshake {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Handshake::Off),
+            "warn" => Ok(Handshake::Warn),
+            "require" => Ok(Handshake::Require),
+            _ => Err(anyhow!("Unknown handshake: {s}")),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum VirtualMeter {
+    #[default]
+    Null,
+    Stdout,
+}
+
+impl FromStr for VirtualMeter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "null" => Ok(VirtualMeter::Null),
+            "stdout" => Ok(VirtualMeter::Stdout),
+            _ => Err(anyhow!("Unknown virtual meter: {s}")),
+        }
+    }
+}
+
+// Turns "option = value" lines into command line arguments, "channel 1 = cpu"
+// becomes --channel 1=cpu and a bare "option" a flag. # starts a comment.
+pub fn config_args(path: &str) -> anyhow::Result<Vec<String>> {
+    let text = fs::read_to_string(path).map_err(|e| anyhow!("Config file {path}: {e}"))?;
+    let mut args = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (key, value) = match line.split_once('=') {
+            Some((k, v)) => (k.trim(), Some(v.trim().trim_matches('"'))),
+            None => (line, None),
+        };
+        let (name, channel) = match key.split_once(char::is_whitespace) {
+            Some((name, ch)) => (name, Some(ch.trim())),
+            None => (key, None),
+        };
+        if name.is_empty() || name.starts_with('-') {
+            bail!("{path}:{}: invalid option {key}", n + 1);
+        }
+        args.push(format!("--{}", name.replace('_', "-")));
+        match (channel, value) {
+            (Some(ch), Some(v)) => args.push(format!("{ch}={v}")),
+            (None, Some(v)) => args.push(v.to_string()),
+            (None, None) => {}
+            (Some(_), None) => bail!("{path}:{}: no value for {key}", n + 1),
+        }
+    }
+    Ok(args)
 }
 
 impl OptsCommon {
+    // the command line, preceded by the options in the --config file
+    pub fn load() -> anyhow::Result<Self> {
+        Self::load_from(env::args().collect(), true)
+    }
+
+    // the same again on SIGHUP, errors are returned instead of exiting
+    pub fn reload() -> anyhow::Result<Self> {
+        Self::load_from(env::args().collect(), false)
+    }
+
+    pub fn load_from(args: Vec<String>, exit_on_error: bool) -> anyhow::Result<Self> {
+        let parse = |args: &[String]| match Self::try_parse_from(args) {
+            Ok(opts) => Ok(opts),
+            Err(e) if exit_on_error => e.exit(),
+            Err(e) => Err(anyhow!(
+                "{}",
+                e.to_string().lines().next().unwrap_or_default()
+            )),
+        };
+        let cli = parse(&args)?;
+        let Some(path) = &cli.config else {
+            return Ok(cli);
+        };
+        let mut full = args[..1].to_vec();
+        full.extend(config_args(path)?);
+        full.extend(args[1..].iter().cloned());
+        parse(&full)
+    }
+
+    // the channels fed from the mapping layer
+    // the legacy per-source options come last and take over their channels
+    pub fn mapping(&self) -> Vec<(u8, SourceSpec)> {
+        let mut mapping = match (self.channel.is_empty(), self.demo) {
+            (true, false) => default_mapping(),
+            (true, true) => demo_mapping(),
+            (false, _) => self.channel.clone(),
+        };
+        for (ch, spec) in self.legacy_mapping() {
+            mapping.retain(|(c, _)| *c != ch);
+            mapping.push((ch, spec));
+        }
+        mapping
+    }
+
+    fn legacy_mapping(&self) -> Vec<(u8, SourceSpec)> {
+        let on = |ch: Option<u8>, spec: SourceSpec| ch.map(|ch| (ch, spec));
+        let if_counters = self.if_counter.iter().map(|(ch, counter)| {
+            let spec = SourceSpec::Net {
+                iface: None,
+                dir: Some(*counter),
+            };
+            Some((*ch, spec))
+        });
+        [
+            on(self.steal_channel, SourceSpec::Steal),
+            on(self.irq_channel, SourceSpec::Irq),
+            on(
+                self.jitter_channel,
+                SourceSpec::Jitter {
+                    iface: None,
+                    dir: None,
+                },
+            ),
+            self.wg_peer.as_ref().map(|peer| {
+                let spec = SourceSpec::WireGuard {
+                    peer: peer.clone(),
+                    iface: None,
+                };
+                (self.wg_channel, spec)
+            }),
+            self.ethtool_stat.as_ref().map(|stat| {
+                let (iface, stat) = stat.split_once(':').unwrap_or((stat, ""));
+                let spec = SourceSpec::Ethtool {
+                    iface: iface.into(),
+                    stat: stat.into(),
+                };
+                (self.ethtool_channel, spec)
+            }),
+            self.snmp_target
+                .as_ref()
+                .map(|t| (self.snmp_channel, SourceSpec::Snmp(t.clone()))),
+        ]
+        .into_iter()
+        .chain(if_counters)
+        .chain([
+            on(self.conntrack_channel, SourceSpec::Conntrack),
+            self.nft_counter
+                .as_ref()
+                .map(|c| (self.nft_channel, SourceSpec::Nft(c.clone()))),
+            on(self.failed_units_channel, SourceSpec::FailedUnits),
+            on(self.journal_channel, SourceSpec::Journal),
+            on(self.procs_running_channel, SourceSpec::ProcsRunning),
+            on(self.procs_blocked_channel, SourceSpec::ProcsBlocked),
+            on(self.perf_channel, SourceSpec::Perf(None)),
+            on(self.pgfault_channel, SourceSpec::PgFault),
+            on(self.pgmajfault_channel, SourceSpec::PgMajFault),
+            on(self.dirty_channel, SourceSpec::Dirty),
+            on(self.writeback_channel, SourceSpec::Writeback),
+            on(self.hugepages_channel, SourceSpec::HugePages),
+            on(self.audio_channel, SourceSpec::Audio),
+            on(self.cpufreq_channel, SourceSpec::CpuFreq),
+            on(self.throttle_channel, SourceSpec::Throttle),
+            self.libvirt_domain.as_ref().map(|domain| {
+                let spec = SourceSpec::Libvirt {
+                    domain: domain.clone(),
+                    metric: None,
+                };
+                (self.libvirt_channel, spec)
+            }),
+            self.k8s_pods.as_ref().map(|pods| {
+                let spec = SourceSpec::K8s {
+                    pods: pods.clone(),
+                    metric: None,
+                };
+                (self.k8s_channel, spec)
+            }),
+            on(self.gpu_temp_channel, SourceSpec::GpuTemp),
+            self.http_url
+                .as_ref()
+                .map(|url| (self.http_channel, SourceSpec::Http(url.clone()))),
+            on(self.ntp_channel, SourceSpec::Ntp),
+            on(self.heartbeat_channel, SourceSpec::Heartbeat),
+        ])
+        .flatten()
+        .collect()
+    }
+
+    pub fn mapping_config(&self) -> MappingConfig {
+        MappingConfig {
+            interface: self.interface.clone(),
+            disks: self.disks.clone(),
+            disk_gauge: self.disk_gauge,
+            cpu_mode: self.cpu_mode,
+            max_mbps: self.max_mbps,
+            period: time::Duration::from_secs(1) / self.samplerate.max(1) as u32,
+            steal_max_pct: self.steal_max_pct,
+            irq_max_pct: self.irq_max_pct,
+            if_events_max: self.if_events_max,
+            jitter_dir: self.jitter_dir,
+            jitter_window: self.jitter_window,
+            nft_max_mbps: self.nft_max_mbps,
+            failed_units_full: self.failed_units_full,
+            journal_priority: self.journal_priority,
+            journal_max_per_min: self.journal_max_per_min,
+            procs_max: self.procs_max,
+            perf_metric: self.perf_metric,
+            perf_ipc_max: self.perf_ipc_max,
+            pgfault_max: self.pgfault_max,
+            pgmajfault_max: self.pgmajfault_max,
+            audio_device: self.audio_device.clone(),
+            audio_peak: self.audio_peak,
+            audio_floor_db: self.audio_floor_db,
+            gpu_temp_idle: self.gpu_temp_idle,
+            gpu_temp_redline: self.gpu_temp_redline,
+            wg_interface: self.wg_interface.clone(),
+            wg_max_mbps: self.wg_max_mbps,
+            ethtool_max: self.ethtool_max,
+            snmp_auth: match self.snmp_version {
+                SnmpVersion::V2c => SnmpAuth::Community(self.snmp_community.clone()),
+                SnmpVersion::V3 => SnmpAuth::User {
+                    name: self.snmp_user.clone().unwrap_or_default(),
+                    auth_password: self.snmp_auth_password.clone(),
+                    priv_password: self.snmp_priv_password.clone(),
+                },
+            },
+            snmp_ifindex: self.snmp_ifindex,
+            snmp_interval: self.snmp_interval,
+            snmp_max_mbps: self.snmp_max_mbps,
+            libvirt_uri: self.libvirt_uri.clone(),
+            libvirt_metric: self.libvirt_metric,
+            libvirt_max: self.libvirt_max,
+            k8s_metric: self.k8s_metric,
+            k8s_url: self.k8s_url.clone(),
+            k8s_token_file: self.k8s_token_file.clone(),
+            k8s_interval: self.k8s_interval,
+            k8s_max: self.k8s_max,
+            http_max_ms: self.http_max_ms,
+            ntp_max_us: self.ntp_max_us,
+            heartbeat_period: self.heartbeat_period,
+            heartbeat_level: self.heartbeat_level,
+        }
+    }
+
+    pub fn adaptive_window(&self, channel: u8) -> Option<time::Duration> {
+        self.adaptive
+            .iter()
+            .rev()
+            .find(|(ch, _)| *ch == channel)
+            .and_then(|(_, secs)| time::Duration::try_from_secs_f64(*secs).ok())
+    }
+
+    pub fn sample_interval(&self, channel: u8) -> time::Duration {
+        self.interval
+            .iter()
+            .rev()
+            .find(|(ch, _)| *ch == channel)
+            .and_then(|(_, secs)| time::Duration::try_from_secs_f64(*secs).ok())
+            .unwrap_or_default()
+    }
+
+    // the USB ids replace the ports when given
+    pub fn meter_ports(&self) -> &[MeterPort] {
+        match self.usb_id.is_empty() {
+            true => &self.port,
+            false => &self.usb_id,
+        }
+    }
+
+    // the protocol is looked up by the port as given, without the channels
+    pub fn serial_config(&self, port: &MeterPort) -> SerialConfig {
+        let path = port.path.strip_prefix("usb:").unwrap_or(&port.path);
+        SerialConfig {
+            handshake: self.handshake,
+            protocol: self
+                .protocol
+                .iter()
+                .rev()
+                .find(|(p, _)| p.strip_prefix("usb:").unwrap_or(p) == path)
+                .map_or_else(Protocol::default, |(_, proto)| *proto),
+        }
+    }
+
+    pub fn quant_step(&self, channel: u8) -> u8 {
+        self.quantize
+            .iter()
+            .rev()
+            .find(|(ch, _)| *ch == channel)
+            .map_or(1, |(_, step)| (*step).max(1))
+    }
+
+    pub fn ballistics(&self, channel: u8) -> Ballistics {
+        let rate = |rates: &[(u8, f64)]| {
+            rates
+                .iter()
+                .rev()
+                .find(|(ch, _)| *ch == channel)
+                .map_or(self.slew_rate, |(_, r)| *r)
+                .max(1.0)
+        };
+        Ballistics {
+            attack: rate(&self.attack),
+            release: rate(&self.release),
+            hold: self
+                .peak_hold
+                .iter()
+                .rev()
+                .find(|(ch, _)| *ch == channel)
+                .map_or(0.0, |(_, secs)| *secs),
+        }
+    }
+
     pub fn get_loglevel(&self) -> Level {
         if self.trace {
             Level::TRACE
@@ -34,11 +1184,24 @@ impl OptsCommon {
         }
     }
 
+    // the gauges are drawn on stdout, so there is no need for a meter
+    pub fn console_output(&self) -> bool {
+        self.tui
+            || self.sparklines
+            || self.statusbar.is_some()
+            || self.sink == Some(VirtualMeter::Stdout)
+            || self.dry_run
+    }
+
     pub fn start_pgm(&self, name: &str) {
-        tracing_subscriber::fmt()
+        let builder = tracing_subscriber::fmt()
             .with_max_level(self.get_loglevel())
-            .with_target(false)
-            .init();
+            .with_target(false);
+        if self.console_output() {
+            builder.with_writer(std::io::stderr).init();
+        } else {
+            builder.init();
+        }
 
         info!("Starting up {name} v{}...", env!("CARGO_PKG_VERSION"));
         debug!("Git branch: {}", env!("GIT_BRANCH"));
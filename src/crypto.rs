@@ -0,0 +1,279 @@
+// crypto.rs
+
+// The hashes and the cipher of the WebSocket handshake and SNMPv3, small and
+// slow but plenty for a few messages a second
+
+pub(crate) fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut msg = data.to_vec();
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    msg.extend((data.len() as u64 * 8).to_be_bytes());
+
+    for chunk in msg.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in chunk.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, wi) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let t = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*wi);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = t;
+        }
+        for (hi, v) in h.iter_mut().zip([a, b, c, d, e]) {
+            *hi = hi.wrapping_add(v);
+        }
+    }
+
+    let mut out = [0u8; 20];
+    for (i, v) in h.iter().enumerate() {
+        out[i * 4..i * 4 + 4].copy_from_slice(&v.to_be_bytes());
+    }
+    out
+}
+
+// RFC 2104
+pub(crate) fn hmac_sha1(key: &[u8], data: &[u8]) -> [u8; 20] {
+    let mut k = [0u8; 64];
+    match key.len() {
+        0..=64 => k[..key.len()].copy_from_slice(key),
+        _ => k[..20].copy_from_slice(&sha1(key)),
+    }
+    let mut inner = k.iter().map(|b| b ^ 0x36).collect::<Vec<u8>>();
+    inner.extend(data);
+    let mut outer = k.iter().map(|b| b ^ 0x5c).collect::<Vec<u8>>();
+    outer.extend(sha1(&inner));
+    sha1(&outer)
+}
+
+#[rustfmt::skip]
+const AES_SBOX: [u8; 256] = [
+    0x63, 0x7c, 0x77, 0x7b, 0xf2, 0x6b, 0x6f, 0xc5, 0x30, 0x01, 0x67, 0x2b, 0xfe, 0xd7, 0xab, 0x76,
+    0xca, 0x82, 0xc9, 0x7d, 0xfa, 0x59, 0x47, 0xf0, 0xad, 0xd4, 0xa2, 0xaf, 0x9c, 0xa4, 0x72, 0xc0,
+    0xb7, 0xfd, 0x93, 0x26, 0x36, 0x3f, 0xf7, 0xcc, 0x34, 0xa5, 0xe5, 0xf1, 0x71, 0xd8, 0x31, 0x15,
+    0x04, 0xc7, 0x23, 0xc3, 0x18, 0x96, 0x05, 0x9a, 0x07, 0x12, 0x80, 0xe2, 0xeb, 0x27, 0xb2, 0x75,
+    0x09, 0x83, 0x2c, 0x1a, 0x1b, 0x6e, 0x5a, 0xa0, 0x52, 0x3b, 0xd6, 0xb3, 0x29, 0xe3, 0x2f, 0x84,
+    0x53, 0xd1, 0x00, 0xed, 0x20, 0xfc, 0xb1, 0x5b, 0x6a, 0xcb, 0xbe, 0x39, 0x4a, 0x4c, 0x58, 0xcf,
+    0xd0, 0xef, 0xaa, 0xfb, 0x43, 0x4d, 0x33, 0x85, 0x45, 0xf9, 0x02, 0x7f, 0x50, 0x3c, 0x9f, 0xa8,
+    0x51, 0xa3, 0x40, 0x8f, 0x92, 0x9d, 0x38, 0xf5, 0xbc, 0xb6, 0xda, 0x21, 0x10, 0xff, 0xf3, 0xd2,
+    0xcd, 0x0c, 0x13, 0xec, 0x5f, 0x97, 0x44, 0x17, 0xc4, 0xa7, 0x7e, 0x3d, 0x64, 0x5d, 0x19, 0x73,
+    0x60, 0x81, 0x4f, 0xdc, 0x22, 0x2a, 0x90, 0x88, 0x46, 0xee, 0xb8, 0x14, 0xde, 0x5e, 0x0b, 0xdb,
+    0xe0, 0x32, 0x3a, 0x0a, 0x49, 0x06, 0x24, 0x5c, 0xc2, 0xd3, 0xac, 0x62, 0x91, 0x95, 0xe4, 0x79,
+    0xe7, 0xc8, 0x37, 0x6d, 0x8d, 0xd5, 0x4e, 0xa9, 0x6c, 0x56, 0xf4, 0xea, 0x65, 0x7a, 0xae, 0x08,
+    0xba, 0x78, 0x25, 0x2e, 0x1c, 0xa6, 0xb4, 0xc6, 0xe8, 0xdd, 0x74, 0x1f, 0x4b, 0xbd, 0x8b, 0x8a,
+    0x70, 0x3e, 0xb5, 0x66, 0x48, 0x03, 0xf6, 0x0e, 0x61, 0x35, 0x57, 0xb9, 0x86, 0xc1, 0x1d, 0x9e,
+    0xe1, 0xf8, 0x98, 0x11, 0x69, 0xd9, 0x8e, 0x94, 0x9b, 0x1e, 0x87, 0xe9, 0xce, 0x55, 0x28, 0xdf,
+    0x8c, 0xa1, 0x89, 0x0d, 0xbf, 0xe6, 0x42, 0x68, 0x41, 0x99, 0x2d, 0x0f, 0xb0, 0x54, 0xbb, 0x16,
+];
+const AES_RCON: [u8; 10] = [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1b, 0x36];
+
+// FIPS-197 AES-128, only the encryption as CFB mode needs no more
+pub(crate) struct Aes128 {
+    round_keys: [[u8; 16]; 11],
+}
+
+impl Aes128 {
+    pub fn new(key: &[u8; 16]) -> Self {
+        let mut w = [[0u8; 4]; 44];
+        for (i, word) in key.chunks(4).enumerate() {
+            w[i].copy_from_slice(word);
+        }
+        for i in 4..44 {
+            let mut t = w[i - 1];
+            if i % 4 == 0 {
+                t = [
+                    AES_SBOX[t[1] as usize] ^ AES_RCON[i / 4 - 1],
+                    AES_SBOX[t[2] as usize],
+                    AES_SBOX[t[3] as usize],
+                    AES_SBOX[t[0] as usize],
+                ];
+            }
+            for j in 0..4 {
+                w[i][j] = w[i - 4][j] ^ t[j];
+            }
+        }
+        let mut round_keys = [[0u8; 16]; 11];
+        for (r, rk) in round_keys.iter_mut().enumerate() {
+            for c in 0..4 {
+                rk[4 * c..4 * c + 4].copy_from_slice(&w[4 * r + c]);
+            }
+        }
+        Self { round_keys }
+    }
+
+    // the state is kept column by column, as the bytes come
+    pub fn encrypt_block(&self, block: &mut [u8; 16]) {
+        xor(block, &self.round_keys[0]);
+        for round in 1..11 {
+            for b in block.iter_mut() {
+                *b = AES_SBOX[*b as usize];
+            }
+            // row r moves r columns to the left
+            let s = *block;
+            for c in 0..4 {
+                for r in 0..4 {
+                    block[r + 4 * c] = s[r + 4 * ((c + r) % 4)];
+                }
+            }
+            if round < 10 {
+                for col in block.chunks_mut(4) {
+                    let a = [col[0], col[1], col[2], col[3]];
+                    let x = a.map(xtime);
+                    col[0] = x[0] ^ x[1] ^ a[1] ^ a[2] ^ a[3];
+                    col[1] = a[0] ^ x[1] ^ x[2] ^ a[2] ^ a[3];
+                    col[2] = a[0] ^ a[1] ^ x[2] ^ x[3] ^ a[3];
+                    col[3] = x[0] ^ a[0] ^ a[1] ^ a[2] ^ x[3];
+                }
+            }
+            xor(block, &self.round_keys[round]);
+        }
+    }
+
+    // CFB-128, the last block may be short
+    pub fn cfb_encrypt(&self, iv: &[u8; 16], data: &mut [u8]) {
+        let mut feedback = *iv;
+        for chunk in data.chunks_mut(16) {
+            self.encrypt_block(&mut feedback);
+            for (b, k) in chunk.iter_mut().zip(feedback) {
+                *b ^= k;
+            }
+            feedback[..chunk.len()].copy_from_slice(chunk);
+        }
+    }
+
+    pub fn cfb_decrypt(&self, iv: &[u8; 16], data: &mut [u8]) {
+        let mut feedback = *iv;
+        for chunk in data.chunks_mut(16) {
+            let cipher = feedback_block(chunk);
+            self.encrypt_block(&mut feedback);
+            for (b, k) in chunk.iter_mut().zip(feedback) {
+                *b ^= k;
+            }
+            feedback = cipher;
+        }
+    }
+}
+
+fn feedback_block(chunk: &[u8]) -> [u8; 16] {
+    let mut block = [0u8; 16];
+    block[..chunk.len()].copy_from_slice(chunk);
+    block
+}
+
+fn xtime(b: u8) -> u8 {
+    (b << 1) ^ if b & 0x80 != 0 { 0x1b } else { 0 }
+}
+
+fn xor(block: &mut [u8; 16], key: &[u8; 16]) {
+    for (b, k) in block.iter_mut().zip(key) {
+        *b ^= k;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    // FIPS 180 examples
+    #[test]
+    fn sha1_vectors() {
+        assert_eq!(
+            sha1(b"abc").to_vec(),
+            hex("a9993e364706816aba3e25717850c26c9cd0d89d")
+        );
+        assert_eq!(
+            sha1(b"").to_vec(),
+            hex("da39a3ee5e6b4b0d3255bfef95601890afd80709")
+        );
+        assert_eq!(
+            sha1(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq").to_vec(),
+            hex("84983e441c3bd26ebaae4aa1f95129e5e54670f1")
+        );
+        assert_eq!(
+            sha1(&[b'a'; 1_000_000]).to_vec(),
+            hex("34aa973cd4c4daa4f61eeb2bdbad27316534016f")
+        );
+    }
+
+    // RFC 2202 test cases 1, 2 and 6
+    #[test]
+    fn hmac_sha1_vectors() {
+        assert_eq!(
+            hmac_sha1(&[0x0b; 20], b"Hi There").to_vec(),
+            hex("b617318655057264e28bc0b6fb378c8ef146be00")
+        );
+        assert_eq!(
+            hmac_sha1(b"Jefe", b"what do ya want for nothing?").to_vec(),
+            hex("effcdf6ae5eb2fa2d27416d5f184df9c259a7c79")
+        );
+        assert_eq!(
+            hmac_sha1(
+                &[0xaa; 80],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            )
+            .to_vec(),
+            hex("aa4ae5e15272d00e95705637ce8a3b55ed402112")
+        );
+    }
+
+    // FIPS-197 C.1
+    #[test]
+    fn aes128_block() {
+        let key: [u8; 16] = hex("000102030405060708090a0b0c0d0e0f").try_into().unwrap();
+        let mut block: [u8; 16] = hex("00112233445566778899aabbccddeeff").try_into().unwrap();
+        Aes128::new(&key).encrypt_block(&mut block);
+        assert_eq!(block.to_vec(), hex("69c4e0d86a7b0430d8cdb78070b4c55a"));
+    }
+
+    // SP 800-38A F.3.13, then a short last block back and forth
+    #[test]
+    fn aes128_cfb() {
+        let key: [u8; 16] = hex("2b7e151628aed2a6abf7158809cf4f3c").try_into().unwrap();
+        let iv: [u8; 16] = hex("000102030405060708090a0b0c0d0e0f").try_into().unwrap();
+        let aes = Aes128::new(&key);
+        let plain = hex("6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e51");
+        let mut data = plain.clone();
+        aes.cfb_encrypt(&iv, &mut data);
+        assert_eq!(
+            data,
+            hex("3b3fd92eb72dad20333449f8e83cfb4ac8a64537a0b3a93fcde3cdad9f1ce58b")
+        );
+        aes.cfb_decrypt(&iv, &mut data);
+        assert_eq!(data, plain);
+
+        let mut data = plain[..21].to_vec();
+        aes.cfb_encrypt(&iv, &mut data);
+        assert_eq!(data, hex("3b3fd92eb72dad20333449f8e83cfb4ac8a64537a0"));
+        aes.cfb_decrypt(&iv, &mut data);
+        assert_eq!(data, plain[..21]);
+    }
+}
+
+// EOF
@@ -0,0 +1,145 @@
+// csv.rs
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    str::FromStr,
+    sync::mpsc,
+    time,
+};
+
+use anyhow::anyhow;
+
+use crate::*;
+
+const CSV_HEADER: &str = "time,channel,source,gauge,raw";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CsvRotate {
+    #[default]
+    None,
+    // a new file every local midnight, the date goes before the extension
+    Daily,
+}
+
+impl FromStr for CsvRotate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(CsvRotate::None),
+            "daily" => Ok(CsvRotate::Daily),
+            _ => Err(anyhow!("Unknown CSV rotation: {s}")),
+        }
+    }
+}
+
+// Appends one row per channel and frame to a CSV file:
+// 2026-05-01T23:59:58.200,1,cpu,123,48.2
+pub struct CsvSink;
+
+impl CsvSink {
+    pub fn spawn<S: AsRef<str>>(path: S, rotate: CsvRotate) -> anyhow::Result<ThreadedSink> {
+        let path = path.as_ref().to_string();
+        // fail early on an unwritable location
+        let first = Self::open(&Self::file_name(&path, rotate, &today()))?;
+        info!("Logging samples to {path}");
+        spawn_sink("csv", move |rx| Self::run(path, rotate, first, rx))
+    }
+
+    fn run(path: String, rotate: CsvRotate, first: BufWriter<File>, rx: mpsc::Receiver<Frame>) {
+        let mut date = today();
+        let mut out = Some(first);
+        while let Ok(frame) = rx.recv() {
+            let now = time::SystemTime::now();
+            let secs = now
+                .duration_since(time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64();
+
+            if rotate == CsvRotate::Daily && today() != date {
+                date = today();
+                out = None;
+            }
+            if out.is_none() {
+                let name = Self::file_name(&path, rotate, &date);
+                out = Self::open(&name)
+                    .map_err(|e| {
+                        error!("CSV {name}: {e}");
+                        count_error("csv");
+                    })
+                    .ok();
+            }
+            let Some(w) = &mut out else {
+                continue;
+            };
+
+            let mut res = Ok(());
+            for (ch, sample) in &frame {
+                let age = sample.ts.elapsed().as_secs_f64();
+                let ts = timestamp(secs - age);
+                let raw = sample.raw.map(|r| r.to_string()).unwrap_or_default();
+                res = res.and_then(|_| {
+                    writeln!(
+                        w,
+                        "{ts},{ch},{},{:.0},{raw}",
+                        sample.source,
+                        sample.value.clamp(0.0, 255.0)
+                    )
+                });
+            }
+            if let Err(e) = res.and_then(|_| w.flush()) {
+                error!("CSV write failed: {e}");
+                count_error("csv");
+                out = None;
+            }
+        }
+    }
+
+    fn file_name(path: &str, rotate: CsvRotate, date: &str) -> String {
+        match rotate {
+            CsvRotate::None => path.to_string(),
+            CsvRotate::Daily => match path.rsplit_once('.') {
+                Some((stem, ext)) if !ext.contains('/') => format!("{stem}-{date}.{ext}"),
+                _ => format!("{path}-{date}"),
+            },
+        }
+    }
+
+    fn open(name: &str) -> anyhow::Result<BufWriter<File>> {
+        let f = OpenOptions::new().create(true).append(true).open(name)?;
+        let empty = f.metadata()?.len() == 0;
+        let mut w = BufWriter::new(f);
+        if empty {
+            writeln!(w, "{CSV_HEADER}")?;
+            w.flush()?;
+        }
+        Ok(w)
+    }
+}
+
+fn today() -> String {
+    let secs = time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let t = sys::sys_localtime(secs as i64);
+    format!("{:04}-{:02}-{:02}", t.year, t.month, t.day)
+}
+
+// local time with milliseconds, e.g. 2026-05-01T23:59:58.200
+fn timestamp(secs: f64) -> String {
+    let t = sys::sys_localtime(secs as i64);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}",
+        t.year,
+        t.month,
+        t.day,
+        t.hour,
+        t.min,
+        t.sec,
+        (secs.fract() * 1000.0) as u32
+    )
+}
+
+// EOF
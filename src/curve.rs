@@ -0,0 +1,77 @@
+// curve.rs
+
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail};
+
+use crate::*;
+
+// Transfer curve from the linear gauge to the needle, full scale stays full scale.
+// log spreads the low end over more of the dial, exp the high end, and
+// gamma:g raises the gauge to the power g, so gamma:0.5 is a gentle log.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Curve {
+    #[default]
+    Linear,
+    // decades shown on the dial, "log" is log:2
+    Log(f64),
+    Exp(f64),
+    Gamma(f64),
+}
+
+impl Curve {
+    pub fn apply(self, gauge: f64) -> f64 {
+        let x = (gauge / 255.0).clamp(0.0, 1.0);
+        let y = match self {
+            Curve::Linear => return gauge,
+            Curve::Log(decades) => {
+                let k = 10f64.powf(decades);
+                (1.0 + (k - 1.0) * x).log10() / decades
+            }
+            Curve::Exp(decades) => {
+                let k = 10f64.powf(decades);
+                (k.powf(x) - 1.0) / (k - 1.0)
+            }
+            Curve::Gamma(g) => x.powf(g),
+        };
+        255.0 * y
+    }
+}
+
+impl FromStr for Curve {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, arg) = match s.split_once(':') {
+            Some((n, a)) => (n, Some(a.parse::<f64>()?)),
+            None => (s, None),
+        };
+        let curve = match (name, arg) {
+            ("lin" | "linear", None) => Curve::Linear,
+            ("log", d) => Curve::Log(d.unwrap_or(2.0)),
+            ("exp", d) => Curve::Exp(d.unwrap_or(2.0)),
+            ("gamma", Some(g)) => Curve::Gamma(g),
+            _ => return Err(anyhow!("Unknown curve: {s}")),
+        };
+        match curve {
+            Curve::Log(d) | Curve::Exp(d) if !(d > 0.0 && d <= 6.0) => {
+                bail!("Curve {s}: decades must be within 0..6")
+            }
+            Curve::Gamma(g) if !(g > 0.0 && g.is_finite()) => {
+                bail!("Curve {s}: gamma must be positive")
+            }
+            _ => Ok(curve),
+        }
+    }
+}
+
+// (channel, curve), the last one given for a channel wins
+pub fn apply_curves(frame: &mut Frame, curves: &[(u8, Curve)]) {
+    for (ch, sample) in frame.iter_mut() {
+        if let Some((_, curve)) = curves.iter().rev().find(|(c, _)| c == ch) {
+            sample.value = curve.apply(sample.value);
+        }
+    }
+}
+
+// EOF
@@ -0,0 +1,558 @@
+// dbus.rs
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::{
+    env,
+    io::{BufRead, BufReader, Read, Write},
+    os::unix::net::UnixStream,
+    str::FromStr,
+    thread, time,
+};
+
+use anyhow::{anyhow, bail};
+
+use crate::*;
+
+const DBUS_NAME: &str = "io.github.sjm42.PerfVumeter";
+const DBUS_PATH: &str = "/io/github/sjm42/PerfVumeter";
+const DBUS_RECONNECT: time::Duration = time::Duration::from_secs(5);
+
+pub(crate) const MSG_METHOD_CALL: u8 = 1;
+const MSG_METHOD_RETURN: u8 = 2;
+const MSG_ERROR: u8 = 3;
+const MSG_SIGNAL: u8 = 4;
+
+const FIELD_PATH: u8 = 1;
+const FIELD_INTERFACE: u8 = 2;
+const FIELD_MEMBER: u8 = 3;
+const FIELD_ERROR_NAME: u8 = 4;
+const FIELD_REPLY_SERIAL: u8 = 5;
+const FIELD_DESTINATION: u8 = 6;
+const FIELD_SENDER: u8 = 7;
+const FIELD_SIGNATURE: u8 = 8;
+
+pub(crate) const ERR_UNKNOWN_METHOD: &str = "org.freedesktop.DBus.Error.UnknownMethod";
+pub(crate) const ERR_INVALID_ARGS: &str = "org.freedesktop.DBus.Error.InvalidArgs";
+
+const INTROSPECT_XML: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="io.github.sjm42.PerfVumeter">
+    <method name="GetChannels"><arg type="a(yd)" direction="out"/></method>
+    <method name="GetChannel"><arg name="channel" type="y" direction="in"/><arg type="d" direction="out"/></method>
+    <method name="SetChannel"><arg name="channel" type="y" direction="in"/><arg name="gauge" type="d" direction="in"/></method>
+    <method name="ClearChannel"><arg name="channel" type="y" direction="in"/></method>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect"><arg type="s" direction="out"/></method>
+  </interface>
+</node>
+"#;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DbusBus {
+    #[default]
+    Session,
+    System,
+}
+
+impl FromStr for DbusBus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "session" => Ok(DbusBus::Session),
+            "system" => Ok(DbusBus::System),
+            _ => Err(anyhow!("Unknown D-Bus bus: {s}")),
+        }
+    }
+}
+
+// Owns io.github.sjm42.PerfVumeter on the bus, so that widgets can read the gauges
+// with GetChannels/GetChannel and pin a channel with SetChannel until ClearChannel.
+pub struct DbusSink;
+
+impl DbusSink {
+    pub fn spawn(bus: DbusBus) -> anyhow::Result<ThreadedSink> {
+        let latest = Arc::new(Mutex::new(Frame::new()));
+        // fail early on a missing bus, later errors only reconnect
+        let mut conn = DbusConn::connect(bus, DBUS_NAME)?;
+        info!("Serving {DBUS_NAME} on the {bus:?} bus");
+
+        let serving = latest.clone();
+        thread::spawn(move || loop {
+            if let Err(e) = conn.serve(&serving) {
+                error!("D-Bus: {e}");
+                count_error("dbus");
+            }
+            loop {
+                thread::sleep(DBUS_RECONNECT);
+                match DbusConn::connect(bus, DBUS_NAME) {
+                    Ok(c) => {
+                        conn = c;
+                        break;
+                    }
+                    Err(e) => debug!("D-Bus reconnect: {e}"),
+                }
+            }
+        });
+
+        spawn_sink("dbus", move |rx| {
+            while let Ok(frame) = rx.recv() {
+                *latest.lock().unwrap() = frame;
+            }
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct DbusMessage {
+    pub msg_type: u8,
+    pub serial: u32,
+    pub path: String,
+    pub interface: String,
+    pub member: String,
+    pub sender: String,
+    pub signature: String,
+    pub body: Vec<u8>,
+}
+
+// A reply to a method call: the signature of the body, or an error name and message
+pub(crate) type DbusReply = Result<&'static str, (&'static str, String)>;
+
+pub(crate) struct DbusConn {
+    stream: UnixStream,
+    tx: DbusSender,
+}
+
+// The sending half of a connection, shared with threads that emit signals
+#[derive(Clone)]
+pub(crate) struct DbusSender {
+    stream: Arc<Mutex<UnixStream>>,
+    serial: Arc<AtomicU32>,
+}
+
+impl DbusConn {
+    // connects and owns the given well-known name
+    pub(crate) fn connect(bus: DbusBus, name: &str) -> anyhow::Result<Self> {
+        let uid = sys::sys_getuid();
+        let (var, default) = match bus {
+            DbusBus::Session => ("DBUS_SESSION_BUS_ADDRESS", format!("/run/user/{uid}/bus")),
+            DbusBus::System => (
+                "DBUS_SYSTEM_BUS_ADDRESS",
+                "/run/dbus/system_bus_socket".to_string(),
+            ),
+        };
+        // unix:path=/run/user/1000/bus,guid=...
+        let path = env::var(var)
+            .ok()
+            .and_then(|addr| {
+                addr.split(';')
+                    .filter_map(|a| a.strip_prefix("unix:"))
+                    .flat_map(|a| a.split(','))
+                    .find_map(|kv| kv.strip_prefix("path=").map(String::from))
+            })
+            .unwrap_or(default);
+
+        let mut stream = UnixStream::connect(&path)?;
+        stream.set_write_timeout(Some(time::Duration::from_secs(5)))?;
+        let uid_hex = uid
+            .to_string()
+            .bytes()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>();
+        stream.write_all(format!("\0AUTH EXTERNAL {uid_hex}\r\n").as_bytes())?;
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+        if !line.starts_with("OK ") {
+            bail!("D-Bus authentication failed: {}", line.trim());
+        }
+        stream.write_all(b"BEGIN\r\n")?;
+
+        let tx = DbusSender {
+            stream: Arc::new(Mutex::new(stream.try_clone()?)),
+            serial: Arc::new(AtomicU32::new(0)),
+        };
+        let mut conn = Self { stream, tx };
+        conn.call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "Hello",
+            "",
+            &[],
+        )?;
+        let mut body = DbusWriter::default();
+        body.string(name);
+        // DBUS_NAME_FLAG_DO_NOT_QUEUE
+        body.u32(4);
+        conn.call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "RequestName",
+            "su",
+            &body.buf,
+        )?;
+        Ok(conn)
+    }
+
+    pub(crate) fn sender(&self) -> DbusSender {
+        self.tx.clone()
+    }
+
+    // send a method call and wait for its reply, skipping anything else
+    pub(crate) fn call(
+        &mut self,
+        dest: &str,
+        path: &str,
+        interface: &str,
+        member: &str,
+        signature: &str,
+        body: &[u8],
+    ) -> anyhow::Result<DbusMessage> {
+        let mut h = DbusWriter::default();
+        h.field_obj(FIELD_PATH, path);
+        h.field_str(FIELD_INTERFACE, interface);
+        h.field_str(FIELD_MEMBER, member);
+        h.field_str(FIELD_DESTINATION, dest);
+        if !signature.is_empty() {
+            h.field_sig(FIELD_SIGNATURE, signature);
+        }
+        let serial = self.tx.send(MSG_METHOD_CALL, h, body)?;
+        loop {
+            let (msg, reply_to) = self.recv()?;
+            if reply_to == Some(serial) {
+                if msg.msg_type == MSG_ERROR {
+                    bail!("{member} failed");
+                }
+                return Ok(msg);
+            }
+        }
+    }
+
+    fn serve(&mut self, latest: &Mutex<Frame>) -> anyhow::Result<()> {
+        // the socket must not time out while idle
+        self.stream.set_read_timeout(None)?;
+        loop {
+            let (msg, _) = self.recv()?;
+            if msg.msg_type != MSG_METHOD_CALL {
+                continue;
+            }
+            let mut args = DbusReader::new(&msg.body);
+            let mut out = DbusWriter::default();
+            let res = match (msg.interface.as_str(), msg.member.as_str()) {
+                ("org.freedesktop.DBus.Introspectable", "Introspect") => {
+                    out.string(INTROSPECT_XML);
+                    Ok("s")
+                }
+                (DBUS_NAME, "GetChannels") if msg.path == DBUS_PATH => {
+                    let frame = latest.lock().unwrap();
+                    out.array(8, |a| {
+                        for (ch, sample) in frame.iter() {
+                            a.align(8);
+                            a.u8(*ch);
+                            a.f64(sample.value.clamp(0.0, 255.0));
+                        }
+                    });
+                    Ok("a(yd)")
+                }
+                (DBUS_NAME, "GetChannel") if msg.signature == "y" => {
+                    let ch = args.u8()?;
+                    match latest.lock().unwrap().get(&ch) {
+                        Some(sample) => {
+                            out.f64(sample.value.clamp(0.0, 255.0));
+                            Ok("d")
+                        }
+                        None => Err((ERR_INVALID_ARGS, format!("No such channel: {ch}"))),
+                    }
+                }
+                (DBUS_NAME, "SetChannel") if msg.signature == "yd" => {
+                    let ch = args.u8()?;
+                    let gauge = args.f64()?;
+                    info!("D-Bus: channel {ch} pinned to {gauge:.0}");
+                    set_override(ch, Some(gauge.clamp(0.0, 255.0)));
+                    Ok("")
+                }
+                (DBUS_NAME, "ClearChannel") if msg.signature == "y" => {
+                    let ch = args.u8()?;
+                    info!("D-Bus: channel {ch} released");
+                    set_override(ch, None);
+                    Ok("")
+                }
+                _ => Err((
+                    ERR_UNKNOWN_METHOD,
+                    format!(
+                        "Unknown method {}.{} with signature \"{}\"",
+                        msg.interface, msg.member, msg.signature
+                    ),
+                )),
+            };
+
+            self.reply(&msg, res, &out.buf)?;
+        }
+    }
+
+    pub(crate) fn reply(
+        &self,
+        msg: &DbusMessage,
+        res: DbusReply,
+        body: &[u8],
+    ) -> anyhow::Result<()> {
+        let mut h = DbusWriter::default();
+        h.field_u32(FIELD_REPLY_SERIAL, msg.serial);
+        h.field_str(FIELD_DESTINATION, &msg.sender);
+        match res {
+            Ok(sig) => {
+                if !sig.is_empty() {
+                    h.field_sig(FIELD_SIGNATURE, sig);
+                }
+                self.tx.send(MSG_METHOD_RETURN, h, body)?;
+            }
+            Err((name, e)) => {
+                h.field_str(FIELD_ERROR_NAME, name);
+                h.field_sig(FIELD_SIGNATURE, "s");
+                let mut err = DbusWriter::default();
+                err.string(&e);
+                self.tx.send(MSG_ERROR, h, &err.buf)?;
+            }
+        }
+        Ok(())
+    }
+
+    // returns the message and the serial it replies to, if any
+    pub(crate) fn recv(&mut self) -> anyhow::Result<(DbusMessage, Option<u32>)> {
+        let mut fixed = [0u8; 16];
+        self.stream.read_exact(&mut fixed)?;
+        if fixed[0] != b'l' {
+            bail!("Big-endian D-Bus messages are not supported");
+        }
+        let u32_at =
+            |i: usize| u32::from_le_bytes([fixed[i], fixed[i + 1], fixed[i + 2], fixed[i + 3]]);
+        let body_len = u32_at(4) as usize;
+        let fields_len = u32_at(12) as usize;
+        let padded = fields_len.div_ceil(8) * 8;
+        let mut rest = vec![0u8; padded + body_len];
+        self.stream.read_exact(&mut rest)?;
+
+        let mut msg = DbusMessage {
+            msg_type: fixed[1],
+            serial: u32_at(8),
+            body: rest[padded..].to_vec(),
+            ..Default::default()
+        };
+        let mut reply_to = None;
+        // the header fields start at offset 16, alignment carries over from the message start
+        let mut header = fixed.to_vec();
+        header.extend(&rest[..fields_len]);
+        let mut r = DbusReader::new(&header);
+        r.pos = 16;
+        while r.pos < header.len() {
+            r.align(8);
+            let code = r.u8()?;
+            let sig = r.signature()?;
+            match sig.as_str() {
+                "s" | "o" => {
+                    let s = r.string()?;
+                    match code {
+                        FIELD_PATH => msg.path = s,
+                        FIELD_INTERFACE => msg.interface = s,
+                        FIELD_MEMBER => msg.member = s,
+                        FIELD_SENDER => msg.sender = s,
+                        _ => {}
+                    }
+                }
+                "g" => {
+                    let s = r.signature()?;
+                    if code == FIELD_SIGNATURE {
+                        msg.signature = s;
+                    }
+                }
+                "u" => {
+                    let v = r.u32()?;
+                    if code == FIELD_REPLY_SERIAL {
+                        reply_to = Some(v);
+                    }
+                }
+                _ => bail!("Unexpected D-Bus header field type {sig}"),
+            }
+        }
+        Ok((msg, reply_to))
+    }
+}
+
+impl DbusSender {
+    // fields holds the header fields already marshalled as a(yv) items
+    fn send(&self, msg_type: u8, fields: DbusWriter, body: &[u8]) -> anyhow::Result<u32> {
+        let serial = self.serial.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut msg = DbusWriter::default();
+        msg.buf.extend([b'l', msg_type, 0, 1]);
+        msg.u32(body.len() as u32);
+        msg.u32(serial);
+        msg.u32(fields.buf.len() as u32);
+        // the fields were marshalled starting at offset 16, which is 8-aligned
+        msg.align(8);
+        msg.buf.extend(&fields.buf);
+        msg.align(8);
+        msg.buf.extend(body);
+        self.stream.lock().unwrap().write_all(&msg.buf)?;
+        Ok(serial)
+    }
+
+    pub(crate) fn signal(
+        &self,
+        path: &str,
+        interface: &str,
+        member: &str,
+        signature: &str,
+        body: &[u8],
+    ) -> anyhow::Result<()> {
+        let mut h = DbusWriter::default();
+        h.field_obj(FIELD_PATH, path);
+        h.field_str(FIELD_INTERFACE, interface);
+        h.field_str(FIELD_MEMBER, member);
+        if !signature.is_empty() {
+            h.field_sig(FIELD_SIGNATURE, signature);
+        }
+        self.send(MSG_SIGNAL, h, body)?;
+        Ok(())
+    }
+}
+
+// Little-endian marshalling, offsets relative to the start of the buffer
+#[derive(Default)]
+pub(crate) struct DbusWriter {
+    pub buf: Vec<u8>,
+}
+
+impl DbusWriter {
+    pub(crate) fn align(&mut self, n: usize) {
+        while !self.buf.len().is_multiple_of(n) {
+            self.buf.push(0);
+        }
+    }
+    pub(crate) fn u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+    pub(crate) fn u32(&mut self, v: u32) {
+        self.align(4);
+        self.buf.extend(v.to_le_bytes());
+    }
+    pub(crate) fn i32(&mut self, v: i32) {
+        self.align(4);
+        self.buf.extend(v.to_le_bytes());
+    }
+    pub(crate) fn boolean(&mut self, v: bool) {
+        self.u32(v as u32);
+    }
+    pub(crate) fn f64(&mut self, v: f64) {
+        self.align(8);
+        self.buf.extend(v.to_le_bytes());
+    }
+    pub(crate) fn string(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.buf.extend(s.as_bytes());
+        self.buf.push(0);
+    }
+    pub(crate) fn signature(&mut self, s: &str) {
+        self.buf.push(s.len() as u8);
+        self.buf.extend(s.as_bytes());
+        self.buf.push(0);
+    }
+    // the signature of the value, then the value itself
+    pub(crate) fn variant<F: FnOnce(&mut Self)>(&mut self, sig: &str, f: F) {
+        self.signature(sig);
+        f(self);
+    }
+    // the length excludes the padding to the first element
+    pub(crate) fn array<F: FnOnce(&mut Self)>(&mut self, elem_align: usize, f: F) {
+        self.u32(0);
+        let len_at = self.buf.len() - 4;
+        self.align(elem_align);
+        let start = self.buf.len();
+        f(self);
+        let len = (self.buf.len() - start) as u32;
+        self.buf[len_at..len_at + 4].copy_from_slice(&len.to_le_bytes());
+    }
+
+    // header fields, a struct of (byte, variant) each
+    fn field_str(&mut self, code: u8, s: &str) {
+        self.align(8);
+        self.u8(code);
+        self.signature("s");
+        self.string(s);
+    }
+    fn field_obj(&mut self, code: u8, s: &str) {
+        self.align(8);
+        self.u8(code);
+        self.signature("o");
+        self.string(s);
+    }
+    fn field_sig(&mut self, code: u8, s: &str) {
+        self.align(8);
+        self.u8(code);
+        self.signature("g");
+        self.signature(s);
+    }
+    fn field_u32(&mut self, code: u8, v: u32) {
+        self.align(8);
+        self.u8(code);
+        self.signature("u");
+        self.u32(v);
+    }
+}
+
+pub(crate) struct DbusReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> DbusReader<'a> {
+    pub(crate) fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+    pub(crate) fn align(&mut self, n: usize) {
+        self.pos = self.pos.div_ceil(n) * n;
+    }
+    pub(crate) fn take(&mut self, n: usize) -> anyhow::Result<&'a [u8]> {
+        let b = self
+            .buf
+            .get(self.pos..self.pos + n)
+            .ok_or_else(|| anyhow!("Truncated D-Bus message"))?;
+        self.pos += n;
+        Ok(b)
+    }
+    pub(crate) fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+    pub(crate) fn u32(&mut self) -> anyhow::Result<u32> {
+        self.align(4);
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+    pub(crate) fn i32(&mut self) -> anyhow::Result<i32> {
+        Ok(self.u32()? as i32)
+    }
+    pub(crate) fn array_i32(&mut self) -> anyhow::Result<Vec<i32>> {
+        let len = self.u32()? as usize;
+        (0..len / 4).map(|_| self.i32()).collect()
+    }
+    pub(crate) fn f64(&mut self) -> anyhow::Result<f64> {
+        self.align(8);
+        Ok(f64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+    pub(crate) fn string(&mut self) -> anyhow::Result<String> {
+        let len = self.u32()? as usize;
+        let s = String::from_utf8_lossy(self.take(len)?).into_owned();
+        self.take(1)?;
+        Ok(s)
+    }
+    pub(crate) fn signature(&mut self) -> anyhow::Result<String> {
+        let len = self.u8()? as usize;
+        let s = String::from_utf8_lossy(self.take(len)?).into_owned();
+        self.take(1)?;
+        Ok(s)
+    }
+}
+
+// EOF
@@ -0,0 +1,129 @@
+// demo.rs
+
+use std::{f64::consts::TAU, fmt, str::FromStr, time};
+
+use anyhow::anyhow;
+
+use crate::*;
+
+// Synthetic gauges for showing off the meters without any load,
+// in percent of full scale
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Waveform {
+    // a slow swing over the whole dial, period in seconds
+    Sine(f64),
+    // wanders up and down like a busy CPU
+    Walk,
+    // quiet with sudden spikes that decay, like network traffic
+    Bursts,
+}
+
+impl fmt::Display for Waveform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Waveform::Sine(period) => write!(f, "sine:{period}"),
+            Waveform::Walk => write!(f, "walk"),
+            Waveform::Bursts => write!(f, "bursts"),
+        }
+    }
+}
+
+impl FromStr for Waveform {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "sine" => Ok(Waveform::Sine(8.0)),
+            Some(("sine", p)) => match p.parse::<f64>()? {
+                p if p > 0.0 && p.is_finite() => Ok(Waveform::Sine(p)),
+                _ => Err(anyhow!("Sine period must be positive: {s}")),
+            },
+            None if s == "walk" => Ok(Waveform::Walk),
+            None if s == "bursts" => Ok(Waveform::Bursts),
+            _ => Err(anyhow!("Unknown waveform: {s}")),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DemoSource {
+    wave: Waveform,
+    start: time::Instant,
+    last: time::Instant,
+    level: f64,
+    rng: u64,
+}
+
+impl DemoSource {
+    pub fn new(wave: Waveform) -> Self {
+        let seed = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        Self {
+            wave,
+            start: time::Instant::now(),
+            last: time::Instant::now(),
+            level: 20.0,
+            // xorshift must not start from 0
+            rng: seed | 1,
+        }
+    }
+
+    pub fn value(&mut self) -> f64 {
+        let dt = self.last.elapsed().as_secs_f64().min(1.0);
+        self.last = time::Instant::now();
+        match self.wave {
+            Waveform::Sine(period) => {
+                let phase = TAU * self.start.elapsed().as_secs_f64() / period;
+                50.0 - 50.0 * phase.cos()
+            }
+            Waveform::Walk => {
+                // a random step pulled back towards the middle
+                let step = (self.random() - 0.5) * 60.0 * dt.sqrt();
+                self.level += step + (40.0 - self.level) * 0.2 * dt;
+                self.level = self.level.clamp(0.0, 100.0);
+                self.level
+            }
+            Waveform::Bursts => {
+                if self.random() < 0.4 * dt {
+                    self.level = (self.level + 40.0 + 60.0 * self.random()).min(100.0);
+                }
+                self.level *= (-1.5 * dt).exp();
+                // a little idle chatter
+                self.level.max(2.0 + 3.0 * self.random())
+            }
+        }
+    }
+
+    // uniform in 0..1
+    fn random(&mut self) -> f64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+impl StatSource for DemoSource {
+    fn sample(&mut self) -> anyhow::Result<f64> {
+        Ok(self.value())
+    }
+    fn name(&self) -> &'static str {
+        "demo"
+    }
+    fn unit(&self) -> &'static str {
+        "%"
+    }
+}
+
+// the meters at a meetup
+pub fn demo_mapping() -> Vec<(u8, SourceSpec)> {
+    vec![
+        (1, SourceSpec::Demo(Waveform::Walk)),
+        (2, SourceSpec::Demo(Waveform::Sine(8.0))),
+        (3, SourceSpec::Demo(Waveform::Bursts)),
+    ]
+}
+
+// EOF
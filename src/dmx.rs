@@ -0,0 +1,205 @@
+// dmx.rs
+
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    net::{Ipv4Addr, UdpSocket},
+    str::FromStr,
+    sync::mpsc,
+};
+
+use anyhow::anyhow;
+
+use crate::*;
+
+const DMX_SLOTS: usize = 512;
+const ARTNET_PORT: u16 = 6454;
+const SACN_PORT: u16 = 5568;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DmxProto {
+    // ArtDmx over UDP, broadcast unless a target is given
+    #[default]
+    ArtNet,
+    // E1.31 over UDP, multicast to the universe address unless a target is given
+    Sacn,
+    // Enttec DMX USB Pro compatible serial dongle
+    Enttec,
+}
+
+impl FromStr for DmxProto {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "artnet" | "art-net" => Ok(DmxProto::ArtNet),
+            "sacn" | "e1.31" => Ok(DmxProto::Sacn),
+            "enttec" | "usb" => Ok(DmxProto::Enttec),
+            _ => Err(anyhow!("Unknown DMX protocol: {s}")),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DmxConfig {
+    pub proto: DmxProto,
+    // host[:port] for the network protocols, device path for enttec
+    pub target: Option<String>,
+    pub universe: u16,
+    // DMX address of meter channel 1, the others follow
+    pub start: u16,
+}
+
+enum DmxOut {
+    Udp(UdpSocket),
+    Serial(File),
+}
+
+// Outputs the gauges as DMX levels, one slot per meter channel,
+// so that lights can follow the load.
+pub struct DmxSink;
+
+impl DmxSink {
+    pub fn spawn(cfg: DmxConfig) -> anyhow::Result<ThreadedSink> {
+        let out = match cfg.proto {
+            DmxProto::Enttec => {
+                let dev = cfg
+                    .target
+                    .as_deref()
+                    .ok_or_else(|| anyhow!("Enttec DMX needs a serial device as target"))?;
+                DmxOut::Serial(OpenOptions::new().write(true).open(dev)?)
+            }
+            DmxProto::ArtNet | DmxProto::Sacn => {
+                let sock = UdpSocket::bind("0.0.0.0:0")?;
+                let (default_host, port) = match cfg.proto {
+                    DmxProto::ArtNet => {
+                        sock.set_broadcast(true)?;
+                        (Ipv4Addr::BROADCAST.to_string(), ARTNET_PORT)
+                    }
+                    _ => {
+                        let [hi, lo] = cfg.universe.to_be_bytes();
+                        (Ipv4Addr::new(239, 255, hi, lo).to_string(), SACN_PORT)
+                    }
+                };
+                let target = match &cfg.target {
+                    Some(t) if t.contains(':') => t.clone(),
+                    Some(t) => format!("{t}:{port}"),
+                    None => format!("{default_host}:{port}"),
+                };
+                sock.connect(&target)?;
+                DmxOut::Udp(sock)
+            }
+        };
+        info!(
+            "Sending DMX via {:?} universe {} from address {}",
+            cfg.proto, cfg.universe, cfg.start
+        );
+        spawn_sink("dmx", move |rx| Self::run(cfg, out, rx))
+    }
+
+    fn run(cfg: DmxConfig, mut out: DmxOut, rx: mpsc::Receiver<Frame>) {
+        let mut slots = [0u8; DMX_SLOTS];
+        let mut seq = 0u8;
+        let cid = sacn_cid();
+        let first = cfg.start.max(1) as usize - 1;
+
+        while let Ok(frame) = rx.recv() {
+            for (ch, sample) in &frame {
+                if let Some(slot) = (first + *ch as usize)
+                    .checked_sub(1)
+                    .and_then(|i| slots.get_mut(i))
+                {
+                    *slot = sample.value.clamp(0.0, 255.0) as u8;
+                }
+            }
+            // zero is reserved in sACN for "no sequencing"
+            seq = seq.wrapping_add(1).max(1);
+            let pkt = match cfg.proto {
+                DmxProto::ArtNet => artdmx(cfg.universe, seq, &slots),
+                DmxProto::Sacn => e131(cfg.universe, seq, &cid, &slots),
+                DmxProto::Enttec => enttec(&slots),
+            };
+            let res = match &mut out {
+                DmxOut::Udp(sock) => sock.send(&pkt).map(|_| ()),
+                DmxOut::Serial(f) => f.write_all(&pkt),
+            };
+            if let Err(e) = res {
+                debug!("DMX: {e}");
+                count_error("dmx");
+            }
+        }
+    }
+}
+
+fn artdmx(universe: u16, seq: u8, slots: &[u8]) -> Vec<u8> {
+    let mut pkt = b"Art-Net\0".to_vec();
+    // OpDmx, little endian
+    pkt.extend([0x00, 0x50]);
+    // protocol version 14
+    pkt.extend([0x00, 0x0e]);
+    pkt.push(seq);
+    // physical port
+    pkt.push(0);
+    // SubUni + Net, i.e. the 15-bit port address little endian
+    pkt.extend((universe & 0x7fff).to_le_bytes());
+    pkt.extend((slots.len() as u16).to_be_bytes());
+    pkt.extend(slots);
+    pkt
+}
+
+fn e131(universe: u16, seq: u8, cid: &[u8; 16], slots: &[u8]) -> Vec<u8> {
+    let len = 126 + slots.len();
+    let flen = |offset: usize| (0x7000 | (len - offset) as u16).to_be_bytes();
+    let mut name = [0u8; 64];
+    let src = format!("perf_vumeter {}", hostname());
+    let n = src.len().min(63);
+    name[..n].copy_from_slice(&src.as_bytes()[..n]);
+
+    let mut pkt = Vec::with_capacity(len);
+    // root layer
+    pkt.extend([0x00, 0x10, 0x00, 0x00]);
+    pkt.extend(b"ASC-E1.17\0\0\0");
+    pkt.extend(flen(16));
+    pkt.extend(4u32.to_be_bytes());
+    pkt.extend(cid);
+    // framing layer
+    pkt.extend(flen(38));
+    pkt.extend(2u32.to_be_bytes());
+    pkt.extend(name);
+    // priority, sync address, sequence, options
+    pkt.push(100);
+    pkt.extend([0, 0]);
+    pkt.push(seq);
+    pkt.push(0);
+    pkt.extend(universe.to_be_bytes());
+    // DMP layer
+    pkt.extend(flen(115));
+    pkt.extend([0x02, 0xa1, 0x00, 0x00, 0x00, 0x01]);
+    pkt.extend((slots.len() as u16 + 1).to_be_bytes());
+    // start code
+    pkt.push(0);
+    pkt.extend(slots);
+    pkt
+}
+
+// "Output Only Send DMX Packet Request" of the Enttec DMX USB Pro
+fn enttec(slots: &[u8]) -> Vec<u8> {
+    let mut pkt = vec![0x7e, 6];
+    pkt.extend((slots.len() as u16 + 1).to_le_bytes());
+    pkt.push(0);
+    pkt.extend(slots);
+    pkt.push(0xe7);
+    pkt
+}
+
+// sACN receivers tell the sources apart by this, keep it stable per host
+fn sacn_cid() -> [u8; 16] {
+    let mut cid = [0u8; 16];
+    let id = std::fs::read_to_string("/etc/machine-id").unwrap_or_else(|_| hostname());
+    for (i, b) in id.trim().bytes().enumerate() {
+        cid[i % 16] = cid[i % 16].wrapping_mul(31).wrapping_add(b);
+    }
+    cid
+}
+
+// EOF
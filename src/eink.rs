@@ -0,0 +1,322 @@
+// eink.rs
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs::{File, OpenOptions},
+    io::Write,
+    os::fd::AsRawFd,
+    sync::mpsc,
+    thread, time,
+};
+
+use anyhow::bail;
+
+use crate::*;
+
+// SPI_IOC_WR_MAX_SPEED_HZ
+const SPI_IOC_WR_MAX_SPEED_HZ: std::os::raw::c_ulong = 0x4004_6b04;
+const EINK_SPI_HZ: u32 = 4_000_000;
+// the default spidev bufsiz
+const SPI_CHUNK: usize = 4096;
+const EINK_INTERVAL: time::Duration = time::Duration::from_secs(60);
+const EINK_BUSY_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+// the panel is mounted landscape, header on top and rows of channels below
+const HEADER_HEIGHT: usize = 10;
+const ROW_HEIGHT: usize = 29;
+
+#[derive(Clone, Debug)]
+pub struct EinkConfig {
+    // spidev device, e.g. /dev/spidev0.0
+    pub spi: String,
+    pub gpiochip: String,
+    // data/command, reset and busy line offsets
+    pub dc: u32,
+    pub rst: u32,
+    pub busy: u32,
+    // panel in its native portrait orientation, e.g. 128x296
+    pub width: usize,
+    pub height: usize,
+    // minutes summarized on the panel
+    pub minutes: usize,
+    pub channels: Vec<u8>,
+}
+
+// min/max/sum of one minute of a channel, in raw units when the source has them
+#[derive(Clone, Copy, Debug)]
+struct Bucket {
+    min: f64,
+    max: f64,
+    sum: f64,
+    n: u32,
+}
+
+impl Bucket {
+    fn new(v: f64) -> Self {
+        Self {
+            min: v,
+            max: v,
+            sum: v,
+            n: 1,
+        }
+    }
+    fn add(&mut self, v: f64) {
+        self.min = self.min.min(v);
+        self.max = self.max.max(v);
+        self.sum += v;
+        self.n += 1;
+    }
+    fn merge(&mut self, other: &Bucket) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.sum += other.sum;
+        self.n += other.n;
+    }
+}
+
+#[derive(Debug, Default)]
+struct EinkChannel {
+    source: &'static str,
+    has_raw: bool,
+    current: Option<Bucket>,
+    // gauge range of the current minute, for the bar
+    gauge: Option<Bucket>,
+    minutes: VecDeque<(Option<Bucket>, Option<Bucket>)>,
+}
+
+// Summarizes the last minutes of each channel as min/avg/max on an SSD1680
+// based e-paper panel (Waveshare 2.9" V2, 2.13" V3/V4), refreshed once a minute
+pub struct EinkSink;
+
+impl EinkSink {
+    pub fn spawn(cfg: EinkConfig) -> anyhow::Result<ThreadedSink> {
+        if !cfg.width.is_multiple_of(8) {
+            bail!(
+                "E-ink panel width must be a multiple of 8, not {}",
+                cfg.width
+            );
+        }
+        let mut panel = Panel {
+            spi: OpenOptions::new().write(true).open(&cfg.spi)?,
+            dc: gpio::GpioLine::output(&cfg.gpiochip, cfg.dc)?,
+            rst: gpio::GpioLine::output(&cfg.gpiochip, cfg.rst)?,
+            busy: gpio::GpioLine::input(&cfg.gpiochip, cfg.busy)?,
+            width: cfg.width,
+            height: cfg.height,
+        };
+        let mut hz = EINK_SPI_HZ;
+        sys::sys_ioctl(panel.spi.as_raw_fd(), SPI_IOC_WR_MAX_SPEED_HZ, &mut hz)?;
+        // see that the panel answers before going to the background
+        panel.reset()?;
+        panel.sleep()?;
+        info!(
+            "E-ink {}x{} on {}, summarizing {} minutes",
+            cfg.width, cfg.height, cfg.spi, cfg.minutes
+        );
+        spawn_sink("eink", move |rx| Self::run(cfg, panel, rx))
+    }
+
+    fn run(cfg: EinkConfig, mut panel: Panel, rx: mpsc::Receiver<Frame>) {
+        let mut channels: BTreeMap<u8, EinkChannel> = BTreeMap::new();
+        let mut canvas = mono::Canvas::new(cfg.height, cfg.width);
+        let mut next_draw = time::Instant::now() + EINK_INTERVAL;
+
+        loop {
+            let timeout = next_draw.saturating_duration_since(time::Instant::now());
+            match rx.recv_timeout(timeout) {
+                Ok(frame) => {
+                    for (ch, sample) in frame {
+                        let c = channels.entry(ch).or_default();
+                        c.source = sample.source;
+                        c.has_raw = sample.raw.is_some_and(f64::is_finite);
+                        let v = match sample.raw.filter(|r| r.is_finite()) {
+                            Some(raw) => raw,
+                            None => sample.value.clamp(0.0, 255.0),
+                        };
+                        let g = sample.value.clamp(0.0, 255.0);
+                        match &mut c.current {
+                            Some(b) => b.add(v),
+                            None => c.current = Some(Bucket::new(v)),
+                        }
+                        match &mut c.gauge {
+                            Some(b) => b.add(g),
+                            None => c.gauge = Some(Bucket::new(g)),
+                        }
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => return,
+            }
+            if time::Instant::now() < next_draw {
+                continue;
+            }
+            next_draw += EINK_INTERVAL;
+
+            for c in channels.values_mut() {
+                c.minutes.push_back((c.current.take(), c.gauge.take()));
+                if c.minutes.len() > cfg.minutes {
+                    c.minutes.pop_front();
+                }
+            }
+            Self::draw(&cfg, &mut canvas, &channels);
+            let start = time::Instant::now();
+            match panel.show(&canvas) {
+                Ok(()) => trace!("E-ink refresh took {:?}", start.elapsed()),
+                Err(e) => {
+                    error!("E-ink: {e}");
+                    count_error("eink");
+                }
+            }
+        }
+    }
+
+    fn draw(cfg: &EinkConfig, canvas: &mut mono::Canvas, channels: &BTreeMap<u8, EinkChannel>) {
+        canvas.clear();
+        let secs = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let t = sys::sys_localtime(secs as i64);
+        canvas.text(1, 1, &hostname(), 1);
+        canvas.text_right(
+            canvas.width - 1,
+            1,
+            &format!("LAST {} MIN  {:02}:{:02}", cfg.minutes, t.hour, t.min),
+            1,
+        );
+        canvas.fill_rect(0, HEADER_HEIGHT - 2, canvas.width, 1);
+
+        let rows = (canvas.height - HEADER_HEIGHT) / ROW_HEIGHT;
+        for (row, ch) in cfg.channels.iter().take(rows).enumerate() {
+            let y = HEADER_HEIGHT + row * ROW_HEIGHT;
+            let Some(c) = channels.get(ch) else {
+                canvas.text(1, y + 1, &format!("{ch} -"), 2);
+                continue;
+            };
+            let (Some(value), Some(gauge)) = summary(c) else {
+                canvas.text(1, y + 1, &format!("{ch} {} -", c.source), 2);
+                continue;
+            };
+            let fmt = |v: f64| match c.has_raw {
+                true => fmt_raw(c.source, v),
+                false => format!("{:.0}%", v / 255.0 * 100.0),
+            };
+            canvas.text(1, y + 1, &format!("{ch} {}", c.source), 2);
+            canvas.text_right(canvas.width - 1, y + 1, &fmt(value.sum / value.n as f64), 2);
+            canvas.text(
+                1,
+                y + 13,
+                &format!(
+                    "MIN {}  AVG {}  MAX {}",
+                    fmt(value.min),
+                    fmt(value.sum / value.n as f64),
+                    fmt(value.max)
+                ),
+                1,
+            );
+
+            // min..max span of the gauge with a tick at the average
+            let bar_w = canvas.width - 2;
+            let px = |g: f64| 1 + (g / 255.0 * (bar_w - 1) as f64).round() as usize;
+            canvas.rect(1, y + 20, bar_w, 6);
+            let (lo, hi) = (px(gauge.min), px(gauge.max));
+            canvas.fill_rect(lo, y + 22, (hi - lo).max(1), 2);
+            canvas.fill_rect(px(gauge.sum / gauge.n as f64), y + 19, 1, 8);
+        }
+    }
+}
+
+fn summary(c: &EinkChannel) -> (Option<Bucket>, Option<Bucket>) {
+    let mut value: Option<Bucket> = None;
+    let mut gauge: Option<Bucket> = None;
+    for (v, g) in &c.minutes {
+        for (acc, b) in [(&mut value, v), (&mut gauge, g)] {
+            match (acc.as_mut(), b) {
+                (Some(a), Some(b)) => a.merge(b),
+                (None, Some(b)) => *acc = Some(*b),
+                _ => {}
+            }
+        }
+    }
+    (value, gauge)
+}
+
+struct Panel {
+    spi: File,
+    dc: gpio::GpioLine,
+    rst: gpio::GpioLine,
+    busy: gpio::GpioLine,
+    width: usize,
+    height: usize,
+}
+
+impl Panel {
+    fn command(&mut self, cmd: u8, data: &[u8]) -> anyhow::Result<()> {
+        self.dc.set(false)?;
+        self.spi.write_all(&[cmd])?;
+        if !data.is_empty() {
+            self.dc.set(true)?;
+            for chunk in data.chunks(SPI_CHUNK) {
+                self.spi.write_all(chunk)?;
+            }
+        }
+        Ok(())
+    }
+
+    // BUSY is high while the controller works
+    fn wait(&mut self) -> anyhow::Result<()> {
+        let start = time::Instant::now();
+        while self.busy.get()? {
+            if start.elapsed() > EINK_BUSY_TIMEOUT {
+                bail!("E-ink panel stuck busy");
+            }
+            thread::sleep(time::Duration::from_millis(10));
+        }
+        Ok(())
+    }
+
+    // hardware reset also wakes the controller from deep sleep
+    fn reset(&mut self) -> anyhow::Result<()> {
+        self.rst.set(false)?;
+        thread::sleep(time::Duration::from_millis(10));
+        self.rst.set(true)?;
+        thread::sleep(time::Duration::from_millis(10));
+        self.wait()?;
+        self.command(0x12, &[])?; // software reset
+        self.wait()
+    }
+
+    fn sleep(&mut self) -> anyhow::Result<()> {
+        self.command(0x10, &[0x01])
+    }
+
+    // full refresh from a landscape canvas, rotated into the portrait RAM
+    fn show(&mut self, canvas: &mono::Canvas) -> anyhow::Result<()> {
+        let [y_lo, y_hi] = (self.height as u16 - 1).to_le_bytes();
+        let mut ram = vec![0xffu8; self.width / 8 * self.height];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if canvas.get(y, self.width - 1 - x) {
+                    ram[y * self.width / 8 + x / 8] &= !(0x80 >> (x % 8));
+                }
+            }
+        }
+
+        self.reset()?;
+        self.command(0x01, &[y_lo, y_hi, 0x00])?; // driver output control
+        self.command(0x11, &[0x03])?; // x and y increment
+        self.command(0x44, &[0x00, (self.width / 8 - 1) as u8])?;
+        self.command(0x45, &[0x00, 0x00, y_lo, y_hi])?;
+        self.command(0x3c, &[0x05])?; // border waveform
+        self.command(0x18, &[0x80])?; // internal temperature sensor
+        self.command(0x4e, &[0x00])?;
+        self.command(0x4f, &[0x00, 0x00])?;
+        self.wait()?;
+        self.command(0x24, &ram)?;
+        self.command(0x22, &[0xf7])?; // full update sequence
+        self.command(0x20, &[])?;
+        self.wait()?;
+        self.sleep()
+    }
+}
+
+// EOF
@@ -0,0 +1,148 @@
+// ethtool.rs
+
+use std::{
+    os::fd::{AsRawFd, OwnedFd},
+    os::raw::c_void,
+    ptr, time,
+};
+
+use anyhow::{anyhow, bail};
+
+use crate::sys::*;
+use crate::*;
+
+const ETHTOOL_GDRVINFO: u32 = 0x03;
+const ETHTOOL_GSTRINGS: u32 = 0x1b;
+const ETHTOOL_GSTATS: u32 = 0x1d;
+const ETH_SS_STATS: u32 = 1;
+const ETH_GSTRING_LEN: usize = 32;
+// offset of n_stats in struct ethtool_drvinfo
+const DRVINFO_N_STATS: usize = 4 + 5 * 32 + 12 + 4;
+const DRVINFO_LEN: usize = DRVINFO_N_STATS + 4 * 4;
+
+#[repr(C)]
+struct IfReq {
+    name: [u8; 16],
+    data: *mut c_void,
+    _pad: [u8; 16],
+}
+
+// A single NIC statistics counter as shown by "ethtool -S", e.g. rx_missed_errors
+// or rx_queue_0_packets, read with the ETHTOOL_GSTATS ioctl.
+#[derive(Debug)]
+pub struct EthtoolStat {
+    pub iface: String,
+    pub name: String,
+    sock: OwnedFd,
+    index: usize,
+    prev_ts: time::Instant,
+    prev_cnt: u64,
+}
+
+impl EthtoolStat {
+    // spec is "iface:stat", e.g. "eth0:rx_missed_errors"
+    pub fn new<S: AsRef<str>>(spec: S) -> anyhow::Result<Self> {
+        let spec = spec.as_ref();
+        let (iface, name) = spec
+            .split_once(':')
+            .filter(|(iface, name)| !iface.is_empty() && !name.is_empty())
+            .ok_or_else(|| anyhow!("Invalid ethtool stat \"{spec}\", expected iface:stat"))?;
+        if iface.len() >= 16 {
+            bail!("Interface name too long: {iface}");
+        }
+        let sock = sys_socket(AF_INET, SOCK_DGRAM, 0)?;
+        let mut stat = Self {
+            iface: iface.into(),
+            name: name.into(),
+            sock,
+            index: 0,
+            prev_ts: time::Instant::now(),
+            prev_cnt: 0,
+        };
+
+        let n_stats = stat.n_stats()?;
+        let names = stat.stat_names(n_stats)?;
+        stat.index = names
+            .iter()
+            .position(|n| n == name)
+            .ok_or_else(|| anyhow!("No ethtool stat {name} on {iface}"))?;
+        stat.prev_cnt = stat.read_counter()?;
+        Ok(stat)
+    }
+
+    // counter increments per second
+    pub fn rate(&mut self) -> anyhow::Result<f64> {
+        let us = self.prev_ts.elapsed().as_micros();
+        self.prev_ts = time::Instant::now();
+        let cnt = self.read_counter()?;
+        let rate = cnt.saturating_sub(self.prev_cnt) as f64 / (us as f64 / 1_000_000.0);
+        self.prev_cnt = cnt;
+        Ok(rate)
+    }
+
+    fn ethtool(&self, buf: &mut [u8]) -> anyhow::Result<()> {
+        let mut ifr = IfReq {
+            name: [0; 16],
+            data: buf.as_mut_ptr() as *mut c_void,
+            _pad: [0; 16],
+        };
+        ifr.name[..self.iface.len()].copy_from_slice(self.iface.as_bytes());
+        sys_ioctl(
+            self.sock.as_raw_fd(),
+            libc::SIOCETHTOOL,
+            ptr::addr_of_mut!(ifr),
+        )
+        .map_err(|e| anyhow!("SIOCETHTOOL on {}: {e}", self.iface))?;
+        Ok(())
+    }
+
+    fn n_stats(&self) -> anyhow::Result<usize> {
+        let mut buf = vec![0u8; DRVINFO_LEN];
+        buf[..4].copy_from_slice(&ETHTOOL_GDRVINFO.to_ne_bytes());
+        self.ethtool(&mut buf)?;
+        Ok(u32::from_ne_bytes(buf[DRVINFO_N_STATS..DRVINFO_N_STATS + 4].try_into()?) as usize)
+    }
+
+    fn stat_names(&self, n_stats: usize) -> anyhow::Result<Vec<String>> {
+        let mut buf = vec![0u8; 12 + n_stats * ETH_GSTRING_LEN];
+        buf[..4].copy_from_slice(&ETHTOOL_GSTRINGS.to_ne_bytes());
+        buf[4..8].copy_from_slice(&ETH_SS_STATS.to_ne_bytes());
+        buf[8..12].copy_from_slice(&(n_stats as u32).to_ne_bytes());
+        self.ethtool(&mut buf)?;
+        Ok(buf[12..]
+            .chunks_exact(ETH_GSTRING_LEN)
+            .map(|s| {
+                let end = s.iter().position(|c| *c == 0).unwrap_or(s.len());
+                String::from_utf8_lossy(&s[..end]).into_owned()
+            })
+            .collect())
+    }
+
+    fn read_counter(&self) -> anyhow::Result<u64> {
+        // the stat count can change with the number of queues, ask every time
+        let n_stats = self.n_stats()?;
+        if self.index >= n_stats {
+            bail!("ethtool stat {} disappeared from {}", self.name, self.iface);
+        }
+        let mut buf = vec![0u8; 8 + n_stats * 8];
+        buf[..4].copy_from_slice(&ETHTOOL_GSTATS.to_ne_bytes());
+        buf[4..8].copy_from_slice(&(n_stats as u32).to_ne_bytes());
+        self.ethtool(&mut buf)?;
+        let off = 8 + self.index * 8;
+        Ok(u64::from_ne_bytes(buf[off..off + 8].try_into()?))
+    }
+}
+
+impl StatSource for EthtoolStat {
+    fn sample(&mut self) -> anyhow::Result<f64> {
+        self.rate()
+    }
+    fn name(&self) -> &'static str {
+        "ethtool"
+    }
+    fn unit(&self) -> &'static str {
+        "/s"
+    }
+}
+
+// EOF
@@ -0,0 +1,375 @@
+// expr.rs
+
+use std::{fmt, str::FromStr};
+
+use anyhow::{anyhow, bail};
+
+use crate::*;
+
+// A gauge computed from other measurements, in percent of full scale, e.g.
+//   channel 3 = expr:max(net.rx, net.tx) / max_mbps * 100
+//   channel 1 = expr:cpu * 0.5 + cpu.iowait * 0.5
+// with + - * / and parentheses, the functions min, max and abs, and the variables
+//   cpu, cpu.iowait, cpu.irq, cpu.steal  weighted load in percent, see CpuStats
+//   cpu.total, io.await                  busy and iowait load whatever --cpu-mode says
+//   net, net.rx, net.tx                  Mbit/s on --interface, net is the busier
+//   disk                                 MB/s of the disks like the disk source
+//   max_mbps                             the --max-mbps setting
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expr {
+    text: String,
+    root: Node,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Num(f64),
+    Var(String),
+    Neg(Box<Node>),
+    Bin(char, Box<Node>, Box<Node>),
+    Call(String, Vec<Node>),
+}
+
+const VARIABLES: [&str; 11] = [
+    "cpu",
+    "cpu.total",
+    "cpu.iowait",
+    "cpu.irq",
+    "cpu.steal",
+    "io.await",
+    "net",
+    "net.rx",
+    "net.tx",
+    "disk",
+    "max_mbps",
+];
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl FromStr for Expr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = ExprParser {
+            chars: s.chars().collect(),
+            pos: 0,
+        };
+        let root = parser.sum()?;
+        parser.skip_space();
+        if parser.pos < parser.chars.len() {
+            bail!(
+                "Unexpected {:?} in expression {s}",
+                parser.chars[parser.pos]
+            );
+        }
+        let expr = Self {
+            text: s.trim().to_string(),
+            root,
+        };
+        for var in expr.variables() {
+            if !VARIABLES.contains(&var.as_str()) {
+                bail!("Unknown variable {var} in expression {s}");
+            }
+        }
+        Ok(expr)
+    }
+}
+
+impl Expr {
+    // every variable once, in the order of appearance
+    pub fn variables(&self) -> Vec<String> {
+        fn walk(node: &Node, vars: &mut Vec<String>) {
+            match node {
+                Node::Num(_) => {}
+                Node::Var(v) if !vars.contains(v) => vars.push(v.clone()),
+                Node::Var(_) => {}
+                Node::Neg(n) => walk(n, vars),
+                Node::Bin(_, a, b) => {
+                    walk(a, vars);
+                    walk(b, vars);
+                }
+                Node::Call(_, args) => args.iter().for_each(|a| walk(a, vars)),
+            }
+        }
+        let mut vars = Vec::new();
+        walk(&self.root, &mut vars);
+        vars
+    }
+
+    pub fn eval(&self, var: &dyn Fn(&str) -> f64) -> f64 {
+        fn eval(node: &Node, var: &dyn Fn(&str) -> f64) -> f64 {
+            match node {
+                Node::Num(n) => *n,
+                Node::Var(v) => var(v),
+                Node::Neg(n) => -eval(n, var),
+                Node::Bin(op, a, b) => {
+                    let (a, b) = (eval(a, var), eval(b, var));
+                    match op {
+                        '+' => a + b,
+                        '-' => a - b,
+                        '*' => a * b,
+                        _ => a / b,
+                    }
+                }
+                Node::Call(func, args) => {
+                    let mut args = args.iter().map(|a| eval(a, var));
+                    match func.as_str() {
+                        "min" => args.fold(f64::INFINITY, f64::min),
+                        "max" => args.fold(f64::NEG_INFINITY, f64::max),
+                        _ => args.next().unwrap_or_default().abs(),
+                    }
+                }
+            }
+        }
+        eval(&self.root, var)
+    }
+}
+
+struct ExprParser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl ExprParser {
+    fn skip_space(&mut self) {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_space();
+        self.chars.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, c: char) -> anyhow::Result<()> {
+        match self.peek() {
+            Some(p) if p == c => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(p) => Err(anyhow!("Expected {c:?} but got {p:?}")),
+            None => Err(anyhow!("Expected {c:?} at the end")),
+        }
+    }
+
+    // sum: product (("+" | "-") product)*
+    fn sum(&mut self) -> anyhow::Result<Node> {
+        let mut node = self.product()?;
+        while let Some(op @ ('+' | '-')) = self.peek() {
+            self.pos += 1;
+            node = Node::Bin(op, Box::new(node), Box::new(self.product()?));
+        }
+        Ok(node)
+    }
+
+    // product: unary (("*" | "/") unary)*
+    fn product(&mut self) -> anyhow::Result<Node> {
+        let mut node = self.unary()?;
+        while let Some(op @ ('*' | '/')) = self.peek() {
+            self.pos += 1;
+            node = Node::Bin(op, Box::new(node), Box::new(self.unary()?));
+        }
+        Ok(node)
+    }
+
+    // unary: "-" unary | number | name | name "(" sum ("," sum)* ")" | "(" sum ")"
+    fn unary(&mut self) -> anyhow::Result<Node> {
+        match self.peek() {
+            Some('-') => {
+                self.pos += 1;
+                Ok(Node::Neg(Box::new(self.unary()?)))
+            }
+            Some('(') => {
+                self.pos += 1;
+                let node = self.sum()?;
+                self.expect(')')?;
+                Ok(node)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let start = self.pos;
+                while let Some(c) = self.chars.get(self.pos) {
+                    let exp_sign = matches!(c, '-' | '+') && self.chars[self.pos - 1] == 'e';
+                    if !(c.is_ascii_digit() || *c == '.' || *c == 'e' || exp_sign) {
+                        break;
+                    }
+                    self.pos += 1;
+                }
+                let num = self.chars[start..self.pos].iter().collect::<String>();
+                Ok(Node::Num(
+                    num.parse().map_err(|e| anyhow!("Number {num}: {e}"))?,
+                ))
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                let name = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+                if self.peek() != Some('(') {
+                    return Ok(Node::Var(name));
+                }
+                self.pos += 1;
+                let mut args = vec![self.sum()?];
+                while self.peek() == Some(',') {
+                    self.pos += 1;
+                    args.push(self.sum()?);
+                }
+                self.expect(')')?;
+                match (name.as_str(), args.len()) {
+                    ("min" | "max", _) | ("abs", 1) => Ok(Node::Call(name, args)),
+                    _ => Err(anyhow!(
+                        "Unknown function {name} of {} argument(s)",
+                        args.len()
+                    )),
+                }
+            }
+            Some(c) => Err(anyhow!("Unexpected {c:?}")),
+            None => Err(anyhow!("Expression ends too soon")),
+        }
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> String {
+        let start = self.pos;
+        while self.chars.get(self.pos).is_some_and(|c| f(*c)) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+}
+
+// Samples only the variables the expression uses, each tick
+pub struct ExprSource {
+    expr: Expr,
+    max_mbps: f64,
+    sources: Vec<(String, Box<dyn StatSource>, f64)>,
+}
+
+impl ExprSource {
+    pub fn new(expr: &Expr, cfg: &MappingConfig) -> anyhow::Result<Self> {
+        let mut sources = Vec::new();
+        for var in expr.variables() {
+            let (source, scale): (Box<dyn StatSource>, f64) = match var.as_str() {
+                "cpu" => (cpu_source(cfg.cpu_mode)?, 1.0),
+                "cpu.total" => (cpu_source(CpuMode::Busy)?, 1.0),
+                "cpu.iowait" | "io.await" => (cpu_source(CpuMode::Iowait)?, 1.0),
+                "cpu.irq" => (cpu_source(CpuMode::Irq)?, 1.0),
+                "cpu.steal" => (cpu_source(CpuMode::Steal)?, 1.0),
+                "net" => {
+                    let busiest = Busiest(vec![
+                        net_source(&cfg.interface, IfCounter::Rx)?,
+                        net_source(&cfg.interface, IfCounter::Tx)?,
+                    ]);
+                    (Box::new(busiest), 1e-6)
+                }
+                "net.rx" => (net_source(&cfg.interface, IfCounter::Rx)?, 1e-6),
+                "net.tx" => (net_source(&cfg.interface, IfCounter::Tx)?, 1e-6),
+                // 512 byte sectors
+                "disk" => (disk_source(None, cfg.disk_gauge)?, 512e-6),
+                _ => continue,
+            };
+            sources.push((var, source, scale));
+        }
+        Ok(Self {
+            expr: expr.clone(),
+            max_mbps: cfg.max_mbps as f64,
+            sources,
+        })
+    }
+}
+
+impl StatSource for ExprSource {
+    fn sample(&mut self) -> anyhow::Result<f64> {
+        let mut values = Vec::with_capacity(self.sources.len());
+        for (var, source, scale) in self.sources.iter_mut() {
+            values.push((var.as_str(), source.sample()? * *scale));
+        }
+        let max_mbps = self.max_mbps;
+        let value = self.expr.eval(&|var| match var {
+            "max_mbps" => max_mbps,
+            _ => values
+                .iter()
+                .find(|(v, _)| *v == var)
+                .map_or(0.0, |(_, x)| *x),
+        });
+        debug!("EXPR {} = {value:.1}", self.expr);
+        Ok(value)
+    }
+    fn name(&self) -> &'static str {
+        "expr"
+    }
+    fn unit(&self) -> &'static str {
+        "%"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(s: &str) -> f64 {
+        let vars = |v: &str| match v {
+            "cpu" => 40.0,
+            "cpu.total" => 60.0,
+            "io.await" => 5.0,
+            "net.rx" => 2.0,
+            "net.tx" => 8.0,
+            _ => 0.0,
+        };
+        s.parse::<Expr>().unwrap().eval(&vars)
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("1 + 2 * 3"), 7.0);
+        assert_eq!(eval("(1 + 2) * 3"), 9.0);
+        assert_eq!(eval("10 - 4 - 3"), 3.0);
+        assert_eq!(eval("24 / 4 / 2"), 3.0);
+        assert_eq!(eval("2 * -3 + 1"), -5.0);
+        assert_eq!(eval("--2"), 2.0);
+        assert_eq!(eval("1.5e2 - 1e-1 * 10"), 149.0);
+        assert_eq!(eval("max(net.rx, net.tx) / 10 * 100"), 80.0);
+        assert_eq!(eval("min(3, abs(-2), 5) + max(1)"), 3.0);
+        assert_eq!(eval("cpu * 0.5 + cpu.total * 0.5"), 50.0);
+    }
+
+    #[test]
+    fn names() {
+        let expr = "cpu.total - io.await + cpu.total".parse::<Expr>().unwrap();
+        assert_eq!(expr.variables(), ["cpu.total", "io.await"]);
+        assert_eq!(eval("cpu.total - io.await"), 55.0);
+        assert_eq!(expr.to_string(), "cpu.total - io.await + cpu.total");
+    }
+
+    #[test]
+    fn errors() {
+        for bad in [
+            "cpu.totals",
+            "io",
+            "gpu + 1",
+            "sqrt(4)",
+            "abs(1, 2)",
+            "1 +",
+            "(1 + 2",
+            "1 2",
+            "1 $ 2",
+            "",
+            "1..2",
+        ] {
+            assert!(bad.parse::<Expr>().is_err(), "{bad}");
+        }
+        let e = "cpu + disks".parse::<Expr>().unwrap_err();
+        assert!(e.to_string().contains("Unknown variable disks"), "{e}");
+    }
+
+    // left to the gauge: infinity pegs the needle and NaN reads zero
+    #[test]
+    fn division_by_zero() {
+        assert_eq!(eval("1 / 0"), f64::INFINITY);
+        assert_eq!(eval("-cpu / 0"), f64::NEG_INFINITY);
+        assert!(eval("0 / 0").is_nan());
+        assert!(eval("disk / disk").is_nan());
+    }
+}
+
+// EOF
@@ -0,0 +1,76 @@
+// fifo.rs
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    os::unix::fs::{FileTypeExt, OpenOptionsExt},
+    path::Path,
+    sync::mpsc,
+};
+
+use anyhow::bail;
+
+use crate::*;
+
+const O_NONBLOCK: i32 = 0o4000;
+// ENXIO: nobody has the FIFO open for reading
+const ENXIO: i32 = 6;
+
+// Writes every frame as one line of JSON into a named pipe, created if missing.
+// Frames are dropped while nobody reads, or when the reader falls behind.
+pub struct FifoSink;
+
+impl FifoSink {
+    pub fn spawn<S: AsRef<str>>(path: S) -> anyhow::Result<ThreadedSink> {
+        let path = path.as_ref().to_string();
+        match std::fs::metadata(&path) {
+            Ok(m) if m.file_type().is_fifo() => {}
+            Ok(_) => bail!("{path} exists and is not a FIFO"),
+            Err(_) => sys::sys_mkfifo(&path, 0o644)?,
+        }
+        info!("Writing JSON frames to FIFO {path}");
+        spawn_sink("fifo", move |rx| Self::run(path, rx))
+    }
+
+    fn run(path: String, rx: mpsc::Receiver<Frame>) {
+        let mut fifo: Option<File> = None;
+        while let Ok(frame) = rx.recv() {
+            if fifo.is_none() {
+                fifo = match open_writer(Path::new(&path)) {
+                    Ok(f) => {
+                        debug!("FIFO {path}: reader attached");
+                        Some(f)
+                    }
+                    Err(e) if e.raw_os_error() == Some(ENXIO) => None,
+                    Err(e) => {
+                        debug!("FIFO {path}: {e}");
+                        count_error("fifo");
+                        None
+                    }
+                };
+            }
+            let Some(f) = &mut fifo else {
+                continue;
+            };
+            let line = frame_json(&frame) + "\n";
+            match f.write(line.as_bytes()) {
+                // a partial line would corrupt the stream, the pipe buffer is large enough
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => trace!("FIFO {path} full"),
+                Err(_) => {
+                    debug!("FIFO {path}: reader went away");
+                    fifo = None;
+                }
+            }
+        }
+    }
+}
+
+fn open_writer(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .write(true)
+        .custom_flags(O_NONBLOCK)
+        .open(path)
+}
+
+// EOF
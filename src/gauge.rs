@@ -0,0 +1,179 @@
+// gauge.rs
+
+use std::time;
+
+use anyhow::bail;
+
+use crate::*;
+
+pub const CHANNELS_NUM: usize = 192; // Remember: channel cmd byte has offset 0x30
+
+pub fn channel_index(channel: u8) -> anyhow::Result<usize> {
+    let ch_i = channel as usize;
+    if ch_i >= CHANNELS_NUM {
+        bail!(
+            "Channel number too large: {ch_i} (maximum {})",
+            CHANNELS_NUM - 1
+        );
+    }
+    Ok(ch_i)
+}
+
+// One needle: where it is, the peak it holds and what was last sent to it
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Channel {
+    pub value: f64,
+    at: Option<time::Instant>,
+    peak: f64,
+    peak_at: Option<time::Instant>,
+    sent: Option<u8>,
+}
+
+impl Channel {
+    // moves the needle towards the gauge, no faster than the ballistics allow
+    pub fn smooth(&mut self, gauge: f64, ballistics: Ballistics, now: time::Instant) -> f64 {
+        let gauge = if gauge.is_nan() {
+            0.0
+        } else {
+            gauge.clamp(0.0, 255.0)
+        };
+
+        // peak hold: the needle stays at the recent maximum, then falls at the release rate
+        let gauge = match self.peak_at {
+            Some(t) if gauge < self.peak && (now - t).as_secs_f64() < ballistics.hold => self.peak,
+            _ => {
+                self.peak = gauge;
+                self.peak_at = Some(now);
+                gauge
+            }
+        };
+        let dt = self.at.map_or(1.0, |t| (now - t).as_secs_f64().min(1.0));
+        self.at = Some(now);
+        self.value = if gauge > self.value {
+            (self.value + ballistics.attack * dt).min(gauge)
+        } else {
+            (self.value - ballistics.release * dt).max(gauge)
+        };
+        self.value
+    }
+
+    // quantizes the smoothed value, None when the result was already sent
+    pub fn quantize(&mut self, value: i16, quant: u8) -> Option<u8> {
+        let step = quant.max(1) as i16;
+        let out_value = ((value.max(0) + step / 2) / step * step).min(255) as u8;
+        if self.sent == Some(out_value) {
+            return None;
+        }
+        self.sent = Some(out_value);
+        Some(out_value)
+    }
+
+    // the next value is sent even if it did not change
+    pub fn resend(&mut self) {
+        self.sent = None;
+    }
+}
+
+// The smoothing state of every channel of one meter
+#[derive(Debug)]
+pub struct GaugeState {
+    channels: Vec<Channel>,
+}
+
+impl Default for GaugeState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GaugeState {
+    pub fn new() -> Self {
+        Self {
+            channels: vec![Channel::default(); CHANNELS_NUM],
+        }
+    }
+
+    pub fn channel(&mut self, channel: u8) -> anyhow::Result<&mut Channel> {
+        Ok(&mut self.channels[channel_index(channel)?])
+    }
+
+    pub fn resend_all(&mut self) {
+        self.channels.iter_mut().for_each(Channel::resend);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(t0: time::Instant, s: f64) -> time::Instant {
+        t0 + time::Duration::from_secs_f64(s)
+    }
+
+    #[test]
+    fn attack_and_release() {
+        let b = Ballistics {
+            attack: 100.0,
+            release: 50.0,
+            hold: 0.0,
+        };
+        let t0 = time::Instant::now();
+        let mut ch = Channel::default();
+        // a second's worth on the first call
+        assert_eq!(ch.smooth(200.0, b, t0), 100.0);
+        assert_eq!(ch.smooth(200.0, b, secs(t0, 0.5)), 150.0);
+        assert_eq!(ch.smooth(0.0, b, secs(t0, 1.0)), 125.0);
+        // a long gap counts as one second
+        assert_eq!(ch.smooth(0.0, b, secs(t0, 3.0)), 75.0);
+        assert_eq!(ch.smooth(80.0, b, secs(t0, 3.1)), 80.0);
+    }
+
+    #[test]
+    fn peak_hold_then_decay() {
+        let b = Ballistics {
+            attack: 1000.0,
+            release: 100.0,
+            hold: 2.0,
+        };
+        let t0 = time::Instant::now();
+        let mut ch = Channel::default();
+        assert_eq!(ch.smooth(200.0, b, t0), 200.0);
+        assert_eq!(ch.smooth(50.0, b, secs(t0, 1.0)), 200.0);
+        assert_eq!(ch.smooth(50.0, b, secs(t0, 1.9)), 200.0);
+        // the hold is over, down at the release rate
+        assert_eq!(ch.smooth(50.0, b, secs(t0, 2.5)), 140.0);
+        assert_eq!(ch.smooth(50.0, b, secs(t0, 3.5)), 50.0);
+    }
+
+    #[test]
+    fn smooth_sanitizes() {
+        let b = Ballistics::default();
+        let t0 = time::Instant::now();
+        let mut ch = Channel::default();
+        assert_eq!(ch.smooth(1000.0, b, t0), 255.0);
+        assert_eq!(ch.smooth(f64::NAN, b, secs(t0, 1.0)), 0.0);
+    }
+
+    #[test]
+    fn quantize_steps() {
+        let mut ch = Channel::default();
+        assert_eq!(ch.quantize(181, 4), Some(180));
+        // the same step is not sent again
+        assert_eq!(ch.quantize(179, 4), None);
+        assert_eq!(ch.quantize(183, 4), Some(184));
+        assert_eq!(ch.quantize(-5, 4), Some(0));
+        assert_eq!(ch.quantize(255, 4), Some(255));
+        ch.resend();
+        assert_eq!(ch.quantize(254, 4), Some(255));
+        assert_eq!(ch.quantize(254, 0), Some(254));
+    }
+
+    #[test]
+    fn channels_out_of_range() {
+        let mut state = GaugeState::new();
+        assert!(state.channel(CHANNELS_NUM as u8 - 1).is_ok());
+        assert!(state.channel(CHANNELS_NUM as u8).is_err());
+    }
+}
+
+// EOF
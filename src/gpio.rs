@@ -0,0 +1,255 @@
+// gpio.rs
+
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc,
+};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    path::Path,
+    str::FromStr,
+    sync::mpsc,
+    thread, time,
+};
+
+use anyhow::{anyhow, bail};
+
+use crate::*;
+
+// line handle ioctls of the v1 chardev ABI
+const GPIO_GET_LINEHANDLE_IOCTL: std::os::raw::c_ulong = 0xc16c_b403;
+const GPIOHANDLE_GET_LINE_VALUES_IOCTL: std::os::raw::c_ulong = 0xc040_b408;
+const GPIOHANDLE_SET_LINE_VALUES_IOCTL: std::os::raw::c_ulong = 0xc040_b409;
+const GPIOHANDLE_REQUEST_INPUT: u32 = 1 << 0;
+const GPIOHANDLE_REQUEST_OUTPUT: u32 = 1 << 1;
+// software PWM period, a moving coil meter averages this out
+const SOFT_PWM_PERIOD: time::Duration = time::Duration::from_millis(10);
+
+#[repr(C)]
+struct GpioHandleRequest {
+    lineoffsets: [u32; 64],
+    flags: u32,
+    default_values: [u8; 64],
+    consumer_label: [u8; 32],
+    lines: u32,
+    fd: i32,
+}
+
+#[repr(C)]
+struct GpioHandleData {
+    values: [u8; 64],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GpioPin {
+    // /sys/class/pwm/pwmchipN/pwmM
+    Hardware { chip: u32, channel: u32 },
+    // line offset on the gpiochip, toggled by a thread
+    Software(u32),
+}
+
+// "pwm0.1" or "gpio18", optionally followed by the duty cycle range
+// in percent, e.g. "gpio18:5-80"
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GpioPwmSpec {
+    pub pin: GpioPin,
+    pub min_pct: f64,
+    pub max_pct: f64,
+}
+
+impl FromStr for GpioPwmSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pin, range) = match s.split_once(':') {
+            Some((pin, range)) => (pin, Some(range)),
+            None => (s, None),
+        };
+        let pin = if let Some(hw) = pin.strip_prefix("pwm") {
+            let (chip, channel) = hw
+                .split_once('.')
+                .ok_or_else(|| anyhow!("Invalid PWM pin {pin}, expected pwmCHIP.CHANNEL"))?;
+            GpioPin::Hardware {
+                chip: chip.parse()?,
+                channel: channel.parse()?,
+            }
+        } else if let Some(line) = pin.strip_prefix("gpio") {
+            GpioPin::Software(line.parse()?)
+        } else {
+            bail!("Invalid PWM pin {pin}, expected pwmCHIP.CHANNEL or gpioLINE");
+        };
+        let (min_pct, max_pct) = match range {
+            Some(r) => {
+                let (min, max) = r
+                    .split_once('-')
+                    .ok_or_else(|| anyhow!("Invalid PWM range {r}, expected min-max"))?;
+                (min.parse::<f64>()?, max.parse::<f64>()?)
+            }
+            None => (0.0, 100.0),
+        };
+        Ok(Self {
+            pin,
+            min_pct: min_pct.clamp(0.0, 100.0),
+            max_pct: max_pct.clamp(0.0, 100.0),
+        })
+    }
+}
+
+enum PwmOut {
+    Hardware { duty_cycle: File, period_ns: u64 },
+    // duty cycle in 1/1000
+    Software(Arc<AtomicU32>),
+}
+
+impl PwmOut {
+    fn set(&mut self, fraction: f64) -> anyhow::Result<()> {
+        match self {
+            PwmOut::Hardware {
+                duty_cycle,
+                period_ns,
+            } => {
+                let ns = (fraction * *period_ns as f64) as u64;
+                duty_cycle.write_all(ns.to_string().as_bytes())?;
+            }
+            PwmOut::Software(duty) => duty.store((fraction * 1000.0) as u32, Ordering::Relaxed),
+        }
+        Ok(())
+    }
+}
+
+// Drives meters from Raspberry Pi PWM or plain GPIO pins, one pin per channel
+pub struct GpioPwmSink;
+
+impl GpioPwmSink {
+    pub fn spawn(
+        pins: Vec<(u8, GpioPwmSpec)>,
+        freq: u32,
+        gpiochip: &str,
+    ) -> anyhow::Result<ThreadedSink> {
+        let mut outs = Vec::with_capacity(pins.len());
+        for (ch, spec) in &pins {
+            let out = match spec.pin {
+                GpioPin::Hardware { chip, channel } => hardware_pwm(chip, channel, freq)?,
+                GpioPin::Software(line) => software_pwm(gpiochip, line)?,
+            };
+            info!("Channel {ch} drives {:?}", spec.pin);
+            outs.push((*ch, *spec, out));
+        }
+        spawn_sink("gpio-pwm", move |rx| Self::run(outs, rx))
+    }
+
+    fn run(mut outs: Vec<(u8, GpioPwmSpec, PwmOut)>, rx: mpsc::Receiver<Frame>) {
+        while let Ok(frame) = rx.recv() {
+            for (ch, spec, out) in outs.iter_mut() {
+                let Some(sample) = frame.get(ch) else {
+                    continue;
+                };
+                let pct = spec.min_pct
+                    + (spec.max_pct - spec.min_pct) * sample.value.clamp(0.0, 255.0) / 255.0;
+                if let Err(e) = out.set(pct / 100.0) {
+                    debug!("GPIO PWM: {e}");
+                    count_error("gpio_pwm");
+                }
+            }
+        }
+    }
+}
+
+fn hardware_pwm(chip: u32, channel: u32, freq: u32) -> anyhow::Result<PwmOut> {
+    let chip_dir = format!("/sys/class/pwm/pwmchip{chip}");
+    let pwm_dir = format!("{chip_dir}/pwm{channel}");
+    if !Path::new(&pwm_dir).exists() {
+        fs::write(format!("{chip_dir}/export"), channel.to_string())?;
+        // udev needs a moment to fix up the permissions
+        thread::sleep(time::Duration::from_millis(100));
+    }
+    let period_ns = 1_000_000_000 / freq.max(1) as u64;
+    // the duty cycle may never exceed the period, also not in between
+    fs::write(format!("{pwm_dir}/duty_cycle"), "0")?;
+    fs::write(format!("{pwm_dir}/period"), period_ns.to_string())?;
+    fs::write(format!("{pwm_dir}/enable"), "1")?;
+    let duty_cycle = OpenOptions::new()
+        .write(true)
+        .open(format!("{pwm_dir}/duty_cycle"))?;
+    Ok(PwmOut::Hardware {
+        duty_cycle,
+        period_ns,
+    })
+}
+
+fn software_pwm(gpiochip: &str, line: u32) -> anyhow::Result<PwmOut> {
+    let mut line = GpioLine::output(gpiochip, line)?;
+    let duty = Arc::new(AtomicU32::new(0));
+    let ret = PwmOut::Software(duty.clone());
+    thread::spawn(move || loop {
+        let on = SOFT_PWM_PERIOD * duty.load(Ordering::Relaxed).min(1000) / 1000;
+        if !on.is_zero() && line.set(true).is_err() {
+            count_error("gpio_pwm");
+        }
+        thread::sleep(on);
+        if on < SOFT_PWM_PERIOD {
+            let _ = line.set(false);
+            thread::sleep(SOFT_PWM_PERIOD - on);
+        }
+    });
+    Ok(ret)
+}
+
+// A single requested line of a gpiochip
+pub(crate) struct GpioLine {
+    handle: OwnedFd,
+}
+
+impl GpioLine {
+    pub fn output(gpiochip: &str, line: u32) -> anyhow::Result<Self> {
+        Self::request(gpiochip, line, GPIOHANDLE_REQUEST_OUTPUT)
+    }
+
+    pub fn input(gpiochip: &str, line: u32) -> anyhow::Result<Self> {
+        Self::request(gpiochip, line, GPIOHANDLE_REQUEST_INPUT)
+    }
+
+    fn request(gpiochip: &str, line: u32, flags: u32) -> anyhow::Result<Self> {
+        let chip = File::open(gpiochip)?;
+        let mut req = GpioHandleRequest {
+            lineoffsets: [0; 64],
+            flags,
+            default_values: [0; 64],
+            consumer_label: [0; 32],
+            lines: 1,
+            fd: -1,
+        };
+        req.lineoffsets[0] = line;
+        req.consumer_label[..12].copy_from_slice(b"perf_vumeter");
+        sys::sys_ioctl(chip.as_raw_fd(), GPIO_GET_LINEHANDLE_IOCTL, &mut req)
+            .map_err(|e| anyhow!("Cannot request line {line} of {gpiochip}: {e}"))?;
+        Ok(Self {
+            handle: unsafe { OwnedFd::from_raw_fd(req.fd) },
+        })
+    }
+
+    pub fn set(&mut self, high: bool) -> anyhow::Result<()> {
+        let mut data = GpioHandleData { values: [0; 64] };
+        data.values[0] = high as u8;
+        sys::sys_ioctl(
+            self.handle.as_raw_fd(),
+            GPIOHANDLE_SET_LINE_VALUES_IOCTL,
+            &mut data,
+        )?;
+        Ok(())
+    }
+
+    pub fn get(&self) -> anyhow::Result<bool> {
+        let mut data = GpioHandleData { values: [0; 64] };
+        sys::sys_ioctl(
+            self.handle.as_raw_fd(),
+            GPIOHANDLE_GET_LINE_VALUES_IOCTL,
+            &mut data,
+        )?;
+        Ok(data.values[0] != 0)
+    }
+}
+
+// EOF
@@ -0,0 +1,203 @@
+// gpu.rs
+
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int, c_uint, c_void};
+use std::{fs, mem, ptr, time};
+
+use anyhow::{anyhow, bail};
+
+use crate::*;
+
+type NvmlFn = unsafe extern "C" fn() -> c_int;
+type NvmlCountFn = unsafe extern "C" fn(count: *mut c_uint) -> c_int;
+type NvmlHandleFn = unsafe extern "C" fn(index: c_uint, device: *mut *mut c_void) -> c_int;
+type NvmlTempFn =
+    unsafe extern "C" fn(device: *mut c_void, sensor: c_int, temp: *mut c_uint) -> c_int;
+type NvmlErrorFn = unsafe extern "C" fn(result: c_int) -> *const c_char;
+
+#[cfg(unix)]
+const LIBNVML: &str = "libnvidia-ml.so.1";
+#[cfg(windows)]
+const LIBNVML: &str = "nvml.dll";
+const NVML_TEMPERATURE_GPU: c_int = 0;
+
+// Hottest GPU temperature in degrees Celsius, from the hwmon sensors
+// of DRM cards (amdgpu, i915...) or from NVML, loaded at runtime from
+// the NVIDIA driver.
+#[derive(Debug)]
+pub struct GpuTemp {
+    fn_temps: Vec<String>,
+    nvml: Option<Nvml>,
+}
+
+impl GpuTemp {
+    pub fn new() -> anyhow::Result<Self> {
+        let mut fn_temps = Vec::new();
+        if let Ok(cards) = fs::read_dir(sys_path("class/drm")) {
+            for card in cards.flatten() {
+                let Ok(hwmons) = fs::read_dir(card.path().join("device/hwmon")) else {
+                    continue;
+                };
+                for hwmon in hwmons.flatten() {
+                    let temp = hwmon.path().join("temp1_input");
+                    if temp.exists() {
+                        fn_temps.push(temp.to_string_lossy().into_owned());
+                    }
+                }
+            }
+        }
+        fn_temps.sort();
+        fn_temps.dedup();
+
+        let nvml = match fn_temps.is_empty() {
+            true => Some(Nvml::open().map_err(|e| anyhow!("No GPU temperature available: {e}"))?),
+            false => None,
+        };
+        let gpu = Self { fn_temps, nvml };
+        gpu.temp()
+            .map_err(|e| anyhow!("No GPU temperature available: {e}"))?;
+        Ok(gpu)
+    }
+
+    // the temperature and when it was read
+    pub fn temp(&self) -> anyhow::Result<(f64, time::Instant)> {
+        if let Some(nvml) = &self.nvml {
+            return Ok((nvml.temp()?, time::Instant::now()));
+        }
+        let temp = self
+            .fn_temps
+            .iter()
+            .map(|f| Ok(fs::read_to_string(f)?.trim().parse::<f64>()? / 1000.0))
+            .try_fold(f64::MIN, |max, t: anyhow::Result<f64>| {
+                anyhow::Ok(max.max(t?))
+            })?;
+        Ok((temp, time::Instant::now()))
+    }
+}
+
+// NVML initialized with the handles of its GPUs, see the NVML API reference
+struct Nvml {
+    handle: *mut c_void,
+    devices: Vec<*mut c_void>,
+    temperature: NvmlTempFn,
+    error_string: NvmlErrorFn,
+    shutdown: NvmlFn,
+}
+
+// NVML is thread safe
+unsafe impl Send for Nvml {}
+
+impl std::fmt::Debug for Nvml {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Nvml({} devices)", self.devices.len())
+    }
+}
+
+impl Nvml {
+    fn open() -> anyhow::Result<Self> {
+        let handle = sys::sys_dlopen(LIBNVML).map_err(|e| anyhow!("NVML: {e}"))?;
+        let sym = |name: &str| {
+            sys::sys_dlsym(handle, name).ok_or_else(|| anyhow!("{LIBNVML} has no {name}"))
+        };
+        let syms = (|| {
+            Ok::<_, anyhow::Error>((
+                sym("nvmlInit_v2")?,
+                sym("nvmlShutdown")?,
+                sym("nvmlErrorString")?,
+                sym("nvmlDeviceGetCount_v2")?,
+                sym("nvmlDeviceGetHandleByIndex_v2")?,
+                sym("nvmlDeviceGetTemperature")?,
+            ))
+        })();
+        let (init, shutdown, error_string, count, by_index, temperature) = match syms {
+            Ok(syms) => syms,
+            Err(e) => {
+                sys::sys_dlclose(handle);
+                return Err(e);
+            }
+        };
+        // SAFETY: the signatures are those of nvml.h
+        let (init, count, by_index) = unsafe {
+            (
+                mem::transmute::<*mut c_void, NvmlFn>(init),
+                mem::transmute::<*mut c_void, NvmlCountFn>(count),
+                mem::transmute::<*mut c_void, NvmlHandleFn>(by_index),
+            )
+        };
+        let error_string = unsafe { mem::transmute::<*mut c_void, NvmlErrorFn>(error_string) };
+        if let Err(e) = Self::check(error_string, unsafe { init() }) {
+            sys::sys_dlclose(handle);
+            return Err(e);
+        }
+        // from here on Drop shuts NVML down again
+        let mut nvml = Self {
+            handle,
+            devices: Vec::new(),
+            temperature: unsafe { mem::transmute::<*mut c_void, NvmlTempFn>(temperature) },
+            error_string,
+            shutdown: unsafe { mem::transmute::<*mut c_void, NvmlFn>(shutdown) },
+        };
+        let mut n: c_uint = 0;
+        nvml.check_ret(unsafe { count(&mut n) })?;
+        for i in 0..n {
+            let mut device = ptr::null_mut();
+            nvml.check_ret(unsafe { by_index(i, &mut device) })?;
+            nvml.devices.push(device);
+        }
+        if nvml.devices.is_empty() {
+            bail!("NVML found no GPUs");
+        }
+        Ok(nvml)
+    }
+
+    // the hottest of the GPUs
+    fn temp(&self) -> anyhow::Result<f64> {
+        let mut max = f64::MIN;
+        for &device in &self.devices {
+            let mut t: c_uint = 0;
+            self.check_ret(unsafe { (self.temperature)(device, NVML_TEMPERATURE_GPU, &mut t) })?;
+            max = max.max(t as f64);
+        }
+        Ok(max)
+    }
+
+    fn check_ret(&self, ret: c_int) -> anyhow::Result<()> {
+        Self::check(self.error_string, ret)
+    }
+
+    // the calls return NVML_SUCCESS, i.e. 0, or an error code
+    fn check(error_string: NvmlErrorFn, ret: c_int) -> anyhow::Result<()> {
+        if ret == 0 {
+            return Ok(());
+        }
+        let msg = unsafe { error_string(ret) };
+        match msg.is_null() {
+            true => bail!("NVML error {ret}"),
+            false => bail!("NVML: {}", unsafe { CStr::from_ptr(msg) }.to_string_lossy()),
+        }
+    }
+}
+
+impl Drop for Nvml {
+    fn drop(&mut self) {
+        unsafe { (self.shutdown)() };
+        sys::sys_dlclose(self.handle);
+    }
+}
+
+impl StatSource for GpuTemp {
+    fn sample(&mut self) -> anyhow::Result<f64> {
+        Ok(self.temp()?.0)
+    }
+    fn name(&self) -> &'static str {
+        "gpu_temp"
+    }
+    fn unit(&self) -> &'static str {
+        "C"
+    }
+    fn sample_at(&mut self) -> anyhow::Result<(f64, time::Instant)> {
+        self.temp()
+    }
+}
+
+// EOF
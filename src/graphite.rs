@@ -0,0 +1,155 @@
+// graphite.rs
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::Write,
+    net::{TcpStream, UdpSocket},
+    str::FromStr,
+    sync::mpsc,
+    time,
+};
+
+use anyhow::anyhow;
+
+use crate::*;
+
+// stay below a typical MTU, statsd servers do not reassemble
+const STATSD_MAX_PACKET: usize = 1400;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum GraphiteProto {
+    // carbon plaintext over TCP, "path value timestamp"
+    #[default]
+    Graphite,
+    // statsd gauges over UDP, "path:value|g"
+    Statsd,
+}
+
+impl FromStr for GraphiteProto {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "graphite" | "carbon" => Ok(GraphiteProto::Graphite),
+            "statsd" => Ok(GraphiteProto::Statsd),
+            _ => Err(anyhow!("Unknown graphite protocol: {s}")),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct GraphiteConfig {
+    // host:port
+    pub target: String,
+    pub proto: GraphiteProto,
+    // "{host}" is replaced with the hostname
+    pub prefix: String,
+    pub flush: time::Duration,
+}
+
+// Sends the average of every gauge and raw value over the flush interval
+// as <prefix>.<source>.ch<N>.gauge and <prefix>.<source>.ch<N>.raw
+pub struct GraphiteSink;
+
+impl GraphiteSink {
+    pub fn spawn(cfg: GraphiteConfig) -> anyhow::Result<ThreadedSink> {
+        info!("Sending {:?} metrics to {}", cfg.proto, cfg.target);
+        spawn_sink("graphite", move |rx| Self::run(cfg, rx))
+    }
+
+    fn run(cfg: GraphiteConfig, rx: mpsc::Receiver<Frame>) {
+        let prefix = cfg.prefix.replace("{host}", &hostname().replace('.', "_"));
+        // path -> (sum, count)
+        let mut acc: BTreeMap<String, (f64, u32)> = BTreeMap::new();
+        let mut next_flush = time::Instant::now() + cfg.flush;
+
+        loop {
+            let timeout = next_flush.saturating_duration_since(time::Instant::now());
+            match rx.recv_timeout(timeout) {
+                Ok(frame) => {
+                    for (ch, sample) in &frame {
+                        let source = match sample.source {
+                            "" => "channel",
+                            s => s,
+                        };
+                        let path = format!("{prefix}.{source}.ch{ch}");
+                        let mut add = |name: &str, v: f64| {
+                            let e = acc.entry(format!("{path}.{name}")).or_default();
+                            e.0 += v;
+                            e.1 += 1;
+                        };
+                        add("gauge", sample.value.clamp(0.0, 255.0));
+                        if let Some(raw) = sample.raw.filter(|r| r.is_finite()) {
+                            add("raw", raw);
+                        }
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => return,
+            }
+            if time::Instant::now() < next_flush {
+                continue;
+            }
+            next_flush = time::Instant::now() + cfg.flush;
+            if acc.is_empty() {
+                continue;
+            }
+
+            let metrics = acc
+                .iter()
+                .map(|(path, (sum, n))| (path.as_str(), sum / *n as f64))
+                .collect::<Vec<_>>();
+            let res = match cfg.proto {
+                GraphiteProto::Graphite => send_graphite(&cfg.target, &metrics),
+                GraphiteProto::Statsd => send_statsd(&cfg.target, &metrics),
+            };
+            match res {
+                Ok(()) => trace!("Sent {} metrics to {}", metrics.len(), cfg.target),
+                Err(e) => {
+                    error!("Sending metrics to {} failed: {e}", cfg.target);
+                    count_error("graphite");
+                }
+            }
+            acc.clear();
+        }
+    }
+}
+
+fn send_graphite(target: &str, metrics: &[(&str, f64)]) -> anyhow::Result<()> {
+    let ts = time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut buf = String::new();
+    for (path, value) in metrics {
+        let _ = writeln!(buf, "{path} {value:.3} {ts}");
+    }
+    let mut stream = TcpStream::connect(target)?;
+    stream.set_write_timeout(Some(time::Duration::from_secs(5)))?;
+    stream.write_all(buf.as_bytes())?;
+    Ok(())
+}
+
+fn send_statsd(target: &str, metrics: &[(&str, f64)]) -> anyhow::Result<()> {
+    let sock = UdpSocket::bind("0.0.0.0:0")?;
+    sock.connect(target)?;
+    let mut buf = String::new();
+    for (path, value) in metrics {
+        let line = format!("{path}:{value:.3}|g");
+        if !buf.is_empty() && buf.len() + line.len() + 1 > STATSD_MAX_PACKET {
+            sock.send(buf.as_bytes())?;
+            buf.clear();
+        }
+        if !buf.is_empty() {
+            buf.push('\n');
+        }
+        buf.push_str(&line);
+    }
+    if !buf.is_empty() {
+        sock.send(buf.as_bytes())?;
+    }
+    Ok(())
+}
+
+// EOF
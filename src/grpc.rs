@@ -0,0 +1,811 @@
+// grpc.rs
+
+use std::sync::{Arc, Mutex};
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    thread, time,
+};
+
+use anyhow::{anyhow, bail};
+
+use crate::*;
+
+// RFC 7540 3.5 and 6
+const H2_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+const H2_DATA: u8 = 0x0;
+const H2_HEADERS: u8 = 0x1;
+const H2_RST_STREAM: u8 = 0x3;
+const H2_SETTINGS: u8 = 0x4;
+const H2_PING: u8 = 0x6;
+const H2_GOAWAY: u8 = 0x7;
+const H2_WINDOW_UPDATE: u8 = 0x8;
+const H2_CONTINUATION: u8 = 0x9;
+const H2_FLAG_END_STREAM: u8 = 0x1;
+const H2_FLAG_ACK: u8 = 0x1;
+const H2_FLAG_END_HEADERS: u8 = 0x4;
+const H2_FLAG_PADDED: u8 = 0x8;
+const H2_FLAG_PRIORITY: u8 = 0x20;
+const H2_SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const H2_SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+const H2_DEFAULT_WINDOW: i64 = 65535;
+const H2_MAX_FRAME: usize = 16384;
+const H2_PROTOCOL_ERROR: u32 = 0x1;
+const H2_FRAME_SIZE_ERROR: u32 = 0x6;
+// a request body larger than this is not one of ours
+const GRPC_MAX_REQUEST: usize = 64 * 1024;
+// a client that cannot keep up gets dropped
+const GRPC_WRITE_TIMEOUT: time::Duration = time::Duration::from_millis(200);
+
+// https://github.com/grpc/grpc/blob/master/doc/statuscodes.md
+const GRPC_OK: u32 = 0;
+const GRPC_INVALID_ARGUMENT: u32 = 3;
+const GRPC_RESOURCE_EXHAUSTED: u32 = 8;
+const GRPC_UNIMPLEMENTED: u32 = 12;
+
+const GRPC_STREAM_SAMPLES: &str = "/perf_vumeter.Meter/StreamSamples";
+const GRPC_SET_OVERRIDE: &str = "/perf_vumeter.Meter/SetOverride";
+
+// Serves the Meter service of proto/perf_vumeter.proto over cleartext HTTP/2:
+// StreamSamples streams every frame to the client, SetOverride pins a channel
+// like SetChannel over D-Bus does. A stream that runs out of flow control window
+// misses frames until the client catches up.
+pub struct GrpcSink;
+
+impl GrpcSink {
+    pub fn spawn<S: AsRef<str>>(listen: S) -> anyhow::Result<ThreadedSink> {
+        let listener = TcpListener::bind(listen.as_ref())?;
+        info!("gRPC server listening on {}", listen.as_ref());
+        let streams: Arc<Mutex<Vec<GrpcStream>>> = Arc::new(Mutex::new(Vec::new()));
+
+        let accepted = streams.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let streams = accepted.clone();
+                thread::spawn(move || {
+                    let peer = stream
+                        .peer_addr()
+                        .map(|a| a.to_string())
+                        .unwrap_or_default();
+                    debug!("gRPC client {peer} connected");
+                    match H2Conn::new(stream, streams).and_then(|mut c| c.serve()) {
+                        Ok(()) => debug!("gRPC client {peer} disconnected"),
+                        Err(e) => info!("gRPC client {peer} dropped: {e}"),
+                    }
+                });
+            }
+        });
+
+        spawn_sink("grpc", move |rx| Self::run(streams, rx))
+    }
+
+    fn run(streams: Arc<Mutex<Vec<GrpcStream>>>, rx: mpsc::Receiver<Frame>) {
+        let host = hostname();
+        while let Ok(frame) = rx.recv() {
+            let mut streams = streams.lock().unwrap();
+            if streams.is_empty() {
+                continue;
+            }
+            let ts_ms = time::SystemTime::now()
+                .duration_since(time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as i64;
+            streams.retain(|s| {
+                let msg = grpc_message(&frame_proto(&frame, &host, ts_ms, &s.channels));
+                match s.writer.lock().unwrap().data(s.id, &msg, false) {
+                    Ok(true) => true,
+                    Ok(false) => {
+                        trace!("gRPC stream {} out of window, frame dropped", s.id);
+                        true
+                    }
+                    Err(e) => {
+                        info!("gRPC stream {} closed: {e}", s.id);
+                        false
+                    }
+                }
+            });
+        }
+    }
+}
+
+// a StreamSamples call in progress
+struct GrpcStream {
+    writer: Arc<Mutex<H2Writer>>,
+    id: u32,
+    // empty for all
+    channels: BTreeSet<u8>,
+}
+
+// the sending half of a connection, shared by its reader and the sink
+struct H2Writer {
+    sock: TcpStream,
+    closed: bool,
+    window: i64,
+    initial_window: i64,
+    // send windows of the open streams we answer
+    windows: HashMap<u32, i64>,
+    max_frame: usize,
+}
+
+impl H2Writer {
+    fn frame(&mut self, kind: u8, flags: u8, id: u32, payload: &[u8]) -> io::Result<()> {
+        let mut buf = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+        buf.push(kind);
+        buf.push(flags);
+        buf.extend(id.to_be_bytes());
+        buf.extend(payload);
+        self.sock.write_all(&buf)
+    }
+
+    fn headers(&mut self, id: u32, fields: &[(&str, &str)], end_stream: bool) -> io::Result<()> {
+        let flags = H2_FLAG_END_HEADERS | if end_stream { H2_FLAG_END_STREAM } else { 0 };
+        if end_stream {
+            self.windows.remove(&id);
+        }
+        self.frame(H2_HEADERS, flags, id, &hpack::hpack_encode(fields))
+    }
+
+    // false when the flow control windows do not let it through
+    fn data(&mut self, id: u32, data: &[u8], end_stream: bool) -> anyhow::Result<bool> {
+        if self.closed {
+            bail!("connection closed");
+        }
+        let stream_window = *self
+            .windows
+            .get(&id)
+            .ok_or_else(|| anyhow!("stream reset"))?;
+        let len = data.len() as i64;
+        if len > self.window.min(stream_window) || data.len() > self.max_frame {
+            return Ok(false);
+        }
+        self.window -= len;
+        self.windows.insert(id, stream_window - len);
+        let flags = if end_stream { H2_FLAG_END_STREAM } else { 0 };
+        self.frame(H2_DATA, flags, id, data)?;
+        Ok(true)
+    }
+
+    fn open(&mut self, id: u32) {
+        self.windows.insert(id, self.initial_window);
+    }
+}
+
+// a request being received
+#[derive(Default)]
+struct H2Request {
+    path: String,
+    body: Vec<u8>,
+}
+
+struct H2Conn {
+    sock: TcpStream,
+    writer: Arc<Mutex<H2Writer>>,
+    streams: Arc<Mutex<Vec<GrpcStream>>>,
+    decoder: hpack::HpackDecoder,
+    requests: HashMap<u32, H2Request>,
+}
+
+impl H2Conn {
+    fn new(sock: TcpStream, streams: Arc<Mutex<Vec<GrpcStream>>>) -> anyhow::Result<Self> {
+        sock.set_write_timeout(Some(GRPC_WRITE_TIMEOUT))?;
+        let writer = H2Writer {
+            sock: sock.try_clone()?,
+            closed: false,
+            window: H2_DEFAULT_WINDOW,
+            initial_window: H2_DEFAULT_WINDOW,
+            windows: HashMap::new(),
+            max_frame: H2_MAX_FRAME,
+        };
+        Ok(Self {
+            sock,
+            writer: Arc::new(Mutex::new(writer)),
+            streams,
+            decoder: hpack::HpackDecoder::new(),
+            requests: HashMap::new(),
+        })
+    }
+
+    fn serve(&mut self) -> anyhow::Result<()> {
+        let res = self.exchange();
+        // the sink drops the streams of a connection gone
+        let mut writer = self.writer.lock().unwrap();
+        writer.closed = true;
+        if let Err(e) = &res {
+            let code = match e.downcast_ref::<H2Error>() {
+                Some(H2Error(code)) => *code,
+                None => H2_PROTOCOL_ERROR,
+            };
+            let mut goaway = 0u32.to_be_bytes().to_vec();
+            goaway.extend(code.to_be_bytes());
+            let _ = writer.frame(H2_GOAWAY, 0, 0, &goaway);
+        }
+        res
+    }
+
+    fn exchange(&mut self) -> anyhow::Result<()> {
+        self.sock
+            .set_read_timeout(Some(time::Duration::from_secs(5)))?;
+        let mut preface = [0u8; 24];
+        self.sock.read_exact(&mut preface)?;
+        if preface != H2_PREFACE {
+            bail!("Not an HTTP/2 client, gRPC needs h2c with prior knowledge");
+        }
+        self.sock.set_read_timeout(None)?;
+        self.writer.lock().unwrap().frame(H2_SETTINGS, 0, 0, &[])?;
+
+        // a header block split into CONTINUATION frames
+        let mut block: Option<(u32, u8, Vec<u8>)> = None;
+        loop {
+            let mut head = [0u8; 9];
+            match self.sock.read_exact(&mut head) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+            let len = u32::from_be_bytes([0, head[0], head[1], head[2]]) as usize;
+            let (kind, flags) = (head[3], head[4]);
+            let id = u32::from_be_bytes([head[5], head[6], head[7], head[8]]) & 0x7fff_ffff;
+            if len > H2_MAX_FRAME {
+                return Err(H2Error(H2_FRAME_SIZE_ERROR).into());
+            }
+            let mut payload = vec![0u8; len];
+            self.sock.read_exact(&mut payload)?;
+
+            if let Some((block_id, block_flags, mut fragment)) = block.take() {
+                if kind != H2_CONTINUATION || id != block_id {
+                    return Err(H2Error(H2_PROTOCOL_ERROR).into());
+                }
+                fragment.extend(payload);
+                match flags & H2_FLAG_END_HEADERS {
+                    0 => block = Some((block_id, block_flags, fragment)),
+                    _ => self.on_headers(block_id, block_flags, &fragment)?,
+                }
+                continue;
+            }
+
+            match kind {
+                H2_SETTINGS if flags & H2_FLAG_ACK == 0 => self.on_settings(&payload)?,
+                H2_PING if flags & H2_FLAG_ACK == 0 => {
+                    self.writer
+                        .lock()
+                        .unwrap()
+                        .frame(H2_PING, H2_FLAG_ACK, 0, &payload)?;
+                }
+                H2_WINDOW_UPDATE if payload.len() == 4 => {
+                    let inc = u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]])
+                        & 0x7fff_ffff;
+                    let mut writer = self.writer.lock().unwrap();
+                    match id {
+                        0 => writer.window += inc as i64,
+                        _ => {
+                            if let Some(w) = writer.windows.get_mut(&id) {
+                                *w += inc as i64;
+                            }
+                        }
+                    }
+                }
+                H2_HEADERS => {
+                    let fragment = unpad(flags, &payload)?;
+                    let fragment = match flags & H2_FLAG_PRIORITY {
+                        0 => fragment,
+                        _ => fragment.get(5..).ok_or(H2Error(H2_PROTOCOL_ERROR))?,
+                    };
+                    match flags & H2_FLAG_END_HEADERS {
+                        0 => block = Some((id, flags, fragment.to_vec())),
+                        _ => self.on_headers(id, flags, fragment)?,
+                    }
+                }
+                H2_DATA => self.on_data(id, flags, &payload)?,
+                H2_RST_STREAM => {
+                    self.requests.remove(&id);
+                    self.writer.lock().unwrap().windows.remove(&id);
+                }
+                H2_GOAWAY => return Ok(()),
+                // PRIORITY, acks and the rest need nothing
+                _ => {}
+            }
+        }
+    }
+
+    fn on_settings(&mut self, payload: &[u8]) -> anyhow::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        for setting in payload.chunks_exact(6) {
+            let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+            match u16::from_be_bytes([setting[0], setting[1]]) {
+                H2_SETTINGS_INITIAL_WINDOW_SIZE => {
+                    // RFC 7540 6.9.2, the open streams move by the difference
+                    let delta = value as i64 - writer.initial_window;
+                    writer.initial_window = value as i64;
+                    for w in writer.windows.values_mut() {
+                        *w += delta;
+                    }
+                }
+                H2_SETTINGS_MAX_FRAME_SIZE => writer.max_frame = value as usize,
+                _ => {}
+            }
+        }
+        writer.frame(H2_SETTINGS, H2_FLAG_ACK, 0, &[])?;
+        Ok(())
+    }
+
+    fn on_headers(&mut self, id: u32, flags: u8, block: &[u8]) -> anyhow::Result<()> {
+        // decoded even for a stream we do not serve, to keep the table in sync
+        let fields = self.decoder.decode(block)?;
+        let path = fields
+            .iter()
+            .find(|(name, _)| name == ":path")
+            .map(|(_, value)| value.clone())
+            .unwrap_or_default();
+        self.requests.insert(
+            id,
+            H2Request {
+                path,
+                body: Vec::new(),
+            },
+        );
+        if flags & H2_FLAG_END_STREAM != 0 {
+            self.respond(id)?;
+        }
+        Ok(())
+    }
+
+    fn on_data(&mut self, id: u32, flags: u8, payload: &[u8]) -> anyhow::Result<()> {
+        // hand the window back at once, the bodies are small and taken whole
+        if !payload.is_empty() {
+            let inc = (payload.len() as u32).to_be_bytes();
+            let mut writer = self.writer.lock().unwrap();
+            writer.frame(H2_WINDOW_UPDATE, 0, 0, &inc)?;
+            if flags & H2_FLAG_END_STREAM == 0 {
+                writer.frame(H2_WINDOW_UPDATE, 0, id, &inc)?;
+            }
+        }
+        let data = unpad(flags, payload)?;
+        if let Some(req) = self.requests.get_mut(&id) {
+            req.body.extend(data);
+            if req.body.len() > GRPC_MAX_REQUEST {
+                return Err(H2Error(H2_PROTOCOL_ERROR).into());
+            }
+        }
+        if flags & H2_FLAG_END_STREAM != 0 {
+            self.respond(id)?;
+        }
+        Ok(())
+    }
+
+    // the client has sent all of its request
+    fn respond(&mut self, id: u32) -> anyhow::Result<()> {
+        let Some(req) = self.requests.remove(&id) else {
+            return Ok(());
+        };
+        let mut writer = self.writer.lock().unwrap();
+        writer.open(id);
+        let msg = match grpc_unwrap(&req.body) {
+            Ok(msg) => msg,
+            Err(e) => return grpc_status(&mut writer, id, GRPC_INVALID_ARGUMENT, &e.to_string()),
+        };
+
+        match req.path.as_str() {
+            GRPC_STREAM_SAMPLES => {
+                let channels = match stream_request_proto(msg) {
+                    Ok(channels) => channels,
+                    Err(e) => {
+                        return grpc_status(&mut writer, id, GRPC_INVALID_ARGUMENT, &e.to_string())
+                    }
+                };
+                writer.headers(id, &GRPC_RESPONSE_HEADERS, false)?;
+                info!("gRPC: streaming samples on stream {id}");
+                self.streams.lock().unwrap().push(GrpcStream {
+                    writer: self.writer.clone(),
+                    id,
+                    channels,
+                });
+                Ok(())
+            }
+            GRPC_SET_OVERRIDE => {
+                let (ch, gauge) = match override_proto(msg) {
+                    Ok(o) => o,
+                    Err(e) => {
+                        return grpc_status(&mut writer, id, GRPC_INVALID_ARGUMENT, &e.to_string())
+                    }
+                };
+                match gauge {
+                    Some(gauge) => info!("gRPC: channel {ch} pinned to {gauge:.0}"),
+                    None => info!("gRPC: channel {ch} released"),
+                }
+                set_override(ch, gauge);
+                writer.headers(id, &GRPC_RESPONSE_HEADERS, false)?;
+                // an empty OverrideReply
+                if !writer.data(id, &grpc_message(&[]), false)? {
+                    return grpc_trailers(&mut writer, id, GRPC_RESOURCE_EXHAUSTED, "");
+                }
+                grpc_trailers(&mut writer, id, GRPC_OK, "")
+            }
+            path => grpc_status(
+                &mut writer,
+                id,
+                GRPC_UNIMPLEMENTED,
+                &format!("Unknown method {path}"),
+            ),
+        }
+    }
+}
+
+// an HTTP/2 error code for the GOAWAY
+#[derive(Debug)]
+struct H2Error(u32);
+
+impl std::fmt::Display for H2Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "HTTP/2 error 0x{:x}", self.0)
+    }
+}
+
+impl std::error::Error for H2Error {}
+
+const GRPC_RESPONSE_HEADERS: [(&str, &str); 2] =
+    [(":status", "200"), ("content-type", "application/grpc")];
+
+// the status alone, for a call that failed before anything was sent
+fn grpc_status(writer: &mut H2Writer, id: u32, status: u32, message: &str) -> anyhow::Result<()> {
+    debug!("gRPC stream {id}: status {status} {message}");
+    let status = status.to_string();
+    let mut fields = GRPC_RESPONSE_HEADERS.to_vec();
+    fields.push(("grpc-status", &status));
+    fields.push(("grpc-message", message));
+    writer.headers(id, &fields, true)?;
+    Ok(())
+}
+
+fn grpc_trailers(writer: &mut H2Writer, id: u32, status: u32, message: &str) -> anyhow::Result<()> {
+    let status = status.to_string();
+    let mut fields = vec![("grpc-status", status.as_str())];
+    if !message.is_empty() {
+        fields.push(("grpc-message", message));
+    }
+    writer.headers(id, &fields, true)?;
+    Ok(())
+}
+
+fn unpad(flags: u8, payload: &[u8]) -> anyhow::Result<&[u8]> {
+    if flags & H2_FLAG_PADDED == 0 {
+        return Ok(payload);
+    }
+    let (&pad, rest) = payload.split_first().ok_or(H2Error(H2_PROTOCOL_ERROR))?;
+    rest.get(..rest.len().saturating_sub(pad as usize))
+        .filter(|_| pad as usize <= rest.len())
+        .ok_or_else(|| H2Error(H2_PROTOCOL_ERROR).into())
+}
+
+// gRPC length-prefixed message, never compressed
+fn grpc_message(msg: &[u8]) -> Vec<u8> {
+    let mut buf = vec![0];
+    buf.extend((msg.len() as u32).to_be_bytes());
+    buf.extend(msg);
+    buf
+}
+
+// the one message of a request body
+fn grpc_unwrap(body: &[u8]) -> anyhow::Result<&[u8]> {
+    match body {
+        [] => Ok(&[]),
+        [0, l0, l1, l2, l3, msg @ ..] => {
+            let len = u32::from_be_bytes([*l0, *l1, *l2, *l3]) as usize;
+            msg.get(..len)
+                .ok_or_else(|| anyhow!("Truncated gRPC message"))
+        }
+        [1, ..] => bail!("Compressed gRPC messages are not supported"),
+        _ => bail!("Invalid gRPC message"),
+    }
+}
+
+// Frame { host = 1; ts_ms = 2; repeated Sample samples = 3 }
+// Sample { channel = 1; gauge = 2; optional raw = 3; source = 4 }
+fn frame_proto(frame: &Frame, host: &str, ts_ms: i64, channels: &BTreeSet<u8>) -> Vec<u8> {
+    let mut msg = Vec::new();
+    pb_bytes(&mut msg, 1, host.as_bytes());
+    pb_varint_field(&mut msg, 2, ts_ms as u64);
+    for (ch, sample) in frame.iter() {
+        if !channels.is_empty() && !channels.contains(ch) {
+            continue;
+        }
+        let mut s = Vec::new();
+        pb_varint_field(&mut s, 1, *ch as u64);
+        pb_double(&mut s, 2, sample.value.clamp(0.0, 255.0));
+        if let Some(raw) = sample.raw.filter(|r| r.is_finite()) {
+            pb_double(&mut s, 3, raw);
+        }
+        pb_bytes(&mut s, 4, sample.source.as_bytes());
+        pb_bytes(&mut msg, 3, &s);
+    }
+    msg
+}
+
+// StreamRequest { repeated uint32 channels = 1 }, packed or not
+fn stream_request_proto(msg: &[u8]) -> anyhow::Result<BTreeSet<u8>> {
+    let mut channels = BTreeSet::new();
+    for field in PbFields(msg) {
+        match field? {
+            (1, PbValue::Varint(ch)) => {
+                channels.insert(pb_channel(ch)?);
+            }
+            (1, PbValue::Bytes(mut packed)) => {
+                while !packed.is_empty() {
+                    channels.insert(pb_channel(pb_read_varint(&mut packed)?)?);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(channels)
+}
+
+// Override { channel = 1; gauge = 2; clear = 3 }, the gauge None when cleared
+fn override_proto(msg: &[u8]) -> anyhow::Result<(u8, Option<f64>)> {
+    let (mut ch, mut gauge, mut clear) = (0, 0.0, false);
+    for field in PbFields(msg) {
+        match field? {
+            (1, PbValue::Varint(v)) => ch = pb_channel(v)?,
+            (2, PbValue::Fixed64(v)) => gauge = f64::from_bits(v),
+            (3, PbValue::Varint(v)) => clear = v != 0,
+            _ => {}
+        }
+    }
+    if !gauge.is_finite() {
+        bail!("Gauge {gauge} is not a number");
+    }
+    Ok((ch, (!clear).then_some(gauge.clamp(0.0, 255.0))))
+}
+
+fn pb_channel(v: u64) -> anyhow::Result<u8> {
+    match v {
+        ch if ch < CHANNELS_NUM as u64 => Ok(ch as u8),
+        ch => bail!(
+            "Channel number too large: {ch} (maximum {})",
+            CHANNELS_NUM - 1
+        ),
+    }
+}
+
+fn pb_varint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+fn pb_varint_field(buf: &mut Vec<u8>, field: u64, v: u64) {
+    pb_varint(buf, field << 3);
+    pb_varint(buf, v);
+}
+
+fn pb_double(buf: &mut Vec<u8>, field: u64, v: f64) {
+    pb_varint(buf, field << 3 | 1);
+    buf.extend(v.to_le_bytes());
+}
+
+fn pb_bytes(buf: &mut Vec<u8>, field: u64, v: &[u8]) {
+    pb_varint(buf, field << 3 | 2);
+    pb_varint(buf, v.len() as u64);
+    buf.extend(v);
+}
+
+fn pb_read_varint(buf: &mut &[u8]) -> anyhow::Result<u64> {
+    let mut v = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&b, rest) = buf
+            .split_first()
+            .ok_or_else(|| anyhow!("Truncated protobuf varint"))?;
+        *buf = rest;
+        v |= ((b & 0x7f) as u64) << shift;
+        if b & 0x80 == 0 {
+            return Ok(v);
+        }
+    }
+    bail!("Protobuf varint too long")
+}
+
+enum PbValue<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32,
+}
+
+// the fields of a protobuf message as (number, value)
+struct PbFields<'a>(&'a [u8]);
+
+impl<'a> Iterator for PbFields<'a> {
+    type Item = anyhow::Result<(u64, PbValue<'a>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.0.is_empty() {
+            return None;
+        }
+        let field = self.field();
+        if field.is_err() {
+            // no way to find the next field
+            self.0 = &[];
+        }
+        Some(field)
+    }
+}
+
+impl<'a> PbFields<'a> {
+    fn field(&mut self) -> anyhow::Result<(u64, PbValue<'a>)> {
+        let key = pb_read_varint(&mut self.0)?;
+        let value = match key & 7 {
+            0 => PbValue::Varint(pb_read_varint(&mut self.0)?),
+            1 => {
+                let (v, rest) = self
+                    .0
+                    .split_first_chunk::<8>()
+                    .ok_or_else(|| anyhow!("Truncated protobuf field"))?;
+                self.0 = rest;
+                PbValue::Fixed64(u64::from_le_bytes(*v))
+            }
+            2 => {
+                let len = pb_read_varint(&mut self.0)? as usize;
+                if len > self.0.len() {
+                    bail!("Truncated protobuf field");
+                }
+                let (v, rest) = self.0.split_at(len);
+                self.0 = rest;
+                PbValue::Bytes(v)
+            }
+            5 => {
+                self.0 = self
+                    .0
+                    .get(4..)
+                    .ok_or_else(|| anyhow!("Truncated protobuf field"))?;
+                PbValue::Fixed32
+            }
+            wire => bail!("Unsupported protobuf wire type {wire}"),
+        };
+        Ok((key >> 3, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send(sock: &mut TcpStream, kind: u8, flags: u8, id: u32, payload: &[u8]) {
+        let mut buf = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+        buf.extend([kind, flags]);
+        buf.extend(id.to_be_bytes());
+        buf.extend(payload);
+        sock.write_all(&buf).unwrap();
+    }
+
+    // the next frame of a stream, the connection level ones skipped
+    fn recv(sock: &mut TcpStream) -> (u8, u8, u32, Vec<u8>) {
+        loop {
+            let mut head = [0u8; 9];
+            sock.read_exact(&mut head).unwrap();
+            let len = u32::from_be_bytes([0, head[0], head[1], head[2]]) as usize;
+            let id = u32::from_be_bytes([head[5], head[6], head[7], head[8]]);
+            let mut payload = vec![0u8; len];
+            sock.read_exact(&mut payload).unwrap();
+            if id != 0 && head[3] != H2_WINDOW_UPDATE {
+                return (head[3], head[4], id, payload);
+            }
+        }
+    }
+
+    fn call(sock: &mut TcpStream, id: u32, path: &str, msg: &[u8]) {
+        let headers = hpack::hpack_encode(&[
+            (":method", "POST"),
+            (":scheme", "http"),
+            (":path", path),
+            (":authority", "localhost"),
+            ("content-type", "application/grpc"),
+            ("te", "trailers"),
+        ]);
+        send(sock, H2_HEADERS, H2_FLAG_END_HEADERS, id, &headers);
+        send(sock, H2_DATA, H2_FLAG_END_STREAM, id, &grpc_message(msg));
+    }
+
+    fn header(fields: &[(String, String)], name: &str) -> String {
+        fields
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.clone())
+            .unwrap_or_default()
+    }
+
+    // a client over loopback, the way grpc-go talks h2c
+    #[test]
+    fn calls_over_http2() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let streams = Arc::new(Mutex::new(Vec::new()));
+        let accepted = streams.clone();
+        thread::spawn(move || {
+            let (sock, _) = listener.accept().unwrap();
+            let _ = H2Conn::new(sock, accepted).and_then(|mut c| c.serve());
+        });
+        let mut sink = spawn_sink("grpc-test", move |rx| GrpcSink::run(streams, rx)).unwrap();
+
+        let mut sock = TcpStream::connect(addr).unwrap();
+        sock.set_read_timeout(Some(time::Duration::from_secs(5)))
+            .unwrap();
+        sock.write_all(H2_PREFACE).unwrap();
+        send(&mut sock, H2_SETTINGS, 0, 0, &[]);
+        let mut decoder = hpack::HpackDecoder::new();
+
+        // Override { channel = 190; clear = true }
+        let mut msg = Vec::new();
+        pb_varint_field(&mut msg, 1, 190);
+        pb_varint_field(&mut msg, 3, 1);
+        call(&mut sock, 1, GRPC_SET_OVERRIDE, &msg);
+        let (kind, flags, id, block) = recv(&mut sock);
+        assert_eq!((kind, flags & H2_FLAG_END_STREAM, id), (H2_HEADERS, 0, 1));
+        let fields = decoder.decode(&block).unwrap();
+        assert_eq!(header(&fields, ":status"), "200");
+        assert_eq!(header(&fields, "content-type"), "application/grpc");
+        assert_eq!(recv(&mut sock), (H2_DATA, 0, 1, grpc_message(&[])));
+        let (kind, flags, _, block) = recv(&mut sock);
+        assert_eq!(
+            (kind, flags & H2_FLAG_END_STREAM),
+            (H2_HEADERS, H2_FLAG_END_STREAM)
+        );
+        assert_eq!(header(&decoder.decode(&block).unwrap(), "grpc-status"), "0");
+
+        let mut msg = Vec::new();
+        pb_varint_field(&mut msg, 1, 200);
+        call(&mut sock, 3, GRPC_SET_OVERRIDE, &msg);
+        let (_, flags, id, block) = recv(&mut sock);
+        assert_eq!((flags & H2_FLAG_END_STREAM, id), (H2_FLAG_END_STREAM, 3));
+        assert_eq!(header(&decoder.decode(&block).unwrap(), "grpc-status"), "3");
+
+        call(&mut sock, 5, "/perf_vumeter.Meter/Nope", &[]);
+        let (_, _, id, block) = recv(&mut sock);
+        assert_eq!(id, 5);
+        assert_eq!(
+            header(&decoder.decode(&block).unwrap(), "grpc-status"),
+            "12"
+        );
+
+        // StreamRequest { channels = [190] } packed
+        let mut msg = Vec::new();
+        pb_bytes(&mut msg, 1, &[190, 1]);
+        call(&mut sock, 7, GRPC_STREAM_SAMPLES, &msg);
+        let (kind, _, id, block) = recv(&mut sock);
+        assert_eq!((kind, id), (H2_HEADERS, 7));
+        assert_eq!(header(&decoder.decode(&block).unwrap(), ":status"), "200");
+        let mut frame = Frame::new();
+        frame.insert(1, Sample::new(10.0));
+        frame.insert(190, Sample::new(300.0).raw(42.5).source("cpu"));
+        sink.frame(&frame);
+        let (kind, _, id, data) = recv(&mut sock);
+        assert_eq!((kind, id), (H2_DATA, 7));
+        let msg = grpc_unwrap(&data).unwrap();
+        let mut samples = Vec::new();
+        for field in PbFields(msg) {
+            if let (3, PbValue::Bytes(s)) = field.unwrap() {
+                samples.push(s.to_vec());
+            }
+        }
+        let mut expected = Vec::new();
+        pb_varint_field(&mut expected, 1, 190);
+        pb_double(&mut expected, 2, 255.0);
+        pb_double(&mut expected, 3, 42.5);
+        pb_bytes(&mut expected, 4, b"cpu");
+        assert_eq!(samples, [expected]);
+    }
+
+    #[test]
+    fn bad_messages() {
+        assert!(grpc_unwrap(&[1, 0, 0, 0, 0]).is_err());
+        assert!(grpc_unwrap(&[0, 0, 0, 0, 9, 1]).is_err());
+        assert!(override_proto(&[0x08]).is_err());
+        assert!(stream_request_proto(&[0x0a, 0x02, 0xc0]).is_err());
+        assert!(unpad(H2_FLAG_PADDED, &[4, 1, 2]).is_err());
+        assert_eq!(unpad(H2_FLAG_PADDED, &[1, 7, 0]).unwrap(), [7]);
+    }
+}
+
+// EOF
@@ -0,0 +1,49 @@
+// heartbeat.rs
+
+use std::{f64::consts::PI, time};
+
+use crate::*;
+
+// Generates a slow pulse so that a glance at the panel tells the daemon is alive.
+// The first quarter of every period is a half-sine bump, the rest is flat zero.
+#[derive(Debug)]
+pub struct Heartbeat {
+    period: time::Duration,
+    level: f64,
+    start: time::Instant,
+}
+
+impl Heartbeat {
+    pub fn new(period: time::Duration, level: f64) -> Self {
+        Self {
+            period,
+            level,
+            start: time::Instant::now(),
+        }
+    }
+
+    pub fn gauge(&self) -> f64 {
+        let period = self.period.as_secs_f64();
+        let phase = (self.start.elapsed().as_secs_f64() % period) / period;
+        if phase < 0.25 {
+            self.level * (phase * 4.0 * PI).sin()
+        } else {
+            0.0
+        }
+    }
+}
+
+// already a gauge, 256 is the full scale
+impl StatSource for Heartbeat {
+    fn sample(&mut self) -> anyhow::Result<f64> {
+        Ok(self.gauge())
+    }
+    fn name(&self) -> &'static str {
+        "heartbeat"
+    }
+    fn unit(&self) -> &'static str {
+        ""
+    }
+}
+
+// EOF
@@ -0,0 +1,206 @@
+// history.rs
+
+use std::{sync::mpsc, time};
+
+use anyhow::{anyhow, bail};
+use rusqlite::{params, Connection};
+
+use crate::*;
+
+const HISTORY_FLUSH: time::Duration = time::Duration::from_secs(10);
+const HISTORY_PRUNE: time::Duration = time::Duration::from_secs(3600);
+// rows kept for the next flush while the database fails
+const HISTORY_BACKLOG: usize = 100_000;
+const HISTORY_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS samples \
+    (ts REAL NOT NULL, channel INTEGER NOT NULL, source TEXT, gauge REAL, raw REAL);\n\
+    CREATE INDEX IF NOT EXISTS samples_ts ON samples (ts);\n";
+
+// one sample: ts, channel, source, gauge and raw value
+type Row = (f64, u8, &'static str, f64, Option<f64>);
+
+// Keeps the samples in an SQLite database, inserted in one transaction
+// per flush and pruned to the retention period.
+pub struct HistorySink;
+
+impl HistorySink {
+    pub fn spawn<S: AsRef<str>>(db: S, retention_days: f64) -> anyhow::Result<ThreadedSink> {
+        let db = db.as_ref().to_string();
+        let conn = Connection::open(&db).map_err(|e| anyhow!("History {db}: {e}"))?;
+        conn.execute_batch(HISTORY_SCHEMA)
+            .map_err(|e| anyhow!("History {db}: {e}"))?;
+        info!("Keeping {retention_days} days of history in {db}");
+        spawn_sink("history", move |rx| Self::run(db, conn, retention_days, rx))
+    }
+
+    fn run(db: String, mut conn: Connection, retention_days: f64, rx: mpsc::Receiver<Frame>) {
+        let mut rows: Vec<Row> = Vec::new();
+        let mut next_flush = time::Instant::now() + HISTORY_FLUSH;
+        let mut next_prune = time::Instant::now();
+
+        loop {
+            let timeout = next_flush.saturating_duration_since(time::Instant::now());
+            match rx.recv_timeout(timeout) {
+                Ok(frame) => {
+                    let now = unix_now();
+                    rows.extend(frame.iter().map(|(ch, sample)| {
+                        (
+                            now - sample.ts.elapsed().as_secs_f64(),
+                            *ch,
+                            sample.source,
+                            sample.value.clamp(0.0, 255.0),
+                            sample.raw.filter(|r| r.is_finite()),
+                        )
+                    }));
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => return,
+            }
+            if time::Instant::now() < next_flush {
+                continue;
+            }
+            next_flush = time::Instant::now() + HISTORY_FLUSH;
+
+            let prune = match time::Instant::now() >= next_prune {
+                true => {
+                    next_prune = time::Instant::now() + HISTORY_PRUNE;
+                    Some(unix_now() - retention_days * 86400.0)
+                }
+                false => None,
+            };
+            if rows.is_empty() && prune.is_none() {
+                continue;
+            }
+            match Self::flush(&mut conn, &rows, prune) {
+                Ok(()) => rows.clear(),
+                Err(e) => {
+                    error!("History {db}: {e}");
+                    count_error("history");
+                    // keep the batch for the next round unless it gets out of hand
+                    if rows.len() > HISTORY_BACKLOG {
+                        rows.clear();
+                    }
+                }
+            }
+        }
+    }
+
+    fn flush(conn: &mut Connection, rows: &[Row], prune: Option<f64>) -> rusqlite::Result<()> {
+        let tx = conn.transaction()?;
+        {
+            let mut insert =
+                tx.prepare_cached("INSERT INTO samples VALUES (?1, ?2, ?3, ?4, ?5)")?;
+            for (ts, ch, source, gauge, raw) in rows {
+                insert.execute(params![ts, ch, source, gauge, raw])?;
+            }
+        }
+        if let Some(before) = prune {
+            tx.execute("DELETE FROM samples WHERE ts < ?1", [before])?;
+        }
+        tx.commit()
+    }
+}
+
+// Print per-minute averages of the recorded channels, newest last
+pub fn history_query(db: &str, channel: Option<u8>, since: &str) -> anyhow::Result<()> {
+    let since = unix_now() - parse_age(since)?;
+    let conn = Connection::open_with_flags(db, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| anyhow!("History {db}: {e}"))?;
+    let mut query = conn.prepare(
+        "SELECT strftime('%Y-%m-%d %H:%M', ts, 'unixepoch', 'localtime') AS minute, \
+         channel, source, round(avg(gauge)) AS gauge, \
+         round(avg(raw), 3) AS raw_avg, round(max(raw), 3) AS raw_max \
+         FROM samples WHERE ts >= ?1 AND (?2 IS NULL OR channel = ?2) \
+         GROUP BY minute, channel ORDER BY minute, channel",
+    )?;
+    let mut rows = query.query(params![since, channel])?;
+    let num = |v: Option<f64>| v.map_or(String::new(), |v| v.to_string());
+    println!(
+        "{:<16}  {:>7}  {:<12}  {:>5}  {:>10}  {:>10}",
+        "minute", "channel", "source", "gauge", "raw_avg", "raw_max"
+    );
+    while let Some(row) = rows.next()? {
+        println!(
+            "{:<16}  {:>7}  {:<12}  {:>5}  {:>10}  {:>10}",
+            row.get::<_, String>(0)?,
+            row.get::<_, u8>(1)?,
+            row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+            num(row.get(3)?),
+            num(row.get(4)?),
+            num(row.get(5)?),
+        );
+    }
+    Ok(())
+}
+
+// "90s", "15m", "2h", "7d", plain numbers are seconds
+pub fn parse_age(s: &str) -> anyhow::Result<f64> {
+    let (num, mult) = match s.char_indices().last() {
+        Some((i, 's')) => (&s[..i], 1.0),
+        Some((i, 'm')) => (&s[..i], 60.0),
+        Some((i, 'h')) => (&s[..i], 3600.0),
+        Some((i, 'd')) => (&s[..i], 86400.0),
+        _ => (s, 1.0),
+    };
+    let age = num
+        .parse::<f64>()
+        .map_err(|e| anyhow!("Invalid age {s}: {e}"))?
+        * mult;
+    if !age.is_finite() || age <= 0.0 {
+        bail!("Invalid age {s}: must be a positive number");
+    }
+    Ok(age)
+}
+
+fn unix_now() -> f64 {
+    time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ages() {
+        assert_eq!(parse_age("90").unwrap(), 90.0);
+        assert_eq!(parse_age("90s").unwrap(), 90.0);
+        assert_eq!(parse_age("15m").unwrap(), 900.0);
+        assert_eq!(parse_age("1.5h").unwrap(), 5400.0);
+        assert_eq!(parse_age("7d").unwrap(), 604800.0);
+        for bad in [
+            "", "h", "x", "10w", "0", "0s", "-5m", "NaN", "nanm", "inf", "infd", "1e400",
+        ] {
+            assert!(parse_age(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn flush_and_prune() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(HISTORY_SCHEMA).unwrap();
+        let rows = [
+            (100.0, 1, "cpu", 128.0, Some(50.0)),
+            (200.0, 1, "cpu", 64.0, None),
+            (200.0, 2, "it's", 0.0, Some(1.5)),
+        ];
+        HistorySink::flush(&mut conn, &rows, None).unwrap();
+        let count = |conn: &Connection| {
+            conn.query_row("SELECT count(*) FROM samples", [], |r| r.get::<_, i64>(0))
+                .unwrap()
+        };
+        assert_eq!(count(&conn), 3);
+        let source: String = conn
+            .query_row("SELECT source FROM samples WHERE channel = 2", [], |r| {
+                r.get(0)
+            })
+            .unwrap();
+        assert_eq!(source, "it's");
+
+        HistorySink::flush(&mut conn, &[], Some(150.0)).unwrap();
+        assert_eq!(count(&conn), 2);
+    }
+}
+
+// EOF
@@ -0,0 +1,509 @@
+// hpack.rs
+
+// RFC 7541 header compression for the HTTP/2 of the gRPC service. Decoding is
+// complete, dynamic table and Huffman strings included, as clients use both.
+// Encoding sticks to the static table and plain literals, which every peer takes.
+
+use std::collections::VecDeque;
+
+use anyhow::{anyhow, bail};
+
+// what we allow the peer's encoder, the default of RFC 7540 6.5.2
+const HPACK_TABLE_SIZE: usize = 4096;
+// RFC 7541 4.1
+const HPACK_ENTRY_OVERHEAD: usize = 32;
+
+#[rustfmt::skip]
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+// RFC 7541 appendix B, code and bit length of every byte and EOS
+#[rustfmt::skip]
+const HUFFMAN_CODES: [(u32, u8); 257] = [
+    (0x1ff8, 13), (0x7fffd8, 23), (0xfffffe2, 28), (0xfffffe3, 28), (0xfffffe4, 28),
+    (0xfffffe5, 28), (0xfffffe6, 28), (0xfffffe7, 28), (0xfffffe8, 28), (0xffffea, 24),
+    (0x3ffffffc, 30), (0xfffffe9, 28), (0xfffffea, 28), (0x3ffffffd, 30), (0xfffffeb, 28),
+    (0xfffffec, 28), (0xfffffed, 28), (0xfffffee, 28), (0xfffffef, 28), (0xffffff0, 28),
+    (0xffffff1, 28), (0xffffff2, 28), (0x3ffffffe, 30), (0xffffff3, 28), (0xffffff4, 28),
+    (0xffffff5, 28), (0xffffff6, 28), (0xffffff7, 28), (0xffffff8, 28), (0xffffff9, 28),
+    (0xffffffa, 28), (0xffffffb, 28), (0x14, 6), (0x3f8, 10), (0x3f9, 10), (0xffa, 12),
+    (0x1ff9, 13), (0x15, 6), (0xf8, 8), (0x7fa, 11), (0x3fa, 10), (0x3fb, 10), (0xf9, 8),
+    (0x7fb, 11), (0xfa, 8), (0x16, 6), (0x17, 6), (0x18, 6), (0x0, 5), (0x1, 5), (0x2, 5),
+    (0x19, 6), (0x1a, 6), (0x1b, 6), (0x1c, 6), (0x1d, 6), (0x1e, 6), (0x1f, 6), (0x5c, 7),
+    (0xfb, 8), (0x7ffc, 15), (0x20, 6), (0xffb, 12), (0x3fc, 10), (0x1ffa, 13), (0x21, 6),
+    (0x5d, 7), (0x5e, 7), (0x5f, 7), (0x60, 7), (0x61, 7), (0x62, 7), (0x63, 7), (0x64, 7),
+    (0x65, 7), (0x66, 7), (0x67, 7), (0x68, 7), (0x69, 7), (0x6a, 7), (0x6b, 7), (0x6c, 7),
+    (0x6d, 7), (0x6e, 7), (0x6f, 7), (0x70, 7), (0x71, 7), (0x72, 7), (0xfc, 8), (0x73, 7),
+    (0xfd, 8), (0x1ffb, 13), (0x7fff0, 19), (0x1ffc, 13), (0x3ffc, 14), (0x22, 6), (0x7ffd, 15),
+    (0x3, 5), (0x23, 6), (0x4, 5), (0x24, 6), (0x5, 5), (0x25, 6), (0x26, 6), (0x27, 6), (0x6, 5),
+    (0x74, 7), (0x75, 7), (0x28, 6), (0x29, 6), (0x2a, 6), (0x7, 5), (0x2b, 6), (0x76, 7),
+    (0x2c, 6), (0x8, 5), (0x9, 5), (0x2d, 6), (0x77, 7), (0x78, 7), (0x79, 7), (0x7a, 7), (0x7b, 7),
+    (0x7ffe, 15), (0x7fc, 11), (0x3ffd, 14), (0x1ffd, 13), (0xffffffc, 28), (0xfffe6, 20),
+    (0x3fffd2, 22), (0xfffe7, 20), (0xfffe8, 20), (0x3fffd3, 22), (0x3fffd4, 22), (0x3fffd5, 22),
+    (0x7fffd9, 23), (0x3fffd6, 22), (0x7fffda, 23), (0x7fffdb, 23), (0x7fffdc, 23), (0x7fffdd, 23),
+    (0x7fffde, 23), (0xffffeb, 24), (0x7fffdf, 23), (0xffffec, 24), (0xffffed, 24), (0x3fffd7, 22),
+    (0x7fffe0, 23), (0xffffee, 24), (0x7fffe1, 23), (0x7fffe2, 23), (0x7fffe3, 23), (0x7fffe4, 23),
+    (0x1fffdc, 21), (0x3fffd8, 22), (0x7fffe5, 23), (0x3fffd9, 22), (0x7fffe6, 23), (0x7fffe7, 23),
+    (0xffffef, 24), (0x3fffda, 22), (0x1fffdd, 21), (0xfffe9, 20), (0x3fffdb, 22), (0x3fffdc, 22),
+    (0x7fffe8, 23), (0x7fffe9, 23), (0x1fffde, 21), (0x7fffea, 23), (0x3fffdd, 22), (0x3fffde, 22),
+    (0xfffff0, 24), (0x1fffdf, 21), (0x3fffdf, 22), (0x7fffeb, 23), (0x7fffec, 23), (0x1fffe0, 21),
+    (0x1fffe1, 21), (0x3fffe0, 22), (0x1fffe2, 21), (0x7fffed, 23), (0x3fffe1, 22), (0x7fffee, 23),
+    (0x7fffef, 23), (0xfffea, 20), (0x3fffe2, 22), (0x3fffe3, 22), (0x3fffe4, 22), (0x7ffff0, 23),
+    (0x3fffe5, 22), (0x3fffe6, 22), (0x7ffff1, 23), (0x3ffffe0, 26), (0x3ffffe1, 26), (0xfffeb, 20),
+    (0x7fff1, 19), (0x3fffe7, 22), (0x7ffff2, 23), (0x3fffe8, 22), (0x1ffffec, 25), (0x3ffffe2, 26),
+    (0x3ffffe3, 26), (0x3ffffe4, 26), (0x7ffffde, 27), (0x7ffffdf, 27), (0x3ffffe5, 26),
+    (0xfffff1, 24), (0x1ffffed, 25), (0x7fff2, 19), (0x1fffe3, 21), (0x3ffffe6, 26),
+    (0x7ffffe0, 27), (0x7ffffe1, 27), (0x3ffffe7, 26), (0x7ffffe2, 27), (0xfffff2, 24),
+    (0x1fffe4, 21), (0x1fffe5, 21), (0x3ffffe8, 26), (0x3ffffe9, 26), (0xffffffd, 28),
+    (0x7ffffe3, 27), (0x7ffffe4, 27), (0x7ffffe5, 27), (0xfffec, 20), (0xfffff3, 24), (0xfffed, 20),
+    (0x1fffe6, 21), (0x3fffe9, 22), (0x1fffe7, 21), (0x1fffe8, 21), (0x7ffff3, 23), (0x3fffea, 22),
+    (0x3fffeb, 22), (0x1ffffee, 25), (0x1ffffef, 25), (0xfffff4, 24), (0xfffff5, 24),
+    (0x3ffffea, 26), (0x7ffff4, 23), (0x3ffffeb, 26), (0x7ffffe6, 27), (0x3ffffec, 26),
+    (0x3ffffed, 26), (0x7ffffe7, 27), (0x7ffffe8, 27), (0x7ffffe9, 27), (0x7ffffea, 27),
+    (0x7ffffeb, 27), (0xffffffe, 28), (0x7ffffec, 27), (0x7ffffed, 27), (0x7ffffee, 27),
+    (0x7ffffef, 27), (0x7fffff0, 27), (0x3ffffee, 26), (0x3fffffff, 30),
+];
+const HUFFMAN_EOS: usize = 256;
+
+#[derive(Debug)]
+pub(crate) struct HpackDecoder {
+    table: VecDeque<(String, String)>,
+    size: usize,
+    max_size: usize,
+}
+
+impl HpackDecoder {
+    pub fn new() -> Self {
+        Self {
+            table: VecDeque::new(),
+            size: 0,
+            max_size: HPACK_TABLE_SIZE,
+        }
+    }
+
+    // the fields of a complete header block, in order
+    pub fn decode(&mut self, mut block: &[u8]) -> anyhow::Result<Vec<(String, String)>> {
+        let mut fields = Vec::new();
+        while let Some(&b) = block.first() {
+            if b & 0x80 != 0 {
+                let index = read_int(&mut block, 7)?;
+                fields.push(self.get(index)?);
+            } else if b & 0xc0 == 0x40 {
+                let field = self.literal(&mut block, 6)?;
+                self.insert(field.clone());
+                fields.push(field);
+            } else if b & 0xe0 == 0x20 {
+                let size = read_int(&mut block, 5)?;
+                if size > HPACK_TABLE_SIZE {
+                    bail!("HPACK table size {size} over the allowed {HPACK_TABLE_SIZE}");
+                }
+                self.max_size = size;
+                self.evict();
+            } else {
+                // without indexing or never indexed
+                fields.push(self.literal(&mut block, 4)?);
+            }
+        }
+        Ok(fields)
+    }
+
+    fn get(&self, index: usize) -> anyhow::Result<(String, String)> {
+        match index {
+            0 => bail!("HPACK index 0"),
+            1..=61 => {
+                let (name, value) = STATIC_TABLE[index - 1];
+                Ok((name.to_string(), value.to_string()))
+            }
+            _ => self
+                .table
+                .get(index - 62)
+                .cloned()
+                .ok_or_else(|| anyhow!("HPACK index {index} out of the table")),
+        }
+    }
+
+    fn literal(&self, block: &mut &[u8], prefix: u8) -> anyhow::Result<(String, String)> {
+        let name = match read_int(block, prefix)? {
+            0 => read_string(block)?,
+            index => self.get(index)?.0,
+        };
+        Ok((name, read_string(block)?))
+    }
+
+    fn insert(&mut self, field: (String, String)) {
+        self.size += field.0.len() + field.1.len() + HPACK_ENTRY_OVERHEAD;
+        self.table.push_front(field);
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.size > self.max_size {
+            match self.table.pop_back() {
+                Some((name, value)) => self.size -= name.len() + value.len() + HPACK_ENTRY_OVERHEAD,
+                None => self.size = 0,
+            }
+        }
+    }
+}
+
+// a header block of the fields, indexed when the static table has them whole
+pub(crate) fn hpack_encode(fields: &[(&str, &str)]) -> Vec<u8> {
+    let mut block = Vec::new();
+    for &(name, value) in fields {
+        match STATIC_TABLE.iter().position(|f| *f == (name, value)) {
+            Some(i) => write_int(&mut block, 0x80, 7, i + 1),
+            None => {
+                // literal without indexing, new name
+                block.push(0);
+                for s in [name, value] {
+                    write_int(&mut block, 0, 7, s.len());
+                    block.extend(s.as_bytes());
+                }
+            }
+        }
+    }
+    block
+}
+
+// RFC 7541 5.1
+fn read_int(block: &mut &[u8], prefix: u8) -> anyhow::Result<usize> {
+    let (&first, mut rest) = block
+        .split_first()
+        .ok_or_else(|| anyhow!("HPACK block ends early"))?;
+    let max = (1usize << prefix) - 1;
+    let mut value = first as usize & max;
+    if value == max {
+        let mut shift = 0;
+        loop {
+            let (&b, r) = rest
+                .split_first()
+                .ok_or_else(|| anyhow!("HPACK block ends early"))?;
+            rest = r;
+            if shift > 28 {
+                bail!("HPACK integer too large");
+            }
+            value += ((b & 0x7f) as usize) << shift;
+            shift += 7;
+            if b & 0x80 == 0 {
+                break;
+            }
+        }
+    }
+    *block = rest;
+    Ok(value)
+}
+
+fn write_int(block: &mut Vec<u8>, flags: u8, prefix: u8, mut value: usize) {
+    let max = (1usize << prefix) - 1;
+    if value < max {
+        block.push(flags | value as u8);
+        return;
+    }
+    block.push(flags | max as u8);
+    value -= max;
+    while value >= 0x80 {
+        block.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    block.push(value as u8);
+}
+
+// RFC 7541 5.2
+fn read_string(block: &mut &[u8]) -> anyhow::Result<String> {
+    let huffman = block.first().is_some_and(|b| b & 0x80 != 0);
+    let len = read_int(block, 7)?;
+    if len > block.len() {
+        bail!("HPACK string runs past the block");
+    }
+    let (raw, rest) = block.split_at(len);
+    *block = rest;
+    let bytes = match huffman {
+        true => huffman_decode(raw)?,
+        false => raw.to_vec(),
+    };
+    String::from_utf8(bytes).map_err(|_| anyhow!("HPACK string is not UTF-8"))
+}
+
+// bit by bit, slow but the headers are few and short
+fn huffman_decode(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let (mut code, mut bits) = (0u32, 0u8);
+    for byte in data {
+        for i in (0..8).rev() {
+            code = code << 1 | (byte >> i & 1) as u32;
+            bits += 1;
+            if bits < 5 {
+                continue;
+            }
+            if let Some(sym) = HUFFMAN_CODES.iter().position(|&c| c == (code, bits)) {
+                if sym == HUFFMAN_EOS {
+                    bail!("HPACK Huffman string with EOS");
+                }
+                out.push(sym as u8);
+                (code, bits) = (0, 0);
+            } else if bits >= 30 {
+                bail!("Invalid HPACK Huffman code");
+            }
+        }
+    }
+    // the padding is the most significant bits of EOS, all ones
+    if bits > 7 || code != (1 << bits) - 1 {
+        bail!("Invalid HPACK Huffman padding");
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        let s = s.replace(' ', "");
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn fields(list: &[(&str, &str)]) -> Vec<(String, String)> {
+        list.iter()
+            .map(|(n, v)| (n.to_string(), v.to_string()))
+            .collect()
+    }
+
+    // RFC 7541 C.1
+    #[test]
+    fn integers() {
+        for (value, prefix, encoded) in [
+            (10, 5, &[0x0a][..]),
+            (1337, 5, &[0x1f, 0x9a, 0x0a]),
+            (42, 8, &[0x2a]),
+        ] {
+            let mut block = Vec::new();
+            write_int(&mut block, 0, prefix, value);
+            assert_eq!(block, encoded);
+            let mut rest = encoded;
+            assert_eq!(read_int(&mut rest, prefix).unwrap(), value);
+            assert!(rest.is_empty());
+        }
+    }
+
+    // RFC 7541 C.2
+    #[test]
+    fn field_representations() {
+        let mut decoder = HpackDecoder::new();
+        let block = hex("400a 6375 7374 6f6d 2d6b 6579 0d63 7573 746f 6d2d 6865 6164 6572");
+        assert_eq!(
+            decoder.decode(&block).unwrap(),
+            fields(&[("custom-key", "custom-header")])
+        );
+        assert_eq!(decoder.size, 55);
+
+        let mut decoder = HpackDecoder::new();
+        let block = hex("040c 2f73 616d 706c 652f 7061 7468");
+        assert_eq!(
+            decoder.decode(&block).unwrap(),
+            fields(&[(":path", "/sample/path")])
+        );
+        let block = hex("1008 7061 7373 776f 7264 0673 6563 7265 74");
+        assert_eq!(
+            decoder.decode(&block).unwrap(),
+            fields(&[("password", "secret")])
+        );
+        assert_eq!(
+            decoder.decode(&[0x82]).unwrap(),
+            fields(&[(":method", "GET")])
+        );
+        assert_eq!(decoder.size, 0);
+    }
+
+    // RFC 7541 C.3 and C.4, the same requests plain and Huffman coded
+    #[test]
+    fn requests() {
+        let expected = [
+            fields(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+            ]),
+            fields(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+                ("cache-control", "no-cache"),
+            ]),
+            fields(&[
+                (":method", "GET"),
+                (":scheme", "https"),
+                (":path", "/index.html"),
+                (":authority", "www.example.com"),
+                ("custom-key", "custom-value"),
+            ]),
+        ];
+        for blocks in [
+            [
+                "8286 8441 0f77 7777 2e65 7861 6d70 6c65 2e63 6f6d",
+                "8286 84be 5808 6e6f 2d63 6163 6865",
+                "8287 85bf 400a 6375 7374 6f6d 2d6b 6579 0c63 7573 746f 6d2d 7661 6c75 65",
+            ],
+            [
+                "8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff",
+                "8286 84be 5886 a8eb 1064 9cbf",
+                "8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf",
+            ],
+        ] {
+            let mut decoder = HpackDecoder::new();
+            for ((block, expected), size) in blocks.iter().zip(&expected).zip([57, 110, 164]) {
+                assert_eq!(&decoder.decode(&hex(block)).unwrap(), expected);
+                assert_eq!(decoder.size, size);
+            }
+        }
+    }
+
+    // RFC 7541 C.5 and C.6, responses evicting from a 256 byte table
+    #[test]
+    fn responses() {
+        let first = [
+            (":status", "302"),
+            ("cache-control", "private"),
+            ("date", "Mon, 21 Oct 2013 20:13:21 GMT"),
+            ("location", "https://www.example.com"),
+        ];
+        let expected = [
+            fields(&first),
+            fields(&[(":status", "307"), first[1], first[2], first[3]]),
+            fields(&[
+                (":status", "200"),
+                first[1],
+                ("date", "Mon, 21 Oct 2013 20:13:22 GMT"),
+                first[3],
+                ("content-encoding", "gzip"),
+                (
+                    "set-cookie",
+                    "foo=ASDJKHQKBZXOQWEOPIUAXQWEOIU; max-age=3600; version=1",
+                ),
+            ]),
+        ];
+        for blocks in [
+            [
+                "4803 3330 3258 0770 7269 7661 7465 611d 4d6f 6e2c 2032 3120 4f63 7420 3230 3133
+                 2032 303a 3133 3a32 3120 474d 546e 1768 7474 7073 3a2f 2f77 7777 2e65 7861 6d70
+                 6c65 2e63 6f6d",
+                "4803 3330 37c1 c0bf",
+                "88c1 611d 4d6f 6e2c 2032 3120 4f63 7420 3230 3133 2032 303a 3133 3a32 3220 474d
+                 54c0 5a04 677a 6970 7738 666f 6f3d 4153 444a 4b48 514b 425a 584f 5157 454f 5049
+                 5541 5851 5745 4f49 553b 206d 6178 2d61 6765 3d33 3630 303b 2076 6572 7369 6f6e
+                 3d31",
+            ],
+            [
+                "4882 6402 5885 aec3 771a 4b61 96d0 7abe 9410 54d4 44a8 2005 9504 0b81 66e0 82a6
+                 2d1b ff6e 919d 29ad 1718 63c7 8f0b 97c8 e9ae 82ae 43d3",
+                "4883 640e ffc1 c0bf",
+                "88c1 6196 d07a be94 1054 d444 a820 0595 040b 8166 e084 a62d 1bff c05a 839b d9ab
+                 77ad 94e7 821d d7f2 e6c7 b335 dfdf cd5b 3960 d5af 2708 7f36 72c1 ab27 0fb5 291f
+                 9587 3160 65c0 03ed 4ee5 b106 3d50 07",
+            ],
+        ] {
+            let mut decoder = HpackDecoder::new();
+            decoder.max_size = 256;
+            for ((block, expected), size) in blocks.iter().zip(&expected).zip([222, 222, 215]) {
+                let block = hex(&block.replace('\n', ""));
+                assert_eq!(&decoder.decode(&block).unwrap(), expected);
+                assert_eq!(decoder.size, size);
+            }
+            assert_eq!(decoder.table.len(), 3);
+            assert_eq!(decoder.table[1], fields(&[("content-encoding", "gzip")])[0]);
+        }
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        let list = [
+            (":status", "200"),
+            ("content-type", "application/grpc"),
+            ("grpc-status", "3"),
+            ("grpc-message", &"x".repeat(200)),
+        ];
+        let block = hpack_encode(&list);
+        assert_eq!(block[0], 0x88);
+        assert_eq!(HpackDecoder::new().decode(&block).unwrap(), fields(&list));
+    }
+
+    #[test]
+    fn bad_blocks() {
+        let mut decoder = HpackDecoder::new();
+        assert!(decoder.decode(&[0x80]).is_err());
+        assert!(decoder.decode(&[0xbe]).is_err());
+        // 4096 is what we allow the peer's table
+        assert!(decoder.decode(&hex("3fe1 1f")).is_ok());
+        assert!(decoder.decode(&hex("3fe2 1f")).is_err());
+        assert!(decoder.decode(&hex("0003 6162")).is_err());
+        // padding that is not all ones
+        assert!(decoder.decode(&hex("0081 00")).is_err());
+    }
+}
+
+// EOF
@@ -0,0 +1,213 @@
+// influx.rs
+
+use std::{
+    fmt::Write as _,
+    io::{Read, Write},
+    net::TcpStream,
+    process::{Command, Stdio},
+    sync::mpsc,
+    time,
+};
+
+use anyhow::{anyhow, bail};
+
+use crate::*;
+
+// give up on buffered points rather than eat all memory while the server is away
+const INFLUX_MAX_BUF: usize = 4 << 20;
+const INFLUX_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+
+#[derive(Clone, Debug)]
+pub struct InfluxConfig {
+    // e.g. http://localhost:8086
+    pub url: String,
+    pub org: String,
+    pub bucket: String,
+    pub token: Option<String>,
+    pub flush: time::Duration,
+}
+
+// Writes every frame as InfluxDB line protocol, one measurement per source:
+// cpu,host=myhost,channel=1 gauge=123,raw=48.2 1700000000000000000
+// Points are batched in the sink thread and posted every flush interval.
+pub struct InfluxSink;
+
+impl InfluxSink {
+    pub fn spawn(cfg: InfluxConfig) -> anyhow::Result<ThreadedSink> {
+        if !cfg.url.starts_with("http://") && !cfg.url.starts_with("https://") {
+            bail!("Unsupported InfluxDB URL: {}", cfg.url);
+        }
+        info!("Writing to InfluxDB {} bucket {}", cfg.url, cfg.bucket);
+        spawn_sink("influx", move |rx| Self::run(cfg, rx))
+    }
+
+    fn run(cfg: InfluxConfig, rx: mpsc::Receiver<Frame>) {
+        let host = escape_tag(&hostname());
+        let write_url = format!(
+            "{}/api/v2/write?org={}&bucket={}&precision=ns",
+            cfg.url.trim_end_matches('/'),
+            url_encode(&cfg.org),
+            url_encode(&cfg.bucket)
+        );
+        let mut buf = String::new();
+        let mut next_flush = time::Instant::now() + cfg.flush;
+
+        loop {
+            let timeout = next_flush.saturating_duration_since(time::Instant::now());
+            match rx.recv_timeout(timeout) {
+                Ok(frame) => Self::append(&mut buf, &host, &frame),
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => return,
+            }
+            if time::Instant::now() < next_flush {
+                continue;
+            }
+            next_flush = time::Instant::now() + cfg.flush;
+            if buf.is_empty() {
+                continue;
+            }
+            match post(&write_url, cfg.token.as_deref(), &buf) {
+                Ok(()) => {
+                    trace!("InfluxDB: wrote {} bytes", buf.len());
+                    buf.clear();
+                }
+                Err(e) => {
+                    error!("InfluxDB write failed: {e}");
+                    count_error("influx");
+                    if buf.len() > INFLUX_MAX_BUF {
+                        error!("InfluxDB: dropping {} bytes of points", buf.len());
+                        buf.clear();
+                    }
+                }
+            }
+        }
+    }
+
+    fn append(buf: &mut String, host: &str, frame: &Frame) {
+        let now = time::SystemTime::now();
+        for (ch, sample) in frame {
+            let ts = now
+                .checked_sub(sample.ts.elapsed())
+                .unwrap_or(now)
+                .duration_since(time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos();
+            let measurement = match sample.source {
+                "" => format!("channel_{ch}"),
+                s => escape_measurement(s),
+            };
+            let _ = write!(
+                buf,
+                "{measurement},host={host},channel={ch} gauge={:.1}",
+                sample.value.clamp(0.0, 255.0)
+            );
+            if let Some(raw) = sample.raw.filter(|r| r.is_finite()) {
+                let _ = write!(buf, ",raw={raw}");
+            }
+            let _ = writeln!(buf, " {ts}");
+        }
+    }
+}
+
+fn post(url: &str, token: Option<&str>, body: &str) -> anyhow::Result<()> {
+    match url.strip_prefix("http://") {
+        Some(rest) => post_http(rest, token, body),
+        // No TLS in here, let curl do the heavy lifting
+        None => post_curl(url, token, body),
+    }
+}
+
+fn post_http(rest: &str, token: Option<&str>, body: &str) -> anyhow::Result<()> {
+    let (hostport, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let host = hostport.split(':').next().unwrap_or(hostport);
+    let addr = if hostport.contains(':') {
+        hostport.to_string()
+    } else {
+        format!("{hostport}:80")
+    };
+
+    let mut stream = TcpStream::connect(&addr)?;
+    stream.set_read_timeout(Some(INFLUX_TIMEOUT))?;
+    stream.set_write_timeout(Some(INFLUX_TIMEOUT))?;
+    let auth = match token {
+        Some(t) => format!("Authorization: Token {t}\r\n"),
+        None => String::new(),
+    };
+    write!(
+        stream,
+        "POST {path} HTTP/1.1\r\nHost: {host}\r\nUser-Agent: perf_vumeter/{}\r\n{auth}\
+         Content-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        env!("CARGO_PKG_VERSION"),
+        body.len()
+    )?;
+    stream.write_all(body.as_bytes())?;
+
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp)?;
+    // "HTTP/1.1 204 No Content"
+    let status = resp
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse::<u16>().ok())
+        .ok_or_else(|| anyhow!("Invalid HTTP response"))?;
+    if !(200..300).contains(&status) {
+        let msg = resp.split("\r\n\r\n").nth(1).unwrap_or_default().trim();
+        bail!("HTTP status {status}: {msg}");
+    }
+    Ok(())
+}
+
+fn post_curl(url: &str, token: Option<&str>, body: &str) -> anyhow::Result<()> {
+    let mut cmd = Command::new("curl");
+    cmd.args(["-s", "-S", "-f", "--data-binary", "@-", "--max-time"])
+        .arg(INFLUX_TIMEOUT.as_secs().to_string());
+    if let Some(t) = token {
+        cmd.arg("-H").arg(format!("Authorization: Token {t}"));
+    }
+    let mut child = cmd
+        .arg(url)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    child
+        .stdin
+        .take()
+        .ok_or_else(|| anyhow!("No stdin for curl"))?
+        .write_all(body.as_bytes())?;
+    let out = child.wait_with_output()?;
+    if !out.status.success() {
+        bail!(
+            "curl exited with {}: {}",
+            out.status,
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+fn escape_measurement(s: &str) -> String {
+    s.replace(',', "\\,").replace(' ', "\\ ")
+}
+
+fn escape_tag(s: &str) -> String {
+    s.replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+fn url_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+// EOF
@@ -0,0 +1,261 @@
+// jpeg.rs
+
+// Baseline JPEG encoder, 4:4:4 with the example tables of the standard.
+// Just enough for the key images of a Stream Deck.
+
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+const LUMA_Q: [u8; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61, 12, 12, 14, 19, 26, 58, 60, 55, 14, 13, 16, 24, 40, 57, 69, 56,
+    14, 17, 22, 29, 51, 87, 80, 62, 18, 22, 37, 56, 68, 109, 103, 77, 24, 35, 55, 64, 81, 104, 113,
+    92, 49, 64, 78, 87, 103, 121, 120, 101, 72, 92, 95, 98, 112, 100, 103, 99,
+];
+
+const CHROMA_Q: [u8; 64] = [
+    17, 18, 24, 47, 99, 99, 99, 99, 18, 21, 26, 66, 99, 99, 99, 99, 24, 26, 56, 99, 99, 99, 99, 99,
+    47, 66, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+];
+
+const DC_LUMA_BITS: [u8; 16] = [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+const DC_CHROMA_BITS: [u8; 16] = [0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0];
+const DC_VALS: [u8; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+
+const AC_LUMA_BITS: [u8; 16] = [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7d];
+const AC_LUMA_VALS: [u8; 162] = [
+    0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61, 0x07,
+    0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xa1, 0x08, 0x23, 0x42, 0xb1, 0xc1, 0x15, 0x52, 0xd1, 0xf0,
+    0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0a, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x25, 0x26, 0x27, 0x28,
+    0x29, 0x2a, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49,
+    0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69,
+    0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89,
+    0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7,
+    0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5,
+    0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe1, 0xe2,
+    0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+
+const AC_CHROMA_BITS: [u8; 16] = [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77];
+const AC_CHROMA_VALS: [u8; 162] = [
+    0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61, 0x71,
+    0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xa1, 0xb1, 0xc1, 0x09, 0x23, 0x33, 0x52, 0xf0,
+    0x15, 0x62, 0x72, 0xd1, 0x0a, 0x16, 0x24, 0x34, 0xe1, 0x25, 0xf1, 0x17, 0x18, 0x19, 0x1a, 0x26,
+    0x27, 0x28, 0x29, 0x2a, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48,
+    0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68,
+    0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87,
+    0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5,
+    0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3,
+    0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda,
+    0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+
+// code and length for every symbol
+struct Huffman([(u16, u8); 256]);
+
+impl Huffman {
+    fn new(bits: &[u8; 16], vals: &[u8]) -> Self {
+        let mut table = [(0u16, 0u8); 256];
+        let mut code = 0u16;
+        let mut k = 0;
+        for (i, n) in bits.iter().enumerate() {
+            for _ in 0..*n {
+                table[vals[k] as usize] = (code, i as u8 + 1);
+                code += 1;
+                k += 1;
+            }
+            code <<= 1;
+        }
+        Self(table)
+    }
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u32,
+    n: u8,
+}
+
+impl BitWriter {
+    fn put(&mut self, code: u16, len: u8) {
+        for i in (0..len).rev() {
+            self.acc = self.acc << 1 | (code >> i & 1) as u32;
+            self.n += 1;
+            if self.n == 8 {
+                let b = self.acc as u8;
+                self.out.push(b);
+                // byte stuffing
+                if b == 0xff {
+                    self.out.push(0);
+                }
+                self.acc = 0;
+                self.n = 0;
+            }
+        }
+    }
+    fn flush(&mut self) {
+        while self.n != 0 {
+            self.put(1, 1);
+        }
+    }
+}
+
+// magnitude category and the bits coding the value
+fn category(v: i32) -> (u8, u16) {
+    let cat = (32 - v.unsigned_abs().leading_zeros()) as u8;
+    let bits = if v < 0 { v - 1 } else { v } as u16 & ((1u32 << cat) - 1) as u16;
+    (cat, bits)
+}
+
+// the base tables are in natural order, the result is in zigzag order
+fn scaled_table(base: &[u8; 64], quality: u8) -> [u8; 64] {
+    let q = quality.clamp(1, 100) as u32;
+    let scale = if q < 50 { 5000 / q } else { 200 - q * 2 };
+    ZIGZAG.map(|i| ((base[i] as u32 * scale + 50) / 100).clamp(1, 255) as u8)
+}
+
+fn encode_block(
+    w: &mut BitWriter,
+    block: &[f32; 64],
+    q: &[u8; 64],
+    prev_dc: &mut i32,
+    dc: &Huffman,
+    ac: &Huffman,
+) {
+    // separable float DCT-II
+    let mut coef = [0f32; 64];
+    for v in 0..8 {
+        for u in 0..8 {
+            let mut sum = 0.0;
+            for y in 0..8 {
+                for x in 0..8 {
+                    sum += block[y * 8 + x]
+                        * (((2 * x + 1) as f32 * u as f32 * std::f32::consts::PI) / 16.0).cos()
+                        * (((2 * y + 1) as f32 * v as f32 * std::f32::consts::PI) / 16.0).cos();
+                }
+            }
+            let cu = if u == 0 {
+                std::f32::consts::FRAC_1_SQRT_2
+            } else {
+                1.0
+            };
+            let cv = if v == 0 {
+                std::f32::consts::FRAC_1_SQRT_2
+            } else {
+                1.0
+            };
+            coef[v * 8 + u] = 0.25 * cu * cv * sum;
+        }
+    }
+    let zz: Vec<i32> = (0..64)
+        .map(|i| (coef[ZIGZAG[i]] / q[i] as f32).round() as i32)
+        .collect();
+
+    let (cat, bits) = category(zz[0] - *prev_dc);
+    *prev_dc = zz[0];
+    w.put(dc.0[cat as usize].0, dc.0[cat as usize].1);
+    w.put(bits, cat);
+
+    let mut run = 0;
+    for &c in &zz[1..] {
+        if c == 0 {
+            run += 1;
+            continue;
+        }
+        while run > 15 {
+            // ZRL
+            w.put(ac.0[0xf0].0, ac.0[0xf0].1);
+            run -= 16;
+        }
+        let (cat, bits) = category(c);
+        let sym = (run << 4 | cat as usize) & 0xff;
+        w.put(ac.0[sym].0, ac.0[sym].1);
+        w.put(bits, cat);
+        run = 0;
+    }
+    if run > 0 {
+        // EOB
+        w.put(ac.0[0].0, ac.0[0].1);
+    }
+}
+
+// rgb holds width * height * 3 bytes, both dimensions multiples of 8
+pub(crate) fn encode_jpeg(rgb: &[u8], width: usize, height: usize, quality: u8) -> Vec<u8> {
+    let lq = scaled_table(&LUMA_Q, quality);
+    let cq = scaled_table(&CHROMA_Q, quality);
+    let mut out = vec![0xff, 0xd8];
+
+    // DQT, tables in zigzag order
+    for (id, t) in [(0u8, &lq), (1, &cq)] {
+        out.extend([0xff, 0xdb, 0x00, 0x43, id]);
+        out.extend(t.iter());
+    }
+    // SOF0
+    out.extend([0xff, 0xc0, 0x00, 0x11, 0x08]);
+    out.extend((height as u16).to_be_bytes());
+    out.extend((width as u16).to_be_bytes());
+    out.extend([0x03, 0x01, 0x11, 0x00, 0x02, 0x11, 0x01, 0x03, 0x11, 0x01]);
+    // DHT
+    for (class_id, bits, vals) in [
+        (0x00u8, &DC_LUMA_BITS, &DC_VALS[..]),
+        (0x10, &AC_LUMA_BITS, &AC_LUMA_VALS[..]),
+        (0x01, &DC_CHROMA_BITS, &DC_VALS[..]),
+        (0x11, &AC_CHROMA_BITS, &AC_CHROMA_VALS[..]),
+    ] {
+        let n: usize = bits.iter().map(|b| *b as usize).sum();
+        out.extend([0xff, 0xc4]);
+        out.extend(((19 + n) as u16).to_be_bytes());
+        out.push(class_id);
+        out.extend(bits);
+        out.extend(&vals[..n]);
+    }
+    // SOS
+    out.extend([
+        0xff, 0xda, 0x00, 0x0c, 0x03, 0x01, 0x00, 0x02, 0x11, 0x03, 0x11, 0x00, 0x3f, 0x00,
+    ]);
+
+    let tables = [
+        Huffman::new(&DC_LUMA_BITS, &DC_VALS),
+        Huffman::new(&AC_LUMA_BITS, &AC_LUMA_VALS),
+        Huffman::new(&DC_CHROMA_BITS, &DC_VALS),
+        Huffman::new(&AC_CHROMA_BITS, &AC_CHROMA_VALS),
+    ];
+    let mut w = BitWriter {
+        out: Vec::new(),
+        acc: 0,
+        n: 0,
+    };
+    let mut prev_dc = [0i32; 3];
+    for by in (0..height).step_by(8) {
+        for bx in (0..width).step_by(8) {
+            let mut blocks = [[0f32; 64]; 3];
+            for y in 0..8 {
+                for x in 0..8 {
+                    let i = ((by + y) * width + bx + x) * 3;
+                    let (r, g, b) = (rgb[i] as f32, rgb[i + 1] as f32, rgb[i + 2] as f32);
+                    blocks[0][y * 8 + x] = 0.299 * r + 0.587 * g + 0.114 * b - 128.0;
+                    blocks[1][y * 8 + x] = -0.168_736 * r - 0.331_264 * g + 0.5 * b;
+                    blocks[2][y * 8 + x] = 0.5 * r - 0.418_688 * g - 0.081_312 * b;
+                }
+            }
+            for c in 0..3 {
+                let (q, dc, ac) = match c {
+                    0 => (&lq, &tables[0], &tables[1]),
+                    _ => (&cq, &tables[2], &tables[3]),
+                };
+                encode_block(&mut w, &blocks[c], q, &mut prev_dc[c], dc, ac);
+            }
+        }
+    }
+    w.flush();
+    out.extend(w.out);
+    out.extend([0xff, 0xd9]);
+    out
+}
+
+// EOF
@@ -0,0 +1,186 @@
+// json.rs
+
+use std::{fmt, iter::Peekable, str::Chars};
+
+use anyhow::{anyhow, bail};
+
+// Just enough JSON for reading the output of external tools
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Num(f64),
+    Str(String),
+    Arr(Vec<Json>),
+    Obj(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        let mut chars = s.chars().peekable();
+        let value = parse_value(&mut chars)?;
+        skip_ws(&mut chars);
+        if chars.peek().is_some() {
+            bail!("Trailing garbage after JSON value");
+        }
+        Ok(value)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Obj(items) => items.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Num(n) => Some(*n),
+            _ => None,
+        }
+    }
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Arr(a) => Some(a),
+            _ => None,
+        }
+    }
+}
+
+// Escape a string for embedding into JSON output, quotes included
+pub struct JsonStr<'a>(pub &'a str);
+
+impl fmt::Display for JsonStr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("\"")?;
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                '\r' => f.write_str("\\r")?,
+                '\t' => f.write_str("\\t")?,
+                c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+                c => write!(f, "{c}")?,
+            }
+        }
+        f.write_str("\"")
+    }
+}
+
+fn skip_ws(chars: &mut Peekable<Chars>) {
+    while chars.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+        chars.next();
+    }
+}
+
+fn expect_word(chars: &mut Peekable<Chars>, word: &str) -> anyhow::Result<()> {
+    for w in word.chars() {
+        if chars.next() != Some(w) {
+            bail!("Invalid JSON literal, expected {word}");
+        }
+    }
+    Ok(())
+}
+
+fn parse_value(chars: &mut Peekable<Chars>) -> anyhow::Result<Json> {
+    skip_ws(chars);
+    match chars.peek().copied() {
+        None => Err(anyhow!("Unexpected end of JSON")),
+        Some('n') => expect_word(chars, "null").map(|_| Json::Null),
+        Some('t') => expect_word(chars, "true").map(|_| Json::Bool(true)),
+        Some('f') => expect_word(chars, "false").map(|_| Json::Bool(false)),
+        Some('"') => Ok(Json::Str(parse_string(chars)?)),
+        Some('[') => {
+            chars.next();
+            let mut items = Vec::new();
+            loop {
+                skip_ws(chars);
+                if chars.peek() == Some(&']') && items.is_empty() {
+                    chars.next();
+                    break;
+                }
+                items.push(parse_value(chars)?);
+                skip_ws(chars);
+                match chars.next() {
+                    Some(',') => continue,
+                    Some(']') => break,
+                    _ => bail!("Expected , or ] in JSON array"),
+                }
+            }
+            Ok(Json::Arr(items))
+        }
+        Some('{') => {
+            chars.next();
+            let mut items = Vec::new();
+            loop {
+                skip_ws(chars);
+                if chars.peek() == Some(&'}') && items.is_empty() {
+                    chars.next();
+                    break;
+                }
+                let key = parse_string(chars)?;
+                skip_ws(chars);
+                if chars.next() != Some(':') {
+                    bail!("Expected : in JSON object");
+                }
+                items.push((key, parse_value(chars)?));
+                skip_ws(chars);
+                match chars.next() {
+                    Some(',') => continue,
+                    Some('}') => break,
+                    _ => bail!("Expected , or }} in JSON object"),
+                }
+            }
+            Ok(Json::Obj(items))
+        }
+        Some(_) => {
+            let mut num = String::new();
+            while let Some(c) = chars.peek().copied() {
+                if !(c.is_ascii_digit() || "+-.eE".contains(c)) {
+                    break;
+                }
+                num.push(c);
+                chars.next();
+            }
+            Ok(Json::Num(num.parse::<f64>().map_err(|e| {
+                anyhow!("Invalid JSON number {num:?}: {e}")
+            })?))
+        }
+    }
+}
+
+fn parse_string(chars: &mut Peekable<Chars>) -> anyhow::Result<String> {
+    if chars.next() != Some('"') {
+        bail!("Expected JSON string");
+    }
+    let mut s = String::new();
+    loop {
+        match chars.next() {
+            None => bail!("Unterminated JSON string"),
+            Some('"') => return Ok(s),
+            Some('\\') => match chars.next() {
+                Some('n') => s.push('\n'),
+                Some('r') => s.push('\r'),
+                Some('t') => s.push('\t'),
+                Some('b') => s.push('\u{8}'),
+                Some('f') => s.push('\u{c}'),
+                Some('u') => {
+                    let hex = chars.by_ref().take(4).collect::<String>();
+                    let code = u32::from_str_radix(&hex, 16)?;
+                    s.push(char::from_u32(code).unwrap_or('\u{fffd}'));
+                }
+                Some(c) => s.push(c),
+                None => bail!("Unterminated JSON string"),
+            },
+            Some(c) => s.push(c),
+        }
+    }
+}
+
+// EOF
@@ -0,0 +1,186 @@
+// k8s.rs
+
+use std::sync::{Arc, Mutex};
+use std::{fmt, process::Command, str::FromStr, thread, time};
+
+use anyhow::{anyhow, bail};
+
+use crate::*;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum K8sMetric {
+    // cores in use
+    #[default]
+    Cpu,
+    // the larger of rx/tx bits per second
+    Net,
+}
+
+impl fmt::Display for K8sMetric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                K8sMetric::Cpu => "cpu",
+                K8sMetric::Net => "net",
+            }
+        )
+    }
+}
+
+impl FromStr for K8sMetric {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cpu" => Ok(K8sMetric::Cpu),
+            "net" => Ok(K8sMetric::Net),
+            _ => Err(anyhow!("Unknown kubernetes metric: {s}")),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct K8sValue {
+    pub value: f64,
+    pub ts: time::Instant,
+}
+
+// Scrapes the kubelet summary API and sums up cpu or network usage
+// of the pods selected with "namespace/name-glob", e.g. "default/web-*".
+// The API only refreshes every ~10s, so it is polled in a background thread
+// which ends after the K8sPods is dropped.
+#[derive(Debug)]
+pub struct K8sPods {
+    pub metric: K8sMetric,
+    value: Arc<Mutex<Option<K8sValue>>>,
+}
+
+impl K8sPods {
+    pub fn new<S: AsRef<str>>(
+        selector: S,
+        metric: K8sMetric,
+        url: S,
+        token_file: S,
+        interval: time::Duration,
+    ) -> anyhow::Result<Self> {
+        let (ns, pods) = selector
+            .as_ref()
+            .split_once('/')
+            .ok_or_else(|| anyhow!("Invalid pod selector, expected namespace/name-glob"))?;
+        let (ns, pods) = (ns.to_string(), pods.to_string());
+        let url = url.as_ref().to_string();
+        let token = std::fs::read_to_string(token_file.as_ref())
+            .map(|t| t.trim().to_string())
+            .ok();
+
+        let value = Arc::new(Mutex::new(None));
+        let ret = Self {
+            metric,
+            value: value.clone(),
+        };
+        thread::spawn(move || {
+            let mut prev: Option<(f64, f64, time::Instant)> = None;
+            loop {
+                match Self::scrape(&url, token.as_deref(), &ns, &pods) {
+                    Ok((cores, net_bytes)) => {
+                        let now = time::Instant::now();
+                        let v = match metric {
+                            K8sMetric::Cpu => Some(cores),
+                            K8sMetric::Net => prev.map(|(_, prev_bytes, prev_ts)| {
+                                8.0 * (net_bytes - prev_bytes).max(0.0)
+                                    / (now - prev_ts).as_secs_f64()
+                            }),
+                        };
+                        trace!("Pods {ns}/{pods}: {cores:.3} cores, {net_bytes} net bytes");
+                        prev = Some((cores, net_bytes, now));
+                        *value.lock().unwrap() = v.map(|value| K8sValue { value, ts: now });
+                    }
+                    Err(e) => {
+                        info!("Kubelet summary: {e}");
+                        count_error("k8s");
+                        *value.lock().unwrap() = None;
+                    }
+                }
+                thread::sleep(interval);
+                if Arc::strong_count(&value) == 1 {
+                    return;
+                }
+            }
+        });
+        Ok(ret)
+    }
+
+    pub fn value(&self) -> Option<K8sValue> {
+        *self.value.lock().unwrap()
+    }
+
+    // total cpu cores and network bytes (rx+tx counted separately, max taken) of matching pods
+    fn scrape(url: &str, token: Option<&str>, ns: &str, pods: &str) -> anyhow::Result<(f64, f64)> {
+        let mut cmd = Command::new("curl");
+        // the kubelet serves a self-signed certificate
+        cmd.args(["-s", "-k", "--max-time", "5"]);
+        if let Some(token) = token {
+            cmd.args(["-H", &format!("Authorization: Bearer {token}")]);
+        }
+        let out = cmd.arg(url).output()?;
+        if !out.status.success() {
+            bail!("curl exited with {}", out.status);
+        }
+        let summary = Json::parse(&String::from_utf8_lossy(&out.stdout))?;
+
+        let (mut cores, mut rx, mut tx) = (0.0, 0.0, 0.0);
+        let mut found = false;
+        for pod in summary
+            .get("pods")
+            .and_then(Json::as_array)
+            .unwrap_or_default()
+        {
+            let Some(pod_ref) = pod.get("podRef") else {
+                continue;
+            };
+            let pod_ns = pod_ref
+                .get("namespace")
+                .and_then(Json::as_str)
+                .unwrap_or("");
+            let pod_name = pod_ref.get("name").and_then(Json::as_str).unwrap_or("");
+            if pod_ns != ns || !glob_match(pods, pod_name) {
+                continue;
+            }
+            found = true;
+            let num = |a: &str, b: &str| pod.get(a).and_then(|j| j.get(b)?.as_f64()).unwrap_or(0.0);
+            cores += num("cpu", "usageNanoCores") / 1e9;
+            rx += num("network", "rxBytes");
+            tx += num("network", "txBytes");
+        }
+        if !found {
+            bail!("No pods matching {ns}/{pods}");
+        }
+        Ok((cores, f64::max(rx, tx)))
+    }
+}
+
+// zero until the first scrape and while the kubelet does not answer,
+// the polling thread counts the errors
+impl StatSource for K8sPods {
+    fn sample(&mut self) -> anyhow::Result<f64> {
+        Ok(self.sample_at()?.0)
+    }
+    fn name(&self) -> &'static str {
+        "k8s"
+    }
+    fn unit(&self) -> &'static str {
+        match self.metric {
+            K8sMetric::Cpu => "cores",
+            K8sMetric::Net => "bit/s",
+        }
+    }
+    fn sample_at(&mut self) -> anyhow::Result<(f64, time::Instant)> {
+        Ok(self
+            .value()
+            .map_or((0.0, time::Instant::now()), |v| (v.value, v.ts)))
+    }
+}
+
+// EOF
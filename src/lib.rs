@@ -1,12 +1,183 @@
 // lib.rs
 
-pub use clap::Parser;
+pub use clap::{CommandFactory, Parser, Subcommand};
 pub use tracing::*;
 
+pub use adaptive::*;
+pub use alert::*;
+pub use audio::*;
+#[cfg(target_os = "linux")]
+pub use can::*;
+pub use clock::*;
+pub use color::*;
 pub use config::*;
+pub use csv::*;
+pub use curve::*;
+#[cfg(unix)]
+pub use dbus::*;
+pub use demo::*;
+pub use dmx::*;
+#[cfg(target_os = "linux")]
+pub use eink::*;
+#[cfg(target_os = "linux")]
+pub use ethtool::*;
+pub use expr::*;
+#[cfg(unix)]
+pub use fifo::*;
+pub use gauge::*;
+#[cfg(target_os = "linux")]
+pub use gpio::*;
+pub use gpu::*;
+pub use graphite::*;
+pub use grpc::*;
+pub use heartbeat::*;
+pub use history::*;
+pub use influx::*;
+pub use json::*;
+pub use k8s::*;
+pub use libvirt::*;
+#[cfg(target_os = "macos")]
+pub use macos::*;
+pub use mapping::*;
+pub use meter::*;
+pub use modbus::*;
+pub use mqtt::*;
+pub use nft::*;
+pub use notify::*;
+pub use openrgb::*;
+pub use osc::*;
+#[cfg(target_os = "linux")]
+pub use pca9685::*;
+#[cfg(target_os = "linux")]
+pub use perf::*;
+pub use pipeline::*;
+#[cfg(unix)]
+pub use plugin::*;
+pub use poller::*;
+pub use probe::*;
+pub use procfs::*;
+pub use record::*;
+pub use remote::*;
+pub use sample::*;
+pub use signal::*;
+pub use sink::*;
+pub use snmp::*;
+#[cfg(feature = "sonify")]
+pub use sonify::*;
+pub use sparkline::*;
+#[cfg(target_os = "linux")]
+pub use ssd1306::*;
 pub use stats::*;
+pub use status::*;
+pub use statusbar::*;
+#[cfg(target_os = "linux")]
+pub use streamdeck::*;
+#[cfg(target_os = "linux")]
+pub use systemd::*;
+pub use ticker::*;
+#[cfg(unix)]
+pub use tray::*;
+pub use tui::*;
+pub use vumeter::*;
+pub use websocket::*;
+#[cfg(windows)]
+pub use windows::*;
+pub use wireguard::*;
+#[cfg(target_os = "linux")]
+pub use ws2812::*;
 
+mod adaptive;
+mod alert;
+mod audio;
+#[cfg(target_os = "linux")]
+mod can;
+mod clock;
+mod color;
 mod config;
+mod crypto;
+mod csv;
+mod curve;
+#[cfg(unix)]
+mod dbus;
+mod demo;
+mod dmx;
+#[cfg(target_os = "linux")]
+mod eink;
+#[cfg(target_os = "linux")]
+mod ethtool;
+mod expr;
+#[cfg(unix)]
+mod fifo;
+mod gauge;
+#[cfg(target_os = "linux")]
+mod gpio;
+mod gpu;
+mod graphite;
+mod grpc;
+mod heartbeat;
+mod history;
+mod hpack;
+mod influx;
+#[cfg(target_os = "linux")]
+mod jpeg;
+mod json;
+mod k8s;
+mod libvirt;
+#[cfg(target_os = "macos")]
+mod macos;
+mod mapping;
+mod meter;
+mod modbus;
+#[cfg(target_os = "linux")]
+mod mono;
+mod mqtt;
+mod nft;
+mod notify;
+mod openrgb;
+mod osc;
+#[cfg(target_os = "linux")]
+mod pca9685;
+#[cfg(target_os = "linux")]
+mod perf;
+mod pipeline;
+#[cfg(unix)]
+mod plugin;
+mod poller;
+mod probe;
+mod procfs;
+mod record;
+mod remote;
+mod sample;
+mod signal;
+mod sink;
+mod snmp;
+#[cfg(feature = "sonify")]
+mod sonify;
+mod sparkline;
+#[cfg(target_os = "linux")]
+mod ssd1306;
 mod stats;
+mod status;
+mod statusbar;
+#[cfg(target_os = "linux")]
+mod streamdeck;
+#[cfg(unix)]
+mod sys;
+#[cfg(windows)]
+#[path = "sys_windows.rs"]
+mod sys;
+#[cfg(target_os = "linux")]
+mod systemd;
+mod ticker;
+#[cfg(unix)]
+mod tray;
+mod tui;
+mod vumeter;
+mod websocket;
+#[cfg(windows)]
+mod windows;
+mod wireguard;
+#[cfg(target_os = "linux")]
+mod ws2812;
 
 // EOF
@@ -0,0 +1,174 @@
+// libvirt.rs
+
+use std::{collections::HashMap, fmt, process::Command, str::FromStr, time};
+
+use anyhow::{anyhow, bail};
+
+use crate::*;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum VmMetric {
+    // percent of the vcpus allocated to the domain
+    #[default]
+    Cpu,
+    // block device bytes read + written per second
+    Block,
+    // the larger of rx/tx bits per second
+    Net,
+}
+
+impl fmt::Display for VmMetric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                VmMetric::Cpu => "cpu",
+                VmMetric::Block => "block",
+                VmMetric::Net => "net",
+            }
+        )
+    }
+}
+
+impl FromStr for VmMetric {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cpu" => Ok(VmMetric::Cpu),
+            "block" | "disk" => Ok(VmMetric::Block),
+            "net" => Ok(VmMetric::Net),
+            _ => Err(anyhow!("Unknown VM metric: {s}")),
+        }
+    }
+}
+
+// Per-domain statistics from "virsh domstats", which takes a good while,
+// so it is run in a background thread
+#[derive(Debug)]
+pub struct VmStats {
+    pub domain: String,
+    pub metric: VmMetric,
+    rate: Poller<f64>,
+}
+
+impl VmStats {
+    pub fn new<S: AsRef<str>>(
+        domain: S,
+        metric: VmMetric,
+        uri: Option<String>,
+        interval: time::Duration,
+    ) -> anyhow::Result<Self> {
+        let domain = domain.as_ref().to_string();
+        let dom = domain.clone();
+        let mut prev: Option<(time::Instant, HashMap<String, i64>)> = None;
+        let rate = Poller::spawn(interval, move || {
+            let stats = Self::read_domstats(&dom, uri.as_deref())?;
+            let now = time::Instant::now();
+            let rate = match &prev {
+                Some((ts, prev)) => {
+                    Self::rate_between(metric, &stats, prev, (now - *ts).as_secs_f64())
+                }
+                None => 0.0,
+            };
+            prev = Some((now, stats));
+            Ok(rate)
+        })?;
+        Ok(Self {
+            domain,
+            metric,
+            rate,
+        })
+    }
+
+    // the latest rate and when it was measured:
+    // cpu in percent, block in bytes/s, net in bits/s
+    pub fn rate(&self) -> anyhow::Result<(f64, time::Instant)> {
+        self.rate.latest()
+    }
+
+    fn rate_between(
+        metric: VmMetric,
+        stats: &HashMap<String, i64>,
+        prev: &HashMap<String, i64>,
+        secs: f64,
+    ) -> f64 {
+        let delta = |suffix: &str| -> f64 {
+            stats
+                .iter()
+                .filter(|(k, _)| k.ends_with(suffix))
+                .filter_map(|(k, v)| prev.get(k).map(|p| (v - p).max(0)))
+                .sum::<i64>() as f64
+        };
+        match metric {
+            VmMetric::Cpu => {
+                let vcpus = stats.get("vcpu.current").copied().unwrap_or(1).max(1);
+                // cpu.time is in nanoseconds
+                100.0 * delta("cpu.time") / 1e9 / secs / vcpus as f64
+            }
+            VmMetric::Block => (delta(".rd.bytes") + delta(".wr.bytes")) / secs,
+            VmMetric::Net => 8.0 * delta(".rx.bytes").max(delta(".tx.bytes")) / secs,
+        }
+    }
+
+    // Example output:
+    // Domain: 'vm1'
+    //   cpu.time=6173016809079
+    //   vcpu.current=2
+    //   net.0.rx.bytes=29537611
+    //   block.0.rd.bytes=1400237056
+    fn read_domstats(domain: &str, uri: Option<&str>) -> anyhow::Result<HashMap<String, i64>> {
+        let mut cmd = Command::new("virsh");
+        if let Some(uri) = uri {
+            cmd.args(["-c", uri]);
+        }
+        let out = cmd
+            .args([
+                "domstats",
+                "--cpu-total",
+                "--vcpu",
+                "--block",
+                "--interface",
+            ])
+            .arg(domain)
+            .output()?;
+        if !out.status.success() {
+            bail!(
+                "virsh exited with {}: {}",
+                out.status,
+                String::from_utf8_lossy(&out.stderr).trim()
+            );
+        }
+        let mut stats = HashMap::with_capacity(64);
+        for line in String::from_utf8_lossy(&out.stdout).lines() {
+            if let Some((k, v)) = line.trim().split_once('=') {
+                if let Ok(n) = v.parse::<i64>() {
+                    stats.insert(k.to_string(), n);
+                }
+            }
+        }
+        Ok(stats)
+    }
+}
+
+impl StatSource for VmStats {
+    fn sample(&mut self) -> anyhow::Result<f64> {
+        Ok(self.rate()?.0)
+    }
+    fn name(&self) -> &'static str {
+        "libvirt"
+    }
+    fn unit(&self) -> &'static str {
+        match self.metric {
+            VmMetric::Cpu => "%",
+            VmMetric::Block => "B/s",
+            VmMetric::Net => "bit/s",
+        }
+    }
+    fn sample_at(&mut self) -> anyhow::Result<(f64, time::Instant)> {
+        self.rate()
+    }
+}
+
+// EOF
@@ -0,0 +1,456 @@
+// macos.rs
+
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_uint, c_void};
+use std::{collections::HashMap, ptr, time};
+
+use anyhow::{anyhow, bail};
+
+use crate::*;
+
+// The cpu, disk and net sources of macOS, behind the same StatSource as the
+// /proc and /sys readers, see mapping.rs
+
+type MachPort = c_uint;
+type KernReturn = c_int;
+type CFTypeRef = *const c_void;
+
+// user, system, idle and nice ticks of each core
+const CPU_STATE_MAX: usize = libc::CPU_STATE_MAX as usize;
+const CPU_STATE_IDLE: usize = libc::CPU_STATE_IDLE as usize;
+const K_CF_NUMBER_SINT64: c_int = 4;
+const K_CF_STRING_ENCODING_UTF8: u32 = 0x0800_0100;
+
+// the head of struct if_data in net/if_var.h, the counters are 32 bits
+#[allow(dead_code)]
+#[repr(C)]
+struct IfData {
+    ifi_type: [u8; 8],
+    ifi_mtu: u32,
+    ifi_metric: u32,
+    ifi_baudrate: u32,
+    ifi_ipackets: u32,
+    ifi_ierrors: u32,
+    ifi_opackets: u32,
+    ifi_oerrors: u32,
+    ifi_collisions: u32,
+    ifi_ibytes: u32,
+    ifi_obytes: u32,
+    ifi_imcasts: u32,
+    ifi_omcasts: u32,
+    ifi_iqdrops: u32,
+}
+
+extern "C" {
+    static mach_task_self_: MachPort;
+    fn mach_host_self() -> MachPort;
+    fn host_processor_info(
+        host: MachPort,
+        flavor: c_int,
+        count: *mut c_uint,
+        info: *mut *mut c_int,
+        info_count: *mut c_uint,
+    ) -> KernReturn;
+    fn vm_deallocate(task: MachPort, address: usize, size: usize) -> KernReturn;
+}
+
+#[link(name = "IOKit", kind = "framework")]
+extern "C" {
+    fn IOServiceMatching(name: *const c_char) -> CFTypeRef;
+    fn IOServiceGetMatchingServices(
+        main_port: MachPort,
+        matching: CFTypeRef,
+        iter: *mut MachPort,
+    ) -> KernReturn;
+    fn IOIteratorNext(iter: MachPort) -> MachPort;
+    fn IOObjectRelease(obj: MachPort) -> KernReturn;
+    fn IORegistryEntryGetChildEntry(
+        entry: MachPort,
+        plane: *const c_char,
+        child: *mut MachPort,
+    ) -> KernReturn;
+    fn IORegistryEntryCreateCFProperty(
+        entry: MachPort,
+        key: CFTypeRef,
+        allocator: CFTypeRef,
+        options: u32,
+    ) -> CFTypeRef;
+}
+
+#[link(name = "CoreFoundation", kind = "framework")]
+extern "C" {
+    fn CFStringCreateWithCString(alloc: CFTypeRef, s: *const c_char, enc: u32) -> CFTypeRef;
+    fn CFStringGetCString(s: CFTypeRef, buf: *mut c_char, len: isize, enc: u32) -> u8;
+    fn CFDictionaryGetValue(dict: CFTypeRef, key: CFTypeRef) -> CFTypeRef;
+    fn CFNumberGetValue(num: CFTypeRef, kind: c_int, value: *mut c_void) -> u8;
+    fn CFRelease(cf: CFTypeRef);
+}
+
+// busy percentage of each core like CpuStats, only the busy mode exists here
+#[derive(Debug)]
+pub struct MacCpuStats {
+    prev_ticks: Vec<[u64; CPU_STATE_MAX]>,
+}
+
+impl MacCpuStats {
+    pub fn new(mode: CpuMode) -> anyhow::Result<Self> {
+        if mode != CpuMode::Busy {
+            bail!("CPU mode {mode:?} is not available on macOS");
+        }
+        Ok(Self {
+            prev_ticks: Self::read_ticks()?,
+        })
+    }
+    pub fn n_cpu(&self) -> usize {
+        self.prev_ticks.len()
+    }
+    // like CpuStats::cpurates(), [0] is the average and the cores follow busiest first
+    pub fn cpurates(&mut self) -> anyhow::Result<Vec<f64>> {
+        let ticks = Self::read_ticks()?;
+        let mut rates = vec![0.0];
+        for (now, prev) in ticks.iter().zip(&self.prev_ticks) {
+            let delta = (0..CPU_STATE_MAX)
+                .map(|i| now[i].wrapping_sub(prev[i]))
+                .collect::<Vec<u64>>();
+            let total = delta.iter().sum::<u64>();
+            rates.push(match total {
+                0 => 0.0,
+                t => 100.0 * (t - delta[CPU_STATE_IDLE]) as f64 / t as f64,
+            });
+        }
+        rates[0] = rates[1..].iter().sum::<f64>() / ticks.len().max(1) as f64;
+        rates[1..].sort_by(|a, b| b.total_cmp(a));
+        self.prev_ticks = ticks;
+        Ok(rates)
+    }
+
+    fn read_ticks() -> anyhow::Result<Vec<[u64; CPU_STATE_MAX]>> {
+        let mut count = 0;
+        let mut info = ptr::null_mut();
+        let mut info_count = 0;
+        let ret = unsafe {
+            host_processor_info(
+                mach_host_self(),
+                libc::PROCESSOR_CPU_LOAD_INFO,
+                &mut count,
+                &mut info,
+                &mut info_count,
+            )
+        };
+        if ret != 0 {
+            bail!("host_processor_info failed: {ret}");
+        }
+        let raw = unsafe { std::slice::from_raw_parts(info, info_count as usize) };
+        let ticks = raw
+            .chunks_exact(CPU_STATE_MAX)
+            .take(count as usize)
+            .map(|c| [c[0], c[1], c[2], c[3]].map(|t| t as u32 as u64))
+            .collect();
+        unsafe {
+            vm_deallocate(
+                mach_task_self_,
+                info as usize,
+                info_count as usize * std::mem::size_of::<c_int>(),
+            )
+        };
+        Ok(ticks)
+    }
+}
+
+impl StatSource for MacCpuStats {
+    fn sample(&mut self) -> anyhow::Result<f64> {
+        let cpu_rates = self.cpurates()?;
+        let load = CpuStats::weighted_load(&cpu_rates, self.n_cpu());
+        debug!("CPU load: {load:.1} sum: {:.1}", cpu_rates[0]);
+        Ok(load)
+    }
+    fn name(&self) -> &'static str {
+        "cpu"
+    }
+    fn unit(&self) -> &'static str {
+        "%"
+    }
+}
+
+// Interface counters from getifaddrs(), the interface may be a list of globs
+// like with IfStats. The 32 bit counters wrap, which the deltas survive as
+// long as the sampling is frequent enough.
+#[derive(Debug)]
+pub struct MacIfStats {
+    iface: String,
+    dir: IfCounter,
+    prev_ts: time::Instant,
+    prev: HashMap<String, u32>,
+}
+
+impl MacIfStats {
+    pub fn new(iface: &str, dir: IfCounter) -> anyhow::Result<Self> {
+        if dir == IfCounter::TxDropped {
+            bail!("Counter {dir} is not available on macOS");
+        }
+        let mut stats = Self {
+            iface: iface.into(),
+            dir,
+            prev_ts: time::Instant::now(),
+            prev: HashMap::new(),
+        };
+        stats.prev = stats.read_counts()?;
+        if stats.prev.is_empty() {
+            warn!("No interface matches {iface}, waiting for it");
+        }
+        Ok(stats)
+    }
+    // per second, bits for the byte counters
+    pub fn rate(&mut self) -> anyhow::Result<f64> {
+        let secs = self.prev_ts.elapsed().as_secs_f64();
+        self.prev_ts = time::Instant::now();
+        let counts = self.read_counts()?;
+        let delta = counts
+            .iter()
+            .filter_map(|(i, c)| self.prev.get(i).map(|p| c.wrapping_sub(*p) as f64))
+            .sum::<f64>();
+        self.prev = counts;
+        let scale = match self.dir {
+            IfCounter::Rx | IfCounter::Tx => 8.0,
+            _ => 1.0,
+        };
+        Ok(scale * delta / secs.max(0.001))
+    }
+
+    fn read_counts(&self) -> anyhow::Result<HashMap<String, u32>> {
+        let patterns = self.iface.split(',').collect::<Vec<&str>>();
+        let (excl, incl): (Vec<&str>, Vec<&str>) =
+            patterns.iter().partition(|p| p.starts_with('!'));
+        let mut ifap = ptr::null_mut();
+        if unsafe { libc::getifaddrs(&mut ifap) } != 0 {
+            bail!("getifaddrs failed: {}", std::io::Error::last_os_error());
+        }
+        let mut counts = HashMap::new();
+        let mut ifa = ifap as *const libc::ifaddrs;
+        while let Some(a) = unsafe { ifa.as_ref() } {
+            ifa = a.ifa_next;
+            let is_link = unsafe { a.ifa_addr.as_ref() }
+                .is_some_and(|s| s.sa_family as c_int == libc::AF_LINK);
+            let data = a.ifa_data as *const IfData;
+            let Some(data) = unsafe { data.as_ref() }.filter(|_| is_link) else {
+                continue;
+            };
+            let name = unsafe { CStr::from_ptr(a.ifa_name) }.to_string_lossy();
+            let included = incl.is_empty() || incl.iter().any(|p| glob_match(p, &name));
+            if !included || excl.iter().any(|p| glob_match(&p[1..], &name)) {
+                continue;
+            }
+            let count = match self.dir {
+                IfCounter::Rx => data.ifi_ibytes,
+                IfCounter::Tx => data.ifi_obytes,
+                IfCounter::RxErrors => data.ifi_ierrors,
+                IfCounter::TxErrors => data.ifi_oerrors,
+                IfCounter::RxDropped | IfCounter::TxDropped => data.ifi_iqdrops,
+            };
+            counts.insert(name.into_owned(), count);
+        }
+        unsafe { libc::freeifaddrs(ifap) };
+        // an interface that is gone shows zero until it comes back
+        Ok(counts)
+    }
+}
+
+impl StatSource for MacIfStats {
+    fn sample(&mut self) -> anyhow::Result<f64> {
+        self.rate()
+    }
+    fn name(&self) -> &'static str {
+        match self.dir {
+            IfCounter::Rx | IfCounter::Tx => "net",
+            _ => "if_counter",
+        }
+    }
+    fn unit(&self) -> &'static str {
+        match self.dir {
+            IfCounter::Rx | IfCounter::Tx => "bit/s",
+            _ => "/s",
+        }
+    }
+}
+
+// Bytes read and written by the IOBlockStorageDriver instances, in 512 byte
+// sectors per second to match DiskStats. Without a device the busiest disk shows.
+#[derive(Debug)]
+pub struct MacDiskStats {
+    device: Option<String>,
+    gauge: DiskGauge,
+    prev_ts: time::Instant,
+    prev: HashMap<String, u64>,
+}
+
+impl MacDiskStats {
+    pub fn with_device(device: Option<&str>, gauge: DiskGauge) -> anyhow::Result<Self> {
+        let mut stats = Self {
+            device: device.map(String::from),
+            gauge,
+            prev_ts: time::Instant::now(),
+            prev: HashMap::new(),
+        };
+        stats.prev = stats.read_bytes()?;
+        if stats.prev.is_empty() {
+            bail!("No disk {} found", device.unwrap_or_default());
+        }
+        Ok(stats)
+    }
+    // busiest first
+    pub fn diskrates(&mut self) -> anyhow::Result<Vec<f64>> {
+        let secs = self.prev_ts.elapsed().as_secs_f64().max(0.001);
+        self.prev_ts = time::Instant::now();
+        let bytes = self.read_bytes()?;
+        let mut rates = bytes
+            .iter()
+            .filter_map(|(d, b)| self.prev.get(d).map(|p| b.saturating_sub(*p) as f64))
+            .map(|b| b / 512.0 / secs)
+            .collect::<Vec<f64>>();
+        rates.sort_by(|a, b| b.total_cmp(a));
+        self.prev = bytes;
+        if rates.is_empty() {
+            rates.push(0.0);
+        }
+        Ok(rates)
+    }
+
+    fn read_bytes(&self) -> anyhow::Result<HashMap<String, u64>> {
+        let class = CString::new("IOBlockStorageDriver")?;
+        let plane = CString::new("IOService")?;
+        let mut iter = 0;
+        // the matching dictionary is consumed by the call
+        let ret = unsafe {
+            IOServiceGetMatchingServices(0, IOServiceMatching(class.as_ptr()), &mut iter)
+        };
+        if ret != 0 {
+            bail!("IOServiceGetMatchingServices failed: {ret}");
+        }
+        let key_stats = CfString::new("Statistics")?;
+        let key_read = CfString::new("Bytes (Read)")?;
+        let key_write = CfString::new("Bytes (Write)")?;
+        let key_bsd = CfString::new("BSD Name")?;
+
+        let mut bytes = HashMap::new();
+        loop {
+            let driver = unsafe { IOIteratorNext(iter) };
+            if driver == 0 {
+                break;
+            }
+            // the BSD name, e.g. disk0, is on the IOMedia below the driver
+            let mut media = 0;
+            let name =
+                match unsafe { IORegistryEntryGetChildEntry(driver, plane.as_ptr(), &mut media) } {
+                    0 => {
+                        let name = cf_property(media, &key_bsd).and_then(|n| n.to_string());
+                        unsafe { IOObjectRelease(media) };
+                        name
+                    }
+                    _ => None,
+                };
+            let stats = cf_property(driver, &key_stats);
+            unsafe { IOObjectRelease(driver) };
+            let (Some(name), Some(stats)) = (name, stats) else {
+                continue;
+            };
+            if self.device.as_ref().is_some_and(|d| *d != name) {
+                continue;
+            }
+            let total = [&key_read, &key_write]
+                .iter()
+                .filter_map(|k| stats.get_i64(k))
+                .sum::<i64>();
+            bytes.insert(name, total.max(0) as u64);
+        }
+        unsafe { IOObjectRelease(iter) };
+        Ok(bytes)
+    }
+}
+
+impl StatSource for MacDiskStats {
+    fn sample(&mut self) -> anyhow::Result<f64> {
+        let disk_rates = self.diskrates()?;
+        debug!("DISK rates: {disk_rates:?}");
+        Ok(self.gauge.apply(&disk_rates))
+    }
+    fn name(&self) -> &'static str {
+        "disk"
+    }
+    fn unit(&self) -> &'static str {
+        "sectors/s"
+    }
+}
+
+// an owned CoreFoundation object, released on drop
+struct CfString(CFTypeRef);
+
+impl CfString {
+    fn new(s: &str) -> anyhow::Result<Self> {
+        let s = CString::new(s)?;
+        let cf = unsafe {
+            CFStringCreateWithCString(ptr::null(), s.as_ptr(), K_CF_STRING_ENCODING_UTF8)
+        };
+        match cf.is_null() {
+            true => Err(anyhow!("CFStringCreateWithCString failed")),
+            false => Ok(Self(cf)),
+        }
+    }
+}
+
+impl Drop for CfString {
+    fn drop(&mut self) {
+        unsafe { CFRelease(self.0) };
+    }
+}
+
+struct CfProperty(CFTypeRef);
+
+impl CfProperty {
+    fn to_string(&self) -> Option<String> {
+        let mut buf = [0 as c_char; 64];
+        match unsafe {
+            CFStringGetCString(
+                self.0,
+                buf.as_mut_ptr(),
+                buf.len() as isize,
+                K_CF_STRING_ENCODING_UTF8,
+            )
+        } {
+            0 => None,
+            _ => Some(
+                unsafe { CStr::from_ptr(buf.as_ptr()) }
+                    .to_string_lossy()
+                    .into_owned(),
+            ),
+        }
+    }
+    // a number in this dictionary, the value stays owned by it
+    fn get_i64(&self, key: &CfString) -> Option<i64> {
+        let num = unsafe { CFDictionaryGetValue(self.0, key.0) };
+        let mut value = 0i64;
+        match !num.is_null()
+            && unsafe {
+                CFNumberGetValue(num, K_CF_NUMBER_SINT64, &mut value as *mut _ as *mut c_void)
+            } != 0
+        {
+            true => Some(value),
+            false => None,
+        }
+    }
+}
+
+impl Drop for CfProperty {
+    fn drop(&mut self) {
+        unsafe { CFRelease(self.0) };
+    }
+}
+
+fn cf_property(entry: MachPort, key: &CfString) -> Option<CfProperty> {
+    let cf = unsafe { IORegistryEntryCreateCFProperty(entry, key.0, ptr::null(), 0) };
+    match cf.is_null() {
+        true => None,
+        false => Some(CfProperty(cf)),
+    }
+}
+
+// EOF
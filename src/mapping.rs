@@ -0,0 +1,824 @@
+// mapping.rs
+
+use std::{fmt, str::FromStr, time};
+
+use anyhow::{anyhow, bail};
+
+use crate::*;
+
+// full scale of the disk gauge in sectors per second, about 100MB/s
+const DISK_FULL_SCALE: f64 = 200_000.0;
+
+// what the perf source shows, see perf.rs
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum PerfMetric {
+    // instructions per cycle
+    #[default]
+    Ipc,
+    // last level cache misses, percent of references
+    LlcMiss,
+}
+
+impl fmt::Display for PerfMetric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                PerfMetric::Ipc => "ipc",
+                PerfMetric::LlcMiss => "llc-miss",
+            }
+        )
+    }
+}
+
+impl FromStr for PerfMetric {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ipc" => Ok(PerfMetric::Ipc),
+            "llc-miss" | "llc_miss" => Ok(PerfMetric::LlcMiss),
+            _ => Err(anyhow!("Unknown perf metric: {s}")),
+        }
+    }
+}
+
+// What a channel shows: "cpu", "net", "net:eth0", "net:eth0:rx", "disk" or "disk:nvme0n1".
+// Without an interface the net source uses --interface, without a direction it
+// shows the busier one. The direction may be any interface counter too, e.g.
+// "net::rx_dropped" against --if-events-max. The disk device may be globs like
+// --disks, without one the disk source shows the busiest of --disks.
+// "steal", "irq" (the busiest cpu), "jitter[:iface[:rx|tx]]", "conntrack",
+// "nft:family/table/name", "failed-units", "journal", "procs-running",
+// "procs-blocked", "perf[:ipc|llc-miss]", "pgfault", "pgmajfault", "dirty",
+// "writeback", "hugepages", "audio", "cpufreq", "throttle", "gpu-temp",
+// "wireguard:peer[:iface]", "ethtool:iface:stat", "snmp:host[:port]",
+// "libvirt:domain[:cpu|block|net]", "k8s:namespace/glob[:cpu|net]", an http(s) URL
+// for its latency, "ntp" and "heartbeat" are the sources that used to have
+// an option of their own, with the full scales and settings of those options.
+// "plugin:/path/libfoo.so[:max[:arg]]" samples a shared library, max is the
+// value giving full scale (100 by default) and the rest goes to its init.
+// "demo:sine[:period]", "demo:walk" and "demo:bursts" are synthetic.
+// "expr:..." computes a gauge from the others, see expr.rs.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SourceSpec {
+    Cpu,
+    Steal,
+    Irq,
+    Net {
+        iface: Option<String>,
+        dir: Option<IfCounter>,
+    },
+    Jitter {
+        iface: Option<String>,
+        dir: Option<IfCounter>,
+    },
+    Disk {
+        device: Option<String>,
+    },
+    Conntrack,
+    Nft(String),
+    FailedUnits,
+    Journal,
+    ProcsRunning,
+    ProcsBlocked,
+    Perf(Option<PerfMetric>),
+    PgFault,
+    PgMajFault,
+    Dirty,
+    Writeback,
+    HugePages,
+    Audio,
+    CpuFreq,
+    Throttle,
+    GpuTemp,
+    WireGuard {
+        peer: String,
+        iface: Option<String>,
+    },
+    Ethtool {
+        iface: String,
+        stat: String,
+    },
+    Snmp(String),
+    Libvirt {
+        domain: String,
+        metric: Option<VmMetric>,
+    },
+    K8s {
+        pods: String,
+        metric: Option<K8sMetric>,
+    },
+    Http(String),
+    Ntp,
+    Heartbeat,
+    Plugin {
+        path: String,
+        max: f64,
+        arg: String,
+    },
+    Demo(Waveform),
+    Expr(Expr),
+}
+
+// "net:eth0:rx", "net::tx" or "net:eth0"
+fn write_iface_dir(
+    f: &mut fmt::Formatter<'_>,
+    iface: &Option<String>,
+    dir: &Option<IfCounter>,
+) -> fmt::Result {
+    match (iface, dir) {
+        (Some(i), _) => write!(f, ":{i}")?,
+        (None, Some(_)) => write!(f, ":")?,
+        (None, None) => {}
+    }
+    match dir {
+        Some(IfCounter::Rx) => write!(f, ":rx"),
+        Some(IfCounter::Tx) => write!(f, ":tx"),
+        Some(counter) => write!(f, ":{counter}"),
+        None => Ok(()),
+    }
+}
+
+impl fmt::Display for SourceSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceSpec::Cpu => write!(f, "cpu"),
+            SourceSpec::Steal => write!(f, "steal"),
+            SourceSpec::Irq => write!(f, "irq"),
+            SourceSpec::Net { iface, dir } => {
+                write!(f, "net")?;
+                write_iface_dir(f, iface, dir)
+            }
+            SourceSpec::Jitter { iface, dir } => {
+                write!(f, "jitter")?;
+                write_iface_dir(f, iface, dir)
+            }
+            SourceSpec::Disk { device: None } => write!(f, "disk"),
+            SourceSpec::Disk { device: Some(d) } => write!(f, "disk:{d}"),
+            SourceSpec::Conntrack => write!(f, "conntrack"),
+            SourceSpec::Nft(counter) => write!(f, "nft:{counter}"),
+            SourceSpec::FailedUnits => write!(f, "failed-units"),
+            SourceSpec::Journal => write!(f, "journal"),
+            SourceSpec::ProcsRunning => write!(f, "procs-running"),
+            SourceSpec::ProcsBlocked => write!(f, "procs-blocked"),
+            SourceSpec::Perf(None) => write!(f, "perf"),
+            SourceSpec::Perf(Some(metric)) => write!(f, "perf:{metric}"),
+            SourceSpec::PgFault => write!(f, "pgfault"),
+            SourceSpec::PgMajFault => write!(f, "pgmajfault"),
+            SourceSpec::Dirty => write!(f, "dirty"),
+            SourceSpec::Writeback => write!(f, "writeback"),
+            SourceSpec::HugePages => write!(f, "hugepages"),
+            SourceSpec::Audio => write!(f, "audio"),
+            SourceSpec::CpuFreq => write!(f, "cpufreq"),
+            SourceSpec::Throttle => write!(f, "throttle"),
+            SourceSpec::GpuTemp => write!(f, "gpu-temp"),
+            SourceSpec::WireGuard { peer, iface: None } => write!(f, "wireguard:{peer}"),
+            SourceSpec::WireGuard {
+                peer,
+                iface: Some(i),
+            } => write!(f, "wireguard:{peer}:{i}"),
+            SourceSpec::Ethtool { iface, stat } => write!(f, "ethtool:{iface}:{stat}"),
+            SourceSpec::Snmp(target) => write!(f, "snmp:{target}"),
+            SourceSpec::Libvirt { domain, metric } => {
+                write!(f, "libvirt:{domain}")?;
+                match metric {
+                    Some(m) => write!(f, ":{m}"),
+                    None => Ok(()),
+                }
+            }
+            SourceSpec::K8s { pods, metric } => {
+                write!(f, "k8s:{pods}")?;
+                match metric {
+                    Some(m) => write!(f, ":{m}"),
+                    None => Ok(()),
+                }
+            }
+            SourceSpec::Http(url) => write!(f, "{url}"),
+            SourceSpec::Ntp => write!(f, "ntp"),
+            SourceSpec::Heartbeat => write!(f, "heartbeat"),
+            SourceSpec::Demo(wave) => write!(f, "demo:{wave}"),
+            SourceSpec::Expr(expr) => write!(f, "expr:{expr}"),
+            SourceSpec::Plugin { path, max, arg } => {
+                write!(f, "plugin:{path}:{max}")?;
+                match arg.is_empty() {
+                    true => Ok(()),
+                    false => write!(f, ":{arg}"),
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for SourceSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // the plugin argument may contain anything
+        if let Some(rest) = s.strip_prefix("plugin:") {
+            let mut parts = rest.splitn(3, ':');
+            let path = parts.next().unwrap_or_default().to_string();
+            if path.is_empty() {
+                bail!("No library given for source {s}");
+            }
+            let max = match parts.next().filter(|m| !m.is_empty()) {
+                Some(m) => m.parse::<f64>()?,
+                None => 100.0,
+            };
+            if max.is_nan() || max <= 0.0 {
+                bail!("Full scale of source {s} must be positive");
+            }
+            let arg = parts.next().unwrap_or_default().to_string();
+            return Ok(SourceSpec::Plugin { path, max, arg });
+        }
+        if let Some(expr) = s.strip_prefix("expr:") {
+            return Ok(SourceSpec::Expr(expr.parse()?));
+        }
+        if let Some(wave) = s.strip_prefix("demo:") {
+            return Ok(SourceSpec::Demo(wave.parse()?));
+        }
+        // the port follows the host, which may be an IPv6 address
+        if let Some(target) = s.strip_prefix("snmp:") {
+            if target.is_empty() {
+                bail!("No agent given for source {s}");
+            }
+            return Ok(SourceSpec::Snmp(target.to_string()));
+        }
+        if s.starts_with("http://") || s.starts_with("https://") {
+            return Ok(SourceSpec::Http(s.to_string()));
+        }
+        let mut parts = s.split(':');
+        let kind = parts.next().unwrap_or_default();
+        let mut arg = || parts.next().filter(|p| !p.is_empty()).map(String::from);
+        let missing = |what: &str| anyhow!("No {what} given for source {s}");
+        let spec = match kind {
+            "cpu" => SourceSpec::Cpu,
+            "steal" => SourceSpec::Steal,
+            "irq" => SourceSpec::Irq,
+            "demo" => SourceSpec::Demo(Waveform::Sine(8.0)),
+            "net" => SourceSpec::Net {
+                iface: arg(),
+                dir: arg().map(|d| d.parse()).transpose()?,
+            },
+            "jitter" => {
+                let iface = arg();
+                let dir = match arg().as_deref() {
+                    None => None,
+                    Some("rx") => Some(IfCounter::Rx),
+                    Some("tx") => Some(IfCounter::Tx),
+                    Some(d) => bail!("Unknown jitter direction {d}, expected rx or tx"),
+                };
+                SourceSpec::Jitter { iface, dir }
+            }
+            "disk" => SourceSpec::Disk { device: arg() },
+            "conntrack" => SourceSpec::Conntrack,
+            "nft" => SourceSpec::Nft(arg().ok_or_else(|| missing("counter"))?),
+            "failed-units" => SourceSpec::FailedUnits,
+            "journal" => SourceSpec::Journal,
+            "procs-running" => SourceSpec::ProcsRunning,
+            "procs-blocked" => SourceSpec::ProcsBlocked,
+            "perf" => SourceSpec::Perf(arg().map(|m| m.parse()).transpose()?),
+            "pgfault" => SourceSpec::PgFault,
+            "pgmajfault" => SourceSpec::PgMajFault,
+            "dirty" => SourceSpec::Dirty,
+            "writeback" => SourceSpec::Writeback,
+            "hugepages" => SourceSpec::HugePages,
+            "audio" => SourceSpec::Audio,
+            "cpufreq" => SourceSpec::CpuFreq,
+            "throttle" => SourceSpec::Throttle,
+            "gpu-temp" => SourceSpec::GpuTemp,
+            "wireguard" => SourceSpec::WireGuard {
+                peer: arg().ok_or_else(|| missing("peer"))?,
+                iface: arg(),
+            },
+            "ethtool" => SourceSpec::Ethtool {
+                iface: arg().ok_or_else(|| missing("interface"))?,
+                stat: arg().ok_or_else(|| missing("stat"))?,
+            },
+            "libvirt" => SourceSpec::Libvirt {
+                domain: arg().ok_or_else(|| missing("domain"))?,
+                metric: arg().map(|m| m.parse()).transpose()?,
+            },
+            "k8s" => SourceSpec::K8s {
+                pods: arg().ok_or_else(|| missing("pods"))?,
+                metric: arg().map(|m| m.parse()).transpose()?,
+            },
+            "ntp" => SourceSpec::Ntp,
+            "heartbeat" => SourceSpec::Heartbeat,
+            _ => return Err(anyhow!("Unknown source: {s}")),
+        };
+        if parts.next().is_some() {
+            bail!("Too many fields in source {s}");
+        }
+        Ok(spec)
+    }
+}
+
+// The three meter box as it always was
+pub fn default_mapping() -> Vec<(u8, SourceSpec)> {
+    vec![
+        (1, SourceSpec::Cpu),
+        (2, SourceSpec::Disk { device: None }),
+        (
+            3,
+            SourceSpec::Net {
+                iface: None,
+                dir: None,
+            },
+        ),
+    ]
+}
+
+// Settings the mapped sources share, the full scales are in the units
+// of the options they come from
+#[derive(Clone, Debug, PartialEq)]
+pub struct MappingConfig {
+    pub interface: String,
+    pub disks: String,
+    pub disk_gauge: DiskGauge,
+    pub cpu_mode: CpuMode,
+    pub max_mbps: u16,
+    // how often the sources read in the background are polled
+    pub period: time::Duration,
+    pub steal_max_pct: f64,
+    pub irq_max_pct: f64,
+    pub if_events_max: u32,
+    pub jitter_dir: IfCounter,
+    pub jitter_window: usize,
+    pub nft_max_mbps: u16,
+    pub failed_units_full: u32,
+    pub journal_priority: u8,
+    pub journal_max_per_min: u32,
+    pub procs_max: u32,
+    pub perf_metric: PerfMetric,
+    pub perf_ipc_max: f64,
+    pub pgfault_max: u32,
+    pub pgmajfault_max: u32,
+    pub audio_device: String,
+    pub audio_peak: bool,
+    pub audio_floor_db: f64,
+    pub gpu_temp_idle: f64,
+    pub gpu_temp_redline: f64,
+    pub wg_interface: String,
+    pub wg_max_mbps: u16,
+    pub ethtool_max: u32,
+    pub snmp_auth: SnmpAuth,
+    pub snmp_ifindex: u32,
+    pub snmp_interval: u32,
+    pub snmp_max_mbps: u16,
+    pub libvirt_uri: Option<String>,
+    pub libvirt_metric: VmMetric,
+    pub libvirt_max: u32,
+    pub k8s_metric: K8sMetric,
+    pub k8s_url: String,
+    pub k8s_token_file: String,
+    pub k8s_interval: u32,
+    pub k8s_max: u32,
+    pub http_max_ms: u32,
+    pub ntp_max_us: u32,
+    pub heartbeat_period: f64,
+    pub heartbeat_level: u8,
+}
+
+// One channel wired to its source, which is sampled and scaled onto
+// the gauge. Any StatSource can be wired, not only the mapped ones.
+pub struct ChannelSource {
+    pub channel: u8,
+    // what the channel shows, for the logs
+    pub label: String,
+    source: Box<dyn StatSource>,
+    // the values of the source giving zero and full scale
+    zero: f64,
+    full_scale: f64,
+    interval: time::Duration,
+    adaptive: Option<AdaptiveScale>,
+    // the two latest samples, the gauge glides from the first to the second
+    prev: Option<Sample>,
+    last: Option<Sample>,
+}
+
+impl ChannelSource {
+    pub fn new(channel: u8, spec: SourceSpec, cfg: &MappingConfig) -> anyhow::Result<Self> {
+        let mbps = |max: u16| max as f64 * 1_000_000.0;
+        let (source, full_scale): (Box<dyn StatSource>, f64) = match &spec {
+            SourceSpec::Cpu => (cpu_source(cfg.cpu_mode)?, 100.0),
+            SourceSpec::Steal => {
+                let mut stats = CpuStats::with_mode(CpuMode::Steal)?;
+                let source = FnSource::new("steal", "%", move || Ok(stats.cpurates()?[0]));
+                (Box::new(source), cfg.steal_max_pct)
+            }
+            // packet processing tends to pile up on one cpu, so show the busiest one
+            SourceSpec::Irq => {
+                let mut stats = CpuStats::with_mode(CpuMode::Irq)?;
+                let source = FnSource::new("irq", "%", move || {
+                    let rates = stats.cpurates()?;
+                    Ok(rates.get(1).copied().unwrap_or(rates[0]))
+                });
+                (Box::new(source), cfg.irq_max_pct)
+            }
+            SourceSpec::Net { iface, dir } => {
+                let iface = iface.as_deref().unwrap_or(&cfg.interface);
+                let dirs = match dir {
+                    Some(d) => vec![*d],
+                    None => vec![IfCounter::Rx, IfCounter::Tx],
+                };
+                let busiest = Busiest(
+                    dirs.into_iter()
+                        .map(|d| net_source(iface, d))
+                        .collect::<anyhow::Result<_>>()?,
+                );
+                let full_scale = match dir {
+                    None | Some(IfCounter::Rx | IfCounter::Tx) => mbps(cfg.max_mbps),
+                    Some(_) => cfg.if_events_max as f64,
+                };
+                (Box::new(busiest), full_scale)
+            }
+            // against the same scale as the traffic gauge
+            SourceSpec::Jitter { iface, dir } => {
+                let jitter = IfJitter::new(
+                    iface.as_deref().unwrap_or(&cfg.interface),
+                    dir.unwrap_or(cfg.jitter_dir),
+                    cfg.jitter_window,
+                )?;
+                (Box::new(jitter), mbps(cfg.max_mbps))
+            }
+            SourceSpec::Disk { device } => {
+                let device = device
+                    .as_deref()
+                    .or(Some(cfg.disks.as_str()).filter(|d| !d.is_empty()));
+                (disk_source(device, cfg.disk_gauge)?, DISK_FULL_SCALE)
+            }
+            SourceSpec::Conntrack => (Box::new(ConntrackStats::new()?), 100.0),
+            SourceSpec::Nft(counter) => (
+                Box::new(NftCounter::new(counter, cfg.period)?),
+                mbps(cfg.nft_max_mbps),
+            ),
+            // no full scale pegs the needle on any failure
+            #[cfg(target_os = "linux")]
+            SourceSpec::FailedUnits => (
+                Box::new(FailedUnits::new(time::Duration::from_secs(5))?),
+                cfg.failed_units_full.max(1) as f64,
+            ),
+            #[cfg(target_os = "linux")]
+            SourceSpec::Journal => (
+                Box::new(JournalRate::new(cfg.journal_priority)?),
+                cfg.journal_max_per_min as f64,
+            ),
+            SourceSpec::ProcsRunning | SourceSpec::ProcsBlocked => {
+                let running = spec == SourceSpec::ProcsRunning;
+                let source = FnSource::new(
+                    if running {
+                        "procs_running"
+                    } else {
+                        "procs_blocked"
+                    },
+                    "",
+                    move || {
+                        let procs = ProcsStats::read()?;
+                        Ok(if running {
+                            procs.running
+                        } else {
+                            procs.blocked
+                        } as f64)
+                    },
+                );
+                let full_scale = match cfg.procs_max {
+                    0 => cpu_count()? as f64,
+                    n => n as f64,
+                };
+                (Box::new(source), full_scale)
+            }
+            #[cfg(target_os = "linux")]
+            SourceSpec::Perf(metric) => {
+                let perf = PerfCounters::new(metric.unwrap_or(cfg.perf_metric))?;
+                let full_scale = match perf.metric {
+                    PerfMetric::Ipc => cfg.perf_ipc_max,
+                    PerfMetric::LlcMiss => 100.0,
+                };
+                (Box::new(perf), full_scale)
+            }
+            SourceSpec::PgFault => {
+                let mut faults = FaultStats::new()?;
+                let source = FnSource::new("pgfault", "/s", move || Ok(faults.faultrates()?.0));
+                (Box::new(source), cfg.pgfault_max as f64)
+            }
+            SourceSpec::PgMajFault => {
+                let mut faults = FaultStats::new()?;
+                let source = FnSource::new("pgmajfault", "/s", move || Ok(faults.faultrates()?.1));
+                (Box::new(source), cfg.pgmajfault_max as f64)
+            }
+            SourceSpec::Dirty => {
+                let source = FnSource::new("dirty", "%", || Ok(DirtyStats::read()?.dirty_pct()));
+                (Box::new(source), 100.0)
+            }
+            SourceSpec::Writeback => {
+                let source =
+                    FnSource::new("writeback", "%", || Ok(DirtyStats::read()?.writeback_pct()));
+                (Box::new(source), 100.0)
+            }
+            SourceSpec::HugePages => {
+                let source = FnSource::new("hugepages", "%", || Ok(HugePages::read()?.used_pct()));
+                (Box::new(source), 100.0)
+            }
+            // dBFS from the floor up to 0 dB
+            SourceSpec::Audio => (
+                Box::new(AudioMeter::new(&cfg.audio_device, cfg.audio_peak)?),
+                0.0,
+            ),
+            SourceSpec::CpuFreq => (Box::new(CpuFreq::new()?), 100.0),
+            #[cfg(target_os = "linux")]
+            SourceSpec::Throttle => (Box::new(ThermalThrottle::new()?), 1.0),
+            SourceSpec::GpuTemp => (
+                Box::new(GpuTemp::new()?),
+                cfg.gpu_temp_redline.max(cfg.gpu_temp_idle + 1.0),
+            ),
+            SourceSpec::WireGuard { peer, iface } => (
+                Box::new(WgPeer::new(
+                    iface.as_deref().unwrap_or(&cfg.wg_interface),
+                    peer.as_str(),
+                )?),
+                mbps(cfg.wg_max_mbps),
+            ),
+            #[cfg(target_os = "linux")]
+            SourceSpec::Ethtool { iface, stat } => (
+                Box::new(EthtoolStat::new(format!("{iface}:{stat}"))?),
+                cfg.ethtool_max as f64,
+            ),
+            SourceSpec::Snmp(target) => (
+                Box::new(SnmpIf::new(
+                    target,
+                    &cfg.snmp_auth,
+                    cfg.snmp_ifindex,
+                    time::Duration::from_secs(cfg.snmp_interval.max(1) as u64),
+                )?),
+                mbps(cfg.snmp_max_mbps),
+            ),
+            SourceSpec::Libvirt { domain, metric } => {
+                let vm = VmStats::new(
+                    domain,
+                    metric.unwrap_or(cfg.libvirt_metric),
+                    cfg.libvirt_uri.clone(),
+                    cfg.period,
+                )?;
+                let full_scale = match vm.metric {
+                    VmMetric::Cpu => 100.0,
+                    _ => cfg.libvirt_max as f64 * 1_000_000.0,
+                };
+                (Box::new(vm), full_scale)
+            }
+            SourceSpec::K8s { pods, metric } => {
+                let pods = K8sPods::new(
+                    pods.as_str(),
+                    metric.unwrap_or(cfg.k8s_metric),
+                    &cfg.k8s_url,
+                    &cfg.k8s_token_file,
+                    time::Duration::from_secs(cfg.k8s_interval.max(1) as u64),
+                )?;
+                let full_scale = match (pods.metric, cfg.k8s_max) {
+                    (K8sMetric::Cpu, 0) => cpu_count()? as f64,
+                    (K8sMetric::Cpu, max) => max as f64,
+                    (K8sMetric::Net, max) => max.max(1) as f64 * 1_000_000.0,
+                };
+                (Box::new(pods), full_scale)
+            }
+            SourceSpec::Http(url) => (
+                Box::new(HttpProbe::new(
+                    url,
+                    cfg.period,
+                    time::Duration::from_millis(cfg.http_max_ms as u64),
+                )?),
+                cfg.http_max_ms as f64,
+            ),
+            SourceSpec::Ntp => (
+                Box::new(ClockOffset::new(time::Duration::from_secs(1))?),
+                cfg.ntp_max_us as f64,
+            ),
+            // proves that the loop is running
+            SourceSpec::Heartbeat => (
+                Box::new(Heartbeat::new(
+                    time::Duration::from_secs_f64(cfg.heartbeat_period.max(0.1)),
+                    cfg.heartbeat_level as f64,
+                )),
+                256.0,
+            ),
+            #[cfg(unix)]
+            SourceSpec::Plugin { path, max, arg } => (Box::new(Plugin::load(path, arg)?), *max),
+            #[cfg(not(target_os = "linux"))]
+            SourceSpec::FailedUnits
+            | SourceSpec::Journal
+            | SourceSpec::Perf(_)
+            | SourceSpec::Throttle
+            | SourceSpec::Ethtool { .. } => bail!("The {spec} source is Linux only"),
+            #[cfg(not(unix))]
+            SourceSpec::Plugin { .. } => bail!("Plugins are not supported on this system"),
+            SourceSpec::Demo(wave) => (Box::new(DemoSource::new(*wave)), 100.0),
+            SourceSpec::Expr(expr) => (Box::new(ExprSource::new(expr, cfg)?), 100.0),
+        };
+        let zero = match spec {
+            SourceSpec::Audio => cfg.audio_floor_db,
+            SourceSpec::GpuTemp => cfg.gpu_temp_idle,
+            _ => 0.0,
+        };
+        let mut source = Self::with_source(channel, spec.to_string(), source, full_scale);
+        source.zero = zero;
+        Ok(source)
+    }
+
+    // full_scale is the value of the source that gives a full scale gauge
+    pub fn with_source(
+        channel: u8,
+        label: String,
+        source: Box<dyn StatSource>,
+        full_scale: f64,
+    ) -> Self {
+        Self {
+            channel,
+            label,
+            source,
+            zero: 0.0,
+            full_scale,
+            interval: time::Duration::ZERO,
+            adaptive: None,
+            prev: None,
+            last: None,
+        }
+    }
+
+    // read the source only this often and interpolate in between,
+    // which delays the gauge by one interval
+    pub fn every(mut self, interval: time::Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    // scale by the history within the window instead of the full scale
+    pub fn adaptive(mut self, window: Option<time::Duration>) -> Self {
+        self.adaptive = window.map(AdaptiveScale::new);
+        self
+    }
+
+    pub fn sample(&mut self) -> anyhow::Result<Sample> {
+        if self.interval.is_zero() {
+            return self.read();
+        }
+        let now = time::Instant::now();
+        match (self.prev, self.last) {
+            (Some(prev), Some(last)) if now - last.ts < self.interval => {
+                let t = (now - last.ts).as_secs_f64() / self.interval.as_secs_f64();
+                let glide = |a: f64, b: f64| a + (b - a) * t;
+                let mut sample = Sample::at(glide(prev.value, last.value), now).source(last.source);
+                if let (Some(a), Some(b)) = (prev.raw, last.raw) {
+                    sample = sample.raw(glide(a, b));
+                }
+                Ok(sample)
+            }
+            (None, Some(last)) if now - last.ts < self.interval => Ok(Sample { ts: now, ..last }),
+            _ => {
+                let sample = self.read()?;
+                let shown = self.last.unwrap_or(sample);
+                self.prev = self.last.replace(sample);
+                Ok(Sample { ts: now, ..shown })
+            }
+        }
+    }
+
+    // a failing source shows zero on its channel instead of ending the loop
+    pub fn sample_or_zero(&mut self) -> Sample {
+        let name = self.source.name();
+        self.sample().unwrap_or_else(|e| {
+            debug!("Channel {} {}: {e}, showing zero", self.channel, self.label);
+            count_error(name);
+            Sample::new(0.0).source(name)
+        })
+    }
+
+    fn read(&mut self) -> anyhow::Result<Sample> {
+        let (value, ts) = self.source.sample_at()?;
+        // an unknown reading, e.g. a failed probe, pegs the needle
+        let gauge = match (value, &mut self.adaptive) {
+            (f64::INFINITY, _) => 255.0,
+            (f64::NEG_INFINITY, _) => 0.0,
+            (_, Some(adaptive)) => adaptive.gauge(value),
+            (_, None) => 256.0 * (value - self.zero) / (self.full_scale - self.zero),
+        };
+        debug!(
+            "{} {} gauge: {gauge:.1} value: {value:.1}{}",
+            self.source.name().to_uppercase(),
+            self.channel,
+            self.source.unit()
+        );
+        let sample = Sample::at(gauge, ts).source(self.source.name());
+        Ok(match value.is_finite() {
+            true => sample.raw(value),
+            false => sample,
+        })
+    }
+}
+
+// The readers behind cpu, net and disk on each platform
+#[cfg(not(any(target_os = "macos", windows)))]
+pub fn cpu_count() -> anyhow::Result<usize> {
+    Ok(CpuStats::new()?.n_cpu())
+}
+#[cfg(not(any(target_os = "macos", windows)))]
+pub(crate) fn cpu_source(mode: CpuMode) -> anyhow::Result<Box<dyn StatSource>> {
+    Ok(Box::new(CpuStats::with_mode(mode)?))
+}
+#[cfg(not(any(target_os = "macos", windows)))]
+pub(crate) fn net_source(iface: &str, dir: IfCounter) -> anyhow::Result<Box<dyn StatSource>> {
+    Ok(Box::new(IfStats::new(iface, dir)?))
+}
+#[cfg(not(any(target_os = "macos", windows)))]
+pub(crate) fn disk_source(
+    device: Option<&str>,
+    gauge: DiskGauge,
+) -> anyhow::Result<Box<dyn StatSource>> {
+    Ok(Box::new(DiskStats::with_device(device, gauge)?))
+}
+
+#[cfg(target_os = "macos")]
+pub fn cpu_count() -> anyhow::Result<usize> {
+    Ok(MacCpuStats::new(CpuMode::Busy)?.n_cpu())
+}
+#[cfg(target_os = "macos")]
+pub(crate) fn cpu_source(mode: CpuMode) -> anyhow::Result<Box<dyn StatSource>> {
+    Ok(Box::new(MacCpuStats::new(mode)?))
+}
+#[cfg(target_os = "macos")]
+pub(crate) fn net_source(iface: &str, dir: IfCounter) -> anyhow::Result<Box<dyn StatSource>> {
+    Ok(Box::new(MacIfStats::new(iface, dir)?))
+}
+#[cfg(target_os = "macos")]
+pub(crate) fn disk_source(
+    device: Option<&str>,
+    gauge: DiskGauge,
+) -> anyhow::Result<Box<dyn StatSource>> {
+    Ok(Box::new(MacDiskStats::with_device(device, gauge)?))
+}
+
+#[cfg(windows)]
+pub fn cpu_count() -> anyhow::Result<usize> {
+    Ok(WinCpuStats::new(CpuMode::Busy)?.n_cpu())
+}
+#[cfg(windows)]
+pub(crate) fn cpu_source(mode: CpuMode) -> anyhow::Result<Box<dyn StatSource>> {
+    Ok(Box::new(WinCpuStats::new(mode)?))
+}
+#[cfg(windows)]
+pub(crate) fn net_source(iface: &str, dir: IfCounter) -> anyhow::Result<Box<dyn StatSource>> {
+    Ok(Box::new(WinIfStats::new(iface, dir)?))
+}
+#[cfg(windows)]
+pub(crate) fn disk_source(
+    device: Option<&str>,
+    gauge: DiskGauge,
+) -> anyhow::Result<Box<dyn StatSource>> {
+    Ok(Box::new(WinDiskStats::with_device(device, gauge)?))
+}
+
+// the highest of several sources, e.g. the busier direction of an interface
+pub(crate) struct Busiest(pub(crate) Vec<Box<dyn StatSource>>);
+
+impl StatSource for Busiest {
+    fn sample(&mut self) -> anyhow::Result<f64> {
+        let mut max = 0.0f64;
+        for source in self.0.iter_mut() {
+            max = max.max(source.sample()?);
+        }
+        Ok(max)
+    }
+    fn name(&self) -> &'static str {
+        self.0.first().map_or("", |s| s.name())
+    }
+    fn unit(&self) -> &'static str {
+        self.0.first().map_or("", |s| s.unit())
+    }
+}
+
+// A reading without a StatSource of its own, e.g. one of the values of /proc/vmstat
+pub(crate) struct FnSource<F> {
+    read: F,
+    name: &'static str,
+    unit: &'static str,
+}
+
+impl<F: FnMut() -> anyhow::Result<f64>> FnSource<F> {
+    pub(crate) fn new(name: &'static str, unit: &'static str, read: F) -> Self {
+        Self { read, name, unit }
+    }
+}
+
+impl<F: FnMut() -> anyhow::Result<f64>> StatSource for FnSource<F> {
+    fn sample(&mut self) -> anyhow::Result<f64> {
+        (self.read)()
+    }
+    fn name(&self) -> &'static str {
+        self.name
+    }
+    fn unit(&self) -> &'static str {
+        self.unit
+    }
+}
+
+// EOF
@@ -0,0 +1,662 @@
+// meter.rs
+
+#[cfg(not(windows))]
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::{collections::BTreeMap, fmt, str::FromStr, thread, time};
+
+use anyhow::{anyhow, bail};
+
+use crate::*;
+
+// Where the output goes, a meter device or anything else showing the gauges.
+// set() is called for the changed channels of a frame with the smoothed and
+// quantized needle positions, flush() once after them.
+pub trait Sink: Send {
+    fn set(&mut self, channel: u8, value: u8) -> anyhow::Result<()>;
+    // the whole frame of a tick before the alerts, for the outputs that show more
+    // than the needles
+    fn frame(&mut self, _frame: &Frame) {}
+    // false for the outputs that only take the frames, they skip the smoothing
+    fn needles(&self) -> bool {
+        true
+    }
+    fn flush(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+    // the hello sweep is only worth watching on real needles
+    fn sweeps(&self) -> bool {
+        false
+    }
+    // true once after the device came back, the meter then starts over
+    fn take_reconnect(&mut self) -> bool {
+        false
+    }
+}
+
+// 3F asks the firmware who it is, it answers with a line like "VUmeter 1.2"
+const IDENTIFY: u8 = 0x3F;
+const IDENTIFY_TIMEOUT: time::Duration = time::Duration::from_millis(500);
+
+const RECONNECT_MIN: time::Duration = time::Duration::from_secs(1);
+const RECONNECT_MAX: time::Duration = time::Duration::from_secs(60);
+// with checksums every needle is sent again this often, a lost frame only
+// leaves it off for a moment
+const CRC_REFRESH: time::Duration = time::Duration::from_secs(1);
+
+// How a serial meter frames its commands
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Protocol {
+    // FD length payload
+    #[default]
+    Plain,
+    // FD length payload crc, the length covers the CRC-8 of the length and
+    // payload, frames that do not check out are dropped until the next FD
+    Crc,
+}
+
+impl FromStr for Protocol {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(Protocol::Plain),
+            "crc" => Ok(Protocol::Crc),
+            _ => Err(anyhow!("Unknown protocol: {s}")),
+        }
+    }
+}
+
+impl Protocol {
+    fn frame(self, payload: &[u8], buf: &mut Vec<u8>) {
+        buf.push(0xFD);
+        match self {
+            Protocol::Plain => {
+                buf.push(payload.len() as u8);
+                buf.extend_from_slice(payload);
+            }
+            Protocol::Crc => {
+                let len = payload.len() as u8 + 1;
+                buf.push(len);
+                buf.extend_from_slice(payload);
+                buf.push(crc8(&[&[len], payload].concat()));
+            }
+        }
+    }
+}
+
+// CRC-8 with the polynomial x^8 + x^2 + x + 1
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |crc, b| {
+        (0..8).fold(crc ^ b, |c, _| match c & 0x80 {
+            0 => c << 1,
+            _ => (c << 1) ^ 0x07,
+        })
+    })
+}
+
+// How the serial meters are opened and spoken to
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SerialConfig {
+    pub handshake: Handshake,
+    pub protocol: Protocol,
+}
+
+// The meter box: 30+channel value for each needle, framed by the Protocol.
+// A failed write or the device node going away closes the port. It is reopened
+// as soon as the node is back, with an exponential backoff while opening or
+// writing keeps failing. The frames in between are dropped.
+pub struct SerialSink {
+    // as given, a device path or usb:VID:PID[:SERIAL]
+    port: String,
+    // the device it was found at
+    path: String,
+    cfg: SerialConfig,
+    ser: Option<File>,
+    buf: Vec<u8>,
+    // the needles as last set, for the refresh
+    needles: BTreeMap<u8, u8>,
+    refresh_at: time::Instant,
+    retry_at: time::Instant,
+    backoff: time::Duration,
+    reconnected: bool,
+}
+
+impl SerialSink {
+    pub fn open(port: &str, cfg: SerialConfig) -> anyhow::Result<Self> {
+        let path = locate(port)?;
+        info!("Opening serial port {path}");
+        Ok(Self {
+            port: port.into(),
+            ser: Some(Self::open_port(&path, cfg)?),
+            path,
+            cfg,
+            buf: Vec::with_capacity(64),
+            needles: BTreeMap::new(),
+            refresh_at: time::Instant::now(),
+            retry_at: time::Instant::now(),
+            backoff: RECONNECT_MIN,
+            reconnected: false,
+        })
+    }
+
+    // attaches once the device is plugged in
+    pub fn waiting(port: &str, cfg: SerialConfig) -> Self {
+        Self {
+            port: port.into(),
+            path: locate(port).unwrap_or_default(),
+            cfg,
+            ser: None,
+            buf: Vec::with_capacity(64),
+            needles: BTreeMap::new(),
+            refresh_at: time::Instant::now(),
+            retry_at: time::Instant::now(),
+            backoff: RECONNECT_MIN,
+            reconnected: false,
+        }
+    }
+
+    fn open_port(path: &str, cfg: SerialConfig) -> anyhow::Result<File> {
+        let mut ser = OpenOptions::new().read(true).write(true).open(path)?;
+        if cfg.handshake == Handshake::Off {
+            return Ok(ser);
+        }
+        match identify(&mut ser, cfg.protocol)? {
+            Some(version) => info!("Meter on {path} is {version}"),
+            None if cfg.handshake == Handshake::Require => {
+                bail!("No meter answering on {path}")
+            }
+            None => warn!("No answer from the meter on {path}, is it the right port?"),
+        }
+        Ok(ser)
+    }
+
+    fn reconnect(&mut self) {
+        let now = time::Instant::now();
+        // polling the node is cheap, a device plugged in gets opened right away
+        if let Ok(path) = locate(&self.port) {
+            self.path = path;
+        }
+        if self.path.is_empty() || !present(&self.path) {
+            self.retry_at = now;
+            return;
+        }
+        if now < self.retry_at {
+            return;
+        }
+        // only a successful write resets the backoff, a flapping device backs off too
+        self.backoff = (self.backoff * 2).min(RECONNECT_MAX);
+        match Self::open_port(&self.path, self.cfg) {
+            Ok(ser) => {
+                info!("Meter attached on {}", self.path);
+                self.ser = Some(ser);
+                self.reconnected = true;
+            }
+            Err(e) => {
+                debug!("Reopening {} failed: {e}", self.path);
+                self.retry_at = now + self.backoff;
+            }
+        }
+    }
+}
+
+impl Sink for SerialSink {
+    fn set(&mut self, channel: u8, value: u8) -> anyhow::Result<()> {
+        self.cfg
+            .protocol
+            .frame(&[0x30 + channel, value], &mut self.buf);
+        self.needles.insert(channel, value);
+        Ok(())
+    }
+    fn flush(&mut self) -> anyhow::Result<()> {
+        let Some(ser) = &mut self.ser else {
+            self.buf.clear();
+            self.reconnect();
+            return Ok(());
+        };
+        if !present(&self.path) {
+            info!("Meter on {} removed", self.path);
+            self.buf.clear();
+            self.ser = None;
+            return Ok(());
+        }
+        if self.cfg.protocol == Protocol::Crc && self.refresh_at.elapsed() >= CRC_REFRESH {
+            self.refresh_at = time::Instant::now();
+            self.buf.clear();
+            for (channel, value) in &self.needles {
+                self.cfg
+                    .protocol
+                    .frame(&[0x30 + channel, *value], &mut self.buf);
+            }
+        }
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let res = ser.write_all(&self.buf);
+        self.buf.clear();
+        match res {
+            Ok(()) => self.backoff = RECONNECT_MIN,
+            Err(e) => {
+                error!(
+                    "Serial port {} lost, retrying in {:?}: {e}",
+                    self.path, self.backoff
+                );
+                count_error("serial");
+                self.ser = None;
+                self.retry_at = time::Instant::now() + self.backoff;
+            }
+        }
+        Ok(())
+    }
+    fn sweeps(&self) -> bool {
+        true
+    }
+    fn take_reconnect(&mut self) -> bool {
+        std::mem::take(&mut self.reconnected)
+    }
+}
+
+// the firmware's answer to IDENTIFY, None if there is none in time
+fn identify(ser: &mut File, protocol: Protocol) -> anyhow::Result<Option<String>> {
+    let mut cmd = Vec::new();
+    protocol.frame(&[IDENTIFY], &mut cmd);
+    ser.write_all(&cmd)?;
+    let deadline = time::Instant::now() + IDENTIFY_TIMEOUT;
+    let mut line = Vec::new();
+    while line.len() < 64 {
+        let left = deadline.saturating_duration_since(time::Instant::now());
+        if left.is_zero() || !sys::sys_poll_read(&*ser, left.as_millis() as i32)? {
+            break;
+        }
+        let mut b = [0u8; 1];
+        match ser.read(&mut b)? {
+            0 => break,
+            _ if b[0] == b'\n' => {
+                let version = String::from_utf8_lossy(&line).trim().to_string();
+                return Ok((!version.is_empty()).then_some(version));
+            }
+            _ => line.push(b[0]),
+        }
+    }
+    Ok(None)
+}
+
+// a USB serial adapter by its vendor and product id, and optionally its serial number
+#[derive(Clone, Debug, PartialEq)]
+pub struct UsbId {
+    pub vid: u16,
+    pub pid: u16,
+    pub serial: Option<String>,
+}
+
+impl fmt::Display for UsbId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04x}:{:04x}", self.vid, self.pid)?;
+        match &self.serial {
+            Some(s) => write!(f, ":{s}"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl FromStr for UsbId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, ':');
+        let mut hex = || {
+            let p = parts.next().unwrap_or_default();
+            u16::from_str_radix(p, 16).map_err(|e| anyhow!("USB id {s}: {e}"))
+        };
+        let (vid, pid) = (hex()?, hex()?);
+        let serial = parts.next().filter(|p| !p.is_empty()).map(String::from);
+        Ok(Self { vid, pid, serial })
+    }
+}
+
+impl UsbId {
+    // the tty of the first matching adapter, e.g. /dev/ttyUSB0
+    pub fn find_tty(&self) -> Option<String> {
+        serial_ports()
+            .into_iter()
+            .find(|p| p.usb.as_ref().is_some_and(|u| self.matches(u)))
+            .map(|p| p.path)
+    }
+
+    // without a serial number any adapter with the ids matches
+    fn matches(&self, found: &UsbId) -> bool {
+        self.vid == found.vid
+            && self.pid == found.pid
+            && self
+                .serial
+                .as_ref()
+                .is_none_or(|s| found.serial.as_ref() == Some(s))
+    }
+}
+
+// A serial port backed by hardware, and the USB adapter it is on
+#[derive(Clone, Debug)]
+pub struct PortInfo {
+    pub path: String,
+    pub usb: Option<UsbId>,
+    pub product: Option<String>,
+}
+
+// the ttys in sysfs that have a device behind them, sorted by name
+#[cfg(not(windows))]
+pub fn serial_ports() -> Vec<PortInfo> {
+    let Ok(entries) = fs::read_dir(sys_path("class/tty")) else {
+        return Vec::new();
+    };
+    let mut ttys = entries
+        .flatten()
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    ttys.sort();
+    ttys.into_iter()
+        .filter_map(|tty| {
+            let device = fs::canonicalize(sys_path(&format!("class/tty/{tty}/device"))).ok()?;
+            // the USB device is one of the parents of the tty's interface
+            let usb = device.ancestors().find(|d| d.join("idVendor").exists());
+            let read = |f: &str| {
+                let v = fs::read_to_string(usb?.join(f)).ok()?;
+                Some(v.trim().to_string())
+            };
+            let id = |f| u16::from_str_radix(&read(f)?, 16).ok();
+            let usb_id = match (id("idVendor"), id("idProduct")) {
+                (Some(vid), Some(pid)) => Some(UsbId {
+                    vid,
+                    pid,
+                    serial: read("serial"),
+                }),
+                _ => None,
+            };
+            Some(PortInfo {
+                path: format!("/dev/{tty}"),
+                usb: usb_id,
+                product: read("product"),
+            })
+        })
+        .collect()
+}
+
+// the COM ports, without the USB ids Windows keeps in the registry
+#[cfg(windows)]
+pub fn serial_ports() -> Vec<PortInfo> {
+    sys::sys_com_ports()
+        .into_iter()
+        .map(|path| PortInfo {
+            path,
+            usb: None,
+            product: None,
+        })
+        .collect()
+}
+
+// the device path of a port, usb:VID:PID[:SERIAL] is looked up
+// and on Windows COM3 is opened as \\.\COM3, as needed from COM10 on
+fn locate(port: &str) -> anyhow::Result<String> {
+    #[cfg(windows)]
+    if port.len() > 3 && port.get(..3).is_some_and(|p| p.eq_ignore_ascii_case("com")) {
+        return Ok(format!(r"\\.\{port}"));
+    }
+    let Some(id) = port.strip_prefix("usb:") else {
+        return Ok(port.into());
+    };
+    let id = id.parse::<UsbId>()?;
+    id.find_tty()
+        .ok_or_else(|| anyhow!("No USB serial device {id} found"))
+}
+
+// whether the device node is there, on Windows only opening the COM port tells
+#[cfg(not(windows))]
+fn present(path: &str) -> bool {
+    std::path::Path::new(path).exists()
+}
+
+#[cfg(windows)]
+fn present(_path: &str) -> bool {
+    true
+}
+
+// prints "channel value" lines, what the meter would be sent
+pub struct StdoutSink;
+
+impl Sink for StdoutSink {
+    fn set(&mut self, channel: u8, value: u8) -> anyhow::Result<()> {
+        writeln!(io::stdout(), "{channel} {value}")?;
+        Ok(())
+    }
+    fn flush(&mut self) -> anyhow::Result<()> {
+        io::stdout().flush()?;
+        Ok(())
+    }
+}
+
+// prints what would be written to a serial meter, the frames in hex included
+pub struct DryRunSink {
+    path: String,
+    protocol: Protocol,
+}
+
+impl DryRunSink {
+    pub fn new(path: &str, protocol: Protocol) -> Self {
+        Self {
+            path: path.into(),
+            protocol,
+        }
+    }
+}
+
+impl Sink for DryRunSink {
+    fn set(&mut self, channel: u8, value: u8) -> anyhow::Result<()> {
+        let mut frame = Vec::new();
+        self.protocol.frame(&[0x30 + channel, value], &mut frame);
+        let hex = frame
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        writeln!(io::stdout(), "{} {channel} {value} {hex}", self.path)?;
+        Ok(())
+    }
+    fn flush(&mut self) -> anyhow::Result<()> {
+        io::stdout().flush()?;
+        Ok(())
+    }
+}
+
+pub struct NullSink;
+
+impl Sink for NullSink {
+    fn set(&mut self, _channel: u8, _value: u8) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+impl VirtualMeter {
+    pub fn sink(self) -> Box<dyn Sink> {
+        match self {
+            VirtualMeter::Null => Box::new(NullSink),
+            VirtualMeter::Stdout => Box::new(StdoutSink),
+        }
+    }
+}
+
+// One meter device, serial or virtual, or another output behind a Sink,
+// with the smoothing state of its channels
+pub struct Meter {
+    pub port: MeterPort,
+    pub out: Box<dyn Sink>,
+    pub gauges: GaugeState,
+}
+
+impl Meter {
+    pub fn new(port: MeterPort, out: Box<dyn Sink>) -> Self {
+        Self {
+            port,
+            out,
+            gauges: GaugeState::new(),
+        }
+    }
+
+    pub fn open(port: &MeterPort, cfg: SerialConfig) -> Self {
+        match SerialSink::open(&port.path, cfg) {
+            Ok(ser) => {
+                let mut meter = Self::new(port.clone(), Box::new(ser));
+                info!("Vu sez hi (:");
+                if let Err(e) = meter.hello() {
+                    info!("Hello sweep failed: {e}");
+                }
+                meter
+            }
+            Err(e) => {
+                info!("No meter attached on {}, waiting for it: {e}", port.path);
+                Self::new(port.clone(), Box::new(SerialSink::waiting(&port.path, cfg)))
+            }
+        }
+    }
+
+    // takes every channel and goes through the same smoothing, without the hello sweep
+    pub fn virtual_meter(virt: VirtualMeter) -> Self {
+        Self::new(MeterPort::default(), virt.sink())
+    }
+
+    // moves the needle towards the gauge, no faster than the ballistics allow
+    pub fn set_vu(
+        &mut self,
+        channel: u8,
+        gauge: f64,
+        quant: u8,
+        ballistics: Ballistics,
+    ) -> anyhow::Result<()> {
+        if !self.out.needles() {
+            return Ok(());
+        }
+        let new_value =
+            self.gauges
+                .channel(channel)?
+                .smooth(gauge, ballistics, time::Instant::now());
+        self.write_vu(channel, new_value.round() as i16, quant)
+    }
+
+    // only writes when the quantized value changes
+    pub fn write_vu(&mut self, channel: u8, value: i16, quant: u8) -> anyhow::Result<()> {
+        match self.gauges.channel(channel)?.quantize(value, quant) {
+            Some(out_value) => self.out.set(channel, out_value),
+            None => Ok(()),
+        }
+    }
+
+    // a device that came back gets the hello sweep and all of its needles again
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        self.out.flush()?;
+        if self.out.take_reconnect() {
+            info!("Vu sez hi (:");
+            self.hello()?;
+            self.gauges.resend_all();
+        }
+        Ok(())
+    }
+
+    // sweeps the mapped device channels, or the first three
+    pub fn hello(&mut self) -> anyhow::Result<()> {
+        if !self.out.sweeps() {
+            return Ok(());
+        }
+        let channels = match self.port.map.is_empty() {
+            true => vec![1, 2, 3],
+            false => self.port.map.iter().map(|(_, dev)| *dev).collect(),
+        };
+        for i in (0i16..=255)
+            .chain((128..=255).rev())
+            .chain(128..=255)
+            .chain((0..=255).rev())
+        {
+            for c in &channels {
+                self.write_vu(*c, i, 1)?;
+            }
+            self.out.flush()?;
+            thread::sleep(time::Duration::new(0, 3_000_000));
+        }
+        for c in &channels {
+            self.gauges.channel(*c)?.value = 0.0;
+        }
+        Ok(())
+    }
+}
+
+pub fn write_frame(
+    meters: &mut [Meter],
+    opts: &OptsCommon,
+    latency_comp: &mut LatencyComp,
+    mut frame: Frame,
+) -> anyhow::Result<()> {
+    // streamed and recorded frames too
+    sanitize(&mut frame);
+    let now = time::Instant::now();
+    for (channel, sample) in frame {
+        let gauge = if opts.latency_comp {
+            latency_comp.compensate(channel, sample, now)
+        } else {
+            sample.value
+        };
+        for meter in meters.iter_mut() {
+            if let Some(dev_channel) = meter.port.device_channel(channel) {
+                meter.set_vu(
+                    dev_channel,
+                    gauge,
+                    opts.quant_step(channel),
+                    opts.ballistics(channel),
+                )?;
+            }
+        }
+    }
+    for meter in meters.iter_mut() {
+        meter.flush()?;
+    }
+    Ok(())
+}
+
+// a virtual meter replaces all of the serial ones, the missing devices are
+// waited for and attached when they are plugged in
+pub fn open_meters(opts: &OptsCommon) -> anyhow::Result<Vec<Meter>> {
+    if let Some(virt) = opts.sink {
+        info!("Using a virtual {virt:?} meter");
+        return Ok(vec![Meter::virtual_meter(virt)]);
+    }
+    if opts.dry_run {
+        info!("Dry run, the meter ports are not opened");
+        return Ok(opts
+            .meter_ports()
+            .iter()
+            .map(|port| {
+                let sink = DryRunSink::new(&port.path, opts.serial_config(port).protocol);
+                Meter::new(port.clone(), Box::new(sink))
+            })
+            .collect());
+    }
+    Ok(opts
+        .meter_ports()
+        .iter()
+        .map(|port| Meter::open(port, opts.serial_config(port)))
+        .collect())
+}
+
+// the meters that are plugged in now, without waiting or the hello sweep
+pub fn attached_meters(opts: &OptsCommon) -> anyhow::Result<Vec<Meter>> {
+    if opts.sink.is_some() || opts.dry_run {
+        return open_meters(opts);
+    }
+    opts.meter_ports()
+        .iter()
+        .map(|port| {
+            let ser = SerialSink::open(&port.path, opts.serial_config(port))
+                .map_err(|e| anyhow!("No meter on {}: {e}", port.path))?;
+            Ok(Meter::new(port.clone(), Box::new(ser)))
+        })
+        .collect()
+}
+
+// EOF
@@ -0,0 +1,324 @@
+// modbus.rs
+
+use std::sync::{Arc, Mutex};
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    process::Command,
+    sync::mpsc,
+    thread, time,
+};
+
+use anyhow::{anyhow, bail};
+
+use crate::*;
+
+const FC_READ_HOLDING: u8 = 0x03;
+const FC_READ_INPUT: u8 = 0x04;
+const FC_WRITE_MULTIPLE: u8 = 0x10;
+const EX_ILLEGAL_FUNCTION: u8 = 0x01;
+const EX_ILLEGAL_ADDRESS: u8 = 0x02;
+const EX_ILLEGAL_VALUE: u8 = 0x03;
+// one register per channel number
+const MODBUS_REGISTERS: usize = 256;
+// registers per write multiple request
+const MODBUS_MAX_WRITE: usize = 123;
+const MODBUS_RECONNECT: time::Duration = time::Duration::from_secs(5);
+const MODBUS_TIMEOUT: time::Duration = time::Duration::from_secs(1);
+
+#[derive(Clone, Debug)]
+pub struct ModbusConfig {
+    // host:port for Modbus TCP, a serial device for RTU
+    pub target: String,
+    pub unit: u8,
+    pub baud: u32,
+    // channel N goes to holding register base + N
+    pub base: u16,
+    // register value of a full scale gauge
+    pub scale: u16,
+}
+
+fn register_value(gauge: f64, scale: u16) -> u16 {
+    (gauge.clamp(0.0, 255.0) / 255.0 * scale as f64).round() as u16
+}
+
+// Serves the gauges over Modbus TCP as holding and input registers,
+// register N holds channel N scaled to 0..scale, any unit id is answered
+pub struct ModbusServerSink;
+
+impl ModbusServerSink {
+    pub fn spawn<S: AsRef<str>>(listen: S, scale: u16) -> anyhow::Result<ThreadedSink> {
+        let listener = TcpListener::bind(listen.as_ref())?;
+        info!("Modbus TCP server listening on {}", listen.as_ref());
+        let registers = Arc::new(Mutex::new([0u16; MODBUS_REGISTERS]));
+
+        let serving = registers.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let registers = serving.clone();
+                thread::spawn(move || {
+                    if let Err(e) = Self::handle(stream, &registers) {
+                        debug!("Modbus client: {e}");
+                    }
+                });
+            }
+        });
+
+        spawn_sink("modbus_server", move |rx| {
+            while let Ok(frame) = rx.recv() {
+                let mut regs = registers.lock().unwrap();
+                for (ch, sample) in frame {
+                    regs[ch as usize] = register_value(sample.value, scale);
+                }
+            }
+        })
+    }
+
+    fn handle(
+        mut stream: TcpStream,
+        registers: &Mutex<[u16; MODBUS_REGISTERS]>,
+    ) -> anyhow::Result<()> {
+        loop {
+            // MBAP header: transaction, protocol, length, unit
+            let mut hdr = [0u8; 7];
+            stream.read_exact(&mut hdr)?;
+            let len = u16::from_be_bytes([hdr[4], hdr[5]]) as usize;
+            if hdr[2..4] != [0, 0] || !(2..=254).contains(&len) {
+                bail!("Not a Modbus TCP request");
+            }
+            let mut pdu = vec![0u8; len - 1];
+            stream.read_exact(&mut pdu)?;
+
+            let reply = Self::respond(&pdu, &registers.lock().unwrap());
+            let mut out = Vec::with_capacity(7 + reply.len());
+            out.extend(&hdr[..4]);
+            out.extend((reply.len() as u16 + 1).to_be_bytes());
+            out.push(hdr[6]);
+            out.extend(&reply);
+            stream.write_all(&out)?;
+        }
+    }
+
+    fn respond(pdu: &[u8], registers: &[u16; MODBUS_REGISTERS]) -> Vec<u8> {
+        let fc = pdu[0];
+        let exception = |code: u8| vec![fc | 0x80, code];
+        match fc {
+            FC_READ_HOLDING | FC_READ_INPUT => {
+                let [_, a_hi, a_lo, n_hi, n_lo] = pdu[..] else {
+                    return exception(EX_ILLEGAL_VALUE);
+                };
+                let addr = u16::from_be_bytes([a_hi, a_lo]) as usize;
+                let count = u16::from_be_bytes([n_hi, n_lo]) as usize;
+                if !(1..=125).contains(&count) {
+                    return exception(EX_ILLEGAL_VALUE);
+                }
+                let Some(regs) = registers.get(addr..addr + count) else {
+                    return exception(EX_ILLEGAL_ADDRESS);
+                };
+                let mut reply = vec![fc, (2 * count) as u8];
+                for r in regs {
+                    reply.extend(r.to_be_bytes());
+                }
+                reply
+            }
+            _ => exception(EX_ILLEGAL_FUNCTION),
+        }
+    }
+}
+
+enum ModbusLink {
+    Tcp(TcpStream, u16),
+    Rtu(File),
+}
+
+// Writes the gauges of every frame to a PLC or panel meter with
+// "write multiple registers", over Modbus TCP or RTU
+pub struct ModbusSink;
+
+impl ModbusSink {
+    pub fn spawn(cfg: ModbusConfig) -> anyhow::Result<ThreadedSink> {
+        info!(
+            "Writing gauges to Modbus unit {} at {} from register {}",
+            cfg.unit, cfg.target, cfg.base
+        );
+        spawn_sink("modbus", move |rx| Self::run(cfg, rx))
+    }
+
+    fn run(cfg: ModbusConfig, rx: mpsc::Receiver<Frame>) {
+        let mut link: Option<ModbusLink> = None;
+        let mut last_attempt: Option<time::Instant> = None;
+        let mut written = [None::<u16>; MODBUS_REGISTERS];
+
+        while let Ok(frame) = rx.recv() {
+            if link.is_none() && last_attempt.is_none_or(|t| t.elapsed() >= MODBUS_RECONNECT) {
+                last_attempt = Some(time::Instant::now());
+                match Self::connect(&cfg) {
+                    Ok(l) => {
+                        info!("Connected to Modbus {}", cfg.target);
+                        link = Some(l);
+                        written = [None; MODBUS_REGISTERS];
+                    }
+                    Err(e) => {
+                        error!("Modbus connect to {} failed: {e}", cfg.target);
+                        count_error("modbus");
+                    }
+                }
+            }
+            let Some(l) = &mut link else {
+                continue;
+            };
+
+            // one write per run of consecutive changed channels
+            let changed = frame
+                .iter()
+                .map(|(ch, s)| (*ch as usize, register_value(s.value, cfg.scale)))
+                .filter(|(ch, v)| written[*ch] != Some(*v))
+                .collect::<Vec<_>>();
+            let runs = changed.chunk_by(|a, b| b.0 == a.0 + 1);
+            for chunk in runs.flat_map(|run| run.chunks(MODBUS_MAX_WRITE)) {
+                let addr = cfg.base.wrapping_add(chunk[0].0 as u16);
+                let values = chunk.iter().map(|(_, v)| *v).collect::<Vec<_>>();
+                if let Err(e) = Self::write_registers(l, &cfg, addr, &values) {
+                    error!("Modbus: {e}");
+                    count_error("modbus");
+                    link = None;
+                    break;
+                }
+                for (ch, v) in chunk {
+                    written[*ch] = Some(*v);
+                }
+            }
+        }
+    }
+
+    fn connect(cfg: &ModbusConfig) -> anyhow::Result<ModbusLink> {
+        if !cfg.target.starts_with('/') {
+            let stream = TcpStream::connect(&cfg.target)?;
+            stream.set_read_timeout(Some(MODBUS_TIMEOUT))?;
+            stream.set_write_timeout(Some(MODBUS_TIMEOUT))?;
+            stream.set_nodelay(true)?;
+            return Ok(ModbusLink::Tcp(stream, 0));
+        }
+        // raw 8N1, reads give up after a second of silence
+        let out = Command::new("stty")
+            .args(["-F", &cfg.target, &cfg.baud.to_string()])
+            .args([
+                "raw", "-echo", "cs8", "-cstopb", "-parenb", "min", "0", "time", "10",
+            ])
+            .output()?;
+        if !out.status.success() {
+            bail!(
+                "stty exited with {}: {}",
+                out.status,
+                String::from_utf8_lossy(&out.stderr).trim()
+            );
+        }
+        let dev = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&cfg.target)?;
+        Ok(ModbusLink::Rtu(dev))
+    }
+
+    fn write_registers(
+        link: &mut ModbusLink,
+        cfg: &ModbusConfig,
+        addr: u16,
+        values: &[u16],
+    ) -> anyhow::Result<()> {
+        let mut pdu = vec![FC_WRITE_MULTIPLE];
+        pdu.extend(addr.to_be_bytes());
+        pdu.extend((values.len() as u16).to_be_bytes());
+        pdu.push((2 * values.len()) as u8);
+        for v in values {
+            pdu.extend(v.to_be_bytes());
+        }
+
+        let reply = match link {
+            ModbusLink::Tcp(stream, transaction) => {
+                *transaction = transaction.wrapping_add(1);
+                let mut req = Vec::with_capacity(7 + pdu.len());
+                req.extend(transaction.to_be_bytes());
+                req.extend([0, 0]);
+                req.extend((pdu.len() as u16 + 1).to_be_bytes());
+                req.push(cfg.unit);
+                req.extend(&pdu);
+                stream.write_all(&req)?;
+
+                let mut hdr = [0u8; 7];
+                stream.read_exact(&mut hdr)?;
+                if hdr[..2] != transaction.to_be_bytes() {
+                    bail!("Modbus reply to another transaction");
+                }
+                let len = u16::from_be_bytes([hdr[4], hdr[5]]) as usize;
+                let mut reply = vec![0u8; len.saturating_sub(1)];
+                stream.read_exact(&mut reply)?;
+                reply
+            }
+            ModbusLink::Rtu(dev) => {
+                let mut req = vec![cfg.unit];
+                req.extend(&pdu);
+                req.extend(crc16(&req).to_le_bytes());
+                dev.write_all(&req)?;
+                Self::read_rtu(dev, cfg.unit)?
+            }
+        };
+        match reply.first() {
+            Some(&FC_WRITE_MULTIPLE) => Ok(()),
+            Some(fc) if fc & 0x80 != 0 => Err(anyhow!(
+                "Modbus exception {} from unit {}",
+                reply.get(1).copied().unwrap_or(0),
+                cfg.unit
+            )),
+            _ => Err(anyhow!("Unexpected Modbus reply {reply:02x?}")),
+        }
+    }
+
+    // the reply to a write is 8 bytes, an exception 5, both end in a CRC
+    fn read_rtu(dev: &mut File, unit: u8) -> anyhow::Result<Vec<u8>> {
+        let mut buf = vec![0u8; 3];
+        read_full(dev, &mut buf)?;
+        if buf[0] != unit {
+            bail!("Modbus reply from unit {}, expected {unit}", buf[0]);
+        }
+        let rest = if buf[1] & 0x80 != 0 { 2 } else { 5 };
+        buf.resize(3 + rest, 0);
+        read_full(dev, &mut buf[3..])?;
+        let (frame, crc) = buf.split_at(buf.len() - 2);
+        if crc16(frame).to_le_bytes() != crc {
+            bail!("Modbus reply CRC mismatch");
+        }
+        Ok(frame[1..].to_vec())
+    }
+}
+
+// a tty read returns 0 on timeout
+fn read_full(dev: &mut File, buf: &mut [u8]) -> anyhow::Result<()> {
+    let mut got = 0;
+    while got < buf.len() {
+        match dev.read(&mut buf[got..])? {
+            0 => bail!("Modbus reply timed out"),
+            n => got += n,
+        }
+    }
+    Ok(())
+}
+
+// CRC-16/MODBUS, sent low byte first
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xffffu16;
+    for b in data {
+        crc ^= *b as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                crc >> 1 ^ 0xa001
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+// EOF
@@ -0,0 +1,353 @@
+// mqtt.rs
+
+use std::{
+    collections::BTreeSet,
+    io::{self, Read, Write},
+    net::TcpStream,
+    sync::mpsc,
+    time,
+};
+
+use anyhow::{anyhow, bail};
+
+use crate::*;
+
+const MQTT_RECONNECT: time::Duration = time::Duration::from_secs(5);
+// how often incoming commands are looked at while no frames arrive
+const MQTT_POLL: time::Duration = time::Duration::from_secs(1);
+
+#[derive(Clone, Debug)]
+pub struct MqttConfig {
+    // host:port
+    pub broker: String,
+    pub client_id: String,
+    // "{channel}" is replaced with the channel number
+    pub topic: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub keepalive: u16,
+    // "hello" published here runs the sweep
+    pub command_topic: String,
+    // Home Assistant discovery prefix, None disables discovery
+    pub discovery: Option<String>,
+}
+
+// Publishes "<topic>/gauge" and "<topic>/raw" of every channel on every frame, QoS 0.
+// With discovery enabled every channel shows up in Home Assistant as a sensor,
+// along with a button for the hello sweep.
+pub struct MqttSink;
+
+impl MqttSink {
+    pub fn spawn(cfg: MqttConfig) -> anyhow::Result<ThreadedSink> {
+        info!("Publishing to MQTT broker {}", cfg.broker);
+        spawn_sink("mqtt", move |rx| Self::run(cfg, rx))
+    }
+
+    fn run(cfg: MqttConfig, rx: mpsc::Receiver<Frame>) {
+        let ping_interval = time::Duration::from_secs((cfg.keepalive as u64 / 2).max(1));
+        let node_id = format!("perf_vumeter_{}", hostname().replace(['.', '-'], "_"));
+        let mut client: Option<MqttClient> = None;
+        let mut last_attempt: Option<time::Instant> = None;
+        // channels announced to Home Assistant on the current connection
+        let mut announced = BTreeSet::new();
+
+        loop {
+            let frame = match rx.recv_timeout(MQTT_POLL) {
+                Ok(frame) => Some(frame),
+                Err(mpsc::RecvTimeoutError::Timeout) => None,
+                Err(mpsc::RecvTimeoutError::Disconnected) => return,
+            };
+
+            if client.is_none() && last_attempt.is_none_or(|t| t.elapsed() >= MQTT_RECONNECT) {
+                last_attempt = Some(time::Instant::now());
+                match Self::connect(&cfg, &node_id) {
+                    Ok(c) => {
+                        info!("Connected to MQTT broker {}", cfg.broker);
+                        client = Some(c);
+                        announced.clear();
+                    }
+                    Err(e) => {
+                        error!("MQTT connect to {} failed: {e}", cfg.broker);
+                        count_error("mqtt");
+                    }
+                }
+            }
+            let Some(c) = &mut client else {
+                continue;
+            };
+
+            let res = (|| {
+                if let Some(frame) = &frame {
+                    if let Some(prefix) = &cfg.discovery {
+                        for (ch, sample) in frame {
+                            if announced.insert(*ch) {
+                                Self::announce(c, &cfg, prefix, &node_id, *ch, sample)?;
+                            }
+                        }
+                    }
+                    Self::publish_frame(c, &cfg, frame)?;
+                }
+                if c.idle() >= ping_interval {
+                    c.ping()?;
+                }
+                for (topic, payload) in c.poll()? {
+                    Self::command(&topic, &payload);
+                }
+                Ok::<_, anyhow::Error>(())
+            })();
+            if let Err(e) = res {
+                error!("MQTT: {e}");
+                count_error("mqtt");
+                client = None;
+            }
+        }
+    }
+
+    fn connect(cfg: &MqttConfig, node_id: &str) -> anyhow::Result<MqttClient> {
+        let mut c = MqttClient::connect(cfg)?;
+        c.subscribe(&cfg.command_topic)?;
+        if let Some(prefix) = &cfg.discovery {
+            let config = format!(
+                "{{\"name\":\"Hello sweep\",\"unique_id\":\"{node_id}_hello\",\
+                 \"command_topic\":{},\"payload_press\":\"hello\",\"device\":{}}}",
+                JsonStr(&cfg.command_topic),
+                device_json(node_id)
+            );
+            c.publish(
+                &format!("{prefix}/button/{node_id}/hello/config"),
+                config.as_bytes(),
+                true,
+            )?;
+        }
+        Ok(c)
+    }
+
+    fn announce(
+        c: &mut MqttClient,
+        cfg: &MqttConfig,
+        prefix: &str,
+        node_id: &str,
+        ch: u8,
+        sample: &Sample,
+    ) -> anyhow::Result<()> {
+        let topic = cfg.topic.replace("{channel}", &ch.to_string());
+        let name = match sample.source {
+            "" => format!("Channel {ch}"),
+            s => format!("{s} (channel {ch})"),
+        };
+        let config = format!(
+            "{{\"name\":{},\"unique_id\":\"{node_id}_ch{ch}\",\"state_topic\":{},\
+             \"state_class\":\"measurement\",\"device\":{}}}",
+            JsonStr(&name),
+            JsonStr(&format!("{topic}/gauge")),
+            device_json(node_id)
+        );
+        debug!("MQTT: announcing channel {ch} to Home Assistant");
+        c.publish(
+            &format!("{prefix}/sensor/{node_id}/ch{ch}/config"),
+            config.as_bytes(),
+            true,
+        )
+    }
+
+    fn command(topic: &str, payload: &[u8]) {
+        match String::from_utf8_lossy(payload).trim() {
+            "hello" => {
+                info!("MQTT: hello sweep requested");
+                request_hello();
+            }
+            other => info!("MQTT: unknown command {other:?} on {topic}"),
+        }
+    }
+
+    fn publish_frame(c: &mut MqttClient, cfg: &MqttConfig, frame: &Frame) -> anyhow::Result<()> {
+        for (ch, sample) in frame {
+            let topic = cfg.topic.replace("{channel}", &ch.to_string());
+            let gauge = sample.value.clamp(0.0, 255.0);
+            c.publish(
+                &format!("{topic}/gauge"),
+                format!("{gauge:.0}").as_bytes(),
+                false,
+            )?;
+            if let Some(raw) = sample.raw {
+                c.publish(
+                    &format!("{topic}/raw"),
+                    format!("{raw:.3}").as_bytes(),
+                    false,
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn device_json(node_id: &str) -> String {
+    format!(
+        "{{\"identifiers\":[\"{node_id}\"],\"name\":{},\"manufacturer\":\"sjm42\",\
+         \"model\":\"perf_vumeter\",\"sw_version\":\"{}\"}}",
+        JsonStr(&format!("perf_vumeter {}", hostname())),
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+// Minimal MQTT 3.1.1 client, QoS 0 only
+#[derive(Debug)]
+pub struct MqttClient {
+    stream: TcpStream,
+    rx_buf: Vec<u8>,
+    last_tx: time::Instant,
+    last_rx: time::Instant,
+    keepalive: time::Duration,
+    packet_id: u16,
+}
+
+impl MqttClient {
+    pub fn connect(cfg: &MqttConfig) -> anyhow::Result<Self> {
+        let stream = TcpStream::connect(&cfg.broker)?;
+        stream.set_read_timeout(Some(time::Duration::from_secs(5)))?;
+        stream.set_write_timeout(Some(time::Duration::from_secs(5)))?;
+        let mut client = Self {
+            stream,
+            rx_buf: Vec::new(),
+            last_tx: time::Instant::now(),
+            last_rx: time::Instant::now(),
+            keepalive: time::Duration::from_secs(cfg.keepalive as u64),
+            packet_id: 0,
+        };
+
+        // clean session, optional username/password
+        let mut flags = 0x02;
+        let mut payload = mqtt_string(&cfg.client_id);
+        if let Some(user) = &cfg.username {
+            flags |= 0x80;
+            payload.extend(mqtt_string(user));
+        }
+        if let Some(pass) = &cfg.password {
+            flags |= 0x40;
+            payload.extend(mqtt_string(pass));
+        }
+        let mut body = mqtt_string("MQTT");
+        body.push(4);
+        body.push(flags);
+        body.extend(cfg.keepalive.to_be_bytes());
+        body.extend(payload);
+        client.send(0x10, &body)?;
+
+        let mut connack = [0u8; 4];
+        client.stream.read_exact(&mut connack)?;
+        if connack[0] != 0x20 {
+            bail!("Expected CONNACK, got packet type 0x{:02x}", connack[0]);
+        }
+        if connack[3] != 0 {
+            bail!("Connection refused, return code {}", connack[3]);
+        }
+        Ok(client)
+    }
+
+    pub fn publish(&mut self, topic: &str, payload: &[u8], retain: bool) -> anyhow::Result<()> {
+        let mut body = mqtt_string(topic);
+        body.extend(payload);
+        self.send(if retain { 0x31 } else { 0x30 }, &body)
+    }
+
+    // QoS 0 subscription, the SUBACK is skipped in poll()
+    pub fn subscribe(&mut self, filter: &str) -> anyhow::Result<()> {
+        self.packet_id = self.packet_id.wrapping_add(1).max(1);
+        let mut body = self.packet_id.to_be_bytes().to_vec();
+        body.extend(mqtt_string(filter));
+        body.push(0);
+        self.send(0x82, &body)
+    }
+
+    pub fn ping(&mut self) -> anyhow::Result<()> {
+        self.send(0xc0, &[])
+    }
+
+    // time since anything was sent
+    pub fn idle(&self) -> time::Duration {
+        self.last_tx.elapsed()
+    }
+
+    // Read whatever the broker has sent without blocking,
+    // returns the (topic, payload) of incoming PUBLISH packets
+    pub fn poll(&mut self) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+        self.stream.set_nonblocking(true)?;
+        let mut buf = [0u8; 4096];
+        let res = loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => break Err(anyhow!("Connection closed by broker")),
+                Ok(n) => {
+                    self.rx_buf.extend(&buf[..n]);
+                    self.last_rx = time::Instant::now();
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(()),
+                Err(e) => break Err(e.into()),
+            }
+        };
+        self.stream.set_nonblocking(false)?;
+        res?;
+        if self.last_rx.elapsed() > self.keepalive * 2 {
+            bail!("Broker has gone silent");
+        }
+
+        let mut publishes = Vec::new();
+        while let Some((header, body, len)) = parse_packet(&self.rx_buf) {
+            if header & 0xf0 == 0x30 && body.len() >= 2 {
+                let tlen = u16::from_be_bytes([body[0], body[1]]) as usize;
+                // QoS 1/2 would have a packet id after the topic, we only subscribe with 0
+                if body.len() >= 2 + tlen {
+                    let topic = String::from_utf8_lossy(&body[2..2 + tlen]).into_owned();
+                    publishes.push((topic, body[2 + tlen..].to_vec()));
+                }
+            }
+            self.rx_buf.drain(..len);
+        }
+        Ok(publishes)
+    }
+
+    fn send(&mut self, header: u8, body: &[u8]) -> anyhow::Result<()> {
+        let mut pkt = Vec::with_capacity(body.len() + 5);
+        pkt.push(header);
+        // remaining length, 7 bits at a time
+        let mut len = body.len();
+        loop {
+            let mut b = (len % 128) as u8;
+            len /= 128;
+            if len > 0 {
+                b |= 0x80;
+            }
+            pkt.push(b);
+            if len == 0 {
+                break;
+            }
+        }
+        pkt.extend(body);
+        self.stream.write_all(&pkt)?;
+        self.last_tx = time::Instant::now();
+        Ok(())
+    }
+}
+
+// one complete packet from the start of buf: (fixed header byte, body, total length)
+fn parse_packet(buf: &[u8]) -> Option<(u8, &[u8], usize)> {
+    let mut len = 0usize;
+    let mut i = 1;
+    loop {
+        let b = *buf.get(i)?;
+        len |= ((b & 0x7f) as usize) << (7 * (i - 1));
+        i += 1;
+        if b & 0x80 == 0 || i > 4 {
+            break;
+        }
+    }
+    let body = buf.get(i..i + len)?;
+    Some((buf[0], body, i + len))
+}
+
+fn mqtt_string(s: &str) -> Vec<u8> {
+    let mut out = (s.len() as u16).to_be_bytes().to_vec();
+    out.extend(s.as_bytes());
+    out
+}
+
+// EOF
@@ -0,0 +1,96 @@
+// nft.rs
+
+use std::{process::Command, time};
+
+use anyhow::{anyhow, bail};
+
+use crate::*;
+
+// Bit rate of a named nftables counter, e.g. "inet/filter/wan_up".
+// The nft command is slow, so it is run in a background thread.
+#[derive(Debug)]
+pub struct NftCounter {
+    pub family: String,
+    pub table: String,
+    pub name: String,
+    rate: Poller<i64>,
+}
+
+impl NftCounter {
+    pub fn new<S: AsRef<str>>(spec: S, interval: time::Duration) -> anyhow::Result<Self> {
+        let spec = spec.as_ref();
+        let parts = spec.split('/').collect::<Vec<&str>>();
+        let [family, table, name] = parts[..] else {
+            bail!("Invalid nft counter \"{spec}\", expected family/table/name");
+        };
+        let (family, table, name) = (family.to_string(), table.to_string(), name.to_string());
+        let args = [family.clone(), table.clone(), name.clone()];
+        let mut prev: Option<(time::Instant, i64)> = None;
+        let rate = Poller::spawn(interval, move || {
+            let bytes = Self::read_bytes(&args)?;
+            let now = time::Instant::now();
+            let rate = match prev {
+                Some((ts, prev_bytes)) => {
+                    ((8 * (bytes - prev_bytes)) as f64 / (now - ts).as_secs_f64()) as i64
+                }
+                None => 0,
+            };
+            prev = Some((now, bytes));
+            Ok(rate)
+        })?;
+        Ok(Self {
+            family,
+            table,
+            name,
+            rate,
+        })
+    }
+
+    // the latest rate and when it was measured
+    pub fn bitrate(&self) -> anyhow::Result<(i64, time::Instant)> {
+        self.rate.latest()
+    }
+
+    // Example output of "nft -j list counter inet filter wan_up":
+    // {"nftables": [{"metainfo": {...}}, {"counter": {"family": "inet", "name": "wan_up",
+    //  "table": "filter", "handle": 3, "packets": 1234, "bytes": 567890}}]}
+    fn read_bytes(counter: &[String; 3]) -> anyhow::Result<i64> {
+        let out = Command::new("nft")
+            .args(["-j", "list", "counter"])
+            .args(counter)
+            .output()?;
+        if !out.status.success() {
+            bail!(
+                "nft exited with {}: {}",
+                out.status,
+                String::from_utf8_lossy(&out.stderr).trim()
+            );
+        }
+        let json = Json::parse(&String::from_utf8_lossy(&out.stdout))?;
+        json.get("nftables")
+            .and_then(Json::as_array)
+            .into_iter()
+            .flatten()
+            .find_map(|item| item.get("counter")?.get("bytes")?.as_f64())
+            .map(|b| b as i64)
+            .ok_or_else(|| anyhow!("No counter bytes in nft output"))
+    }
+}
+
+impl StatSource for NftCounter {
+    fn sample(&mut self) -> anyhow::Result<f64> {
+        Ok(self.sample_at()?.0)
+    }
+    fn name(&self) -> &'static str {
+        "nft"
+    }
+    fn unit(&self) -> &'static str {
+        "bit/s"
+    }
+    fn sample_at(&mut self) -> anyhow::Result<(f64, time::Instant)> {
+        let (rate, ts) = self.bitrate()?;
+        Ok((rate as f64, ts))
+    }
+}
+
+// EOF
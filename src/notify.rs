@@ -0,0 +1,95 @@
+// notify.rs
+
+use std::time;
+#[cfg(target_os = "linux")]
+use std::{
+    env,
+    os::linux::net::SocketAddrExt,
+    os::unix::net::{SocketAddr, UnixDatagram},
+};
+
+#[cfg(target_os = "linux")]
+use crate::*;
+
+// sd_notify over $NOTIFY_SOCKET: READY=1 once the loop runs, and WATCHDOG=1
+// from the loop at half the WatchdogSec= interval, so a loop stuck e.g. on
+// a blocked serial write gets the service restarted. A no-op outside systemd,
+// and so on the other systems than Linux.
+#[derive(Debug)]
+pub struct Notifier {
+    #[cfg(target_os = "linux")]
+    sock: Option<(UnixDatagram, SocketAddr)>,
+    watchdog: Option<time::Duration>,
+    last_ping: time::Instant,
+}
+
+impl Notifier {
+    #[cfg(target_os = "linux")]
+    pub fn from_env() -> Self {
+        let sock = env::var("NOTIFY_SOCKET").ok().and_then(|path| {
+            let addr = match path.strip_prefix('@') {
+                Some(name) => SocketAddr::from_abstract_name(name),
+                None => SocketAddr::from_pathname(&path),
+            };
+            match (UnixDatagram::unbound(), addr) {
+                (Ok(sock), Ok(addr)) => Some((sock, addr)),
+                (Err(e), _) | (_, Err(e)) => {
+                    info!("NOTIFY_SOCKET {path}: {e}");
+                    None
+                }
+            }
+        });
+        // only when the watchdog is meant for this process
+        let pid_ok = env::var("WATCHDOG_PID").map_or(true, |p| p == std::process::id().to_string());
+        let watchdog = env::var("WATCHDOG_USEC")
+            .ok()
+            .and_then(|us| us.parse::<u64>().ok())
+            .filter(|us| *us > 0 && pid_ok)
+            .map(|us| time::Duration::from_micros(us / 2));
+        if let Some(w) = watchdog.filter(|_| sock.is_some()) {
+            info!("Pinging the systemd watchdog every {w:?}");
+        }
+        Self {
+            sock,
+            watchdog,
+            last_ping: time::Instant::now(),
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn from_env() -> Self {
+        Self {
+            watchdog: None,
+            last_ping: time::Instant::now(),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn notify(&self, state: &str) {
+        if let Some((sock, addr)) = &self.sock {
+            if let Err(e) = sock.send_to_addr(state.as_bytes(), addr) {
+                info!("sd_notify failed: {e}");
+                count_error("sd_notify");
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn notify(&self, _state: &str) {}
+
+    pub fn ready(&self) {
+        self.notify("READY=1");
+    }
+
+    // call once per round of the loop
+    pub fn ping(&mut self) {
+        if let Some(interval) = self.watchdog {
+            if self.last_ping.elapsed() >= interval {
+                self.last_ping = time::Instant::now();
+                self.notify("WATCHDOG=1");
+            }
+        }
+    }
+}
+
+// EOF
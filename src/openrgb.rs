@@ -0,0 +1,308 @@
+// openrgb.rs
+
+use std::{
+    collections::HashMap,
+    io::{ErrorKind, Read, Write},
+    net::TcpStream,
+    str::FromStr,
+    sync::mpsc,
+    time,
+};
+
+use anyhow::{anyhow, bail};
+
+use crate::*;
+
+const OPENRGB_PORT: u16 = 6742;
+const OPENRGB_RECONNECT: time::Duration = time::Duration::from_secs(5);
+
+// SDK packet ids, the protocol is version 0 unless the client asks for more
+const REQUEST_CONTROLLER_COUNT: u32 = 0;
+const REQUEST_CONTROLLER_DATA: u32 = 1;
+const SET_CLIENT_NAME: u32 = 50;
+const DEVICE_LIST_UPDATED: u32 = 100;
+const UPDATE_LEDS: u32 = 1050;
+const SET_CUSTOM_MODE: u32 = 1100;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OpenRgbStyle {
+    // the whole segment glows in the channel color, for ambient lights
+    #[default]
+    Fill,
+    // a lit bar grows along the segment, for key rows and strips
+    Bar,
+}
+
+impl FromStr for OpenRgbStyle {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fill" => Ok(OpenRgbStyle::Fill),
+            "bar" => Ok(OpenRgbStyle::Bar),
+            _ => Err(anyhow!("Unknown OpenRGB style: {s}")),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct OpenRgbConfig {
+    // host[:port] of the OpenRGB SDK server
+    pub target: String,
+    // controller index as listed by the server
+    pub device: u32,
+    // the LEDs of the device are split into equal segments, one per channel
+    pub channels: Vec<u8>,
+    pub style: OpenRgbStyle,
+}
+
+// Colors the LEDs of an OpenRGB controller from green to red by load
+pub struct OpenRgbSink;
+
+impl OpenRgbSink {
+    pub fn spawn(cfg: OpenRgbConfig) -> anyhow::Result<ThreadedSink> {
+        info!("Driving OpenRGB device {} on {}", cfg.device, cfg.target);
+        spawn_sink("openrgb", move |rx| Self::run(cfg, rx))
+    }
+
+    fn run(cfg: OpenRgbConfig, rx: mpsc::Receiver<Frame>) {
+        let target = if cfg.target.contains(':') {
+            cfg.target.clone()
+        } else {
+            format!("{}:{OPENRGB_PORT}", cfg.target)
+        };
+        let mut client: Option<OpenRgbClient> = None;
+        let mut last_attempt: Option<time::Instant> = None;
+        let mut levels: HashMap<u8, f64> = HashMap::new();
+
+        while let Ok(frame) = rx.recv() {
+            for (ch, sample) in frame {
+                levels.insert(ch, sample.value.clamp(0.0, 255.0));
+            }
+
+            if client.is_none() && last_attempt.is_none_or(|t| t.elapsed() >= OPENRGB_RECONNECT) {
+                last_attempt = Some(time::Instant::now());
+                match OpenRgbClient::connect(&target, cfg.device) {
+                    Ok(c) => {
+                        info!(
+                            "Connected to OpenRGB on {target}, device has {} LEDs",
+                            c.leds
+                        );
+                        client = Some(c);
+                    }
+                    Err(e) => {
+                        error!("OpenRGB connect to {target} failed: {e}");
+                        count_error("openrgb");
+                    }
+                }
+            }
+            let Some(c) = &mut client else {
+                continue;
+            };
+
+            let colors = Self::colors(&cfg, &levels, c.leds);
+            let res = c.update_leds(&colors).and_then(|_| c.device_list_updated());
+            match res {
+                Ok(false) => {}
+                Ok(true) => {
+                    info!("OpenRGB device list changed, reconnecting");
+                    client = None;
+                    last_attempt = None;
+                }
+                Err(e) => {
+                    error!("OpenRGB: {e}");
+                    count_error("openrgb");
+                    client = None;
+                }
+            }
+        }
+    }
+
+    fn colors(cfg: &OpenRgbConfig, levels: &HashMap<u8, f64>, leds: usize) -> Vec<Rgb> {
+        let segments = cfg.channels.len().max(1);
+        (0..leds)
+            .map(|i| {
+                let seg = i * segments / leds;
+                let first = seg * leds / segments;
+                let len = (seg + 1) * leds / segments - first;
+                let level = cfg
+                    .channels
+                    .get(seg)
+                    .and_then(|ch| levels.get(ch))
+                    .copied()
+                    .unwrap_or(0.0)
+                    / 255.0;
+                match cfg.style {
+                    OpenRgbStyle::Fill => Rgb::load(level),
+                    OpenRgbStyle::Bar if ((i - first) as f64) < (level * len as f64).round() => {
+                        Rgb::load((i - first) as f64 / len as f64)
+                    }
+                    OpenRgbStyle::Bar => Rgb::default(),
+                }
+            })
+            .collect()
+    }
+}
+
+struct OpenRgbClient {
+    stream: TcpStream,
+    device: u32,
+    leds: usize,
+}
+
+impl OpenRgbClient {
+    fn connect(target: &str, device: u32) -> anyhow::Result<Self> {
+        let stream = TcpStream::connect(target)?;
+        stream.set_read_timeout(Some(time::Duration::from_secs(5)))?;
+        stream.set_write_timeout(Some(time::Duration::from_secs(5)))?;
+        let mut c = Self {
+            stream,
+            device,
+            leds: 0,
+        };
+        c.send(0, SET_CLIENT_NAME, b"perf-vumeter\0")?;
+
+        c.send(0, REQUEST_CONTROLLER_COUNT, &[])?;
+        let count = c.reply(REQUEST_CONTROLLER_COUNT)?;
+        let count = u32::from_le_bytes(
+            count
+                .get(..4)
+                .ok_or_else(|| anyhow!("Short reply"))?
+                .try_into()?,
+        );
+        if device >= count {
+            bail!("No OpenRGB device {device}, the server has {count}");
+        }
+
+        c.send(device, REQUEST_CONTROLLER_DATA, &[])?;
+        let data = c.reply(REQUEST_CONTROLLER_DATA)?;
+        let (name, leds) = parse_controller(&data)?;
+        if leds == 0 {
+            bail!("OpenRGB device {device} ({name}) has no LEDs");
+        }
+        c.leds = leds;
+        debug!("OpenRGB device {device}: {name}");
+
+        c.send(device, SET_CUSTOM_MODE, &[])?;
+        Ok(c)
+    }
+
+    fn send(&mut self, device: u32, id: u32, payload: &[u8]) -> anyhow::Result<()> {
+        let mut pkt = Vec::with_capacity(16 + payload.len());
+        pkt.extend(b"ORGB");
+        pkt.extend(device.to_le_bytes());
+        pkt.extend(id.to_le_bytes());
+        pkt.extend((payload.len() as u32).to_le_bytes());
+        pkt.extend(payload);
+        self.stream.write_all(&pkt)?;
+        Ok(())
+    }
+
+    fn recv(&mut self) -> anyhow::Result<(u32, Vec<u8>)> {
+        let mut hdr = [0u8; 16];
+        self.stream.read_exact(&mut hdr)?;
+        if &hdr[..4] != b"ORGB" {
+            bail!("Bad OpenRGB packet magic");
+        }
+        let word = |i: usize| u32::from_le_bytes(hdr[i..i + 4].try_into().unwrap());
+        let mut payload = vec![0u8; word(12) as usize];
+        self.stream.read_exact(&mut payload)?;
+        Ok((word(8), payload))
+    }
+
+    // skipping any notifications that arrive in between
+    fn reply(&mut self, id: u32) -> anyhow::Result<Vec<u8>> {
+        loop {
+            let (got, payload) = self.recv()?;
+            if got == id {
+                return Ok(payload);
+            }
+        }
+    }
+
+    fn update_leds(&mut self, colors: &[Rgb]) -> anyhow::Result<()> {
+        let size = 4 + 2 + 4 * colors.len();
+        let mut payload = Vec::with_capacity(size);
+        payload.extend((size as u32).to_le_bytes());
+        payload.extend((colors.len() as u16).to_le_bytes());
+        for c in colors {
+            payload.extend([c.0, c.1, c.2, 0]);
+        }
+        self.send(self.device, UPDATE_LEDS, &payload)
+    }
+
+    // The server never answers UPDATE_LEDS, so whatever is pending is a notification
+    fn device_list_updated(&mut self) -> anyhow::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut updated = false;
+        let res = loop {
+            let mut hdr = [0u8; 1];
+            match self.stream.peek(&mut hdr) {
+                Ok(0) => break Err(anyhow!("Connection closed by server")),
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break Ok(updated),
+                Err(e) => break Err(e.into()),
+            }
+            self.stream.set_nonblocking(false)?;
+            let (id, _) = self.recv()?;
+            updated |= id == DEVICE_LIST_UPDATED;
+            self.stream.set_nonblocking(true)?;
+        };
+        self.stream.set_nonblocking(false)?;
+        res
+    }
+}
+
+// Walk the protocol 0 controller description up to the LED list,
+// returns the device name and LED count
+fn parse_controller(data: &[u8]) -> anyhow::Result<(String, usize)> {
+    let mut r = Reader { data, pos: 0 };
+    r.skip(4 + 4)?; // data size, device type
+    let name = r.string()?;
+    for _ in 0..4 {
+        r.string()?; // description, version, serial, location
+    }
+    let modes = r.u16()?;
+    r.skip(4)?; // active mode
+    for _ in 0..modes {
+        r.string()?;
+        r.skip(9 * 4)?; // value, flags, speeds, color limits, speed, direction, color mode
+        let colors = r.u16()? as usize;
+        r.skip(4 * colors)?;
+    }
+    let zones = r.u16()?;
+    for _ in 0..zones {
+        r.string()?;
+        r.skip(4 * 4)?; // type, leds min/max/count
+        let matrix = r.u16()? as usize;
+        r.skip(matrix)?;
+    }
+    Ok((name, r.u16()? as usize))
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn skip(&mut self, n: usize) -> anyhow::Result<&[u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + n)
+            .ok_or_else(|| anyhow!("Truncated OpenRGB controller data"))?;
+        self.pos += n;
+        Ok(bytes)
+    }
+    fn u16(&mut self) -> anyhow::Result<u16> {
+        Ok(u16::from_le_bytes(self.skip(2)?.try_into()?))
+    }
+    // length prefixed, NUL terminated
+    fn string(&mut self) -> anyhow::Result<String> {
+        let len = self.u16()? as usize;
+        let bytes = self.skip(len)?;
+        Ok(String::from_utf8_lossy(bytes.strip_suffix(&[0]).unwrap_or(bytes)).into_owned())
+    }
+}
+
+// EOF
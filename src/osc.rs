@@ -0,0 +1,48 @@
+// osc.rs
+
+use std::{net::UdpSocket, sync::mpsc};
+
+use crate::*;
+
+// Sends every gauge as an Open Sound Control message with one float argument,
+// scaled to 0.0..1.0 the way TouchOSC faders and most lighting consoles expect.
+// "{channel}" and "{source}" in the address pattern are filled in per channel.
+pub struct OscSink;
+
+impl OscSink {
+    pub fn spawn<S: AsRef<str>>(target: S, address: S) -> anyhow::Result<ThreadedSink> {
+        let sock = UdpSocket::bind("0.0.0.0:0")?;
+        sock.connect(target.as_ref())?;
+        info!("Sending OSC to {}", target.as_ref());
+        let address = address.as_ref().to_string();
+        spawn_sink("osc", move |rx| Self::run(sock, address, rx))
+    }
+
+    fn run(sock: UdpSocket, address: String, rx: mpsc::Receiver<Frame>) {
+        while let Ok(frame) = rx.recv() {
+            for (ch, sample) in &frame {
+                let addr = address
+                    .replace("{channel}", &ch.to_string())
+                    .replace("{source}", sample.source);
+                let value = (sample.value / 255.0).clamp(0.0, 1.0) as f32;
+                if let Err(e) = sock.send(&osc_message(&addr, value)) {
+                    debug!("OSC: {e}");
+                    count_error("osc");
+                }
+            }
+        }
+    }
+}
+
+// address, type tags ",f" and the big-endian float, strings NUL padded to 4 bytes
+fn osc_message(address: &str, value: f32) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(address.len() + 12);
+    for s in [address, ",f"] {
+        msg.extend(s.as_bytes());
+        msg.extend(std::iter::repeat_n(0, 4 - s.len() % 4));
+    }
+    msg.extend(value.to_be_bytes());
+    msg
+}
+
+// EOF
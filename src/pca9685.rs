@@ -0,0 +1,112 @@
+// pca9685.rs
+
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::Write,
+    os::fd::AsRawFd,
+    sync::mpsc,
+    thread, time,
+};
+
+use anyhow::bail;
+
+use crate::*;
+
+const I2C_SLAVE: std::os::raw::c_ulong = 0x0703;
+const PCA9685_OSC_HZ: f64 = 25_000_000.0;
+const PCA9685_OUTPUTS: u8 = 16;
+const REG_MODE1: u8 = 0x00;
+const REG_MODE2: u8 = 0x01;
+const REG_LED0_ON_L: u8 = 0x06;
+const REG_PRESCALE: u8 = 0xfe;
+const MODE1_RESTART: u8 = 0x80;
+const MODE1_AI: u8 = 0x20;
+const MODE1_SLEEP: u8 = 0x10;
+const MODE2_OUTDRV: u8 = 0x04;
+const LED_FULL: u8 = 0x10;
+
+#[derive(Clone, Debug)]
+pub struct Pca9685Config {
+    // i2c-dev device, e.g. /dev/i2c-1
+    pub bus: String,
+    pub addr: u16,
+    pub freq: u32,
+    // (meter channel, pwm output), empty maps channels 1..16 to outputs 0..15
+    pub map: Vec<(u8, u8)>,
+    // duty cycle of a full scale reading, out of 4095
+    pub max_duty: u16,
+}
+
+// Drives analog meters straight from a PCA9685 PWM board over i2c-dev
+pub struct Pca9685Sink;
+
+impl Pca9685Sink {
+    pub fn spawn(cfg: Pca9685Config) -> anyhow::Result<ThreadedSink> {
+        if let Some((_, out)) = cfg.map.iter().find(|(_, out)| *out >= PCA9685_OUTPUTS) {
+            bail!("PCA9685 has no output {out}");
+        }
+        let mut dev = OpenOptions::new().read(true).write(true).open(&cfg.bus)?;
+        sys::sys_ioctl_int(dev.as_raw_fd(), I2C_SLAVE, cfg.addr as _)?;
+        Self::init(&mut dev, cfg.freq)?;
+        info!(
+            "PCA9685 at 0x{:02x} on {} running at {} Hz",
+            cfg.addr, cfg.bus, cfg.freq
+        );
+        spawn_sink("pca9685", move |rx| Self::run(cfg, dev, rx))
+    }
+
+    fn init(dev: &mut File, freq: u32) -> anyhow::Result<()> {
+        let prescale = (PCA9685_OSC_HZ / (4096.0 * freq as f64)).round() - 1.0;
+        // the prescaler can only be written while sleeping
+        dev.write_all(&[REG_MODE1, MODE1_SLEEP])?;
+        dev.write_all(&[REG_PRESCALE, prescale.clamp(3.0, 255.0) as u8])?;
+        dev.write_all(&[REG_MODE2, MODE2_OUTDRV])?;
+        dev.write_all(&[REG_MODE1, MODE1_AI])?;
+        thread::sleep(time::Duration::from_micros(500));
+        dev.write_all(&[REG_MODE1, MODE1_AI | MODE1_RESTART])?;
+        Ok(())
+    }
+
+    fn run(cfg: Pca9685Config, mut dev: File, rx: mpsc::Receiver<Frame>) {
+        let map: HashMap<u8, u8> = if cfg.map.is_empty() {
+            (1..=PCA9685_OUTPUTS).map(|ch| (ch, ch - 1)).collect()
+        } else {
+            cfg.map.iter().copied().collect()
+        };
+        let mut last: HashMap<u8, u16> = HashMap::new();
+
+        while let Ok(frame) = rx.recv() {
+            for (ch, sample) in &frame {
+                let Some(out) = map.get(ch) else {
+                    continue;
+                };
+                let duty = (sample.value.clamp(0.0, 255.0) / 255.0 * cfg.max_duty as f64) as u16;
+                if last.get(out) == Some(&duty) {
+                    continue;
+                }
+                if let Err(e) = set_duty(&mut dev, *out, duty) {
+                    debug!("PCA9685: {e}");
+                    count_error("pca9685");
+                    continue;
+                }
+                last.insert(*out, duty);
+            }
+        }
+    }
+}
+
+// ON at 0, OFF at duty, with the full-on and full-off bits for the extremes
+fn set_duty(dev: &mut File, out: u8, duty: u16) -> anyhow::Result<()> {
+    let (on, off) = match duty {
+        0 => (0, (LED_FULL as u16) << 8),
+        d if d >= 4095 => ((LED_FULL as u16) << 8, 0),
+        d => (0, d),
+    };
+    let [on_l, on_h] = on.to_le_bytes();
+    let [off_l, off_h] = off.to_le_bytes();
+    dev.write_all(&[REG_LED0_ON_L + 4 * out, on_l, on_h, off_l, off_h])?;
+    Ok(())
+}
+
+// EOF
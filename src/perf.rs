@@ -0,0 +1,148 @@
+// perf.rs
+
+use std::os::raw::{c_int, c_ulong};
+use std::{fs::File, io::Read, os::fd::FromRawFd};
+
+use anyhow::bail;
+
+use crate::*;
+
+const PERF_TYPE_HARDWARE: u32 = 0;
+const PERF_COUNT_HW_CPU_CYCLES: u64 = 0;
+const PERF_COUNT_HW_INSTRUCTIONS: u64 = 1;
+const PERF_COUNT_HW_CACHE_REFERENCES: u64 = 2;
+const PERF_COUNT_HW_CACHE_MISSES: u64 = 3;
+const PERF_FLAG_FD_CLOEXEC: c_ulong = 8;
+
+// The first version of struct perf_event_attr (PERF_ATTR_SIZE_VER0),
+// the kernel zero-fills everything after it.
+#[repr(C)]
+#[derive(Default)]
+struct PerfEventAttr {
+    type_: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+}
+
+// System-wide hardware counter pairs, one set per online cpu.
+// Needs CAP_PERFMON or kernel.perf_event_paranoid <= 0.
+#[derive(Debug)]
+pub struct PerfCounters {
+    pub metric: PerfMetric,
+    counters: Vec<(File, File)>,
+    prev: (u64, u64),
+}
+
+impl PerfCounters {
+    pub fn new(metric: PerfMetric) -> anyhow::Result<Self> {
+        let (numer, denom) = match metric {
+            PerfMetric::Ipc => (PERF_COUNT_HW_INSTRUCTIONS, PERF_COUNT_HW_CPU_CYCLES),
+            PerfMetric::LlcMiss => (PERF_COUNT_HW_CACHE_MISSES, PERF_COUNT_HW_CACHE_REFERENCES),
+        };
+        let counters = online_cpus()?
+            .into_iter()
+            .map(|cpu| Ok((open_counter(numer, cpu)?, open_counter(denom, cpu)?)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let mut perf = Self {
+            metric,
+            counters,
+            prev: (0, 0),
+        };
+        perf.prev = perf.read_sums()?;
+        Ok(perf)
+    }
+
+    // IPC as a plain ratio, LLC misses in percent
+    pub fn value(&mut self) -> anyhow::Result<f64> {
+        let sums = self.read_sums()?;
+        let numer = sums.0.wrapping_sub(self.prev.0) as f64;
+        let denom = sums.1.wrapping_sub(self.prev.1) as f64;
+        self.prev = sums;
+        if denom <= 0.0 {
+            return Ok(0.0);
+        }
+        Ok(match self.metric {
+            PerfMetric::Ipc => numer / denom,
+            PerfMetric::LlcMiss => 100.0 * numer / denom,
+        })
+    }
+
+    fn read_sums(&mut self) -> anyhow::Result<(u64, u64)> {
+        let mut sums = (0u64, 0u64);
+        for (numer, denom) in self.counters.iter_mut() {
+            sums.0 = sums.0.wrapping_add(read_counter(numer)?);
+            sums.1 = sums.1.wrapping_add(read_counter(denom)?);
+        }
+        Ok(sums)
+    }
+}
+
+impl StatSource for PerfCounters {
+    fn sample(&mut self) -> anyhow::Result<f64> {
+        self.value()
+    }
+    fn name(&self) -> &'static str {
+        "perf"
+    }
+    fn unit(&self) -> &'static str {
+        match self.metric {
+            PerfMetric::Ipc => "",
+            PerfMetric::LlcMiss => "%",
+        }
+    }
+}
+
+fn open_counter(config: u64, cpu: c_int) -> anyhow::Result<File> {
+    let attr = PerfEventAttr {
+        type_: PERF_TYPE_HARDWARE,
+        size: std::mem::size_of::<PerfEventAttr>() as u32,
+        config,
+        ..Default::default()
+    };
+    // pid -1 + cpu N counts everything running on that cpu
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_perf_event_open,
+            &attr as *const PerfEventAttr,
+            -1 as c_int,
+            cpu,
+            -1 as c_int,
+            PERF_FLAG_FD_CLOEXEC,
+        )
+    };
+    if fd < 0 {
+        bail!(
+            "perf_event_open failed on cpu {cpu}: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    Ok(unsafe { File::from_raw_fd(fd as c_int) })
+}
+
+fn read_counter(f: &mut File) -> anyhow::Result<u64> {
+    let mut buf = [0u8; 8];
+    f.read_exact(&mut buf)?;
+    Ok(u64::from_ne_bytes(buf))
+}
+
+// Parse /sys/devices/system/cpu/online, e.g. "0-3,6,8-9"
+fn online_cpus() -> anyhow::Result<Vec<c_int>> {
+    let online = std::fs::read_to_string(sys_path("devices/system/cpu/online"))?;
+    let mut cpus = Vec::new();
+    for range in online.trim().split(',').filter(|r| !r.is_empty()) {
+        match range.split_once('-') {
+            Some((a, b)) => cpus.extend(a.parse::<c_int>()?..=b.parse::<c_int>()?),
+            None => cpus.push(range.parse::<c_int>()?),
+        }
+    }
+    Ok(cpus)
+}
+
+// EOF
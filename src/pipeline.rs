@@ -0,0 +1,272 @@
+// pipeline.rs
+
+use std::collections::BTreeSet;
+
+use crate::*;
+
+// One round of the measure loop, the same for the daemon and for VuMeter:
+// the channel sources are sampled on top of the gauges set from the outside,
+// then come the curves, the overrides and the sanity check. That frame is
+// what the sinks see, the meters get it with the alerts flashing on top.
+// While paused every channel shown so far is parked at zero.
+#[derive(Default)]
+pub struct Pipeline {
+    sources: Vec<ChannelSource>,
+    curves: Vec<(u8, Curve)>,
+    alerts: Alerts,
+    // what was shown, parked while paused
+    channels: BTreeSet<u8>,
+    // lost their source on reload, zero on the next round
+    dropped: Vec<u8>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // the mapping, curves and alerts of the options
+    pub fn from_opts(opts: &OptsCommon) -> anyhow::Result<Self> {
+        let mut pipeline = Self::new();
+        pipeline.sources = Self::sources(opts)?;
+        pipeline.curves = opts.curve.clone();
+        pipeline.alerts = Alerts::new(&opts.alert);
+        for source in &pipeline.sources {
+            info!("Channel {} shows {}", source.channel, source.label);
+        }
+        Ok(pipeline)
+    }
+
+    pub fn push(&mut self, source: ChannelSource) {
+        self.sources.push(source);
+    }
+
+    pub fn curve(&mut self, channel: u8, curve: Curve) {
+        self.curves.push((channel, curve));
+    }
+
+    pub fn alerts(&mut self, specs: &[(u8, AlertSpec)]) {
+        self.alerts.set_specs(specs);
+    }
+
+    // SIGHUP: the mapping and everything the loop reads from the options is replaced,
+    // while the meters and the sinks stay as they were.
+    pub fn reload(&mut self, opts: &mut OptsCommon) {
+        match OptsCommon::reload() {
+            Ok(new) => self.replace(opts, new),
+            Err(e) => error!("Reload failed, keeping the old configuration: {e}"),
+        }
+    }
+
+    // the sources are only rebuilt when their mapping changed
+    fn replace(&mut self, opts: &mut OptsCommon, new: OptsCommon) {
+        if new.mapping() != opts.mapping()
+            || new.mapping_config() != opts.mapping_config()
+            || new.interval != opts.interval
+            || new.adaptive != opts.adaptive
+        {
+            match Self::sources(&new) {
+                Ok(sources) => {
+                    let old = std::mem::replace(&mut self.sources, sources);
+                    self.dropped = old
+                        .iter()
+                        .map(|s| s.channel)
+                        .filter(|ch| self.sources.iter().all(|s| s.channel != *ch))
+                        .collect();
+                }
+                Err(e) => {
+                    error!("Reload failed, keeping the old configuration: {e}");
+                    return;
+                }
+            }
+            for source in &self.sources {
+                info!("Channel {} shows {}", source.channel, source.label);
+            }
+        }
+        if new.meter_ports() != opts.meter_ports()
+            || new.protocol != opts.protocol
+            || new.samplerate != opts.samplerate
+        {
+            info!("Meter ports and the sample rate only change on restart");
+        }
+        self.curves = new.curve.clone();
+        self.alerts.set_specs(&new.alert);
+        *opts = new;
+        info!("Configuration reloaded");
+    }
+
+    // pending holds the gauges set from the outside, tap sees the frame before the alerts
+    pub fn tick<F: FnMut(&Frame)>(&mut self, pending: Frame, mut tap: F) -> Frame {
+        let mut frame = pending;
+        for ch in self.dropped.drain(..) {
+            frame.insert(ch, Sample::new(0.0));
+        }
+        if is_paused() {
+            frame.extend(self.channels.iter().map(|ch| (*ch, Sample::new(0.0))));
+            return frame;
+        }
+        for source in self.sources.iter_mut() {
+            frame.insert(source.channel, source.sample_or_zero());
+        }
+        apply_curves(&mut frame, &self.curves);
+        apply_overrides(&mut frame);
+        sanitize(&mut frame);
+        tap(&frame);
+        self.channels.extend(frame.keys().copied());
+        self.alerts.apply(&mut frame);
+        frame
+    }
+
+    fn sources(opts: &OptsCommon) -> anyhow::Result<Vec<ChannelSource>> {
+        let cfg = opts.mapping_config();
+        opts.mapping()
+            .into_iter()
+            .map(|(ch, spec)| {
+                Ok(ChannelSource::new(ch, spec, &cfg)?
+                    .every(opts.sample_interval(ch))
+                    .adaptive(opts.adaptive_window(ch)))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time;
+
+    use super::*;
+
+    // the samples to give, with when they were taken
+    struct Stub(Vec<(f64, time::Instant)>);
+
+    impl StatSource for Stub {
+        fn sample(&mut self) -> anyhow::Result<f64> {
+            Ok(self.sample_at()?.0)
+        }
+        fn name(&self) -> &'static str {
+            "stub"
+        }
+        fn unit(&self) -> &'static str {
+            ""
+        }
+        fn sample_at(&mut self) -> anyhow::Result<(f64, time::Instant)> {
+            Ok(self.0.remove(0))
+        }
+    }
+
+    fn stub(channel: u8, samples: &[(f64, time::Instant)]) -> ChannelSource {
+        ChannelSource::with_source(
+            channel,
+            "stub".into(),
+            Box::new(Stub(samples.to_vec())),
+            128.0,
+        )
+    }
+
+    struct Recorder(Arc<Mutex<Vec<(u8, u8)>>>);
+
+    impl Sink for Recorder {
+        fn set(&mut self, channel: u8, value: u8) -> anyhow::Result<()> {
+            self.0.lock().unwrap().push((channel, value));
+            Ok(())
+        }
+    }
+
+    fn parse_opts(args: &[&str]) -> OptsCommon {
+        OptsCommon::try_parse_from([&["perf_vumeter"], args].concat()).unwrap()
+    }
+
+    #[test]
+    fn tick_to_the_sink() {
+        let t0 = time::Instant::now();
+        let t1 = t0 + time::Duration::from_secs(1);
+        let mut pipeline = Pipeline::new();
+        pipeline.push(stub(1, &[(64.0, t0), (96.0, t1)]));
+        pipeline.push(stub(2, &[(f64::NAN, t0), (f64::NAN, t1)]));
+        pipeline.push(stub(4, &[(f64::INFINITY, t0), (f64::INFINITY, t1)]));
+        let mut pending = Frame::new();
+        pending.insert(3, Sample::new(200.0).source("set"));
+
+        let frame = pipeline.tick(pending, |_| {});
+        assert_eq!(frame.keys().copied().collect::<Vec<u8>>(), [1, 2, 3, 4]);
+        assert_eq!(frame[&1].value, 128.0);
+        assert_eq!(frame[&1].raw, Some(64.0));
+        assert_eq!(frame[&1].source, "stub");
+        // sanitized to zero, the unknown reading pegs the needle
+        assert_eq!(frame[&2].value, 0.0);
+        assert_eq!(frame[&2].raw, None);
+        assert_eq!(frame[&3].value, 200.0);
+        assert_eq!(frame[&4].value, 255.0);
+
+        // extrapolated by the slope between the samples, at most a second ahead
+        let mut latency_comp = LatencyComp::new(time::Duration::from_secs(1));
+        let now = t1 + time::Duration::from_millis(500);
+        assert_eq!(latency_comp.compensate(1, frame[&1], t1), 128.0);
+        let next = pipeline.tick(Frame::new(), |_| {});
+        assert_eq!(next[&1].value, 192.0);
+        assert_eq!(latency_comp.compensate(1, next[&1], now), 224.0);
+        let later = t1 + time::Duration::from_secs(5);
+        assert_eq!(latency_comp.compensate(1, next[&1], later), 256.0);
+
+        let set = Arc::new(Mutex::new(Vec::new()));
+        let mut meters = [Meter::new(
+            MeterPort::default(),
+            Box::new(Recorder(set.clone())),
+        )];
+        let opts = parse_opts(&[]);
+        write_frame(&mut meters, &opts, &mut latency_comp, frame).unwrap();
+        assert_eq!(*set.lock().unwrap(), [(1, 128), (2, 0), (3, 200), (4, 255)]);
+    }
+
+    #[test]
+    fn threaded_sinks_get_the_frames() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let sink = spawn_sink("test", move |frames| {
+            for frame in frames {
+                tx.send(frame).unwrap();
+            }
+        })
+        .unwrap();
+        let mut meters = vec![Meter::new(MeterPort::default(), Box::new(sink))];
+        let mut pipeline = Pipeline::new();
+        let mut pending = Frame::new();
+        pending.insert(5, Sample::new(100.0));
+        let frame = pipeline.tick(pending, |frame| feed_sinks(&mut meters, frame));
+        let got = rx.recv_timeout(time::Duration::from_secs(5)).unwrap();
+        assert_eq!(got.keys().copied().collect::<Vec<u8>>(), [5]);
+        assert_eq!(got[&5].value, 100.0);
+
+        // no needles to smooth
+        let mut latency_comp = LatencyComp::new(time::Duration::from_secs(1));
+        write_frame(&mut meters, &parse_opts(&[]), &mut latency_comp, frame).unwrap();
+        assert_eq!(meters[0].gauges.channel(5).unwrap().value, 0.0);
+    }
+
+    #[test]
+    fn reload_drops_and_curves() {
+        let t0 = time::Instant::now();
+        let mut opts = parse_opts(&["--channel", "1=demo:sine", "--channel", "2=demo:walk"]);
+        let mut pipeline = Pipeline::new();
+        pipeline.push(stub(1, &[(64.0, t0)]));
+        pipeline.push(stub(2, &[(64.0, t0)]));
+        pipeline.tick(Frame::new(), |_| {});
+
+        let new = parse_opts(&["--channel", "1=demo:sine", "--curve", "6=gamma:2"]);
+        pipeline.replace(&mut opts, new);
+        assert_eq!(opts.curve, [(6, Curve::Gamma(2.0))]);
+
+        let mut pending = Frame::new();
+        pending.insert(6, Sample::new(64.0));
+        let frame = pipeline.tick(pending, |_| {});
+        assert_eq!(frame.keys().copied().collect::<Vec<u8>>(), [1, 2, 6]);
+        assert_eq!(frame[&1].source, "demo");
+        // the channel without a source any more goes to zero once
+        assert_eq!(frame[&2].value, 0.0);
+        assert_eq!(frame[&6].value, Curve::Gamma(2.0).apply(64.0));
+        let frame = pipeline.tick(Frame::new(), |_| {});
+        assert_eq!(frame.keys().copied().collect::<Vec<u8>>(), [1]);
+    }
+}
+
+// EOF
@@ -0,0 +1,103 @@
+// plugin.rs
+
+use std::os::raw::{c_char, c_void};
+use std::{ffi::CString, fmt, mem};
+
+use anyhow::{anyhow, bail};
+
+use crate::*;
+
+type InitFn = unsafe extern "C" fn(arg: *const c_char) -> *mut c_void;
+type SampleFn = unsafe extern "C" fn(ctx: *mut c_void) -> f64;
+type FreeFn = unsafe extern "C" fn(ctx: *mut c_void);
+
+// A source in a shared library with a C ABI, see examples/plugin/README.md:
+//   void *perf_vumeter_init(const char *arg);   optional, NULL is an error
+//   double perf_vumeter_sample(void *ctx);      required, NaN is counted as an error
+//                                               and shows zero
+//   void perf_vumeter_free(void *ctx);          optional
+// The library is loaded once and sampled from the measure loop.
+pub struct Plugin {
+    pub path: String,
+    handle: *mut c_void,
+    ctx: *mut c_void,
+    sample_fn: SampleFn,
+    free_fn: Option<FreeFn>,
+}
+
+impl fmt::Debug for Plugin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Plugin").field("path", &self.path).finish()
+    }
+}
+
+impl Plugin {
+    pub fn load(path: &str, arg: &str) -> anyhow::Result<Self> {
+        let arg = CString::new(arg)?;
+        let handle = sys::sys_dlopen(path).map_err(|e| anyhow!("Plugin {path}: {e}"))?;
+        let sym = |name: &str| sys::sys_dlsym(handle, name);
+
+        let Some(sample) = sym("perf_vumeter_sample") else {
+            sys::sys_dlclose(handle);
+            bail!("Plugin {path} has no perf_vumeter_sample");
+        };
+        // SAFETY: the plugin ABI defines the signatures of these symbols
+        let sample_fn = unsafe { mem::transmute::<*mut c_void, SampleFn>(sample) };
+        let free_fn =
+            sym("perf_vumeter_free").map(|f| unsafe { mem::transmute::<*mut c_void, FreeFn>(f) });
+        let ctx = match sym("perf_vumeter_init") {
+            Some(init) => {
+                let init = unsafe { mem::transmute::<*mut c_void, InitFn>(init) };
+                let ctx = unsafe { init(arg.as_ptr()) };
+                if ctx.is_null() {
+                    sys::sys_dlclose(handle);
+                    bail!("Plugin {path} failed to initialize");
+                }
+                ctx
+            }
+            None => std::ptr::null_mut(),
+        };
+        info!("Loaded plugin {path}");
+        Ok(Self {
+            path: path.into(),
+            handle,
+            ctx,
+            sample_fn,
+            free_fn,
+        })
+    }
+
+    // a failed sample shows zero instead of ending the measure loop
+    pub fn sample(&mut self) -> f64 {
+        let value = unsafe { (self.sample_fn)(self.ctx) };
+        if value.is_nan() {
+            debug!("Plugin {} failed to sample, showing zero", self.path);
+            count_error("plugin");
+            return 0.0;
+        }
+        value
+    }
+}
+
+impl StatSource for Plugin {
+    fn sample(&mut self) -> anyhow::Result<f64> {
+        Ok(Plugin::sample(self))
+    }
+    fn name(&self) -> &'static str {
+        "plugin"
+    }
+    fn unit(&self) -> &'static str {
+        ""
+    }
+}
+
+impl Drop for Plugin {
+    fn drop(&mut self) {
+        if let Some(free) = self.free_fn {
+            unsafe { free(self.ctx) };
+        }
+        sys::sys_dlclose(self.handle);
+    }
+}
+
+// EOF
@@ -0,0 +1,51 @@
+// poller.rs
+
+use std::sync::{Arc, Mutex};
+use std::{thread, time};
+
+use anyhow::anyhow;
+
+// Runs a slow reading, e.g. one that forks a command, over and over in a
+// background thread so that the measure loop only picks up the latest result.
+// The first reading is done right away and its error fails the start.
+// The thread ends after the Poller is dropped.
+#[derive(Debug)]
+pub struct Poller<T> {
+    latest: Arc<Mutex<Result<(T, time::Instant), String>>>,
+}
+
+impl<T: Clone + Send + 'static> Poller<T> {
+    pub fn spawn<F>(interval: time::Duration, mut read: F) -> anyhow::Result<Self>
+    where
+        F: FnMut() -> anyhow::Result<T> + Send + 'static,
+    {
+        let latest = Arc::new(Mutex::new(Ok((read()?, time::Instant::now()))));
+        let poller = Self {
+            latest: latest.clone(),
+        };
+        thread::spawn(move || {
+            let mut start = time::Instant::now();
+            loop {
+                if let Some(left) = interval.checked_sub(start.elapsed()) {
+                    thread::sleep(left);
+                }
+                if Arc::strong_count(&latest) == 1 {
+                    return;
+                }
+                start = time::Instant::now();
+                let res = read()
+                    .map(|value| (value, time::Instant::now()))
+                    .map_err(|e| e.to_string());
+                *latest.lock().unwrap() = res;
+            }
+        });
+        Ok(poller)
+    }
+
+    // the latest reading and when it was taken, or why it failed
+    pub fn latest(&self) -> anyhow::Result<(T, time::Instant)> {
+        self.latest.lock().unwrap().clone().map_err(|e| anyhow!(e))
+    }
+}
+
+// EOF
@@ -0,0 +1,165 @@
+// probe.rs
+
+use std::sync::{Arc, Mutex};
+use std::{
+    io::{Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    process::Command,
+    thread, time,
+};
+
+use anyhow::{anyhow, bail};
+
+use crate::*;
+
+// Measures time-to-first-byte of a URL in a background thread,
+// so that a slow web server does not stall the measure loop.
+#[derive(Debug)]
+pub struct HttpProbe {
+    pub url: String,
+    latency: Arc<Mutex<(Option<time::Duration>, time::Instant)>>,
+}
+
+impl HttpProbe {
+    pub fn new<S: AsRef<str>>(
+        url: S,
+        interval: time::Duration,
+        timeout: time::Duration,
+    ) -> anyhow::Result<Self> {
+        let url = url.as_ref().to_string();
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            bail!("Unsupported URL: {url}");
+        }
+        let latency = Arc::new(Mutex::new((None, time::Instant::now())));
+        let probe = Self {
+            url: url.clone(),
+            latency: latency.clone(),
+        };
+
+        thread::spawn(move || {
+            // a target that is down fails every probe, warn only once
+            let mut failing = false;
+            loop {
+                let start = time::Instant::now();
+                let res = Self::ttfb(&url, timeout);
+                match &res {
+                    Ok(d) => {
+                        if failing {
+                            info!("HTTP probe {url} is back");
+                            failing = false;
+                        }
+                        trace!("HTTP probe {url}: {} ms", d.as_millis());
+                    }
+                    Err(e) => {
+                        match failing {
+                            true => debug!("HTTP probe {url} failed: {e}"),
+                            false => warn!("HTTP probe {url} failed: {e}"),
+                        }
+                        failing = true;
+                        count_error("http");
+                    }
+                }
+                *latency.lock().unwrap() = (res.ok(), time::Instant::now());
+                if let Some(left) = interval.checked_sub(start.elapsed()) {
+                    thread::sleep(left);
+                }
+                if Arc::strong_count(&latency) == 1 {
+                    return;
+                }
+            }
+        });
+        Ok(probe)
+    }
+
+    // None means the latest probe failed or has not completed yet
+    pub fn latency(&self) -> Option<time::Duration> {
+        self.latency.lock().unwrap().0
+    }
+
+    // when the latest probe completed
+    pub fn captured(&self) -> time::Instant {
+        self.latency.lock().unwrap().1
+    }
+
+    pub fn ttfb(url: &str, timeout: time::Duration) -> anyhow::Result<time::Duration> {
+        match url.strip_prefix("http://") {
+            Some(rest) => Self::ttfb_http(rest, timeout),
+            // No TLS in here, let curl do the heavy lifting
+            None => Self::ttfb_curl(url, timeout),
+        }
+    }
+
+    fn ttfb_http(rest: &str, timeout: time::Duration) -> anyhow::Result<time::Duration> {
+        let (hostport, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let host = hostport.split(':').next().unwrap_or(hostport);
+        let addr_s = if hostport.contains(':') {
+            hostport.to_string()
+        } else {
+            format!("{hostport}:80")
+        };
+
+        let start = time::Instant::now();
+        let addr = addr_s
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| anyhow!("Cannot resolve {addr_s}"))?;
+        let mut stream = TcpStream::connect_timeout(&addr, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        write!(
+            stream,
+            "GET {path} HTTP/1.1\r\nHost: {host}\r\nUser-Agent: perf_vumeter/{}\r\nConnection: close\r\n\r\n",
+            env!("CARGO_PKG_VERSION")
+        )?;
+        let mut buf = [0u8; 1];
+        if stream.read(&mut buf)? == 0 {
+            bail!("Connection closed");
+        }
+        Ok(start.elapsed())
+    }
+
+    fn ttfb_curl(url: &str, timeout: time::Duration) -> anyhow::Result<time::Duration> {
+        let out = Command::new("curl")
+            .args([
+                "-o",
+                "/dev/null",
+                "-s",
+                "-w",
+                "%{time_starttransfer}",
+                "--max-time",
+            ])
+            .arg(format!("{:.3}", timeout.as_secs_f64()))
+            .arg(url)
+            .output()?;
+        if !out.status.success() {
+            bail!("curl exited with {}", out.status);
+        }
+        let secs = String::from_utf8_lossy(&out.stdout).trim().parse::<f64>()?;
+        Ok(time::Duration::from_secs_f64(secs))
+    }
+}
+
+// latency in milliseconds, a failed probe is infinite and pegs the needle
+impl StatSource for HttpProbe {
+    fn sample(&mut self) -> anyhow::Result<f64> {
+        Ok(self.sample_at()?.0)
+    }
+    fn name(&self) -> &'static str {
+        "http"
+    }
+    fn unit(&self) -> &'static str {
+        "ms"
+    }
+    fn sample_at(&mut self) -> anyhow::Result<(f64, time::Instant)> {
+        let (latency, ts) = *self.latency.lock().unwrap();
+        Ok((
+            latency.map_or(f64::INFINITY, |d| d.as_secs_f64() * 1000.0),
+            ts,
+        ))
+    }
+}
+
+// EOF
@@ -0,0 +1,133 @@
+// procfs.rs
+
+use std::sync::RwLock;
+
+// Where the readers find /proc and /sys, moved elsewhere to run against
+// fixture files or a snapshot copied from another machine
+static PROC_ROOT: RwLock<String> = RwLock::new(String::new());
+static SYS_ROOT: RwLock<String> = RwLock::new(String::new());
+
+pub fn set_fs_roots(proc_root: &str, sys_root: &str) {
+    *PROC_ROOT.write().unwrap() = proc_root.trim_end_matches('/').into();
+    *SYS_ROOT.write().unwrap() = sys_root.trim_end_matches('/').into();
+}
+
+// proc_path("stat") is /proc/stat unless the root was moved
+pub fn proc_path(path: &str) -> String {
+    rooted(&PROC_ROOT, "/proc", path)
+}
+
+pub fn sys_path(path: &str) -> String {
+    rooted(&SYS_ROOT, "/sys", path)
+}
+
+fn rooted(root: &RwLock<String>, default: &str, path: &str) -> String {
+    let root = root.read().unwrap();
+    match root.is_empty() {
+        true => format!("{default}/{path}"),
+        false => format!("{root}/{path}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Mutex, MutexGuard};
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+        process, thread, time,
+    };
+
+    use crate::*;
+
+    static FS_ROOTS: Mutex<()> = Mutex::new(());
+
+    // the roots are process global: one test at a time moves them, and they
+    // are put back with the fixtures removed even when an assertion fails
+    struct FixtureRoots {
+        root: PathBuf,
+        _lock: MutexGuard<'static, ()>,
+    }
+
+    impl FixtureRoots {
+        fn new(root: PathBuf) -> Self {
+            let lock = FS_ROOTS.lock().unwrap_or_else(|e| e.into_inner());
+            set_fs_roots(
+                root.join("proc").to_str().unwrap(),
+                root.join("sys").to_str().unwrap(),
+            );
+            Self { root, _lock: lock }
+        }
+    }
+
+    impl Drop for FixtureRoots {
+        fn drop(&mut self) {
+            set_fs_roots("", "");
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    fn write(root: &Path, path: &str, content: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    fn proc_stat(cpu0: i64, cpu1: i64) -> String {
+        // user nice system idle iowait irq softirq steal
+        format!(
+            "cpu  100 0 100 1000 {} 0 0 0\ncpu0 50 0 50 500 {cpu0} 0 0 0\n\
+             cpu1 50 0 50 500 {cpu1} 0 0 0\nintr 1 2 3\n",
+            cpu0 + cpu1
+        )
+    }
+
+    fn diskstats(sda: i64, nvme: i64) -> String {
+        format!(
+            "   8       0 sda 10 0 {sda} 0 10 0 {sda} 0 0 0 0\n\
+             8       1 sda1 10 0 {sda} 0 10 0 {sda} 0 0 0 0\n\
+             259       0 nvme0n1 10 0 {nvme} 0 10 0 {nvme} 0 0 0 0\n"
+        )
+    }
+
+    // every reader must go through the moved roots, the fixtures change
+    // between the samples and nothing in the real /proc or /sys does that
+    #[test]
+    fn readers_use_the_fs_roots() {
+        let root = std::env::temp_dir().join(format!("perf_vumeter_fixture_{}", process::id()));
+        let (proc_root, sys_root) = (root.join("proc"), root.join("sys"));
+        let _roots = FixtureRoots::new(root);
+        write(&proc_root, "stat", &proc_stat(0, 0));
+        write(&proc_root, "diskstats", &diskstats(0, 0));
+        write(&sys_root, "class/net/eth0/statistics/rx_bytes", "1000\n");
+
+        let started = time::Instant::now();
+        let mut cpu = CpuStats::with_mode(CpuMode::Iowait).unwrap();
+        let mut disk = DiskStats::with_device(None, DiskGauge::Sum).unwrap();
+        let mut net = IfStats::new("eth0", IfCounter::Rx).unwrap();
+        let pause = time::Duration::from_millis(50);
+        thread::sleep(pause);
+        write(&proc_root, "stat", &proc_stat(10, 30));
+        write(&proc_root, "diskstats", &diskstats(100, 300));
+        write(&sys_root, "class/net/eth0/statistics/rx_bytes", "6000\n");
+
+        // the busier core first, the total averages the two
+        let rates = cpu.cpurates().unwrap();
+        assert_eq!(rates.len(), 3);
+        assert!((rates[1] / rates[2] - 3.0).abs() < 1e-9);
+        assert!((rates[0] / rates[1] - 2.0 / 3.0).abs() < 1e-9);
+
+        // sda and nvme0n1 but not the partition, 200 and 600 sectors
+        let rates = disk.diskrates().unwrap();
+        assert_eq!(rates.len(), 2);
+        assert!((rates[0] / rates[1] - 3.0).abs() < 1e-9);
+
+        // 5000 bytes in at least the pause and at most the whole test
+        let bits = net.bitrate().unwrap() as f64;
+        let elapsed = started.elapsed().as_secs_f64();
+        assert!(bits <= 40_000.0 / pause.as_secs_f64());
+        assert!(bits >= 40_000.0 / elapsed - 1.0);
+    }
+}
+
+// EOF
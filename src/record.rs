@@ -0,0 +1,85 @@
+// record.rs
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::time;
+
+use anyhow::bail;
+
+use crate::*;
+
+// Recording format: "VUREC" version, then per frame:
+// ms since the start u32 BE, count u8, per channel: channel u8, gauge f32 BE
+const REC_MAGIC: [u8; 5] = *b"VUREC";
+const REC_VERSION: u8 = 1;
+
+pub struct FrameRecorder {
+    out: BufWriter<File>,
+    start: time::Instant,
+}
+
+impl FrameRecorder {
+    pub fn create(path: &str) -> anyhow::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(&REC_MAGIC)?;
+        out.write_all(&[REC_VERSION])?;
+        Ok(Self {
+            out,
+            start: time::Instant::now(),
+        })
+    }
+
+    pub fn write(&mut self, frame: &Frame) -> anyhow::Result<()> {
+        let ms = self.start.elapsed().as_millis().min(u32::MAX as u128) as u32;
+        self.out.write_all(&ms.to_be_bytes())?;
+        self.out.write_all(&[frame.len().min(255) as u8])?;
+        for (ch, sample) in frame.iter().take(255) {
+            self.out.write_all(&[*ch])?;
+            self.out.write_all(&(sample.value as f32).to_be_bytes())?;
+        }
+        // a recording cut short by a crash is still good up to here
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+pub struct FrameReplay {
+    input: BufReader<File>,
+}
+
+impl FrameReplay {
+    pub fn open(path: &str) -> anyhow::Result<Self> {
+        let mut input = BufReader::new(File::open(path)?);
+        let mut head = [0u8; 6];
+        input.read_exact(&mut head)?;
+        if head[..5] != REC_MAGIC || head[5] != REC_VERSION {
+            bail!("{path} is not a gauge recording");
+        }
+        Ok(Self { input })
+    }
+
+    // the next frame and when it was taken after the start, None at the end
+    pub fn next_frame(&mut self) -> anyhow::Result<Option<(time::Duration, Frame)>> {
+        let mut head = [0u8; 5];
+        match self.input.read_exact(&mut head) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        let ms = u32::from_be_bytes([head[0], head[1], head[2], head[3]]);
+        let mut frame = Frame::new();
+        for _ in 0..head[4] {
+            let mut entry = [0u8; 5];
+            self.input.read_exact(&mut entry)?;
+            if entry[0] as usize >= CHANNELS_NUM {
+                debug!("Recorded channel {} out of range, skipped", entry[0]);
+                continue;
+            }
+            let gauge = f32::from_be_bytes([entry[1], entry[2], entry[3], entry[4]]);
+            frame.insert(entry[0], Sample::new(gauge as f64).source("replay"));
+        }
+        Ok(Some((time::Duration::from_millis(ms as u64), frame)))
+    }
+}
+
+// EOF
@@ -0,0 +1,155 @@
+// remote.rs
+
+use std::{net::UdpSocket, str::FromStr, time};
+
+use anyhow::{anyhow, bail};
+
+use crate::*;
+
+// Agent -> display wire format, one UDP datagram per sample round:
+// 'V' 'U' version count, then per channel: channel u8, gauge f32 BE, age_ms u16 BE
+const FRAME_MAGIC: [u8; 2] = *b"VU";
+const FRAME_VERSION: u8 = 1;
+const ENTRY_LEN: usize = 7;
+
+#[derive(Debug)]
+pub struct FrameSender {
+    sock: UdpSocket,
+}
+
+impl FrameSender {
+    pub fn new<S: AsRef<str>>(display: S) -> anyhow::Result<Self> {
+        let sock = UdpSocket::bind("[::]:0").or_else(|_| UdpSocket::bind("0.0.0.0:0"))?;
+        sock.connect(display.as_ref())?;
+        Ok(Self { sock })
+    }
+
+    // e.g. 255.255.255.255:4242 or 192.168.1.255:4242
+    pub fn broadcast<S: AsRef<str>>(target: S) -> anyhow::Result<Self> {
+        let sock = UdpSocket::bind("0.0.0.0:0")?;
+        sock.set_broadcast(true)?;
+        sock.connect(target.as_ref())?;
+        Ok(Self { sock })
+    }
+
+    pub fn send(&self, frame: &Frame) -> anyhow::Result<()> {
+        self.sock.send(&encode_frame(frame))?;
+        Ok(())
+    }
+
+    pub fn send_json(&self, frame: &Frame) -> anyhow::Result<()> {
+        self.sock.send(frame_json(frame).as_bytes())?;
+        Ok(())
+    }
+}
+
+pub fn encode_frame(frame: &Frame) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(4 + frame.len() * ENTRY_LEN);
+    buf.extend(FRAME_MAGIC);
+    buf.push(FRAME_VERSION);
+    buf.push(frame.len().min(255) as u8);
+    for (ch, sample) in frame.iter().take(255) {
+        let age = sample.ts.elapsed().as_millis().min(u16::MAX as u128) as u16;
+        buf.push(*ch);
+        buf.extend((sample.value as f32).to_be_bytes());
+        buf.extend(age.to_be_bytes());
+    }
+    buf
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum BroadcastFormat {
+    // the agent/display wire format
+    #[default]
+    Binary,
+    // one JSON object per datagram, see frame_json()
+    Json,
+}
+
+impl FromStr for BroadcastFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "binary" => Ok(BroadcastFormat::Binary),
+            "json" => Ok(BroadcastFormat::Json),
+            _ => Err(anyhow!("Unknown broadcast format: {s}")),
+        }
+    }
+}
+
+// Broadcasts every frame on the LAN, any number of displays can listen in
+pub struct BroadcastSink;
+
+impl BroadcastSink {
+    pub fn spawn<S: AsRef<str>>(
+        target: S,
+        format: BroadcastFormat,
+    ) -> anyhow::Result<ThreadedSink> {
+        let sender = FrameSender::broadcast(&target)?;
+        info!("Broadcasting {format:?} frames to {}", target.as_ref());
+        spawn_sink("broadcast", move |rx| {
+            while let Ok(frame) = rx.recv() {
+                let res = match format {
+                    BroadcastFormat::Binary => sender.send(&frame),
+                    BroadcastFormat::Json => sender.send_json(&frame),
+                };
+                if let Err(e) = res {
+                    debug!("Broadcast: {e}");
+                    count_error("broadcast");
+                }
+            }
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct FrameReceiver {
+    sock: UdpSocket,
+}
+
+impl FrameReceiver {
+    pub fn new<S: AsRef<str>>(listen: S) -> anyhow::Result<Self> {
+        let sock = UdpSocket::bind(listen.as_ref())?;
+        Ok(Self { sock })
+    }
+
+    // None on timeout, samples are back-dated by the age the agent reported
+    pub fn recv(&self, timeout: time::Duration) -> anyhow::Result<Option<Frame>> {
+        self.sock.set_read_timeout(Some(timeout))?;
+        let mut buf = [0u8; 4 + 255 * ENTRY_LEN];
+        let len = match self.sock.recv(&mut buf) {
+            Ok(len) => len,
+            Err(e)
+                if e.kind() == std::io::ErrorKind::WouldBlock
+                    || e.kind() == std::io::ErrorKind::TimedOut =>
+            {
+                return Ok(None)
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let buf = &buf[..len];
+        if len < 4 || buf[..2] != FRAME_MAGIC || buf[2] != FRAME_VERSION {
+            bail!("Invalid frame received");
+        }
+        let n = buf[3] as usize;
+        if len < 4 + n * ENTRY_LEN {
+            bail!("Truncated frame received");
+        }
+
+        let now = time::Instant::now();
+        let mut frame = Frame::new();
+        for e in buf[4..4 + n * ENTRY_LEN].chunks_exact(ENTRY_LEN) {
+            if e[0] as usize >= CHANNELS_NUM {
+                debug!("Frame entry for channel {} out of range, skipped", e[0]);
+                continue;
+            }
+            let value = f32::from_be_bytes([e[1], e[2], e[3], e[4]]) as f64;
+            let age = time::Duration::from_millis(u16::from_be_bytes([e[5], e[6]]) as u64);
+            frame.insert(e[0], Sample::at(value, now.checked_sub(age).unwrap_or(now)));
+        }
+        Ok(Some(frame))
+    }
+}
+
+// EOF
@@ -0,0 +1,123 @@
+// sample.rs
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time,
+};
+
+use crate::*;
+
+// A gauge value together with the moment it was captured, the name of
+// its source and optionally the raw metric it was computed from (bps, %, ms...)
+#[derive(Clone, Copy, Debug)]
+pub struct Sample {
+    pub value: f64,
+    pub raw: Option<f64>,
+    pub source: &'static str,
+    pub ts: time::Instant,
+}
+
+impl Sample {
+    pub fn new(value: f64) -> Self {
+        Self::at(value, time::Instant::now())
+    }
+    pub fn at(value: f64, ts: time::Instant) -> Self {
+        Self {
+            value,
+            raw: None,
+            source: "",
+            ts,
+        }
+    }
+    pub fn raw(mut self, raw: f64) -> Self {
+        self.raw = Some(raw);
+        self
+    }
+    pub fn source(mut self, source: &'static str) -> Self {
+        self.source = source;
+        self
+    }
+}
+
+// One round of samples, keyed by channel
+pub type Frame = BTreeMap<u8, Sample>;
+
+// Gauges pinned from the outside, e.g. over D-Bus, win over the measured ones
+static OVERRIDES: Mutex<BTreeMap<u8, f64>> = Mutex::new(BTreeMap::new());
+
+pub fn set_override(channel: u8, gauge: Option<f64>) {
+    let mut overrides = OVERRIDES.lock().unwrap();
+    match gauge {
+        Some(g) => overrides.insert(channel, g),
+        None => overrides.remove(&channel),
+    };
+}
+
+pub fn apply_overrides(frame: &mut Frame) {
+    for (ch, gauge) in OVERRIDES.lock().unwrap().iter() {
+        frame.insert(*ch, Sample::new(*gauge).source("override"));
+    }
+}
+
+// NaN or infinite gauges, e.g. from a rate over next to no time or a counter
+// glitch, drop the needle to zero instead of reaching the meters and sinks.
+// They are counted as errors for /status.
+pub fn sanitize(frame: &mut Frame) {
+    for (ch, sample) in frame.iter_mut() {
+        if !sample.value.is_finite() {
+            debug!(
+                "Channel {ch} {}: gauge {}, showing zero",
+                sample.source, sample.value
+            );
+            count_error("gauge_nan");
+            sample.value = 0.0;
+        }
+        sample.raw = sample.raw.filter(|r| r.is_finite());
+    }
+}
+
+// Compensates for pipeline delay by extrapolating each channel to the present,
+// using the slope between its two latest distinct samples. The lookahead is capped
+// so that a stale source cannot push the needle far beyond anything measured.
+#[derive(Debug)]
+pub struct LatencyComp {
+    max_ahead: time::Duration,
+    prev: HashMap<u8, Sample>,
+    last: HashMap<u8, Sample>,
+}
+
+impl LatencyComp {
+    pub fn new(max_ahead: time::Duration) -> Self {
+        Self {
+            max_ahead,
+            prev: HashMap::new(),
+            last: HashMap::new(),
+        }
+    }
+
+    pub fn compensate(&mut self, channel: u8, sample: Sample, now: time::Instant) -> f64 {
+        // slow sources repeat the same sample, do not let that flatten the slope
+        match self.last.get(&channel) {
+            Some(last) if last.ts == sample.ts => {}
+            Some(last) => {
+                self.prev.insert(channel, *last);
+                self.last.insert(channel, sample);
+            }
+            None => {
+                self.last.insert(channel, sample);
+            }
+        }
+
+        match self.prev.get(&channel) {
+            Some(prev) if sample.ts > prev.ts => {
+                let slope = (sample.value - prev.value) / (sample.ts - prev.ts).as_secs_f64();
+                let delay = now.saturating_duration_since(sample.ts).min(self.max_ahead);
+                sample.value + slope * delay.as_secs_f64()
+            }
+            _ => sample.value,
+        }
+    }
+}
+
+// EOF
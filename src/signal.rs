@@ -0,0 +1,43 @@
+// signal.rs
+
+#[cfg(unix)]
+use std::os::raw::c_int;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(unix)]
+use crate::*;
+
+// the handler only raises flags, the measure loop acts on them
+static RELOAD_REQUEST: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_signal(signum: c_int) {
+    match signum {
+        sys::SIGHUP => RELOAD_REQUEST.store(true, Ordering::Relaxed),
+        sys::SIGUSR1 => request_hello(),
+        sys::SIGUSR2 => set_paused(!is_paused()),
+        _ => {}
+    }
+}
+
+// SIGHUP re-reads the configuration, SIGUSR1 runs the hello sweep
+// and SIGUSR2 pauses or resumes
+#[cfg(unix)]
+pub fn catch_signals() -> anyhow::Result<()> {
+    for signum in [sys::SIGHUP, sys::SIGUSR1, sys::SIGUSR2] {
+        sys::sys_signal(signum, on_signal)?;
+    }
+    Ok(())
+}
+
+// Windows has no such signals, there the configuration is only read on start
+#[cfg(not(unix))]
+pub fn catch_signals() -> anyhow::Result<()> {
+    Ok(())
+}
+
+pub fn take_reload_request() -> bool {
+    RELOAD_REQUEST.swap(false, Ordering::Relaxed)
+}
+
+// EOF
@@ -0,0 +1,142 @@
+// sink.rs
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{fmt::Write as _, fs, sync::mpsc, thread, time};
+
+use crate::*;
+
+const SINK_QUEUE: usize = 16;
+
+// The outputs that show more than needle positions run in their own threads
+// and get a copy of every frame through a Sink, they are driven like the meters.
+// A slow or stuck sink drops frames instead of blocking the measure loop.
+pub struct ThreadedSink {
+    tx: mpsc::SyncSender<Frame>,
+}
+
+impl Sink for ThreadedSink {
+    fn set(&mut self, _channel: u8, _value: u8) -> anyhow::Result<()> {
+        Ok(())
+    }
+    fn frame(&mut self, frame: &Frame) {
+        if let Err(mpsc::TrySendError::Full(_)) = self.tx.try_send(frame.clone()) {
+            trace!("Sink queue full, frame dropped");
+        }
+    }
+    fn needles(&self) -> bool {
+        false
+    }
+}
+
+pub fn spawn_sink<F>(name: &str, run: F) -> anyhow::Result<ThreadedSink>
+where
+    F: FnOnce(mpsc::Receiver<Frame>) + Send + 'static,
+{
+    let (tx, rx) = mpsc::sync_channel(SINK_QUEUE);
+    thread::Builder::new()
+        .name(name.to_string())
+        .spawn(move || run(rx))?;
+    Ok(ThreadedSink { tx })
+}
+
+// the frame of a tick, before the alerts, to every sink that wants it
+pub fn feed_sinks(meters: &mut [Meter], frame: &Frame) {
+    for meter in meters.iter_mut() {
+        meter.out.frame(frame);
+    }
+}
+
+// set from the outside, e.g. over MQTT, the measure loop runs the hello sweep
+static HELLO_REQUEST: AtomicBool = AtomicBool::new(false);
+
+pub fn request_hello() {
+    HELLO_REQUEST.store(true, Ordering::Relaxed);
+}
+
+pub fn take_hello_request() -> bool {
+    HELLO_REQUEST.swap(false, Ordering::Relaxed)
+}
+
+// set from the outside, e.g. from the tray menu or with SIGUSR2, parks the
+// needles and suspends the measuring until resumed
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+pub fn set_paused(paused: bool) {
+    PAUSED.store(paused, Ordering::Relaxed);
+}
+
+pub fn is_paused() -> bool {
+    PAUSED.load(Ordering::Relaxed)
+}
+
+pub fn hostname() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|h| h.trim().to_string())
+        .unwrap_or_else(|_| "localhost".into())
+}
+
+// {"ts":1700000000123,"channels":[...]}
+pub fn frame_json(frame: &Frame) -> String {
+    let ts = time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    format!("{{\"ts\":{ts},\"channels\":{}}}", channels_json(frame))
+}
+
+// [{"channel":1,"source":"cpu","gauge":123,"raw":48.2}, ...]
+pub fn channels_json(frame: &Frame) -> String {
+    let mut json = String::from("[");
+    for (i, (ch, sample)) in frame.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        let _ = write!(
+            json,
+            "{{\"channel\":{ch},\"source\":{},\"gauge\":{:.0}",
+            JsonStr(sample.source),
+            sample.value.clamp(0.0, 255.0)
+        );
+        if let Some(raw) = sample.raw.filter(|r| r.is_finite()) {
+            let _ = write!(json, ",\"raw\":{raw}");
+        }
+        json.push('}');
+    }
+    json.push(']');
+    json
+}
+
+// Compact human readable number, e.g. 12.3k or 4.56M
+pub fn fmt_si(v: f64) -> String {
+    let (div, suffix) = match v.abs() {
+        a if a >= 1e9 => (1e9, "G"),
+        a if a >= 1e6 => (1e6, "M"),
+        a if a >= 1e3 => (1e3, "k"),
+        _ => (1.0, ""),
+    };
+    let x = v / div;
+    match x.abs() {
+        a if a >= 100.0 => format!("{x:.0}{suffix}"),
+        a if a >= 10.0 => format!("{x:.1}{suffix}"),
+        _ => format!("{x:.2}{suffix}"),
+    }
+}
+
+// Raw value with the unit its source measures in, e.g. "12.5%" or "1.20Mbit/s"
+pub fn fmt_raw(source: &str, raw: f64) -> String {
+    match source {
+        "cpu" | "steal" | "irq" | "dirty" | "writeback" | "hugepages" | "conntrack" | "cpufreq" => {
+            format!("{raw:.1}%")
+        }
+        "net" | "nft" | "wireguard" | "snmp" | "jitter" => format!("{}bit/s", fmt_si(raw)),
+        // 512 byte sectors
+        "disk" => format!("{}B/s", fmt_si(raw * 512.0)),
+        "http" => format!("{raw:.0}ms"),
+        "ntp" => format!("{raw:.0}us"),
+        "audio" => format!("{raw:.1}dB"),
+        "gpu_temp" => format!("{raw:.0}C"),
+        _ => fmt_si(raw),
+    }
+}
+
+// EOF
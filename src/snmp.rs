@@ -0,0 +1,920 @@
+// snmp.rs
+
+use std::sync::{Arc, Mutex};
+use std::{
+    fmt, io,
+    net::{IpAddr, SocketAddr, ToSocketAddrs, UdpSocket},
+    str::FromStr,
+    thread, time,
+};
+
+use anyhow::{anyhow, bail};
+
+use crate::*;
+
+const IF_HC_IN_OCTETS: &str = "1.3.6.1.2.1.31.1.1.1.6";
+const IF_HC_OUT_OCTETS: &str = "1.3.6.1.2.1.31.1.1.1.10";
+const IF_IN_OCTETS: &str = "1.3.6.1.2.1.2.2.1.10";
+const IF_OUT_OCTETS: &str = "1.3.6.1.2.1.2.2.1.16";
+
+const BER_INTEGER: u8 = 0x02;
+const BER_OCTET_STRING: u8 = 0x04;
+const BER_NULL: u8 = 0x05;
+const BER_OID: u8 = 0x06;
+const BER_SEQUENCE: u8 = 0x30;
+const BER_COUNTER32: u8 = 0x41;
+const BER_COUNTER64: u8 = 0x46;
+const PDU_GET_REQUEST: u8 = 0xa0;
+const PDU_RESPONSE: u8 = 0xa2;
+const PDU_REPORT: u8 = 0xa8;
+const SNMP_PORT: u16 = 161;
+const SNMP_V2C: i64 = 1;
+const SNMP_V3: i64 = 3;
+
+// RFC 3412 and 3414
+const MSG_MAX_SIZE: usize = 1500;
+const MSG_FLAG_AUTH: u8 = 0x01;
+const MSG_FLAG_PRIV: u8 = 0x02;
+const MSG_FLAG_REPORTABLE: u8 = 0x04;
+const USM_SECURITY_MODEL: i64 = 3;
+const HMAC_SHA_96_LEN: usize = 12;
+const USM_NOT_IN_TIME_WINDOWS: &str = "1.3.6.1.6.3.15.1.1.2.0";
+const USM_UNKNOWN_ENGINE_IDS: &str = "1.3.6.1.6.3.15.1.1.4.0";
+const USM_STATS: [(&str, &str); 6] = [
+    ("1.3.6.1.6.3.15.1.1.1.0", "unsupported security level"),
+    (USM_NOT_IN_TIME_WINDOWS, "not in time window"),
+    ("1.3.6.1.6.3.15.1.1.3.0", "unknown user name"),
+    (USM_UNKNOWN_ENGINE_IDS, "unknown engine ID"),
+    (
+        "1.3.6.1.6.3.15.1.1.5.0",
+        "wrong digest, check the auth password",
+    ),
+    (
+        "1.3.6.1.6.3.15.1.1.6.0",
+        "decryption error, check the priv password",
+    ),
+];
+
+// v1 is left out, it has no 64-bit counters
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SnmpVersion {
+    #[default]
+    V2c,
+    V3,
+}
+
+impl FromStr for SnmpVersion {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "2c" => Ok(SnmpVersion::V2c),
+            "3" => Ok(SnmpVersion::V3),
+            _ => Err(anyhow!("Unsupported SNMP version: {s}, use 2c or 3")),
+        }
+    }
+}
+
+impl fmt::Display for SnmpVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnmpVersion::V2c => write!(f, "2c"),
+            SnmpVersion::V3 => write!(f, "3"),
+        }
+    }
+}
+
+// A v2c community, or a v3 user with HMAC-SHA-96 authentication and AES-128
+// privacy when the passwords are given, the agent must use the same protocols
+#[derive(Clone, Debug, PartialEq)]
+pub enum SnmpAuth {
+    Community(String),
+    User {
+        name: String,
+        auth_password: Option<String>,
+        priv_password: Option<String>,
+    },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SnmpRates {
+    pub rx_bps: i64,
+    pub tx_bps: i64,
+    pub ts: time::Instant,
+}
+
+// Polls the octet counters of a remote interface with SNMP v2c or v3 in a background thread.
+// 64-bit ifHC counters are preferred, with a fallback to the wrapping 32-bit ones.
+#[derive(Debug)]
+pub struct SnmpIf {
+    rates: Arc<Mutex<Option<SnmpRates>>>,
+}
+
+impl SnmpIf {
+    pub fn new<S: AsRef<str>>(
+        target: S,
+        auth: &SnmpAuth,
+        ifindex: u32,
+        interval: time::Duration,
+    ) -> anyhow::Result<Self> {
+        let target = target.as_ref().to_string();
+        let addr = snmp_addr(&target)?;
+        let sock = UdpSocket::bind(if addr.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        })?;
+        sock.connect(addr)?;
+        sock.set_read_timeout(Some(interval.min(time::Duration::from_secs(2))))?;
+
+        // the v3 engine is discovered with the first poll, an agent that is
+        // down at startup only leaves the gauge at zero until it answers
+        let security = match auth {
+            SnmpAuth::Community(community) => Security::Community(community.clone()),
+            SnmpAuth::User {
+                name,
+                auth_password,
+                priv_password,
+            } => Security::Usm(Usm::new(name, auth_password, priv_password)?),
+        };
+        let mut client = SnmpClient {
+            sock,
+            security,
+            request_id: 1,
+        };
+
+        let rates = Arc::new(Mutex::new(None));
+        let ret = Self {
+            rates: rates.clone(),
+        };
+        thread::spawn(move || {
+            let mut oids = None;
+            let mut prev: Option<([Counter; 2], time::Instant)> = None;
+            let mut failing = false;
+            loop {
+                if Arc::strong_count(&rates) == 1 {
+                    return;
+                }
+                match client.counters(&mut oids, ifindex, &target) {
+                    Ok(cnt) => {
+                        if failing {
+                            info!("SNMP {target} is back");
+                            failing = false;
+                        }
+                        let now = time::Instant::now();
+                        if let Some((p, p_ts)) = prev {
+                            let secs = (now - p_ts).as_secs_f64();
+                            let rate =
+                                |c: Counter, p: Counter| (8.0 * c.delta(p) as f64 / secs) as i64;
+                            *rates.lock().unwrap() = Some(SnmpRates {
+                                rx_bps: rate(cnt[0], p[0]),
+                                tx_bps: rate(cnt[1], p[1]),
+                                ts: now,
+                            });
+                        }
+                        prev = Some((cnt, now));
+                    }
+                    Err(e) => {
+                        match failing {
+                            true => debug!("SNMP {target}: {e}"),
+                            false => warn!("SNMP {target}: {e}"),
+                        }
+                        failing = true;
+                        count_error("snmp");
+                        *rates.lock().unwrap() = None;
+                    }
+                }
+                thread::sleep(interval);
+            }
+        });
+        Ok(ret)
+    }
+
+    // None until the first full interval, or when the agent stopped answering
+    pub fn rates(&self) -> Option<SnmpRates> {
+        *self.rates.lock().unwrap()
+    }
+}
+
+// the busier direction, zero until the first full interval and while the
+// agent does not answer, the polling thread counts the errors
+impl StatSource for SnmpIf {
+    fn sample(&mut self) -> anyhow::Result<f64> {
+        Ok(self.sample_at()?.0)
+    }
+    fn name(&self) -> &'static str {
+        "snmp"
+    }
+    fn unit(&self) -> &'static str {
+        "bit/s"
+    }
+    fn sample_at(&mut self) -> anyhow::Result<(f64, time::Instant)> {
+        Ok(self.rates().map_or((0.0, time::Instant::now()), |r| {
+            (r.rx_bps.max(r.tx_bps) as f64, r.ts)
+        }))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Counter {
+    C32(u64),
+    C64(u64),
+}
+
+impl Counter {
+    fn delta(self, prev: Counter) -> u64 {
+        match (self, prev) {
+            (Counter::C32(c), Counter::C32(p)) => c.wrapping_sub(p) & 0xffff_ffff,
+            (Counter::C64(c), Counter::C64(p)) => c.wrapping_sub(p),
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SnmpClient {
+    sock: UdpSocket,
+    security: Security,
+    request_id: i64,
+}
+
+#[derive(Debug)]
+enum Security {
+    Community(String),
+    Usm(Usm),
+}
+
+impl SnmpClient {
+    fn get(&mut self, oids: &[String; 2]) -> anyhow::Result<[Counter; 2]> {
+        self.request_id = (self.request_id + 1) & 0x7fff_ffff;
+        let mut varbinds = Vec::new();
+        for oid in oids {
+            let mut vb = ber_oid(oid)?;
+            vb.extend(ber_tlv(BER_NULL, &[]));
+            varbinds.extend(ber_tlv(BER_SEQUENCE, &vb));
+        }
+        let request_id = self.request_id;
+        let pdu = get_request(request_id, &varbinds);
+        match &mut self.security {
+            Security::Community(community) => {
+                let mut msg = ber_int(SNMP_V2C);
+                msg.extend(ber_tlv(BER_OCTET_STRING, community.as_bytes()));
+                msg.extend(&pdu);
+                self.sock.send(&ber_tlv(BER_SEQUENCE, &msg))?;
+                // stale answers to earlier requests are skipped
+                recv_reply(&self.sock, |buf| {
+                    parse_response(parse_v2c(buf)?, request_id)
+                })
+            }
+            Security::Usm(usm) => {
+                let response = usm.exchange(&self.sock, &pdu)?;
+                parse_response(&response, request_id)?
+                    .ok_or_else(|| anyhow!("SNMP response to another request"))
+            }
+        }
+    }
+}
+
+impl SnmpClient {
+    // the 64-bit counters if the agent has them, else the 32-bit ones,
+    // which are settled on with the first answer
+    fn counters(
+        &mut self,
+        oids: &mut Option<[String; 2]>,
+        ifindex: u32,
+        target: &str,
+    ) -> anyhow::Result<[Counter; 2]> {
+        if let Some(oids) = oids {
+            return self.get(oids);
+        }
+        let hc = [
+            format!("{IF_HC_IN_OCTETS}.{ifindex}"),
+            format!("{IF_HC_OUT_OCTETS}.{ifindex}"),
+        ];
+        match self.get(&hc) {
+            Ok(cnt) => {
+                *oids = Some(hc);
+                Ok(cnt)
+            }
+            // no answer tells nothing about the counters
+            Err(e) if e.downcast_ref::<io::Error>().is_some() => Err(e),
+            Err(e) => {
+                info!("SNMP {target}: no 64-bit counters ({e}), trying 32-bit");
+                let c32 = [
+                    format!("{IF_IN_OCTETS}.{ifindex}"),
+                    format!("{IF_OUT_OCTETS}.{ifindex}"),
+                ];
+                let cnt = self.get(&c32)?;
+                *oids = Some(c32);
+                Ok(cnt)
+            }
+        }
+    }
+}
+
+// host, host:port, [v6]:port or a bare v6 address, on port 161 unless given
+fn snmp_addr(target: &str) -> anyhow::Result<SocketAddr> {
+    let bare = target
+        .strip_prefix('[')
+        .and_then(|t| t.strip_suffix(']'))
+        .unwrap_or(target);
+    if let Ok(ip) = bare.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, SNMP_PORT));
+    }
+    let target = match target.matches(':').count() {
+        0 => format!("{target}:{SNMP_PORT}"),
+        1 => target.to_string(),
+        _ if target.starts_with('[') => target.to_string(),
+        _ => bail!("Invalid SNMP target {target}, an IPv6 address with a port goes in brackets"),
+    };
+    target
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| anyhow!("Cannot resolve {target}"))
+}
+
+fn get_request(request_id: i64, varbinds: &[u8]) -> Vec<u8> {
+    let mut pdu = ber_int(request_id);
+    pdu.extend(ber_int(0));
+    pdu.extend(ber_int(0));
+    pdu.extend(ber_tlv(BER_SEQUENCE, varbinds));
+    ber_tlv(PDU_GET_REQUEST, &pdu)
+}
+
+// waits for the datagram the parser takes, ignoring the others until the read timeout
+fn recv_reply<T, F>(sock: &UdpSocket, mut parse: F) -> anyhow::Result<T>
+where
+    F: FnMut(&[u8]) -> anyhow::Result<Option<T>>,
+{
+    let mut buf = [0u8; MSG_MAX_SIZE];
+    loop {
+        let len = sock.recv(&mut buf)?;
+        if let Some(reply) = parse(&buf[..len])? {
+            return Ok(reply);
+        }
+    }
+}
+
+// the PDU of a v2c message
+fn parse_v2c(buf: &[u8]) -> anyhow::Result<&[u8]> {
+    let (_, msg, _) = ber_read(buf, BER_SEQUENCE)?;
+    let (_, _version, rest) = ber_read(msg, BER_INTEGER)?;
+    let (_, _community, pdu) = ber_read(rest, BER_OCTET_STRING)?;
+    Ok(pdu)
+}
+
+// the counters of a Response PDU, None if it answers an earlier request
+fn parse_response(pdu: &[u8], request_id: i64) -> anyhow::Result<Option<[Counter; 2]>> {
+    let (_, pdu, _) = ber_read(pdu, PDU_RESPONSE)?;
+    let (_, req_id, rest) = ber_read(pdu, BER_INTEGER)?;
+    if ber_to_u64(req_id) as i64 != request_id {
+        return Ok(None);
+    }
+    let (_, err_status, rest) = ber_read(rest, BER_INTEGER)?;
+    if ber_to_u64(err_status) != 0 {
+        bail!("SNMP error status {}", ber_to_u64(err_status));
+    }
+    let (_, _err_index, rest) = ber_read(rest, BER_INTEGER)?;
+    let (_, mut varbinds, _) = ber_read(rest, BER_SEQUENCE)?;
+
+    let mut counters = [Counter::C32(0); 2];
+    for c in counters.iter_mut() {
+        let (_, vb, rest) = ber_read(varbinds, BER_SEQUENCE)?;
+        varbinds = rest;
+        let (_, _oid, value) = ber_read(vb, BER_OID)?;
+        let (tag, _, _) = ber_next(value)?;
+        let (_, v, _) = ber_read(value, tag)?;
+        *c = match tag {
+            BER_COUNTER32 => Counter::C32(ber_to_u64(v)),
+            BER_COUNTER64 => Counter::C64(ber_to_u64(v)),
+            // noSuchObject, noSuchInstance etc.
+            _ => bail!("Unexpected SNMP value type 0x{tag:02x}"),
+        };
+    }
+    Ok(Some(counters))
+}
+
+// the usmStats counter a Report PDU is about
+fn report_reason(pdu: &[u8]) -> anyhow::Result<String> {
+    let (_, pdu, _) = ber_read(pdu, PDU_REPORT)?;
+    let (_, _req_id, rest) = ber_read(pdu, BER_INTEGER)?;
+    let (_, _err_status, rest) = ber_read(rest, BER_INTEGER)?;
+    let (_, _err_index, rest) = ber_read(rest, BER_INTEGER)?;
+    let (_, varbinds, _) = ber_read(rest, BER_SEQUENCE)?;
+    let (_, vb, _) = ber_read(varbinds, BER_SEQUENCE)?;
+    let (_, oid, _) = ber_read(vb, BER_OID)?;
+    Ok(oid_to_string(oid))
+}
+
+// SNMPv3 with the user based security model of RFC 3414. The agent's engine
+// is discovered first, then the keys are localized to its ID and its clock
+// is followed from the authenticated answers.
+#[derive(Debug)]
+struct Usm {
+    user: String,
+    // the passwords, hashed to keys once the engine ID is known
+    auth_password: Option<String>,
+    priv_password: Option<String>,
+    engine: Option<Engine>,
+    msg_id: i64,
+    salt: u64,
+}
+
+#[derive(Debug)]
+struct Engine {
+    id: Vec<u8>,
+    boots: i64,
+    time: i64,
+    synced: time::Instant,
+    auth_key: Option<[u8; 20]>,
+    priv_key: Option<[u8; 16]>,
+}
+
+impl Engine {
+    // the agent's uptime as we count it
+    fn now(&self) -> i64 {
+        self.time + self.synced.elapsed().as_secs() as i64
+    }
+}
+
+// what a v3 message carries, the slices point into the datagram
+struct UsmMessage<'a> {
+    msg_id: i64,
+    flags: u8,
+    engine_id: &'a [u8],
+    boots: i64,
+    time: i64,
+    auth_params: &'a [u8],
+    priv_params: &'a [u8],
+    data: &'a [u8],
+}
+
+impl Usm {
+    fn new(
+        user: &str,
+        auth_password: &Option<String>,
+        priv_password: &Option<String>,
+    ) -> anyhow::Result<Self> {
+        if user.is_empty() {
+            bail!("SNMP v3 needs --snmp-user");
+        }
+        if priv_password.is_some() && auth_password.is_none() {
+            bail!("SNMP v3 privacy needs --snmp-auth-password too");
+        }
+        for password in [auth_password, priv_password].into_iter().flatten() {
+            if password.len() < 8 {
+                bail!("SNMP v3 passwords must be at least 8 characters");
+            }
+        }
+        let seed = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        Ok(Self {
+            user: user.to_string(),
+            auth_password: auth_password.clone(),
+            priv_password: priv_password.clone(),
+            engine: None,
+            msg_id: (seed & 0xffff) as i64,
+            salt: seed ^ (std::process::id() as u64) << 32,
+        })
+    }
+
+    // Sends the PDU and returns the one answering it. A clock out of sync or a
+    // restarted agent is recovered from once, other reports are errors.
+    fn exchange(&mut self, sock: &UdpSocket, pdu: &[u8]) -> anyhow::Result<Vec<u8>> {
+        if self.engine.is_none() {
+            self.discover(sock)?;
+        }
+        let mut retried = false;
+        loop {
+            sock.send(&self.encode(pdu)?)?;
+            let reply = recv_reply(sock, |buf| self.decode(buf))?;
+            if reply.first() != Some(&PDU_REPORT) {
+                return Ok(reply);
+            }
+            match (report_reason(&reply)?.as_str(), retried) {
+                // decode() took over the clock of the report
+                (USM_NOT_IN_TIME_WINDOWS, false) => {}
+                (USM_UNKNOWN_ENGINE_IDS, false) => self.discover(sock)?,
+                (oid, _) => {
+                    let reason = USM_STATS
+                        .iter()
+                        .find(|(o, _)| *o == oid)
+                        .map_or(oid, |(_, reason)| reason);
+                    bail!("SNMP agent reports {reason}");
+                }
+            }
+            retried = true;
+        }
+    }
+
+    // an empty unauthenticated request, the agent reports its engine ID, boots and time
+    fn discover(&mut self, sock: &UdpSocket) -> anyhow::Result<()> {
+        self.engine = None;
+        sock.send(&self.encode(&get_request(0, &[]))?)?;
+        recv_reply(sock, |buf| self.decode(buf))?;
+        if self.engine.is_none() {
+            bail!("SNMP agent did not tell its engine ID");
+        }
+        Ok(())
+    }
+
+    fn flags(&self) -> u8 {
+        match (&self.engine, &self.auth_password, &self.priv_password) {
+            (None, _, _) => 0,
+            (Some(_), Some(_), Some(_)) => MSG_FLAG_AUTH | MSG_FLAG_PRIV,
+            (Some(_), Some(_), None) => MSG_FLAG_AUTH,
+            (Some(_), None, _) => 0,
+        }
+    }
+
+    // without an engine a discovery probe from no user
+    fn encode(&mut self, pdu: &[u8]) -> anyhow::Result<Vec<u8>> {
+        self.msg_id = (self.msg_id + 1) & 0x7fff_ffff;
+        let flags = self.flags();
+        let (engine_id, boots, now, user) = match &self.engine {
+            Some(e) => (e.id.as_slice(), e.boots, e.now(), self.user.as_bytes()),
+            None => (&[][..], 0, 0, &[][..]),
+        };
+
+        let mut scoped = ber_tlv(BER_OCTET_STRING, engine_id);
+        scoped.extend(ber_tlv(BER_OCTET_STRING, &[]));
+        scoped.extend(pdu);
+        let mut data = ber_tlv(BER_SEQUENCE, &scoped);
+        let mut salt = Vec::new();
+        if let Some(key) = self.engine.as_ref().and_then(|e| e.priv_key) {
+            self.salt = self.salt.wrapping_add(1);
+            salt = self.salt.to_be_bytes().to_vec();
+            crypto::Aes128::new(&key).cfb_encrypt(&aes_iv(boots, now, &salt), &mut data);
+            data = ber_tlv(BER_OCTET_STRING, &data);
+        }
+
+        let auth_params = if flags & MSG_FLAG_AUTH != 0 {
+            &[0u8; HMAC_SHA_96_LEN][..]
+        } else {
+            &[]
+        };
+        let mut params = ber_tlv(BER_OCTET_STRING, engine_id);
+        params.extend(ber_int(boots));
+        params.extend(ber_int(now));
+        params.extend(ber_tlv(BER_OCTET_STRING, user));
+        params.extend(ber_tlv(BER_OCTET_STRING, auth_params));
+        params.extend(ber_tlv(BER_OCTET_STRING, &salt));
+
+        let mut header = ber_int(self.msg_id);
+        header.extend(ber_int(MSG_MAX_SIZE as i64));
+        header.extend(ber_tlv(BER_OCTET_STRING, &[flags | MSG_FLAG_REPORTABLE]));
+        header.extend(ber_int(USM_SECURITY_MODEL));
+
+        let mut msg = ber_int(SNMP_V3);
+        msg.extend(ber_tlv(BER_SEQUENCE, &header));
+        msg.extend(ber_tlv(BER_OCTET_STRING, &ber_tlv(BER_SEQUENCE, &params)));
+        msg.extend(data);
+        let mut msg = ber_tlv(BER_SEQUENCE, &msg);
+
+        if let Some(key) = self.engine.as_ref().and_then(|e| e.auth_key) {
+            // the digest is taken with the parameter zeroed and then put in its place
+            let at = offset_in(&msg, parse_v3(&msg)?.auth_params);
+            let mac = crypto::hmac_sha1(&key, &msg);
+            msg[at..at + HMAC_SHA_96_LEN].copy_from_slice(&mac[..HMAC_SHA_96_LEN]);
+        }
+        Ok(msg)
+    }
+
+    // the PDU of a v3 message answering the last one sent, None for other messages
+    fn decode(&mut self, buf: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        let m = parse_v3(buf)?;
+        if m.msg_id != self.msg_id {
+            return Ok(None);
+        }
+        let authentic = m.flags & MSG_FLAG_AUTH != 0;
+        match &mut self.engine {
+            None => {
+                if m.engine_id.is_empty() {
+                    bail!("SNMP agent did not tell its engine ID");
+                }
+                self.engine = Some(self.localize(m.engine_id, m.boots, m.time));
+            }
+            Some(engine) if authentic => {
+                let Some(key) = engine.auth_key else {
+                    bail!("Unexpected authenticated SNMP message");
+                };
+                if m.auth_params.len() != HMAC_SHA_96_LEN {
+                    bail!("Invalid SNMP authentication parameters");
+                }
+                let mut zeroed = buf.to_vec();
+                let at = offset_in(buf, m.auth_params);
+                zeroed[at..at + HMAC_SHA_96_LEN].fill(0);
+                if crypto::hmac_sha1(&key, &zeroed)[..HMAC_SHA_96_LEN] != *m.auth_params {
+                    bail!("SNMP message with a wrong digest");
+                }
+                // the agent is authoritative for its clock
+                engine.boots = m.boots;
+                engine.time = m.time;
+                engine.synced = time::Instant::now();
+            }
+            Some(_) => {}
+        }
+
+        let mut scoped = m.data.to_vec();
+        if m.flags & MSG_FLAG_PRIV != 0 {
+            let Some(key) = self.engine.as_ref().and_then(|e| e.priv_key) else {
+                bail!("Unexpected encrypted SNMP message");
+            };
+            let (_, encrypted, _) = ber_read(m.data, BER_OCTET_STRING)?;
+            scoped = encrypted.to_vec();
+            crypto::Aes128::new(&key)
+                .cfb_decrypt(&aes_iv(m.boots, m.time, m.priv_params), &mut scoped);
+        }
+        let (_, scoped, _) = ber_read(&scoped, BER_SEQUENCE)
+            .map_err(|_| anyhow!("SNMP message that does not decrypt"))?;
+        let (_, _context_engine_id, rest) = ber_read(scoped, BER_OCTET_STRING)?;
+        let (_, _context_name, pdu) = ber_read(rest, BER_OCTET_STRING)?;
+        // only reports may come unauthenticated once there is a key
+        let keyed = self.engine.as_ref().is_some_and(|e| e.auth_key.is_some());
+        if keyed && !authentic && pdu.first() != Some(&PDU_REPORT) {
+            bail!("Unauthenticated SNMP response");
+        }
+        Ok(Some(pdu.to_vec()))
+    }
+
+    fn localize(&self, engine_id: &[u8], boots: i64, time: i64) -> Engine {
+        let auth_key = self
+            .auth_password
+            .as_ref()
+            .map(|p| localized_key(p, engine_id));
+        let priv_key = self.priv_password.as_ref().map(|p| {
+            let mut key = [0u8; 16];
+            key.copy_from_slice(&localized_key(p, engine_id)[..16]);
+            key
+        });
+        Engine {
+            id: engine_id.to_vec(),
+            boots,
+            time,
+            synced: time::Instant::now(),
+            auth_key,
+            priv_key,
+        }
+    }
+}
+
+fn parse_v3(buf: &[u8]) -> anyhow::Result<UsmMessage<'_>> {
+    let (_, msg, _) = ber_read(buf, BER_SEQUENCE)?;
+    let (_, version, rest) = ber_read(msg, BER_INTEGER)?;
+    if ber_to_u64(version) as i64 != SNMP_V3 {
+        bail!("Not an SNMP v3 message");
+    }
+    let (_, header, rest) = ber_read(rest, BER_SEQUENCE)?;
+    let (_, params, data) = ber_read(rest, BER_OCTET_STRING)?;
+
+    let (_, msg_id, header) = ber_read(header, BER_INTEGER)?;
+    let (_, _max_size, header) = ber_read(header, BER_INTEGER)?;
+    let (_, flags, header) = ber_read(header, BER_OCTET_STRING)?;
+    let (_, model, _) = ber_read(header, BER_INTEGER)?;
+    if ber_to_u64(model) as i64 != USM_SECURITY_MODEL || flags.len() != 1 {
+        bail!("Unsupported SNMP v3 security model");
+    }
+
+    let (_, params, _) = ber_read(params, BER_SEQUENCE)?;
+    let (_, engine_id, params) = ber_read(params, BER_OCTET_STRING)?;
+    let (_, boots, params) = ber_read(params, BER_INTEGER)?;
+    let (_, time, params) = ber_read(params, BER_INTEGER)?;
+    let (_, _user, params) = ber_read(params, BER_OCTET_STRING)?;
+    let (_, auth_params, params) = ber_read(params, BER_OCTET_STRING)?;
+    let (_, priv_params, _) = ber_read(params, BER_OCTET_STRING)?;
+    Ok(UsmMessage {
+        msg_id: ber_to_u64(msg_id) as i64,
+        flags: flags[0],
+        engine_id,
+        boots: ber_to_u64(boots) as i64,
+        time: ber_to_u64(time) as i64,
+        auth_params,
+        priv_params,
+        data,
+    })
+}
+
+// where a slice taken from buf starts in it
+fn offset_in(buf: &[u8], part: &[u8]) -> usize {
+    part.as_ptr() as usize - buf.as_ptr() as usize
+}
+
+// RFC 3414 A.2.2: a megabyte of the repeated password hashed, then with the engine ID
+fn localized_key(password: &str, engine_id: &[u8]) -> [u8; 20] {
+    let stretched = password.bytes().cycle().take(1 << 20).collect::<Vec<u8>>();
+    let ku = crypto::sha1(&stretched);
+    let mut kul = ku.to_vec();
+    kul.extend(engine_id);
+    kul.extend(ku);
+    crypto::sha1(&kul)
+}
+
+// RFC 3826 3.1.2.1
+fn aes_iv(boots: i64, time: i64, salt: &[u8]) -> [u8; 16] {
+    let mut iv = [0u8; 16];
+    iv[..4].copy_from_slice(&(boots as u32).to_be_bytes());
+    iv[4..8].copy_from_slice(&(time as u32).to_be_bytes());
+    let n = salt.len().min(8);
+    iv[8..8 + n].copy_from_slice(&salt[..n]);
+    iv
+}
+
+fn ber_len(len: usize) -> Vec<u8> {
+    if len < 0x80 {
+        return vec![len as u8];
+    }
+    let bytes = len.to_be_bytes();
+    let skip = bytes.iter().take_while(|b| **b == 0).count();
+    let mut out = vec![0x80 | (bytes.len() - skip) as u8];
+    out.extend(&bytes[skip..]);
+    out
+}
+
+fn ber_tlv(tag: u8, value: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    out.extend(ber_len(value.len()));
+    out.extend(value);
+    out
+}
+
+fn ber_int(n: i64) -> Vec<u8> {
+    let bytes = n.to_be_bytes();
+    // strip redundant leading bytes but keep the sign bit intact
+    let mut i = 0;
+    while i < 7
+        && ((bytes[i] == 0 && bytes[i + 1] & 0x80 == 0)
+            || (bytes[i] == 0xff && bytes[i + 1] & 0x80 != 0))
+    {
+        i += 1;
+    }
+    ber_tlv(BER_INTEGER, &bytes[i..])
+}
+
+fn ber_oid(oid: &str) -> anyhow::Result<Vec<u8>> {
+    let arcs = oid
+        .split('.')
+        .map(|a| a.parse::<u64>())
+        .collect::<Result<Vec<u64>, _>>()?;
+    if arcs.len() < 2 {
+        bail!("Invalid OID {oid}");
+    }
+    let mut out = vec![(arcs[0] * 40 + arcs[1]) as u8];
+    for arc in &arcs[2..] {
+        let mut chunk = vec![(arc & 0x7f) as u8];
+        let mut a = arc >> 7;
+        while a > 0 {
+            chunk.push(0x80 | (a & 0x7f) as u8);
+            a >>= 7;
+        }
+        out.extend(chunk.iter().rev());
+    }
+    Ok(ber_tlv(BER_OID, &out))
+}
+
+// returns tag, value and the remaining bytes
+fn ber_next(buf: &[u8]) -> anyhow::Result<(u8, &[u8], &[u8])> {
+    if buf.len() < 2 {
+        bail!("Truncated SNMP message");
+    }
+    let tag = buf[0];
+    let (len, hdr) = if buf[1] & 0x80 == 0 {
+        (buf[1] as usize, 2)
+    } else {
+        let n = (buf[1] & 0x7f) as usize;
+        if n == 0 || n > 4 || buf.len() < 2 + n {
+            bail!("Invalid BER length");
+        }
+        (
+            buf[2..2 + n]
+                .iter()
+                .fold(0usize, |l, b| l << 8 | *b as usize),
+            2 + n,
+        )
+    };
+    if buf.len() < hdr + len {
+        bail!("Truncated SNMP message");
+    }
+    Ok((tag, &buf[hdr..hdr + len], &buf[hdr + len..]))
+}
+
+fn ber_read(buf: &[u8], want: u8) -> anyhow::Result<(u8, &[u8], &[u8])> {
+    let (tag, value, rest) = ber_next(buf)?;
+    if tag != want {
+        bail!("Unexpected BER tag 0x{tag:02x}, wanted 0x{want:02x}");
+    }
+    Ok((tag, value, rest))
+}
+
+fn ber_to_u64(v: &[u8]) -> u64 {
+    v.iter().fold(0u64, |n, b| n << 8 | *b as u64)
+}
+
+fn oid_to_string(v: &[u8]) -> String {
+    let Some((first, rest)) = v.split_first() else {
+        return String::new();
+    };
+    let mut arcs = vec![(first / 40) as u64, (first % 40) as u64];
+    let mut arc = 0u64;
+    for b in rest {
+        arc = arc << 7 | (b & 0x7f) as u64;
+        if b & 0x80 == 0 {
+            arcs.push(arc);
+            arc = 0;
+        }
+    }
+    arcs.iter()
+        .map(|a| a.to_string())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 3414 A.3.2
+    #[test]
+    fn password_to_key() {
+        let engine_id = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2];
+        assert_eq!(
+            localized_key("maplesyrup", &engine_id),
+            [
+                0x66, 0x95, 0xfe, 0xbc, 0x92, 0x88, 0xe3, 0x62, 0x82, 0x23, 0x5f, 0xc7, 0x15, 0x1f,
+                0x12, 0x84, 0x97, 0xb3, 0x8f, 0x3f
+            ]
+        );
+    }
+
+    #[test]
+    fn ber_encoding() {
+        assert_eq!(ber_int(0), [0x02, 0x01, 0x00]);
+        assert_eq!(ber_int(127), [0x02, 0x01, 0x7f]);
+        assert_eq!(ber_int(128), [0x02, 0x02, 0x00, 0x80]);
+        assert_eq!(ber_int(-1), [0x02, 0x01, 0xff]);
+        assert_eq!(ber_int(-129), [0x02, 0x02, 0xff, 0x7f]);
+        assert_eq!(ber_len(127), [0x7f]);
+        assert_eq!(ber_len(300), [0x82, 0x01, 0x2c]);
+        assert_eq!(
+            ber_oid("1.3.6.1.2.1.31.1.1.1.6.2").unwrap(),
+            [0x06, 0x0b, 0x2b, 0x06, 0x01, 0x02, 0x01, 0x1f, 0x01, 0x01, 0x01, 0x06, 0x02]
+        );
+        assert_eq!(
+            ber_oid("1.3.6.1.4.1.200000").unwrap(),
+            [0x06, 0x08, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x8c, 0x9a, 0x40]
+        );
+        assert!(ber_oid("1").is_err());
+    }
+
+    #[test]
+    fn ber_round_trip() {
+        for oid in ["1.3.6.1.2.1.2.2.1.10.7", "1.3.6.1.4.1.200000.1"] {
+            let encoded = ber_oid(oid).unwrap();
+            let (_, value, rest) = ber_read(&encoded, BER_OID).unwrap();
+            assert_eq!(oid_to_string(value), oid);
+            assert!(rest.is_empty());
+        }
+        let long = vec![0x5a; 300];
+        let encoded = ber_tlv(BER_OCTET_STRING, &long);
+        let (tag, value, _) = ber_next(&encoded).unwrap();
+        assert_eq!((tag, value), (BER_OCTET_STRING, &long[..]));
+        assert!(ber_next(&[BER_OCTET_STRING, 0x05, 1, 2]).is_err());
+        assert!(ber_read(&ber_int(1), BER_SEQUENCE).is_err());
+
+        // a request and the response to it, as an agent would answer
+        let varbinds = [IF_HC_IN_OCTETS, IF_HC_OUT_OCTETS]
+            .iter()
+            .flat_map(|oid| {
+                let mut vb = ber_oid(&format!("{oid}.2")).unwrap();
+                vb.extend(ber_tlv(BER_NULL, &[]));
+                ber_tlv(BER_SEQUENCE, &vb)
+            })
+            .collect::<Vec<u8>>();
+        let request = get_request(4711, &varbinds);
+        let (_, pdu, _) = ber_read(&request, PDU_GET_REQUEST).unwrap();
+        let (_, req_id, _) = ber_read(pdu, BER_INTEGER).unwrap();
+        assert_eq!(ber_to_u64(req_id), 4711);
+
+        let mut answer = ber_int(4711);
+        answer.extend(ber_int(0));
+        answer.extend(ber_int(0));
+        let mut vbs = Vec::new();
+        for (oid, value) in [(IF_HC_IN_OCTETS, 1u64 << 40), (IF_HC_OUT_OCTETS, 12345)] {
+            let mut vb = ber_oid(&format!("{oid}.2")).unwrap();
+            let bytes = value.to_be_bytes();
+            vb.extend(ber_tlv(
+                BER_COUNTER64,
+                &bytes[bytes.iter().take_while(|b| **b == 0).count()..],
+            ));
+            vbs.extend(ber_tlv(BER_SEQUENCE, &vb));
+        }
+        answer.extend(ber_tlv(BER_SEQUENCE, &vbs));
+        let response = ber_tlv(PDU_RESPONSE, &answer);
+        let counters = parse_response(&response, 4711).unwrap().unwrap();
+        assert!(matches!(counters, [Counter::C64(a), Counter::C64(12345)] if a == 1 << 40));
+        assert!(parse_response(&response, 4712).unwrap().is_none());
+    }
+}
+
+// EOF
@@ -0,0 +1,181 @@
+// sonify.rs
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::{collections::HashMap, f64::consts::TAU, sync::mpsc, thread, time};
+
+use anyhow::anyhow;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+use crate::*;
+
+// pitch and level glide to a new gauge in about this long, jumps would click
+const SONIFY_GLIDE: f64 = 0.02;
+const SONIFY_RETRY: time::Duration = time::Duration::from_secs(5);
+
+#[derive(Clone, Debug)]
+pub struct SonifyConfig {
+    // output device by name, "default" for the default one
+    pub device: String,
+    // one sine voice per channel
+    pub channels: Vec<u8>,
+    // pitch of an idle and of a full scale gauge in Hz, exponential in between
+    pub low_hz: f64,
+    pub high_hz: f64,
+    // percent, shared by all voices
+    pub volume: f64,
+}
+
+#[derive(Debug, Default)]
+struct Voice {
+    phase: f64,
+    freq: f64,
+    amp: f64,
+}
+
+// the voices of an output stream, fed the gauges from the sink thread
+struct Synth {
+    cfg: SonifyConfig,
+    gauges: Arc<Mutex<HashMap<u8, f64>>>,
+    voices: Vec<Voice>,
+    rate: f64,
+}
+
+// Plays each channel as a tone through cpal, the pitch rising with the gauge
+// and the tone fading in with it, so an idle machine stays almost silent
+pub struct SonifySink;
+
+impl SonifySink {
+    pub fn spawn(cfg: SonifyConfig) -> anyhow::Result<ThreadedSink> {
+        info!(
+            "Sonifying channels {:?} at {:.0}..{:.0} Hz",
+            cfg.channels, cfg.low_hz, cfg.high_hz
+        );
+        spawn_sink("sonify", move |rx| Self::run(cfg, rx))
+    }
+
+    // the stream stays on this thread, it is not Send everywhere
+    fn run(cfg: SonifyConfig, rx: mpsc::Receiver<Frame>) {
+        let gauges = Arc::new(Mutex::new(HashMap::new()));
+        let failed = Arc::new(AtomicBool::new(false));
+        loop {
+            failed.store(false, Ordering::Relaxed);
+            let _stream = match Self::play(&cfg, gauges.clone(), failed.clone()) {
+                Ok(stream) => stream,
+                Err(e) => {
+                    error!("Sonify: {e}");
+                    count_error("sonify");
+                    thread::sleep(SONIFY_RETRY);
+                    continue;
+                }
+            };
+            while !failed.load(Ordering::Relaxed) {
+                match rx.recv_timeout(time::Duration::from_secs(1)) {
+                    Ok(frame) => {
+                        let mut gauges = gauges.lock().unwrap();
+                        for (ch, sample) in frame {
+                            gauges.insert(ch, sample.value.clamp(0.0, 255.0) / 255.0);
+                        }
+                    }
+                    Err(mpsc::RecvTimeoutError::Timeout) => {}
+                    Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                }
+            }
+            thread::sleep(SONIFY_RETRY);
+        }
+    }
+
+    fn play(
+        cfg: &SonifyConfig,
+        gauges: Arc<Mutex<HashMap<u8, f64>>>,
+        failed: Arc<AtomicBool>,
+    ) -> anyhow::Result<cpal::Stream> {
+        let host = cpal::default_host();
+        let device = match cfg.device.as_str() {
+            "default" => host.default_output_device(),
+            name => host
+                .output_devices()?
+                .find(|d| d.name().is_ok_and(|n| n == name)),
+        }
+        .ok_or_else(|| anyhow!("No audio output device {}", cfg.device))?;
+        let supported = device.default_output_config()?;
+        let config = supported.config();
+        info!(
+            "Sonify: playing on {} at {} Hz",
+            device.name().unwrap_or_default(),
+            config.sample_rate.0
+        );
+        let synth = Synth {
+            cfg: cfg.clone(),
+            gauges,
+            voices: cfg
+                .channels
+                .iter()
+                .map(|_| Voice {
+                    freq: cfg.low_hz,
+                    ..Default::default()
+                })
+                .collect(),
+            rate: config.sample_rate.0 as f64,
+        };
+        let stream = match supported.sample_format() {
+            cpal::SampleFormat::F32 => Self::stream::<f32>(&device, &config, synth, failed)?,
+            cpal::SampleFormat::I16 => Self::stream::<i16>(&device, &config, synth, failed)?,
+            cpal::SampleFormat::U16 => Self::stream::<u16>(&device, &config, synth, failed)?,
+            format => return Err(anyhow!("Unsupported sample format {format}")),
+        };
+        stream.play()?;
+        Ok(stream)
+    }
+
+    // the same mono mix on every channel of the device
+    fn stream<T>(
+        device: &cpal::Device,
+        config: &cpal::StreamConfig,
+        mut synth: Synth,
+        failed: Arc<AtomicBool>,
+    ) -> anyhow::Result<cpal::Stream>
+    where
+        T: cpal::SizedSample + cpal::FromSample<f32>,
+    {
+        let width = config.channels.max(1) as usize;
+        let stream = device.build_output_stream(
+            config,
+            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                let gauges = synth.gauges.lock().unwrap().clone();
+                for frame in data.chunks_mut(width) {
+                    let value = T::from_sample(synth.next(&gauges) as f32);
+                    frame.fill(value);
+                }
+            },
+            move |e| {
+                error!("Sonify: {e}");
+                count_error("sonify");
+                failed.store(true, Ordering::Relaxed);
+            },
+            None,
+        )?;
+        Ok(stream)
+    }
+}
+
+impl Synth {
+    // one sample of the mix, every voice gliding towards its gauge
+    fn next(&mut self, gauges: &HashMap<u8, f64>) -> f64 {
+        let cfg = &self.cfg;
+        let share = cfg.volume.clamp(0.0, 100.0) / 100.0 / self.voices.len().max(1) as f64;
+        let glide = 1.0 - (-1.0 / (self.rate * SONIFY_GLIDE)).exp();
+        let mut mix = 0.0;
+        for (ch, voice) in cfg.channels.iter().zip(self.voices.iter_mut()) {
+            let level = gauges.get(ch).copied().unwrap_or(0.0);
+            let freq = cfg.low_hz * (cfg.high_hz / cfg.low_hz).powf(level);
+            voice.freq += (freq - voice.freq) * glide;
+            voice.amp += (share * level.sqrt() - voice.amp) * glide;
+            voice.phase = (voice.phase + TAU * voice.freq / self.rate) % TAU;
+            mix += voice.amp * voice.phase.sin();
+        }
+        mix.clamp(-1.0, 1.0)
+    }
+}
+
+// EOF
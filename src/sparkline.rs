@@ -0,0 +1,103 @@
+// sparkline.rs
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::Write as _,
+    io::{self, Write},
+    sync::mpsc,
+    time,
+};
+
+use crate::*;
+
+// two samples per braille character
+const SPARK_LEN: usize = 60;
+const SPARK_INTERVAL: time::Duration = time::Duration::from_secs(1);
+// dots of the left and right braille column, from the bottom up
+const SPARK_LEFT: [u32; 4] = [0x40, 0x04, 0x02, 0x01];
+const SPARK_RIGHT: [u32; 4] = [0x80, 0x20, 0x10, 0x08];
+
+// Prints a rolling braille sparkline of every channel once per second,
+// the highest gauge seen during each second makes up one column.
+pub struct SparklineSink;
+
+#[derive(Debug, Default)]
+struct SparkChannel {
+    source: &'static str,
+    raw: Option<f64>,
+    peak: f64,
+    history: VecDeque<f64>,
+}
+
+impl SparklineSink {
+    pub fn spawn() -> anyhow::Result<ThreadedSink> {
+        spawn_sink("sparkline", Self::run)
+    }
+
+    fn run(rx: mpsc::Receiver<Frame>) {
+        let mut channels: BTreeMap<u8, SparkChannel> = BTreeMap::new();
+        let mut next_print = time::Instant::now() + SPARK_INTERVAL;
+        let mut out = io::stdout();
+
+        loop {
+            let timeout = next_print.saturating_duration_since(time::Instant::now());
+            match rx.recv_timeout(timeout) {
+                Ok(frame) => {
+                    for (ch, sample) in frame {
+                        let c = channels.entry(ch).or_default();
+                        c.source = sample.source;
+                        c.raw = sample.raw;
+                        c.peak = c.peak.max(sample.value.clamp(0.0, 255.0));
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => return,
+            }
+            if time::Instant::now() < next_print {
+                continue;
+            }
+            next_print += SPARK_INTERVAL;
+
+            let mut lines = String::new();
+            for (ch, c) in channels.iter_mut() {
+                if c.history.len() == SPARK_LEN {
+                    c.history.pop_front();
+                }
+                c.history.push_back(c.peak);
+                let source = match c.source {
+                    "" => "-",
+                    s => s,
+                };
+                let raw = c.raw.map(fmt_si).unwrap_or_default();
+                let _ = writeln!(
+                    lines,
+                    "{ch:>3} {source:<14.14} {} {:>3} {raw:>8.8}",
+                    sparkline(&c.history),
+                    c.peak as u8
+                );
+                c.peak = 0.0;
+            }
+            lines.push('\n');
+            if out.write_all(lines.as_bytes()).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+// Right aligned, so that the newest value is always in the same column
+fn sparkline(history: &VecDeque<f64>) -> String {
+    let dots = |v: f64| ((v / 256.0 * 5.0) as usize).min(4);
+    let mut values = vec![0.0; SPARK_LEN - history.len()];
+    values.extend(history.iter().copied());
+    values
+        .chunks(2)
+        .map(|pair| {
+            let left = SPARK_LEFT[..dots(pair[0])].iter().sum::<u32>();
+            let right = SPARK_RIGHT[..dots(pair[1])].iter().sum::<u32>();
+            char::from_u32(0x2800 + left + right).unwrap_or(' ')
+        })
+        .collect()
+}
+
+// EOF
@@ -0,0 +1,191 @@
+// ssd1306.rs
+
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::Write,
+    os::fd::AsRawFd,
+    sync::mpsc,
+    time,
+};
+
+use anyhow::bail;
+
+use crate::*;
+
+const I2C_SLAVE: std::os::raw::c_ulong = 0x0703;
+const SSD1306_WIDTH: usize = 128;
+// control bytes, Co = 0
+const CTRL_CMD: u8 = 0x00;
+const CTRL_DATA: u8 = 0x40;
+// some i2c adapters cannot do longer transfers
+const I2C_CHUNK: usize = 32;
+const SSD1306_INTERVAL: time::Duration = time::Duration::from_millis(100);
+// one row of text and a bar per channel
+const ROW_HEIGHT: usize = 16;
+
+#[derive(Clone, Debug)]
+pub struct Ssd1306Config {
+    // i2c-dev device, e.g. /dev/i2c-1
+    pub bus: String,
+    pub addr: u16,
+    // 32 or 64
+    pub height: usize,
+    // channels from the top down, as many as fit
+    pub channels: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+struct OledChannel {
+    source: &'static str,
+    raw: Option<f64>,
+    gauge: f64,
+}
+
+// Draws a labeled bar graph with the raw value of each channel on a 128 pixel
+// wide SSD1306 OLED over i2c-dev, at most ten times per second
+pub struct Ssd1306Sink;
+
+impl Ssd1306Sink {
+    pub fn spawn(cfg: Ssd1306Config) -> anyhow::Result<ThreadedSink> {
+        if cfg.height != 32 && cfg.height != 64 {
+            bail!("SSD1306 height must be 32 or 64, not {}", cfg.height);
+        }
+        let mut dev = OpenOptions::new().read(true).write(true).open(&cfg.bus)?;
+        sys::sys_ioctl_int(dev.as_raw_fd(), I2C_SLAVE, cfg.addr as _)?;
+        Self::init(&mut dev, cfg.height)?;
+        info!(
+            "SSD1306 128x{} at 0x{:02x} on {}",
+            cfg.height, cfg.addr, cfg.bus
+        );
+        spawn_sink("ssd1306", move |rx| Self::run(cfg, dev, rx))
+    }
+
+    fn init(dev: &mut File, height: usize) -> anyhow::Result<()> {
+        let com_pins = if height == 64 { 0x12 } else { 0x02 };
+        #[rustfmt::skip]
+        let cmds = [
+            0xae,                       // display off
+            0xd5, 0x80,                 // clock divider
+            0xa8, height as u8 - 1,     // multiplex ratio
+            0xd3, 0x00,                 // display offset
+            0x40,                       // start line 0
+            0x8d, 0x14,                 // charge pump on
+            0x20, 0x00,                 // horizontal addressing
+            0xa1,                       // column 127 is SEG0
+            0xc8,                       // scan COM from the bottom
+            0xda, com_pins,
+            0x81, 0xcf,                 // contrast
+            0xd9, 0xf1,                 // precharge
+            0xdb, 0x40,                 // VCOMH deselect level
+            0xa4,                       // follow the RAM
+            0xa6,                       // not inverted
+            0xaf,                       // display on
+        ];
+        command(dev, &cmds)
+    }
+
+    fn run(cfg: Ssd1306Config, mut dev: File, rx: mpsc::Receiver<Frame>) {
+        let mut channels: HashMap<u8, OledChannel> = HashMap::new();
+        let mut canvas = mono::Canvas::new(SSD1306_WIDTH, cfg.height);
+        let mut last_draw: Option<time::Instant> = None;
+        let mut shown = Vec::new();
+
+        while let Ok(frame) = rx.recv() {
+            for (ch, sample) in frame {
+                let c = channels.entry(ch).or_default();
+                c.source = sample.source;
+                c.raw = sample.raw;
+                c.gauge = sample.value.clamp(0.0, 255.0);
+            }
+            if last_draw.is_some_and(|t| t.elapsed() < SSD1306_INTERVAL) {
+                continue;
+            }
+            last_draw = Some(time::Instant::now());
+
+            canvas.clear();
+            for (row, ch) in cfg
+                .channels
+                .iter()
+                .take(cfg.height / ROW_HEIGHT)
+                .enumerate()
+            {
+                let c = channels.get(ch);
+                Self::draw_row(&mut canvas, row * ROW_HEIGHT, *ch, c);
+            }
+            let pages = pack_pages(&canvas);
+            if pages == shown {
+                continue;
+            }
+            match write_pages(&mut dev, &pages, cfg.height) {
+                Ok(()) => shown = pages,
+                Err(e) => {
+                    debug!("SSD1306: {e}");
+                    count_error("ssd1306");
+                }
+            }
+        }
+    }
+
+    // "1 CPU        12.5%" above a bar of the gauge
+    fn draw_row(canvas: &mut mono::Canvas, y: usize, ch: u8, c: Option<&OledChannel>) {
+        let source = c.map(|c| c.source).unwrap_or_default();
+        canvas.text(0, y + 1, &format!("{ch} {source}"), 1);
+        let value = match c {
+            Some(OledChannel { raw: Some(raw), .. }) if raw.is_finite() => fmt_raw(source, *raw),
+            Some(c) => format!("{:.0}", c.gauge),
+            None => "-".into(),
+        };
+        canvas.text_right(SSD1306_WIDTH, y + 1, &value, 1);
+
+        let bar_w = SSD1306_WIDTH - 4;
+        canvas.rect(0, y + 8, SSD1306_WIDTH, 7);
+        let gauge = c.map(|c| c.gauge).unwrap_or(0.0);
+        let lit = (gauge / 255.0 * bar_w as f64).round() as usize;
+        canvas.fill_rect(2, y + 10, lit, 3);
+    }
+}
+
+// 8 pixel tall pages, one byte per column with the top row in bit 0
+fn pack_pages(canvas: &mono::Canvas) -> Vec<u8> {
+    let mut buf = vec![0u8; canvas.width * canvas.height / 8];
+    for (i, byte) in buf.iter_mut().enumerate() {
+        let (page, x) = (i / canvas.width, i % canvas.width);
+        for bit in 0..8 {
+            if canvas.get(x, page * 8 + bit) {
+                *byte |= 1 << bit;
+            }
+        }
+    }
+    buf
+}
+
+fn command(dev: &mut File, cmds: &[u8]) -> anyhow::Result<()> {
+    let mut buf = vec![CTRL_CMD];
+    buf.extend(cmds);
+    dev.write_all(&buf)?;
+    Ok(())
+}
+
+fn write_pages(dev: &mut File, pages: &[u8], height: usize) -> anyhow::Result<()> {
+    command(
+        dev,
+        &[
+            0x21,
+            0,
+            SSD1306_WIDTH as u8 - 1,
+            0x22,
+            0,
+            (height / 8) as u8 - 1,
+        ],
+    )?;
+    for chunk in pages.chunks(I2C_CHUNK) {
+        let mut buf = Vec::with_capacity(chunk.len() + 1);
+        buf.push(CTRL_DATA);
+        buf.extend(chunk);
+        dev.write_all(&buf)?;
+    }
+    Ok(())
+}
+
+// EOF
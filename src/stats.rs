@@ -1,16 +1,49 @@
 // stats.rs
 
-use std::{cmp::Ordering, fmt, io::{self, BufRead}, time};
-use std::{collections::HashMap, fs::File, path::Path};
+#[cfg(target_os = "linux")]
+use std::os::unix::fs::FileExt;
+use std::{
+    cmp::Ordering,
+    fmt,
+    io::{self, BufRead},
+    time,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    path::Path,
+    str::FromStr,
+};
 
 use anyhow::anyhow;
 
+use crate::*;
+
 const CPU_JIFF: f64 = 100.0;
 
-#[derive(Debug)]
+// A metric that is read once per sample, in its own unit.
+// The mapping layer scales it onto a channel.
+pub trait StatSource {
+    fn sample(&mut self) -> anyhow::Result<f64>;
+    // short name the sinks show, e.g. "cpu"
+    fn name(&self) -> &'static str;
+    // unit of the samples, e.g. "%" or "bit/s"
+    fn unit(&self) -> &'static str;
+    // the sample and when it was taken, for the sources read in a background thread
+    fn sample_at(&mut self) -> anyhow::Result<(f64, time::Instant)> {
+        Ok((self.sample()?, time::Instant::now()))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub enum IfCounter {
+    #[default]
     Rx,
     Tx,
+    RxErrors,
+    TxErrors,
+    RxDropped,
+    TxDropped,
 }
 
 impl fmt::Display for IfCounter {
@@ -21,87 +54,418 @@ impl fmt::Display for IfCounter {
             match self {
                 IfCounter::Rx => "rx_bytes",
                 IfCounter::Tx => "tx_bytes",
+                IfCounter::RxErrors => "rx_errors",
+                IfCounter::TxErrors => "tx_errors",
+                IfCounter::RxDropped => "rx_dropped",
+                IfCounter::TxDropped => "tx_dropped",
             }
         )
     }
 }
 
+impl FromStr for IfCounter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "rx" | "rx_bytes" => IfCounter::Rx,
+            "tx" | "tx_bytes" => IfCounter::Tx,
+            "rx_errors" => IfCounter::RxErrors,
+            "tx_errors" => IfCounter::TxErrors,
+            "rx_dropped" => IfCounter::RxDropped,
+            "tx_dropped" => IfCounter::TxDropped,
+            _ => return Err(anyhow!("Unknown interface counter: {s}")),
+        })
+    }
+}
+
+const IF_RESCAN: time::Duration = time::Duration::from_secs(10);
+
+// Traffic counters of one interface, or the sum over a comma separated
+// list of interfaces like "bond0,wg0". Glob patterns such as "en*,!veth*"
+// are matched against /sys/class/net, re-scanned every IF_RESCAN so that
+// interfaces coming and going are picked up automatically.
 #[derive(Debug)]
 pub struct IfStats {
     pub iface: String,
     pub dir: IfCounter,
-    fn_stats: String,
+    patterns: Vec<String>,
+    dynamic: bool,
+    ifaces: Vec<String>,
+    last_scan: time::Instant,
     prev_ts: time::Instant,
-    prev_cnt: i64,
+    prev_cnt: HashMap<String, i64>,
 }
 
 impl IfStats {
     pub fn new<S: AsRef<str>>(iface: S, dir: IfCounter) -> anyhow::Result<Self> {
-        let fn_stats = format!("/sys/class/net/{if}/statistics/{dir}", if = iface.as_ref());
-        let prev_cnt = Self::read_number(&fn_stats)?;
-        Ok(Self {
+        let patterns = iface
+            .as_ref()
+            .split(',')
+            .map(str::trim)
+            .filter(|i| !i.is_empty())
+            .map(String::from)
+            .collect::<Vec<String>>();
+        if patterns.is_empty() {
+            return Err(anyhow!("No interface given"));
+        }
+        let dynamic = patterns
+            .iter()
+            .any(|p| p.starts_with('!') || p.contains(['*', '?']));
+        let mut stats = Self {
             iface: iface.as_ref().to_string(),
             dir,
-            fn_stats,
+            ifaces: if dynamic {
+                Vec::new()
+            } else {
+                patterns.clone()
+            },
+            patterns,
+            dynamic,
+            last_scan: time::Instant::now(),
             prev_ts: time::Instant::now(),
-            prev_cnt,
-        })
+            prev_cnt: HashMap::new(),
+        };
+        if dynamic {
+            stats.scan()?;
+        }
+        stats.prev_cnt = stats.read_counts();
+        for i in stats
+            .ifaces
+            .iter()
+            .filter(|i| !stats.prev_cnt.contains_key(*i))
+        {
+            warn!("Interface {i} not found, waiting for it");
+        }
+        Ok(stats)
     }
     pub fn bitrate(&mut self) -> anyhow::Result<i64> {
+        Ok((8.0 * self.rate()?) as i64)
+    }
+    // counter increments per second, i.e. bytes, errors or drops
+    pub fn rate(&mut self) -> anyhow::Result<f64> {
+        if self.dynamic && self.last_scan.elapsed() >= IF_RESCAN {
+            self.scan()?;
+        }
         let us = self.prev_ts.elapsed().as_micros();
         self.prev_ts = time::Instant::now();
-        let cnt = Self::read_number(&self.fn_stats)?;
-        let rate = ((8 * (cnt - self.prev_cnt)) as f64 / (us as f64 / 1_000_000.0)) as i64;
+        let cnt = self.read_counts();
+        if !self.dynamic {
+            for i in &self.ifaces {
+                match (self.prev_cnt.contains_key(i), cnt.contains_key(i)) {
+                    (true, false) => info!("Interface {i} is gone, showing zero"),
+                    (false, true) => info!("Interface {i} is back"),
+                    _ => {}
+                }
+            }
+        }
+        // only interfaces seen on both rounds count, and a counter going
+        // backwards means the interface was re-created
+        let delta = cnt
+            .iter()
+            .filter_map(|(i, c)| self.prev_cnt.get(i).map(|p| c - p))
+            .filter(|d| *d >= 0)
+            .sum::<i64>();
+        let rate = delta as f64 / (us as f64 / 1_000_000.0);
         self.prev_cnt = cnt;
         Ok(rate)
     }
-    fn read_number<P>(filename: P) -> anyhow::Result<i64>
-        where
-            P: AsRef<Path>,
-    {
-        let mut lines = io::BufReader::new(File::open(filename)?).lines();
-        if let Some(line) = lines.next() {
-            return Ok(line?.parse::<i64>()?);
+    fn scan(&mut self) -> anyhow::Result<()> {
+        let (excl, incl): (Vec<&String>, Vec<&String>) =
+            self.patterns.iter().partition(|p| p.starts_with('!'));
+        let mut ifaces = Vec::new();
+        for entry in std::fs::read_dir(sys_path("class/net"))? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            let included = incl.is_empty() || incl.iter().any(|p| glob_match(p, &name));
+            let excluded = excl.iter().any(|p| glob_match(&p[1..], &name));
+            if included && !excluded {
+                ifaces.push(name);
+            }
+        }
+        ifaces.sort();
+        if ifaces != self.ifaces {
+            info!("Interfaces matching {}: {}", self.iface, ifaces.join(","));
+        }
+        self.ifaces = ifaces;
+        self.last_scan = time::Instant::now();
+        Ok(())
+    }
+    // interfaces may vanish any time, e.g. a VPN going down or a USB NIC
+    // unplugged, and are left out until they come back
+    fn read_counts(&self) -> HashMap<String, i64> {
+        let mut counts = HashMap::with_capacity(self.ifaces.len());
+        for i in &self.ifaces {
+            let fn_stats = sys_path(&format!("class/net/{i}/statistics/{dir}", dir = self.dir));
+            if let Ok(c) = read_number(&fn_stats) {
+                counts.insert(i.clone(), c);
+            }
+        }
+        counts
+    }
+}
+
+// traffic in bit/s, errors and drops per second
+impl StatSource for IfStats {
+    fn sample(&mut self) -> anyhow::Result<f64> {
+        match self.dir {
+            IfCounter::Rx | IfCounter::Tx => Ok(self.bitrate()? as f64),
+            _ => self.rate(),
+        }
+    }
+    fn name(&self) -> &'static str {
+        match self.dir {
+            IfCounter::Rx | IfCounter::Tx => "net",
+            _ => "if_counter",
+        }
+    }
+    fn unit(&self) -> &'static str {
+        match self.dir {
+            IfCounter::Rx | IfCounter::Tx => "bit/s",
+            _ => "/s",
+        }
+    }
+}
+
+// Shell style wildcard matching with * and ?
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let p = pattern.chars().collect::<Vec<char>>();
+    let n = name.chars().collect::<Vec<char>>();
+    let (mut pi, mut ni) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while ni < n.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == n[ni]) {
+            pi += 1;
+            ni += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ni));
+            pi += 1;
+        } else if let Some((sp, sn)) = star {
+            // backtrack, let the star eat one more char
+            pi = sp + 1;
+            ni = sn + 1;
+            star = Some((sp, sn + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+// Standard deviation of the most recent bitrate samples,
+// i.e. how bursty the link is rather than how busy.
+#[derive(Debug)]
+pub struct IfJitter {
+    stats: IfStats,
+    window: usize,
+    rates: VecDeque<f64>,
+}
+
+impl IfJitter {
+    pub fn new<S: AsRef<str>>(iface: S, dir: IfCounter, window: usize) -> anyhow::Result<Self> {
+        Ok(Self {
+            stats: IfStats::new(iface, dir)?,
+            window: window.max(2),
+            rates: VecDeque::with_capacity(window.max(2)),
+        })
+    }
+    pub fn jitter(&mut self) -> anyhow::Result<f64> {
+        if self.rates.len() == self.window {
+            self.rates.pop_front();
+        }
+        self.rates.push_back(self.stats.bitrate()? as f64);
+
+        let n = self.rates.len() as f64;
+        let mean = self.rates.iter().sum::<f64>() / n;
+        let var = self.rates.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / n;
+        Ok(var.sqrt())
+    }
+}
+
+// standard deviation of the bit rate
+impl StatSource for IfJitter {
+    fn sample(&mut self) -> anyhow::Result<f64> {
+        self.jitter()
+    }
+    fn name(&self) -> &'static str {
+        "jitter"
+    }
+    fn unit(&self) -> &'static str {
+        "bit/s"
+    }
+}
+
+fn read_number<P>(filename: P) -> anyhow::Result<i64>
+where
+    P: AsRef<Path>,
+{
+    let mut lines = io::BufReader::new(File::open(filename)?).lines();
+    if let Some(line) = lines.next() {
+        return Ok(line?.parse::<i64>()?);
+    }
+    Err(anyhow!("empty"))
+}
+
+#[derive(Debug)]
+pub struct ConntrackStats {
+    fn_count: String,
+    fn_max: String,
+}
+
+impl ConntrackStats {
+    pub fn new() -> anyhow::Result<Self> {
+        let stats = Self {
+            fn_count: proc_path("sys/net/netfilter/nf_conntrack_count"),
+            fn_max: proc_path("sys/net/netfilter/nf_conntrack_max"),
+        };
+        // fail early if the nf_conntrack module is not loaded
+        stats.usage()?;
+        Ok(stats)
+    }
+    // connection table usage in percent
+    pub fn usage(&self) -> anyhow::Result<f64> {
+        let count = read_number(&self.fn_count)?;
+        let max = read_number(&self.fn_max)?;
+        if max <= 0 {
+            return Ok(0.0);
+        }
+        Ok(100.0 * count as f64 / max as f64)
+    }
+}
+
+impl StatSource for ConntrackStats {
+    fn sample(&mut self) -> anyhow::Result<f64> {
+        self.usage()
+    }
+    fn name(&self) -> &'static str {
+        "conntrack"
+    }
+    fn unit(&self) -> &'static str {
+        "%"
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CpuMode {
+    // 100% minus idle
+    #[default]
+    Busy,
+    // waiting for I/O to complete
+    Iowait,
+    // servicing hard and soft interrupts
+    Irq,
+    // time stolen by the hypervisor
+    Steal,
+}
+
+impl CpuMode {
+    // columns of /proc/stat summed up for this mode
+    fn columns(&self) -> &'static [usize] {
+        match self {
+            CpuMode::Busy => &[4],
+            CpuMode::Iowait => &[5],
+            CpuMode::Irq => &[6, 7],
+            CpuMode::Steal => &[8],
+        }
+    }
+}
+
+impl FromStr for CpuMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "busy" => Ok(CpuMode::Busy),
+            "iowait" => Ok(CpuMode::Iowait),
+            "irq" => Ok(CpuMode::Irq),
+            "steal" => Ok(CpuMode::Steal),
+            _ => Err(anyhow!("Unknown cpu mode: {s}")),
         }
-        Err(anyhow!("empty"))
     }
 }
 
 #[derive(Debug)]
 pub struct CpuStats {
+    pub mode: CpuMode,
     prev_ts: time::Instant,
-    prev_idle: Vec<i64>,
+    // per /proc/stat line, "cpu" first and then the online cores
+    prev_jiffies: Vec<(String, i64)>,
 }
 
 impl CpuStats {
     pub fn new() -> anyhow::Result<Self> {
+        Self::with_mode(CpuMode::Busy)
+    }
+    pub fn with_mode(mode: CpuMode) -> anyhow::Result<Self> {
         Ok(Self {
+            mode,
             prev_ts: time::Instant::now(),
-            prev_idle: Self::read_cpuidle()?,
+            prev_jiffies: Self::read_jiffies(mode)?,
         })
     }
     pub fn cpurates(&mut self) -> anyhow::Result<Vec<f64>> {
         let us = self.prev_ts.elapsed().as_micros();
         self.prev_ts = time::Instant::now();
 
-        let idle = Self::read_cpuidle()?;
+        let jiffies = Self::read_jiffies(self.mode)?;
         let factor = 100.0 * 1_000_000.0 / (us as f64 * CPU_JIFF);
-        let n_cpu = (idle.len() - 1) as f64;
+        let n_cpu = (jiffies.len() - 1) as f64;
+        // cores going offline or online, e.g. with core parking, leave the total
+        // meaningless for one round, the cores seen on both rounds still count
+        let changed = jiffies
+            .iter()
+            .map(|(n, _)| n)
+            .ne(self.prev_jiffies.iter().map(|(n, _)| n));
+        if changed {
+            info!("Online CPUs changed, now {n_cpu}");
+        }
 
-        let mut rates = Vec::with_capacity(idle.len());
-        for (i, r) in idle.iter().enumerate() {
+        let mut rates = Vec::with_capacity(jiffies.len());
+        for (i, (name, r)) in jiffies.iter().enumerate() {
+            let Some((_, prev)) = self.prev_jiffies.iter().find(|(n, _)| n == name) else {
+                continue;
+            };
             let factor2 = if i == 0 { n_cpu } else { 1.0 };
-            // cpu usage is 100% minus idle.
-            let rate = 100.0 - ((factor * (r - self.prev_idle[i]) as f64) / factor2);
-            rates.push(rate);
+            let rate = (factor * (r - prev) as f64) / factor2;
+            rates.push(match self.mode {
+                // cpu usage is 100% minus idle.
+                CpuMode::Busy => 100.0 - rate,
+                _ => rate,
+            });
+        }
+        if changed && rates.len() > 1 {
+            rates[0] = rates[1..].iter().sum::<f64>() / (rates.len() - 1) as f64;
         }
         // Rust refuses to just sort() f64, because NaN etc.
         rates[1..].sort_by(|a, b| b.partial_cmp(a).unwrap_or(Ordering::Equal));
-        self.prev_idle = idle;
+        self.prev_jiffies = jiffies;
         Ok(rates)
     }
     pub fn n_cpu(&self) -> usize {
-        self.prev_idle.len() - 1
+        self.prev_jiffies.len() - 1
+    }
+    // the busiest cores weigh the most, a single hot thread still moves the needle,
+    // 100 is one core's worth of full scale
+    pub fn weighted_load(cpu_rates: &[f64], n_cpu: usize) -> f64 {
+        // fewer rates than cores while the online cores change
+        let n_cpu = n_cpu.min(cpu_rates.len().saturating_sub(1));
+        if n_cpu == 0 {
+            return 0.0;
+        }
+        let mut cpu_gauge = if n_cpu >= 2 {
+            (cpu_rates[1] + cpu_rates[2]) / 2.0
+        } else {
+            cpu_rates[1]
+        };
+
+        if n_cpu >= 6 {
+            cpu_gauge += (cpu_rates[3] + cpu_rates[4]) / 2.0;
+            cpu_gauge += (cpu_rates[5] + cpu_rates[6]) / 3.0;
+        } else if n_cpu >= 4 {
+            cpu_gauge += (cpu_rates[3] + cpu_rates[4]) * 0.80;
+        } else {
+            cpu_gauge *= 2.56;
+        }
+        cpu_gauge / 2.56
     }
 
     // Documentation of /proc/stat
@@ -111,39 +475,491 @@ impl CpuStats {
     // cpu0 395460 280 162807 11177794 29191 0 196711 0 0 0
     // cpu1 396373 662 172640 11169911 29418 0 45639 0 0 0
     // intr 976024260 34 0 0 0 0 0 0 0 1 0 0 0 0 0 0 0 0 0 0 0 0 0 0...
+    // The columns are: user nice system idle iowait irq softirq steal guest guest_nice
 
-    fn read_cpuidle() -> anyhow::Result<Vec<i64>> {
-        let mut cpu_idle = Vec::with_capacity(32);
-        for line in io::BufReader::new(File::open("/proc/stat")?).lines() {
+    fn read_jiffies(mode: CpuMode) -> anyhow::Result<Vec<(String, i64)>> {
+        let mut cpu_jiffies = Vec::with_capacity(32);
+        for line in io::BufReader::new(File::open(proc_path("stat"))?).lines() {
             let line = line?;
             let items = line.split_ascii_whitespace().collect::<Vec<&str>>();
-            if !items[0].starts_with("cpu") {
+            if !items.first().is_some_and(|i| i.starts_with("cpu")) {
                 break;
             }
-            cpu_idle.push(items[4].parse::<i64>()?);
+            let mut sum = 0;
+            for col in mode.columns() {
+                // old kernels do not have all the columns
+                if let Some(item) = items.get(*col) {
+                    sum += item.parse::<i64>()?;
+                }
+            }
+            cpu_jiffies.push((items[0].to_string(), sum));
+        }
+        if cpu_jiffies.len() < 2 {
+            return Err(anyhow!("No CPUs found in {}", proc_path("stat")));
+        }
+        Ok(cpu_jiffies)
+    }
+}
+
+// the weighted load of the busiest cores
+impl StatSource for CpuStats {
+    fn sample(&mut self) -> anyhow::Result<f64> {
+        // Note: cpu_rates[0] is total/summary, the rest are sorted largest first
+        let cpu_rates = self.cpurates()?;
+        let load = Self::weighted_load(&cpu_rates, self.n_cpu());
+        debug!(
+            "CPU load: {load:.1} sum: {sum:.1} -- {list}",
+            sum = cpu_rates[0],
+            list = cpu_rates[1..]
+                .iter()
+                .map(|a| format!("{a:.1}"))
+                .collect::<Vec<String>>()
+                .join(" ")
+        );
+        Ok(load)
+    }
+    fn name(&self) -> &'static str {
+        "cpu"
+    }
+    fn unit(&self) -> &'static str {
+        "%"
+    }
+}
+
+// procs_running and procs_blocked from /proc/stat, e.g.
+// procs_running 3
+// procs_blocked 0
+#[derive(Debug, Default)]
+pub struct ProcsStats {
+    pub running: i64,
+    pub blocked: i64,
+}
+
+impl ProcsStats {
+    pub fn read() -> anyhow::Result<Self> {
+        let mut stats = Self::default();
+        for line in io::BufReader::new(File::open(proc_path("stat"))?).lines() {
+            let line = line?;
+            if let Some(n) = line.strip_prefix("procs_running ") {
+                stats.running = n.trim().parse::<i64>()?;
+            } else if let Some(n) = line.strip_prefix("procs_blocked ") {
+                stats.blocked = n.trim().parse::<i64>()?;
+            }
+        }
+        Ok(stats)
+    }
+}
+
+// https://www.kernel.org/doc/Documentation/filesystems/proc.rst
+// Example input:
+// nr_dirty 1234
+// pgfault 123456789
+// pgmajfault 12345
+pub fn read_vmstat() -> anyhow::Result<HashMap<String, i64>> {
+    let mut stats = HashMap::with_capacity(256);
+    for line in io::BufReader::new(File::open(proc_path("vmstat"))?).lines() {
+        let line = line?;
+        if let Some((k, v)) = line.split_once(' ') {
+            stats.insert(k.into(), v.trim().parse::<i64>()?);
+        }
+    }
+    Ok(stats)
+}
+
+#[derive(Debug)]
+pub struct FaultStats {
+    prev_ts: time::Instant,
+    prev_faults: (i64, i64),
+}
+
+impl FaultStats {
+    pub fn new() -> anyhow::Result<Self> {
+        Ok(Self {
+            prev_ts: time::Instant::now(),
+            prev_faults: Self::read_faults()?,
+        })
+    }
+    // all page faults and major faults per second
+    pub fn faultrates(&mut self) -> anyhow::Result<(f64, f64)> {
+        let us = self.prev_ts.elapsed().as_micros();
+        self.prev_ts = time::Instant::now();
+        let faults = Self::read_faults()?;
+        let secs = us as f64 / 1_000_000.0;
+        let rates = (
+            (faults.0 - self.prev_faults.0) as f64 / secs,
+            (faults.1 - self.prev_faults.1) as f64 / secs,
+        );
+        self.prev_faults = faults;
+        Ok(rates)
+    }
+    fn read_faults() -> anyhow::Result<(i64, i64)> {
+        let vmstat = read_vmstat()?;
+        let get = |k: &str| {
+            vmstat
+                .get(k)
+                .copied()
+                .ok_or_else(|| anyhow!("No {k} in vmstat"))
+        };
+        Ok((get("pgfault")?, get("pgmajfault")?))
+    }
+}
+
+// Dirty and writeback pages together with the kernel's current dirty
+// throttling threshold, all of them counted in pages.
+#[derive(Debug, Default)]
+pub struct DirtyStats {
+    pub dirty: i64,
+    pub writeback: i64,
+    pub threshold: i64,
+    pub bg_threshold: i64,
+}
+
+impl DirtyStats {
+    pub fn read() -> anyhow::Result<Self> {
+        let vmstat = read_vmstat()?;
+        let get = |k: &str| {
+            vmstat
+                .get(k)
+                .copied()
+                .ok_or_else(|| anyhow!("No {k} in vmstat"))
+        };
+        Ok(Self {
+            dirty: get("nr_dirty")?,
+            writeback: get("nr_writeback")?,
+            threshold: get("nr_dirty_threshold")?,
+            bg_threshold: get("nr_dirty_background_threshold")?,
+        })
+    }
+    // percent of the dirty threshold, where writers start getting throttled
+    pub fn dirty_pct(&self) -> f64 {
+        100.0 * self.dirty as f64 / self.threshold.max(1) as f64
+    }
+    pub fn writeback_pct(&self) -> f64 {
+        100.0 * self.writeback as f64 / self.threshold.max(1) as f64
+    }
+}
+
+// Example input:
+// MemTotal:       32768000 kB
+// HugePages_Total:    1024
+// HugePages_Free:      512
+pub fn read_meminfo() -> anyhow::Result<HashMap<String, i64>> {
+    let mut info = HashMap::with_capacity(64);
+    for line in io::BufReader::new(File::open(proc_path("meminfo"))?).lines() {
+        let line = line?;
+        if let Some((k, v)) = line.split_once(':') {
+            if let Some(n) = v.split_ascii_whitespace().next() {
+                info.insert(k.into(), n.parse::<i64>()?);
+            }
         }
-        Ok(cpu_idle)
+    }
+    Ok(info)
+}
+
+#[derive(Debug, Default)]
+pub struct HugePages {
+    pub total: i64,
+    pub free: i64,
+}
+
+impl HugePages {
+    pub fn read() -> anyhow::Result<Self> {
+        let info = read_meminfo()?;
+        let get = |k: &str| {
+            info.get(k)
+                .copied()
+                .ok_or_else(|| anyhow!("No {k} in meminfo"))
+        };
+        Ok(Self {
+            total: get("HugePages_Total")?,
+            free: get("HugePages_Free")?,
+        })
+    }
+    // percent of hugepages in use, zero when none are configured
+    pub fn used_pct(&self) -> f64 {
+        if self.total <= 0 {
+            return 0.0;
+        }
+        100.0 * (self.total - self.free) as f64 / self.total as f64
+    }
+}
+
+// Average current cpu frequency relative to the hardware min/max.
+// Without cpufreq in sysfs fall back to "cpu MHz" in /proc/cpuinfo,
+// against the highest frequency seen so far.
+#[derive(Debug)]
+pub struct CpuFreq {
+    fn_cur: Vec<String>,
+    min_khz: f64,
+    max_khz: f64,
+}
+
+impl CpuFreq {
+    pub fn new() -> anyhow::Result<Self> {
+        let mut fn_cur = Vec::new();
+        let mut min_khz = f64::MAX;
+        let mut max_khz = 0.0f64;
+        for entry in std::fs::read_dir(sys_path("devices/system/cpu"))? {
+            let path = entry?.path().join("cpufreq");
+            if !path.join("scaling_cur_freq").exists() {
+                continue;
+            }
+            min_khz = min_khz.min(read_number(path.join("cpuinfo_min_freq"))? as f64);
+            max_khz = max_khz.max(read_number(path.join("cpuinfo_max_freq"))? as f64);
+            fn_cur.push(path.join("scaling_cur_freq").to_string_lossy().into_owned());
+        }
+        if fn_cur.is_empty() {
+            min_khz = 0.0;
+        }
+        Ok(Self {
+            fn_cur,
+            min_khz,
+            max_khz,
+        })
+    }
+    // average current frequency in kHz
+    pub fn cur_khz(&mut self) -> anyhow::Result<f64> {
+        let freqs = if self.fn_cur.is_empty() {
+            Self::read_cpuinfo()?
+        } else {
+            self.fn_cur
+                .iter()
+                .map(|f| Ok(read_number(f)? as f64))
+                .collect::<anyhow::Result<Vec<f64>>>()?
+        };
+        if freqs.is_empty() {
+            return Err(anyhow!("No cpu frequencies found"));
+        }
+        let avg = freqs.iter().sum::<f64>() / freqs.len() as f64;
+        if self.fn_cur.is_empty() {
+            self.max_khz = self.max_khz.max(avg);
+        }
+        Ok(avg)
+    }
+    // position between min and max frequency in percent
+    pub fn freq_pct(&mut self) -> anyhow::Result<f64> {
+        let cur = self.cur_khz()?;
+        let range = self.max_khz - self.min_khz;
+        if range <= 0.0 {
+            return Ok(0.0);
+        }
+        Ok(100.0 * (cur - self.min_khz) / range)
+    }
+    // Example input: "cpu MHz		: 3400.000"
+    fn read_cpuinfo() -> anyhow::Result<Vec<f64>> {
+        let mut freqs = Vec::with_capacity(32);
+        for line in io::BufReader::new(File::open(proc_path("cpuinfo"))?).lines() {
+            let line = line?;
+            if let Some((k, v)) = line.split_once(':') {
+                if k.trim() == "cpu MHz" {
+                    freqs.push(v.trim().parse::<f64>()? * 1000.0);
+                }
+            }
+        }
+        Ok(freqs)
+    }
+}
+
+impl StatSource for CpuFreq {
+    fn sample(&mut self) -> anyhow::Result<f64> {
+        self.freq_pct()
+    }
+    fn name(&self) -> &'static str {
+        "cpufreq"
+    }
+    fn unit(&self) -> &'static str {
+        "%"
+    }
+}
+
+#[cfg(target_os = "linux")]
+const MSR_IA32_PACKAGE_THERM_STATUS: u64 = 0x1b1;
+
+// Detects thermal throttling from the thermal_throttle event counters in sysfs,
+// and on x86 also from the package thermal status MSR when /dev/cpu/0/msr is readable.
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub struct ThermalThrottle {
+    fn_counts: Vec<String>,
+    prev_total: i64,
+    msr: Option<File>,
+}
+
+#[cfg(target_os = "linux")]
+impl ThermalThrottle {
+    pub fn new() -> anyhow::Result<Self> {
+        let mut fn_counts = Vec::new();
+        for entry in std::fs::read_dir(sys_path("devices/system/cpu"))? {
+            let path = entry?.path().join("thermal_throttle");
+            for f in ["core_throttle_count", "package_throttle_count"] {
+                if path.join(f).exists() {
+                    fn_counts.push(path.join(f).to_string_lossy().into_owned());
+                }
+            }
+        }
+        let msr = File::open("/dev/cpu/0/msr").ok();
+        if fn_counts.is_empty() && msr.is_none() {
+            return Err(anyhow!("No thermal throttle information available"));
+        }
+        let mut tt = Self {
+            fn_counts,
+            prev_total: 0,
+            msr,
+        };
+        tt.prev_total = tt.read_total()?;
+        Ok(tt)
+    }
+    // true when throttling happened since the previous call or is going on right now
+    pub fn throttling(&mut self) -> anyhow::Result<bool> {
+        let total = self.read_total()?;
+        let counted = total != self.prev_total;
+        self.prev_total = total;
+        Ok(counted || self.msr_active()?)
+    }
+    fn read_total(&self) -> anyhow::Result<i64> {
+        self.fn_counts.iter().map(read_number).sum()
+    }
+    // bit 0 of IA32_PACKAGE_THERM_STATUS is the current thermal status
+    fn msr_active(&self) -> anyhow::Result<bool> {
+        let Some(msr) = &self.msr else {
+            return Ok(false);
+        };
+        let mut buf = [0u8; 8];
+        msr.read_exact_at(&mut buf, MSR_IA32_PACKAGE_THERM_STATUS)?;
+        Ok(u64::from_le_bytes(buf) & 1 != 0)
+    }
+}
+
+// one while throttling, smoothing takes care of the decay
+#[cfg(target_os = "linux")]
+impl StatSource for ThermalThrottle {
+    fn sample(&mut self) -> anyhow::Result<f64> {
+        Ok(if self.throttling()? { 1.0 } else { 0.0 })
+    }
+    fn name(&self) -> &'static str {
+        "throttle"
+    }
+    fn unit(&self) -> &'static str {
+        ""
+    }
+}
+
+// How the rates of several disks make up the disk gauge: the busiest one,
+// the sum of the disks below any md or dm device, or a percentile, e.g. p50
+// for the median disk
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DiskGauge {
+    #[default]
+    Busiest,
+    Sum,
+    Percentile(f64),
+}
+
+impl FromStr for DiskGauge {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "busiest" => Ok(DiskGauge::Busiest),
+            "sum" => Ok(DiskGauge::Sum),
+            _ => match s.strip_prefix('p').map(str::parse::<f64>) {
+                Some(Ok(p)) if (0.0..=100.0).contains(&p) => Ok(DiskGauge::Percentile(p)),
+                _ => Err(anyhow!(
+                    "Unknown disk gauge: {s}, expected busiest, sum or p0-p100"
+                )),
+            },
+        }
+    }
+}
+
+impl DiskGauge {
+    // the rates are sorted busiest first, no disks give zero
+    pub fn apply(&self, rates: &[f64]) -> f64 {
+        if rates.is_empty() {
+            return 0.0;
+        }
+        match self {
+            DiskGauge::Busiest => rates[0],
+            DiskGauge::Sum => rates.iter().sum(),
+            DiskGauge::Percentile(p) => {
+                // nearest rank, counted from the quietest disk
+                let rank = (p / 100.0 * rates.len() as f64).ceil().max(1.0) as usize;
+                rates[rates.len() - rank.min(rates.len())]
+            }
+        }
+    }
+}
+
+// Disk names matched against comma separated globs, "!" excludes. Without
+// any including pattern the whole disks are matched.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DiskFilter {
+    incl: Vec<String>,
+    excl: Vec<String>,
+}
+
+impl DiskFilter {
+    pub fn new(patterns: &str) -> Self {
+        let (excl, incl): (Vec<&str>, Vec<&str>) = patterns
+            .split(',')
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .partition(|p| p.starts_with('!'));
+        Self {
+            incl: incl.into_iter().map(String::from).collect(),
+            excl: excl.into_iter().map(|p| p[1..].to_string()).collect(),
+        }
+    }
+    pub fn matches(&self, name: &str) -> bool {
+        let included = match self.incl.is_empty() {
+            true => is_whole_disk(name),
+            false => self.incl.iter().any(|p| glob_match(p, name)),
+        };
+        included && !self.excl.iter().any(|p| glob_match(p, name))
     }
 }
 
 #[derive(Debug)]
 pub struct DiskStats {
+    // globs of the devices, all the whole disks by default
+    device: Option<String>,
+    filter: DiskFilter,
+    gauge: DiskGauge,
     prev_ts: time::Instant,
     prev_stats: HashMap<String, (i64, i64)>,
 }
 
 impl DiskStats {
     pub fn new() -> anyhow::Result<Self> {
-        Ok(Self {
+        Self::with_device(None, DiskGauge::Busiest)
+    }
+    pub fn with_device(device: Option<&str>, gauge: DiskGauge) -> anyhow::Result<Self> {
+        let mut stats = Self {
+            device: device.map(String::from),
+            filter: DiskFilter::new(device.unwrap_or_default()),
+            gauge,
             prev_ts: time::Instant::now(),
-            prev_stats: Self::read_diskstats()?,
-        })
+            prev_stats: HashMap::new(),
+        };
+        stats.prev_stats = stats.read_diskstats()?;
+        if stats.prev_stats.is_empty() {
+            match &stats.device {
+                Some(dev) => return Err(anyhow!("No such disk: {dev}")),
+                None => warn!("No disks found, showing zero"),
+            }
+        }
+        Ok(stats)
     }
     pub fn diskrates(&mut self) -> anyhow::Result<Vec<f64>> {
         let us = self.prev_ts.elapsed().as_micros();
         self.prev_ts = time::Instant::now();
 
-        let stats = Self::read_diskstats()?;
+        let mut stats = self.read_diskstats()?;
+        if self.gauge == DiskGauge::Sum {
+            for dev in stacked(&stats) {
+                stats.remove(&dev);
+            }
+        }
         let mut rates = Vec::with_capacity(stats.len());
 
         for (k, v) in &stats {
@@ -162,16 +978,17 @@ impl DiskStats {
         Ok(rates)
     }
     // https://www.kernel.org/doc/Documentation/ABI/testing/procfs-diskstats
-    fn read_diskstats() -> anyhow::Result<HashMap<String, (i64, i64)>> {
+    fn read_diskstats(&self) -> anyhow::Result<HashMap<String, (i64, i64)>> {
         let mut stats = HashMap::with_capacity(32);
-        for line in io::BufReader::new(File::open("/proc/diskstats")?).lines() {
+        for line in io::BufReader::new(File::open(proc_path("diskstats"))?).lines() {
             let line = line?;
             let items = line.split_ascii_whitespace().collect::<Vec<&str>>();
+            if items.len() < 10 {
+                continue;
+            }
             let devname = items[2];
-            // collect sectors read and sectors written from "sd?" and "nvme???"
-            if devname.starts_with("sd") && devname.len() == 3
-                || devname.starts_with("nvme") && devname.len() == 7
-            {
+            // collect sectors read and sectors written from the matching disks
+            if self.filter.matches(devname) {
                 let sect_rd = items[5].parse::<i64>()?;
                 let sect_wrt = items[9].parse::<i64>()?;
                 stats.insert(devname.into(), (sect_rd, sect_wrt));
@@ -180,4 +997,71 @@ impl DiskStats {
         Ok(stats)
     }
 }
+
+// The md and dm devices on top of other devices in stats, their I/O is counted
+// again in the devices below them. On a partition, e.g. md0 on sda1 and sdb1,
+// the disk it is on counts.
+fn stacked(stats: &HashMap<String, (i64, i64)>) -> Vec<String> {
+    let below = |member: &str| {
+        if stats.contains_key(member) {
+            return true;
+        }
+        let Ok(part) = std::fs::canonicalize(sys_path(&format!("class/block/{member}"))) else {
+            return false;
+        };
+        part.join("partition").exists()
+            && part
+                .parent()
+                .and_then(|disk| disk.file_name())
+                .is_some_and(|disk| stats.contains_key(&*disk.to_string_lossy()))
+    };
+    stats
+        .keys()
+        .filter(|dev| {
+            std::fs::read_dir(sys_path(&format!("block/{dev}/slaves"))).is_ok_and(|members| {
+                members
+                    .flatten()
+                    .any(|m| below(&m.file_name().to_string_lossy()))
+            })
+        })
+        .cloned()
+        .collect()
+}
+
+// Disks rather than their partitions: sda, vda, xvda, hda, nvme0n1, mmcblk0,
+// md0 and dm-0, but not sda1, nvme0n1p1, mmcblk0p1 or mmcblk0boot0
+pub fn is_whole_disk(name: &str) -> bool {
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let letters = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_lowercase());
+    if let Some(rest) = name.strip_prefix("nvme") {
+        return rest
+            .split_once('n')
+            .is_some_and(|(ctrl, ns)| digits(ctrl) && digits(ns));
+    }
+    if let Some(n) = name.strip_prefix("mmcblk") {
+        return digits(n);
+    }
+    if let Some(n) = name.strip_prefix("dm-").or_else(|| name.strip_prefix("md")) {
+        return digits(n);
+    }
+    ["sd", "vd", "xvd", "hd"]
+        .iter()
+        .any(|p| name.strip_prefix(p).is_some_and(letters))
+}
+
+// sectors per second of the busiest disk by default, zero without any disks
+impl StatSource for DiskStats {
+    fn sample(&mut self) -> anyhow::Result<f64> {
+        let disk_rates = self.diskrates()?;
+        debug!("DISK rates: {disk_rates:?}");
+        Ok(self.gauge.apply(&disk_rates))
+    }
+    fn name(&self) -> &'static str {
+        "disk"
+    }
+    fn unit(&self) -> &'static str {
+        "sectors/s"
+    }
+}
+
 // EOF
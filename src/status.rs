@@ -0,0 +1,114 @@
+// status.rs
+
+use std::sync::{Arc, Mutex};
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    thread, time,
+};
+
+use crate::*;
+
+// errors of background threads and sinks, these do not stop the program
+static ERROR_COUNTS: Mutex<BTreeMap<&'static str, u64>> = Mutex::new(BTreeMap::new());
+
+pub fn count_error(what: &'static str) {
+    *ERROR_COUNTS.lock().unwrap().entry(what).or_default() += 1;
+}
+
+pub fn error_counts() -> Vec<(&'static str, u64)> {
+    ERROR_COUNTS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(k, v)| (*k, *v))
+        .collect()
+}
+
+// Serves GET /status with the latest frame, uptime and error counters:
+// {"version":"1.0.0","uptime":12.3,"age_ms":40,"channels":[...],"errors":{"mqtt":2}}
+pub struct StatusSink;
+
+impl StatusSink {
+    pub fn spawn<S: AsRef<str>>(listen: S) -> anyhow::Result<ThreadedSink> {
+        let listener = TcpListener::bind(listen.as_ref())?;
+        info!("Status API listening on http://{}/status", listen.as_ref());
+        let started = time::Instant::now();
+        let latest = Arc::new(Mutex::new((Frame::new(), time::Instant::now())));
+
+        let serving = latest.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Err(e) = Self::handle(stream, &serving, started) {
+                    debug!("Status API: {e}");
+                }
+            }
+        });
+
+        spawn_sink("status", move |rx| {
+            while let Ok(frame) = rx.recv() {
+                *latest.lock().unwrap() = (frame, time::Instant::now());
+            }
+        })
+    }
+
+    fn handle(
+        stream: TcpStream,
+        latest: &Mutex<(Frame, time::Instant)>,
+        started: time::Instant,
+    ) -> anyhow::Result<()> {
+        stream.set_read_timeout(Some(time::Duration::from_secs(2)))?;
+        stream.set_write_timeout(Some(time::Duration::from_secs(2)))?;
+        let mut reader = BufReader::new(&stream);
+        let mut request = String::new();
+        reader.read_line(&mut request)?;
+        // skip the headers
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                break;
+            }
+        }
+
+        let mut parts = request.split_whitespace();
+        let (status, body) = match (parts.next(), parts.next()) {
+            (Some("GET"), Some("/status" | "/")) => ("200 OK", Self::status_json(latest, started)),
+            (Some("GET"), _) => ("404 Not Found", "{\"error\":\"not found\"}".to_string()),
+            _ => (
+                "405 Method Not Allowed",
+                "{\"error\":\"method not allowed\"}".to_string(),
+            ),
+        };
+        write!(
+            &stream,
+            "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+             Access-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )?;
+        Ok(())
+    }
+
+    fn status_json(latest: &Mutex<(Frame, time::Instant)>, started: time::Instant) -> String {
+        let (channels, age) = {
+            let latest = latest.lock().unwrap();
+            (channels_json(&latest.0), latest.1.elapsed())
+        };
+        let mut errors = String::new();
+        for (i, (what, n)) in error_counts().iter().enumerate() {
+            if i > 0 {
+                errors.push(',');
+            }
+            let _ = write!(errors, "{}:{n}", JsonStr(what));
+        }
+        format!(
+            "{{\"version\":{},\"uptime\":{:.1},\"age_ms\":{},\"channels\":{channels},\"errors\":{{{errors}}}}}",
+            JsonStr(env!("CARGO_PKG_VERSION")),
+            started.elapsed().as_secs_f64(),
+            age.as_millis()
+        )
+    }
+}
+
+// EOF
@@ -0,0 +1,185 @@
+// statusbar.rs
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{self, Write},
+    str::FromStr,
+    sync::mpsc,
+    time,
+};
+
+use anyhow::anyhow;
+
+use crate::*;
+
+const BAR_INTERVAL: time::Duration = time::Duration::from_secs(1);
+// the waybar module gets the "critical" class at or above this gauge
+const BAR_CRITICAL: f64 = 230.0;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum BarFormat {
+    // one JSON object per line for a waybar custom module with "return-type": "json"
+    #[default]
+    Waybar,
+    // the i3bar protocol, one block per channel, also understood by swaybar
+    I3bar,
+}
+
+impl FromStr for BarFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "waybar" => Ok(BarFormat::Waybar),
+            "i3bar" | "swaybar" => Ok(BarFormat::I3bar),
+            _ => Err(anyhow!("Unknown status bar format: {s}")),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct BarChannel {
+    source: &'static str,
+    raw: Option<f64>,
+    peak: f64,
+}
+
+// Prints the highest gauge of every channel during each second to stdout
+// as status bar JSON, with Font Awesome icons by source
+pub struct StatusBarSink;
+
+impl StatusBarSink {
+    pub fn spawn(format: BarFormat) -> anyhow::Result<ThreadedSink> {
+        spawn_sink("statusbar", move |rx| Self::run(format, rx))
+    }
+
+    fn run(format: BarFormat, rx: mpsc::Receiver<Frame>) {
+        let mut channels: BTreeMap<u8, BarChannel> = BTreeMap::new();
+        let mut next_print = time::Instant::now() + BAR_INTERVAL;
+        let mut out = io::stdout();
+        let mut first = true;
+
+        if format == BarFormat::I3bar && out.write_all(b"{\"version\":1}\n[\n").is_err() {
+            return;
+        }
+        loop {
+            let timeout = next_print.saturating_duration_since(time::Instant::now());
+            match rx.recv_timeout(timeout) {
+                Ok(frame) => {
+                    for (ch, sample) in frame {
+                        let c = channels.entry(ch).or_default();
+                        c.source = sample.source;
+                        c.raw = sample.raw;
+                        c.peak = c.peak.max(sample.value.clamp(0.0, 255.0));
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => return,
+            }
+            if time::Instant::now() < next_print {
+                continue;
+            }
+            next_print += BAR_INTERVAL;
+            if channels.is_empty() {
+                continue;
+            }
+
+            let mut line = match format {
+                BarFormat::Waybar => waybar_json(&channels),
+                BarFormat::I3bar => i3bar_json(&channels),
+            };
+            if format == BarFormat::I3bar && !first {
+                line.insert(0, ',');
+            }
+            line.push('\n');
+            first = false;
+            if out
+                .write_all(line.as_bytes())
+                .and_then(|_| out.flush())
+                .is_err()
+            {
+                return;
+            }
+            for c in channels.values_mut() {
+                c.peak = 0.0;
+            }
+        }
+    }
+}
+
+fn icon(source: &str) -> char {
+    match source {
+        "cpu" | "cpufreq" | "steal" | "irq" | "perf" | "procs_running" | "procs_blocked" => {
+            '\u{f2db}'
+        }
+        "net" | "nft" | "if_counter" | "ethtool" | "wireguard" | "snmp" | "conntrack" => '\u{f6ff}',
+        "disk" | "dirty" | "writeback" => '\u{f0a0}',
+        "pgfault" | "pgmajfault" | "hugepages" => '\u{f538}',
+        "gpu_temp" | "throttle" => '\u{f2c9}',
+        "audio" => '\u{f028}',
+        "ntp" | "jitter" => '\u{f017}',
+        _ => '\u{f0e4}',
+    }
+}
+
+fn percent(gauge: f64) -> u32 {
+    (gauge / 255.0 * 100.0).round() as u32
+}
+
+fn tooltip_line(ch: u8, c: &BarChannel) -> String {
+    let source = match c.source {
+        "" => "channel",
+        s => s,
+    };
+    let mut line = format!("ch{ch} {source} {}%", percent(c.peak));
+    if let Some(raw) = c.raw.filter(|r| r.is_finite()) {
+        let _ = write!(line, " ({})", fmt_si(raw));
+    }
+    line
+}
+
+// {"text": " 42%  7%", "tooltip": "...", "class": "normal", "percentage": 42}
+fn waybar_json(channels: &BTreeMap<u8, BarChannel>) -> String {
+    let text = channels
+        .values()
+        .map(|c| format!("{} {}%", icon(c.source), percent(c.peak)))
+        .collect::<Vec<_>>()
+        .join("  ");
+    let tooltip = channels
+        .iter()
+        .map(|(ch, c)| tooltip_line(*ch, c))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let peak = channels.values().map(|c| c.peak).fold(0.0, f64::max);
+    let class = if peak >= BAR_CRITICAL {
+        "critical"
+    } else {
+        "normal"
+    };
+    format!(
+        "{{\"text\":{},\"tooltip\":{},\"class\":\"{class}\",\"percentage\":{}}}",
+        JsonStr(&text),
+        JsonStr(&tooltip),
+        percent(peak)
+    )
+}
+
+// [{"name": "perf_vumeter", "instance": "ch1", "full_text": " 42%", "color": "#aaff00"}, ...]
+fn i3bar_json(channels: &BTreeMap<u8, BarChannel>) -> String {
+    let blocks = channels
+        .iter()
+        .map(|(ch, c)| {
+            let Rgb(r, g, b) = Rgb::load(c.peak / 255.0);
+            format!(
+                "{{\"name\":\"perf_vumeter\",\"instance\":\"ch{ch}\",\"full_text\":{},\
+                 \"short_text\":\"{}%\",\"color\":\"#{r:02x}{g:02x}{b:02x}\"}}",
+                JsonStr(&format!("{} {}%", icon(c.source), percent(c.peak))),
+                percent(c.peak)
+            )
+        })
+        .collect::<Vec<_>>();
+    format!("[{}]", blocks.join(","))
+}
+
+// EOF
@@ -0,0 +1,226 @@
+// streamdeck.rs
+
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::Write,
+    os::fd::AsRawFd,
+    str::FromStr,
+    sync::mpsc,
+};
+
+use anyhow::anyhow;
+
+use crate::*;
+
+const SD_REPORT_LEN: usize = 1024;
+const SD_HEADER_LEN: usize = 8;
+// HIDIOCSFEATURE(32)
+const HIDIOCSFEATURE_32: std::os::raw::c_ulong = 0xc020_4806;
+const SD_JPEG_QUALITY: u8 = 90;
+// only redraw a key when its gauge moves at least this much
+const SD_REDRAW_STEP: f64 = 2.0;
+const SD_BACKGROUND: [u8; 3] = [16, 16, 16];
+const SD_SCALE: [u8; 3] = [160, 160, 160];
+const SD_NEEDLE: [u8; 3] = [255, 255, 255];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum StreamDeckModel {
+    // Original V2 and MK.2, 15 keys
+    #[default]
+    Mk2,
+    // 32 keys
+    Xl,
+}
+
+impl StreamDeckModel {
+    fn key_size(self) -> usize {
+        match self {
+            StreamDeckModel::Mk2 => 72,
+            StreamDeckModel::Xl => 96,
+        }
+    }
+    fn keys(self) -> usize {
+        match self {
+            StreamDeckModel::Mk2 => 15,
+            StreamDeckModel::Xl => 32,
+        }
+    }
+}
+
+impl FromStr for StreamDeckModel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mk2" | "original-v2" => Ok(StreamDeckModel::Mk2),
+            "xl" => Ok(StreamDeckModel::Xl),
+            _ => Err(anyhow!("Unknown Stream Deck model: {s}")),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum StreamDeckStyle {
+    #[default]
+    Bar,
+    Needle,
+}
+
+impl FromStr for StreamDeckStyle {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bar" => Ok(StreamDeckStyle::Bar),
+            "needle" => Ok(StreamDeckStyle::Needle),
+            _ => Err(anyhow!("Unknown Stream Deck style: {s}")),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct StreamDeckConfig {
+    // hidraw device, e.g. /dev/hidraw3
+    pub device: String,
+    pub model: StreamDeckModel,
+    pub style: StreamDeckStyle,
+    // meter channel shown on each key, starting from the top left key
+    pub keys: Vec<u8>,
+    // percent
+    pub brightness: u8,
+}
+
+// Draws a bar or needle meter of each channel onto the Stream Deck keys over hidraw
+pub struct StreamDeckSink;
+
+impl StreamDeckSink {
+    pub fn spawn(cfg: StreamDeckConfig) -> anyhow::Result<ThreadedSink> {
+        if cfg.keys.len() > cfg.model.keys() {
+            return Err(anyhow!(
+                "Stream Deck {:?} has only {} keys",
+                cfg.model,
+                cfg.model.keys()
+            ));
+        }
+        let dev = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&cfg.device)?;
+        let mut feature = [0u8; 32];
+        feature[..3].copy_from_slice(&[0x03, 0x08, cfg.brightness.min(100)]);
+        sys::sys_ioctl(dev.as_raw_fd(), HIDIOCSFEATURE_32, feature.as_mut_ptr())?;
+        info!("Stream Deck {:?} on {}", cfg.model, cfg.device);
+        spawn_sink("streamdeck", move |rx| Self::run(cfg, dev, rx))
+    }
+
+    fn run(cfg: StreamDeckConfig, mut dev: File, rx: mpsc::Receiver<Frame>) {
+        let mut shown: HashMap<usize, f64> = HashMap::new();
+        while let Ok(frame) = rx.recv() {
+            for (key, ch) in cfg.keys.iter().enumerate() {
+                let Some(sample) = frame.get(ch) else {
+                    continue;
+                };
+                let gauge = sample.value.clamp(0.0, 255.0);
+                if shown
+                    .get(&key)
+                    .is_some_and(|g| (g - gauge).abs() < SD_REDRAW_STEP)
+                {
+                    continue;
+                }
+                let size = cfg.model.key_size();
+                let img = jpeg::encode_jpeg(
+                    &render(cfg.style, size, gauge / 255.0),
+                    size,
+                    size,
+                    SD_JPEG_QUALITY,
+                );
+                match write_key(&mut dev, key as u8, &img) {
+                    Ok(()) => {
+                        shown.insert(key, gauge);
+                    }
+                    Err(e) => {
+                        debug!("Stream Deck: {e}");
+                        count_error("streamdeck");
+                    }
+                }
+            }
+        }
+    }
+}
+
+// the image goes out in 1024 byte output reports
+fn write_key(dev: &mut File, key: u8, img: &[u8]) -> anyhow::Result<()> {
+    for (page, chunk) in img.chunks(SD_REPORT_LEN - SD_HEADER_LEN).enumerate() {
+        let last = (page + 1) * (SD_REPORT_LEN - SD_HEADER_LEN) >= img.len();
+        let mut report = [0u8; SD_REPORT_LEN];
+        report[0] = 0x02;
+        report[1] = 0x07;
+        report[2] = key;
+        report[3] = last as u8;
+        report[4..6].copy_from_slice(&(chunk.len() as u16).to_le_bytes());
+        report[6..8].copy_from_slice(&(page as u16).to_le_bytes());
+        report[SD_HEADER_LEN..SD_HEADER_LEN + chunk.len()].copy_from_slice(chunk);
+        dev.write_all(&report)?;
+    }
+    Ok(())
+}
+
+// green through yellow to red
+fn level_color(level: f64) -> [u8; 3] {
+    let l = level.clamp(0.0, 1.0);
+    let r = (l * 2.0).min(1.0);
+    let g = ((1.0 - l) * 2.0).min(1.0);
+    [(r * 255.0) as u8, (g * 255.0) as u8, 0]
+}
+
+// RGB pixels, rotated 180 degrees the way the keys are mounted
+fn render(style: StreamDeckStyle, size: usize, level: f64) -> Vec<u8> {
+    let s = size as f64;
+    let mut img = vec![0u8; size * size * 3];
+    for y in 0..size {
+        for x in 0..size {
+            let (fx, fy) = (x as f64 + 0.5, y as f64 + 0.5);
+            let color = match style {
+                StreamDeckStyle::Bar => {
+                    let (left, right) = (s * 0.25, s * 0.75);
+                    let top = s * 0.9 - level * s * 0.8;
+                    if fx >= left && fx < right && fy >= top && fy < s * 0.9 {
+                        level_color((s * 0.9 - fy) / (s * 0.8))
+                    } else {
+                        SD_BACKGROUND
+                    }
+                }
+                StreamDeckStyle::Needle => {
+                    // pivot below the middle, the scale spans +-50 degrees
+                    let (px, py) = (s / 2.0, s * 0.9);
+                    let radius = s * 0.75;
+                    let (dx, dy) = (fx - px, py - fy);
+                    let dist = dx.hypot(dy);
+                    let angle = dx.atan2(dy).to_degrees();
+                    let needle = (level * 100.0 - 50.0).to_radians();
+                    let (nx, ny) = (needle.sin(), needle.cos());
+                    // distance from the needle line, only in front of the pivot
+                    let along = dx * nx + dy * ny;
+                    let across = (dx * ny - dy * nx).abs();
+                    if along > 0.0 && along < radius * 1.05 && across < s / 48.0 + 0.5 {
+                        SD_NEEDLE
+                    } else if (dist - radius).abs() < s / 40.0 + 0.5 && angle.abs() <= 50.0 {
+                        if angle > 30.0 {
+                            level_color(1.0)
+                        } else {
+                            SD_SCALE
+                        }
+                    } else {
+                        SD_BACKGROUND
+                    }
+                }
+            };
+            let i = ((size - 1 - y) * size + (size - 1 - x)) * 3;
+            img[i..i + 3].copy_from_slice(&color);
+        }
+    }
+    img
+}
+
+// EOF
@@ -0,0 +1,148 @@
+// sys.rs
+
+// Safe wrappers of the few libc calls std does not wrap for us
+#[cfg(target_os = "linux")]
+use std::os::fd::{FromRawFd, OwnedFd};
+#[cfg(target_os = "linux")]
+use std::os::raw::c_ulong;
+use std::os::raw::{c_int, c_void};
+use std::{
+    ffi::{CStr, CString},
+    io, mem,
+    os::fd::AsRawFd,
+};
+
+#[cfg(target_os = "linux")]
+pub(crate) use libc::{AF_CAN, AF_INET, SOCK_DGRAM, SOCK_RAW};
+// the numbers of SIGUSR1 and SIGUSR2 differ between the kernels and architectures
+pub(crate) use libc::{SIGHUP, SIGUSR1, SIGUSR2};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct LocalTime {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub min: u32,
+    pub sec: u32,
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn sys_socket(domain: c_int, ty: c_int, protocol: c_int) -> io::Result<OwnedFd> {
+    let fd = unsafe { libc::socket(domain, ty, protocol) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+// bind to a raw sockaddr of any family
+#[cfg(target_os = "linux")]
+pub(crate) fn sys_bind<T>(fd: c_int, addr: &T) -> io::Result<()> {
+    let len = mem::size_of::<T>() as libc::socklen_t;
+    if unsafe { libc::bind(fd, addr as *const T as *const libc::sockaddr, len) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// true if the file has something to read within timeout_ms
+pub(crate) fn sys_poll_read(file: &impl AsRawFd, timeout_ms: c_int) -> io::Result<bool> {
+    let mut pfd = libc::pollfd {
+        fd: file.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    match unsafe { libc::poll(&mut pfd, 1, timeout_ms) } {
+        n if n < 0 => Err(io::Error::last_os_error()),
+        0 => Ok(false),
+        _ => Ok(true),
+    }
+}
+
+// the handler may only touch atomics
+pub(crate) fn sys_signal(signum: c_int, handler: extern "C" fn(c_int)) -> io::Result<()> {
+    if unsafe { libc::signal(signum, handler as libc::sighandler_t) } == libc::SIG_ERR {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub(crate) fn sys_getuid() -> u32 {
+    unsafe { libc::getuid() }
+}
+
+pub(crate) fn sys_mkfifo(path: &str, mode: u32) -> io::Result<()> {
+    let path = CString::new(path).map_err(io::Error::other)?;
+    if unsafe { libc::mkfifo(path.as_ptr(), mode as libc::mode_t) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// broken down local time of a unix timestamp
+pub(crate) fn sys_localtime(secs: i64) -> LocalTime {
+    let t = secs as libc::time_t;
+    let mut tm: libc::tm = unsafe { mem::zeroed() };
+    tm.tm_mday = 1;
+    tm.tm_year = 70;
+    unsafe { libc::localtime_r(&t, &mut tm) };
+    LocalTime {
+        year: tm.tm_year + 1900,
+        month: tm.tm_mon as u32 + 1,
+        day: tm.tm_mday as u32,
+        hour: tm.tm_hour as u32,
+        min: tm.tm_min as u32,
+        sec: tm.tm_sec as u32,
+    }
+}
+
+// ioctl with a pointer argument
+#[cfg(target_os = "linux")]
+pub(crate) fn sys_ioctl<T>(fd: c_int, request: c_ulong, arg: *mut T) -> io::Result<c_int> {
+    let ret = unsafe { libc::ioctl(fd, request as _, arg as *mut c_void) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ret)
+}
+
+// ioctl with a plain integer argument
+#[cfg(target_os = "linux")]
+pub(crate) fn sys_ioctl_int(fd: c_int, request: c_ulong, arg: c_ulong) -> io::Result<c_int> {
+    let ret = unsafe { libc::ioctl(fd, request as _, arg) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ret)
+}
+
+fn dl_error() -> io::Error {
+    let msg = unsafe { libc::dlerror() };
+    match msg.is_null() {
+        true => io::Error::other("unknown dynamic loader error"),
+        false => io::Error::other(unsafe { CStr::from_ptr(msg) }.to_string_lossy()),
+    }
+}
+
+pub(crate) fn sys_dlopen(path: &str) -> io::Result<*mut c_void> {
+    let path = CString::new(path).map_err(io::Error::other)?;
+    let handle = unsafe { libc::dlopen(path.as_ptr(), libc::RTLD_NOW) };
+    if handle.is_null() {
+        return Err(dl_error());
+    }
+    Ok(handle)
+}
+
+// None if the library has no such symbol
+pub(crate) fn sys_dlsym(handle: *mut c_void, symbol: &str) -> Option<*mut c_void> {
+    let symbol = CString::new(symbol).ok()?;
+    let sym = unsafe { libc::dlsym(handle, symbol.as_ptr()) };
+    (!sym.is_null()).then_some(sym)
+}
+
+pub(crate) fn sys_dlclose(handle: *mut c_void) {
+    unsafe { libc::dlclose(handle) };
+}
+
+// EOF
@@ -0,0 +1,101 @@
+// sys_windows.rs
+
+// What sys.rs does on the unixes, as far as the rest of the crate needs it on Windows
+use std::os::raw::{c_int, c_void};
+use std::{ffi::CString, io, mem, os::windows::io::AsRawHandle, ptr};
+
+use windows_sys::Win32::Devices::Communication::{SetCommTimeouts, COMMTIMEOUTS};
+use windows_sys::Win32::Foundation::{FreeLibrary, FILETIME, SYSTEMTIME};
+use windows_sys::Win32::Storage::FileSystem::QueryDosDeviceW;
+use windows_sys::Win32::System::LibraryLoader::{GetProcAddress, LoadLibraryW};
+use windows_sys::Win32::System::Time::{FileTimeToSystemTime, SystemTimeToTzSpecificLocalTime};
+
+const MAXDWORD: u32 = u32::MAX;
+// 100ns intervals from 1601 to 1970
+const UNIX_EPOCH_FILETIME: i64 = 116_444_736_000_000_000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct LocalTime {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub min: u32,
+    pub sec: u32,
+}
+
+// A serial port cannot be polled, instead the next read returns what is
+// there or waits at most timeout_ms and then reads nothing
+pub(crate) fn sys_poll_read(file: &impl AsRawHandle, timeout_ms: c_int) -> io::Result<bool> {
+    let timeouts = COMMTIMEOUTS {
+        ReadIntervalTimeout: MAXDWORD,
+        ReadTotalTimeoutMultiplier: MAXDWORD,
+        ReadTotalTimeoutConstant: timeout_ms.max(1) as u32,
+        WriteTotalTimeoutMultiplier: 0,
+        WriteTotalTimeoutConstant: 0,
+    };
+    if unsafe { SetCommTimeouts(file.as_raw_handle(), &timeouts) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(true)
+}
+
+// broken down local time of a unix timestamp
+pub(crate) fn sys_localtime(secs: i64) -> LocalTime {
+    let ticks = (secs * 10_000_000 + UNIX_EPOCH_FILETIME) as u64;
+    let ft = FILETIME {
+        dwLowDateTime: ticks as u32,
+        dwHighDateTime: (ticks >> 32) as u32,
+    };
+    let (mut utc, mut st): (SYSTEMTIME, SYSTEMTIME) = unsafe { (mem::zeroed(), mem::zeroed()) };
+    unsafe {
+        FileTimeToSystemTime(&ft, &mut utc);
+        SystemTimeToTzSpecificLocalTime(ptr::null(), &utc, &mut st);
+    }
+    LocalTime {
+        year: st.wYear as i32,
+        month: st.wMonth as u32,
+        day: st.wDay as u32,
+        hour: st.wHour as u32,
+        min: st.wMinute as u32,
+        sec: st.wSecond as u32,
+    }
+}
+
+// the COM ports in the DOS device names, e.g. COM3, by number
+pub(crate) fn sys_com_ports() -> Vec<String> {
+    let mut buf = vec![0u16; 1 << 16];
+    let n = unsafe { QueryDosDeviceW(ptr::null(), buf.as_mut_ptr(), buf.len() as u32) };
+    let mut ports = buf[..n as usize]
+        .split(|c| *c == 0)
+        .map(String::from_utf16_lossy)
+        .filter_map(|name| {
+            let num = name.strip_prefix("COM")?.parse::<u32>().ok()?;
+            Some((num, name))
+        })
+        .collect::<Vec<_>>();
+    ports.sort();
+    ports.into_iter().map(|(_, name)| name).collect()
+}
+
+pub(crate) fn sys_dlopen(path: &str) -> io::Result<*mut c_void> {
+    let path = path.encode_utf16().chain([0]).collect::<Vec<_>>();
+    let handle = unsafe { LoadLibraryW(path.as_ptr()) };
+    if handle.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(handle)
+}
+
+// None if the library has no such symbol
+pub(crate) fn sys_dlsym(handle: *mut c_void, symbol: &str) -> Option<*mut c_void> {
+    let symbol = CString::new(symbol).ok()?;
+    let sym = unsafe { GetProcAddress(handle, symbol.as_ptr() as *const u8) }?;
+    Some(sym as *mut c_void)
+}
+
+pub(crate) fn sys_dlclose(handle: *mut c_void) {
+    unsafe { FreeLibrary(handle) };
+}
+
+// EOF
@@ -0,0 +1,256 @@
+// systemd.rs
+
+use std::os::raw::{c_int, c_void};
+use std::sync::{Arc, Mutex};
+use std::{collections::VecDeque, io, mem, process::Command, ptr, thread, time};
+
+use anyhow::{anyhow, bail};
+
+use crate::*;
+
+// Number of failed systemd units, read from the manager over D-Bus
+// with busctl. Polled in a background thread.
+#[derive(Debug)]
+pub struct FailedUnits {
+    count: Arc<Mutex<Option<u32>>>,
+}
+
+impl FailedUnits {
+    pub fn new(interval: time::Duration) -> anyhow::Result<Self> {
+        // fail early if systemd cannot be reached at all
+        let count = Arc::new(Mutex::new(Some(Self::query()?)));
+        let ret = Self {
+            count: count.clone(),
+        };
+
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            if Arc::strong_count(&count) == 1 {
+                return;
+            }
+            let res = Self::query();
+            match &res {
+                Ok(n) => trace!("Failed units: {n}"),
+                Err(e) => {
+                    info!("Failed units query failed: {e}");
+                    count_error("failed_units");
+                }
+            }
+            *count.lock().unwrap() = res.ok();
+        });
+        Ok(ret)
+    }
+
+    // None if the latest query failed
+    pub fn count(&self) -> Option<u32> {
+        *self.count.lock().unwrap()
+    }
+
+    // Example output: "u 2"
+    fn query() -> anyhow::Result<u32> {
+        let out = Command::new("busctl")
+            .args([
+                "get-property",
+                "org.freedesktop.systemd1",
+                "/org/freedesktop/systemd1",
+                "org.freedesktop.systemd1.Manager",
+                "NFailedUnits",
+            ])
+            .output()?;
+        if !out.status.success() {
+            bail!("busctl exited with {}", out.status);
+        }
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        let n = stdout
+            .split_ascii_whitespace()
+            .nth(1)
+            .ok_or_else(|| anyhow!("Unexpected busctl output: {stdout}"))?;
+        Ok(n.parse::<u32>()?)
+    }
+}
+
+// an unknown count is infinite and pegs the needle
+impl StatSource for FailedUnits {
+    fn sample(&mut self) -> anyhow::Result<f64> {
+        Ok(self.count().map_or(f64::INFINITY, |n| n as f64))
+    }
+    fn name(&self) -> &'static str {
+        "failed_units"
+    }
+    fn unit(&self) -> &'static str {
+        ""
+    }
+}
+
+type JournalOpenFn = unsafe extern "C" fn(ret: *mut *mut c_void, flags: c_int) -> c_int;
+type JournalMatchFn =
+    unsafe extern "C" fn(j: *mut c_void, data: *const c_void, size: usize) -> c_int;
+type JournalFn = unsafe extern "C" fn(j: *mut c_void) -> c_int;
+type JournalWaitFn = unsafe extern "C" fn(j: *mut c_void, timeout_usec: u64) -> c_int;
+type JournalCloseFn = unsafe extern "C" fn(j: *mut c_void);
+
+const LIBSYSTEMD: &str = "libsystemd.so.0";
+const SD_JOURNAL_LOCAL_ONLY: c_int = 1;
+const JOURNAL_WINDOW: time::Duration = time::Duration::from_secs(60);
+// how often the thread looks whether its source is still there
+const JOURNAL_WAIT: time::Duration = time::Duration::from_secs(1);
+
+// Rate of journal entries at or above the given priority (0=emerg .. 7=debug),
+// followed with the sd-journal API in a background thread. libsystemd is loaded
+// at runtime, without it or the journal the source fails to start.
+#[derive(Debug)]
+pub struct JournalRate {
+    events: Arc<Mutex<VecDeque<time::Instant>>>,
+}
+
+impl JournalRate {
+    pub fn new(priority: u8) -> anyhow::Result<Self> {
+        let mut journal = SdJournal::open(priority)?;
+        let events = Arc::new(Mutex::new(VecDeque::new()));
+        let ret = Self {
+            events: events.clone(),
+        };
+
+        thread::spawn(move || {
+            while Arc::strong_count(&events) > 1 {
+                match journal.follow() {
+                    Ok(0) => {}
+                    Ok(n) => {
+                        let mut events = events.lock().unwrap();
+                        let now = time::Instant::now();
+                        events.extend(std::iter::repeat_n(now, n));
+                        Self::expire(&mut events);
+                    }
+                    Err(e) => {
+                        error!("Journal: {e}");
+                        count_error("journal");
+                        thread::sleep(time::Duration::from_secs(5));
+                    }
+                }
+            }
+        });
+        Ok(ret)
+    }
+
+    // entries during the last minute
+    pub fn per_minute(&self) -> usize {
+        let mut events = self.events.lock().unwrap();
+        Self::expire(&mut events);
+        events.len()
+    }
+
+    fn expire(events: &mut VecDeque<time::Instant>) {
+        while events
+            .front()
+            .is_some_and(|ts| ts.elapsed() > JOURNAL_WINDOW)
+        {
+            events.pop_front();
+        }
+    }
+}
+
+// an open journal with the calls of libsystemd it needs, see sd-journal(3)
+struct SdJournal {
+    handle: *mut c_void,
+    j: *mut c_void,
+    next: JournalFn,
+    wait: JournalWaitFn,
+    close: JournalCloseFn,
+}
+
+// only ever used by one thread at a time
+unsafe impl Send for SdJournal {}
+
+impl SdJournal {
+    // positioned at the end, only the new entries of the priorities are seen
+    fn open(priority: u8) -> anyhow::Result<Self> {
+        let handle = sys::sys_dlopen(LIBSYSTEMD).map_err(|e| anyhow!("Journal: {e}"))?;
+        let sym = |name: &str| {
+            sys::sys_dlsym(handle, name).ok_or_else(|| anyhow!("{LIBSYSTEMD} has no {name}"))
+        };
+        let syms = (|| {
+            Ok::<_, anyhow::Error>((
+                sym("sd_journal_open")?,
+                sym("sd_journal_add_match")?,
+                sym("sd_journal_seek_tail")?,
+                sym("sd_journal_previous")?,
+                sym("sd_journal_next")?,
+                sym("sd_journal_wait")?,
+                sym("sd_journal_close")?,
+            ))
+        })();
+        let (open, add_match, seek_tail, previous, next, wait, close) = match syms {
+            Ok(syms) => syms,
+            Err(e) => {
+                sys::sys_dlclose(handle);
+                return Err(e);
+            }
+        };
+        // SAFETY: the signatures are those of sd-journal(3)
+        let (open, add_match, seek_tail, previous) = unsafe {
+            (
+                mem::transmute::<*mut c_void, JournalOpenFn>(open),
+                mem::transmute::<*mut c_void, JournalMatchFn>(add_match),
+                mem::transmute::<*mut c_void, JournalFn>(seek_tail),
+                mem::transmute::<*mut c_void, JournalFn>(previous),
+            )
+        };
+        let mut journal = Self {
+            handle,
+            j: ptr::null_mut(),
+            next: unsafe { mem::transmute::<*mut c_void, JournalFn>(next) },
+            wait: unsafe { mem::transmute::<*mut c_void, JournalWaitFn>(wait) },
+            close: unsafe { mem::transmute::<*mut c_void, JournalCloseFn>(close) },
+        };
+        check(unsafe { open(&mut journal.j, SD_JOURNAL_LOCAL_ONLY) })?;
+        // matches of the same field are alternatives
+        for p in 0..=priority.min(7) {
+            let m = format!("PRIORITY={p}");
+            check(unsafe { add_match(journal.j, m.as_ptr() as *const c_void, m.len()) })?;
+        }
+        check(unsafe { seek_tail(journal.j) })?;
+        check(unsafe { previous(journal.j) })?;
+        Ok(journal)
+    }
+
+    // waits a while for new entries, then counts them
+    fn follow(&mut self) -> anyhow::Result<usize> {
+        check(unsafe { (self.wait)(self.j, JOURNAL_WAIT.as_micros() as u64) })?;
+        let mut n = 0;
+        while check(unsafe { (self.next)(self.j) })? > 0 {
+            n += 1;
+        }
+        Ok(n)
+    }
+}
+
+impl Drop for SdJournal {
+    fn drop(&mut self) {
+        if !self.j.is_null() {
+            unsafe { (self.close)(self.j) };
+        }
+        sys::sys_dlclose(self.handle);
+    }
+}
+
+// the calls return a negative errno on failure
+fn check(ret: c_int) -> io::Result<c_int> {
+    match ret {
+        r if r < 0 => Err(io::Error::from_raw_os_error(-r)),
+        r => Ok(r),
+    }
+}
+
+impl StatSource for JournalRate {
+    fn sample(&mut self) -> anyhow::Result<f64> {
+        Ok(self.per_minute() as f64)
+    }
+    fn name(&self) -> &'static str {
+        "journal"
+    }
+    fn unit(&self) -> &'static str {
+        "/min"
+    }
+}
+
+// EOF
@@ -0,0 +1,46 @@
+// ticker.rs
+
+use std::{thread, time};
+
+use crate::*;
+
+// Wakes up at fixed deadlines, one period apart from the start, so the time
+// spent sampling does not add up to drift. A loop that fell behind by more
+// than a period skips the missed ticks instead of sampling in a burst.
+#[derive(Debug)]
+pub struct Ticker {
+    period: time::Duration,
+    next: time::Instant,
+}
+
+impl Ticker {
+    pub fn new(period: time::Duration) -> Self {
+        Self {
+            period,
+            next: time::Instant::now() + period,
+        }
+    }
+
+    pub fn period(&self) -> time::Duration {
+        self.period
+    }
+
+    // sleeps until the next deadline, returns at once when it has passed
+    pub fn wait(&mut self) {
+        let now = time::Instant::now();
+        match self.next.checked_duration_since(now) {
+            Some(wait) => thread::sleep(wait),
+            None => {
+                let behind = now - self.next;
+                let missed = (behind.as_nanos() / self.period.as_nanos().max(1)) as u32;
+                if missed > 0 {
+                    debug!("Sampling fell {missed} tick(s) behind");
+                    self.next += self.period * missed;
+                }
+            }
+        }
+        self.next += self.period;
+    }
+}
+
+// EOF
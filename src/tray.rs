@@ -0,0 +1,388 @@
+// tray.rs
+
+use std::sync::{Arc, Mutex};
+use std::{process, sync::mpsc, thread, time};
+
+use crate::dbus::*;
+use crate::*;
+
+const ITEM_PATH: &str = "/StatusNotifierItem";
+const MENU_PATH: &str = "/MenuBar";
+const ITEM_IFACE: &str = "org.kde.StatusNotifierItem";
+const MENU_IFACE: &str = "com.canonical.dbusmenu";
+const PROPS_IFACE: &str = "org.freedesktop.DBus.Properties";
+const TRAY_RECONNECT: time::Duration = time::Duration::from_secs(5);
+const TRAY_INTERVAL: time::Duration = time::Duration::from_secs(1);
+const ICON_SIZE: usize = 22;
+
+const MENU_PAUSE: i32 = 1;
+const MENU_HELLO: i32 = 2;
+
+const INTROSPECT_XML: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="org.kde.StatusNotifierItem">
+    <method name="Activate"><arg type="i" direction="in"/><arg type="i" direction="in"/></method>
+    <method name="SecondaryActivate"><arg type="i" direction="in"/><arg type="i" direction="in"/></method>
+    <signal name="NewIcon"/>
+    <signal name="NewToolTip"/>
+  </interface>
+  <interface name="com.canonical.dbusmenu">
+    <method name="GetLayout"><arg type="i" direction="in"/><arg type="i" direction="in"/><arg type="as" direction="in"/><arg type="u" direction="out"/><arg type="(ia{sv}av)" direction="out"/></method>
+    <method name="GetGroupProperties"><arg type="ai" direction="in"/><arg type="as" direction="in"/><arg type="a(ia{sv})" direction="out"/></method>
+    <method name="Event"><arg type="i" direction="in"/><arg type="s" direction="in"/><arg type="v" direction="in"/><arg type="u" direction="in"/></method>
+    <method name="AboutToShow"><arg type="i" direction="in"/><arg type="b" direction="out"/></method>
+    <signal name="LayoutUpdated"><arg type="u"/><arg type="i"/></signal>
+  </interface>
+  <interface name="org.freedesktop.DBus.Properties">
+    <method name="Get"><arg type="s" direction="in"/><arg type="s" direction="in"/><arg type="v" direction="out"/></method>
+    <method name="GetAll"><arg type="s" direction="in"/><arg type="a{sv}" direction="out"/></method>
+  </interface>
+</node>
+"#;
+
+#[derive(Default)]
+struct TrayState {
+    frame: Frame,
+    // bumped whenever the menu changes
+    revision: u32,
+    sender: Option<DbusSender>,
+}
+
+// A StatusNotifierItem on the session bus, the icon shows the channels as
+// little bars. A click pauses the meters, a middle click sweeps them, and
+// the menu has both.
+pub struct TraySink;
+
+impl TraySink {
+    pub fn spawn(channels: Vec<u8>) -> anyhow::Result<ThreadedSink> {
+        let state = Arc::new(Mutex::new(TrayState::default()));
+        let name = format!("org.kde.StatusNotifierItem-{}-1", process::id());
+        // fail early without a bus or a tray, later errors only reconnect
+        let mut conn = Self::connect(&name)?;
+        info!("Showing the tray icon as {name}");
+
+        let serving = state.clone();
+        let tray_channels = channels.clone();
+        thread::spawn(move || loop {
+            serving.lock().unwrap().sender = Some(conn.sender());
+            if let Err(e) = Self::serve(&mut conn, &serving, &tray_channels) {
+                error!("Tray: {e}");
+                count_error("tray");
+            }
+            serving.lock().unwrap().sender = None;
+            loop {
+                thread::sleep(TRAY_RECONNECT);
+                match Self::connect(&name) {
+                    Ok(c) => {
+                        conn = c;
+                        break;
+                    }
+                    Err(e) => debug!("Tray reconnect: {e}"),
+                }
+            }
+        });
+
+        spawn_sink("tray", move |rx| Self::run(channels, state, rx))
+    }
+
+    fn connect(name: &str) -> anyhow::Result<DbusConn> {
+        let mut conn = DbusConn::connect(DbusBus::Session, name)?;
+        let mut body = DbusWriter::default();
+        body.string(name);
+        conn.call(
+            "org.kde.StatusNotifierWatcher",
+            "/StatusNotifierWatcher",
+            "org.kde.StatusNotifierWatcher",
+            "RegisterStatusNotifierItem",
+            "s",
+            &body.buf,
+        )?;
+        Ok(conn)
+    }
+
+    // the host fetches the icon and the tooltip again on these signals
+    fn run(channels: Vec<u8>, state: Arc<Mutex<TrayState>>, rx: mpsc::Receiver<Frame>) {
+        let mut last_icon = Vec::new();
+        let mut next_update = time::Instant::now();
+
+        while let Ok(frame) = rx.recv() {
+            let mut st = state.lock().unwrap();
+            st.frame = frame;
+            if time::Instant::now() < next_update {
+                continue;
+            }
+            next_update = time::Instant::now() + TRAY_INTERVAL;
+            let Some(sender) = &st.sender else {
+                continue;
+            };
+            let icon = pixmap(&st.frame, &channels);
+            let mut res = sender.signal(ITEM_PATH, ITEM_IFACE, "NewToolTip", "", &[]);
+            if icon != last_icon {
+                res = res.and_then(|_| sender.signal(ITEM_PATH, ITEM_IFACE, "NewIcon", "", &[]));
+                last_icon = icon;
+            }
+            if let Err(e) = res {
+                debug!("Tray: {e}");
+                count_error("tray");
+            }
+        }
+    }
+
+    fn serve(conn: &mut DbusConn, state: &Mutex<TrayState>, channels: &[u8]) -> anyhow::Result<()> {
+        loop {
+            let (msg, _) = conn.recv()?;
+            if msg.msg_type != MSG_METHOD_CALL {
+                continue;
+            }
+            let mut args = DbusReader::new(&msg.body);
+            let mut out = DbusWriter::default();
+            let mut menu_changed = false;
+            let res: DbusReply = match (
+                msg.path.as_str(),
+                msg.interface.as_str(),
+                msg.member.as_str(),
+            ) {
+                (_, "org.freedesktop.DBus.Introspectable", "Introspect") => {
+                    out.string(INTROSPECT_XML);
+                    Ok("s")
+                }
+                (ITEM_PATH, PROPS_IFACE, "Get") | (MENU_PATH, PROPS_IFACE, "Get") => {
+                    let (_iface, prop) = (args.string()?, args.string()?);
+                    let st = state.lock().unwrap();
+                    match property(&mut out, &msg.path, &prop, &st, channels) {
+                        true => Ok("v"),
+                        false => Err((ERR_INVALID_ARGS, format!("No such property: {prop}"))),
+                    }
+                }
+                (ITEM_PATH, PROPS_IFACE, "GetAll") | (MENU_PATH, PROPS_IFACE, "GetAll") => {
+                    let st = state.lock().unwrap();
+                    let names: &[&str] = match msg.path == ITEM_PATH {
+                        true => &[
+                            "Category",
+                            "Id",
+                            "Title",
+                            "Status",
+                            "IconName",
+                            "IconPixmap",
+                            "ToolTip",
+                            "ItemIsMenu",
+                            "Menu",
+                        ],
+                        false => &["Version", "TextDirection", "Status", "IconThemePath"],
+                    };
+                    out.array(8, |a| {
+                        for name in names {
+                            a.align(8);
+                            a.string(name);
+                            property(a, &msg.path, name, &st, channels);
+                        }
+                    });
+                    Ok("a{sv}")
+                }
+                (ITEM_PATH, ITEM_IFACE, "Activate") => {
+                    toggle_pause();
+                    menu_changed = true;
+                    Ok("")
+                }
+                (ITEM_PATH, ITEM_IFACE, "SecondaryActivate") => {
+                    request_hello();
+                    Ok("")
+                }
+                (ITEM_PATH, ITEM_IFACE, "ContextMenu") | (ITEM_PATH, ITEM_IFACE, "Scroll") => {
+                    Ok("")
+                }
+                (MENU_PATH, MENU_IFACE, "GetLayout") => {
+                    out.u32(state.lock().unwrap().revision);
+                    out.align(8);
+                    out.i32(0);
+                    write_props(&mut out, &[("children-display", Prop::Str("submenu"))]);
+                    out.array(1, |a| {
+                        for id in [MENU_PAUSE, MENU_HELLO] {
+                            a.variant("(ia{sv}av)", |a| {
+                                a.align(8);
+                                a.i32(id);
+                                write_props(a, &menu_props(id));
+                                a.array(1, |_| {});
+                            });
+                        }
+                    });
+                    Ok("u(ia{sv}av)")
+                }
+                (MENU_PATH, MENU_IFACE, "GetGroupProperties") => {
+                    let mut ids = args.array_i32()?;
+                    ids.retain(|id| [MENU_PAUSE, MENU_HELLO].contains(id));
+                    if ids.is_empty() {
+                        ids = vec![MENU_PAUSE, MENU_HELLO];
+                    }
+                    out.array(8, |a| {
+                        for id in ids {
+                            a.align(8);
+                            a.i32(id);
+                            write_props(a, &menu_props(id));
+                        }
+                    });
+                    Ok("a(ia{sv})")
+                }
+                (MENU_PATH, MENU_IFACE, "Event") => {
+                    let (id, event) = (args.i32()?, args.string()?);
+                    if event == "clicked" {
+                        match id {
+                            MENU_PAUSE => {
+                                toggle_pause();
+                                menu_changed = true;
+                            }
+                            MENU_HELLO => request_hello(),
+                            _ => {}
+                        }
+                    }
+                    Ok("")
+                }
+                (MENU_PATH, MENU_IFACE, "AboutToShow") => {
+                    out.boolean(false);
+                    Ok("b")
+                }
+                _ => Err((
+                    ERR_UNKNOWN_METHOD,
+                    format!(
+                        "Unknown method {}.{} on {}",
+                        msg.interface, msg.member, msg.path
+                    ),
+                )),
+            };
+            conn.reply(&msg, res, &out.buf)?;
+
+            if menu_changed {
+                let mut st = state.lock().unwrap();
+                st.revision += 1;
+                let mut body = DbusWriter::default();
+                body.u32(st.revision);
+                body.i32(0);
+                let sender = conn.sender();
+                sender.signal(MENU_PATH, MENU_IFACE, "LayoutUpdated", "ui", &body.buf)?;
+                sender.signal(ITEM_PATH, ITEM_IFACE, "NewIcon", "", &[])?;
+            }
+        }
+    }
+}
+
+fn toggle_pause() {
+    let paused = !is_paused();
+    info!("Tray: meters {}", if paused { "paused" } else { "resumed" });
+    set_paused(paused);
+}
+
+enum Prop {
+    Str(&'static str),
+    Int(i32),
+}
+
+fn menu_props(id: i32) -> Vec<(&'static str, Prop)> {
+    match id {
+        MENU_PAUSE => vec![
+            ("label", Prop::Str("Pause meters")),
+            ("toggle-type", Prop::Str("checkmark")),
+            ("toggle-state", Prop::Int(is_paused() as i32)),
+        ],
+        _ => vec![("label", Prop::Str("Sweep meters"))],
+    }
+}
+
+fn write_props(w: &mut DbusWriter, props: &[(&str, Prop)]) {
+    w.array(8, |a| {
+        for (key, value) in props {
+            a.align(8);
+            a.string(key);
+            match value {
+                Prop::Str(s) => a.variant("s", |a| a.string(s)),
+                Prop::Int(i) => a.variant("i", |a| a.i32(*i)),
+            }
+        }
+    });
+}
+
+// writes the property as a variant, false if there is no such property
+fn property(w: &mut DbusWriter, path: &str, name: &str, st: &TrayState, channels: &[u8]) -> bool {
+    let string = |w: &mut DbusWriter, s: &str| w.variant("s", |w| w.string(s));
+    match (path, name) {
+        (ITEM_PATH, "Category") => string(w, "Hardware"),
+        (ITEM_PATH, "Id") => string(w, "perf-vumeter"),
+        (ITEM_PATH, "Title") => string(w, "perf-vumeter"),
+        (ITEM_PATH, "Status") => string(w, "Active"),
+        (ITEM_PATH, "IconName") => string(w, ""),
+        (ITEM_PATH, "IconPixmap") => w.variant("a(iiay)", |w| {
+            let icon = pixmap(&st.frame, channels);
+            w.array(8, |a| {
+                a.align(8);
+                a.i32(ICON_SIZE as i32);
+                a.i32(ICON_SIZE as i32);
+                a.array(1, |a| a.buf.extend(&icon));
+            });
+        }),
+        (ITEM_PATH, "ToolTip") => w.variant("(sa(iiay)ss)", |w| {
+            w.align(8);
+            w.string("");
+            w.array(8, |_| {});
+            w.string(match is_paused() {
+                true => "perf-vumeter (paused)",
+                false => "perf-vumeter",
+            });
+            w.string(&tooltip(&st.frame, channels));
+        }),
+        (ITEM_PATH, "ItemIsMenu") => w.variant("b", |w| w.boolean(false)),
+        (ITEM_PATH, "Menu") => w.variant("o", |w| w.string(MENU_PATH)),
+        (MENU_PATH, "Version") => w.variant("u", |w| w.u32(3)),
+        (MENU_PATH, "TextDirection") => string(w, "ltr"),
+        (MENU_PATH, "Status") => string(w, "normal"),
+        (MENU_PATH, "IconThemePath") => w.variant("as", |w| w.array(4, |_| {})),
+        _ => return false,
+    }
+    true
+}
+
+fn tooltip(frame: &Frame, channels: &[u8]) -> String {
+    channels
+        .iter()
+        .filter_map(|ch| frame.get(ch).map(|s| (ch, s)))
+        .map(|(ch, s)| match s.raw {
+            Some(raw) => format!("{ch} {}: {}", s.source, fmt_raw(s.source, raw)),
+            None => format!("{ch} {}: {:.0}", s.source, s.value.clamp(0.0, 255.0)),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// ARGB32 in network byte order, one bar per channel on a faint track,
+// grey while paused
+fn pixmap(frame: &Frame, channels: &[u8]) -> Vec<u8> {
+    let mut icon = vec![0u8; ICON_SIZE * ICON_SIZE * 4];
+    let n = channels.len().clamp(1, ICON_SIZE / 2);
+    let bar_w = (ICON_SIZE - (n - 1)) / n;
+    let x0 = (ICON_SIZE - (n * bar_w + n - 1)) / 2;
+    let paused = is_paused();
+
+    for (i, ch) in channels.iter().take(n).enumerate() {
+        let level = frame
+            .get(ch)
+            .map(|s| s.value.clamp(0.0, 255.0) / 255.0)
+            .unwrap_or(0.0);
+        let fill = (level * ICON_SIZE as f64).round() as usize;
+        let Rgb(r, g, b) = match paused {
+            true => Rgb(0x80, 0x80, 0x80),
+            false => Rgb::load(level),
+        };
+        for y in 0..ICON_SIZE {
+            let px = match ICON_SIZE - y <= fill {
+                true => [0xff, r, g, b],
+                false => [0x40, 0x80, 0x80, 0x80],
+            };
+            for x in x0 + i * (bar_w + 1)..x0 + i * (bar_w + 1) + bar_w {
+                let at = (y * ICON_SIZE + x) * 4;
+                icon[at..at + 4].copy_from_slice(&px);
+            }
+        }
+    }
+    icon
+}
+
+// EOF
@@ -0,0 +1,129 @@
+// tui.rs
+
+use std::{collections::BTreeMap, io, sync::mpsc, time};
+
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::Line,
+    widgets::{Gauge, Paragraph},
+    Terminal,
+};
+
+use crate::*;
+
+const TUI_FPS: u64 = 30;
+// fraction of the remaining distance the needle travels per redraw
+const TUI_EASING: f64 = 0.25;
+const TUI_RED_ZONE: f64 = 224.0;
+const TUI_YELLOW_ZONE: f64 = 160.0;
+
+// Draws every channel as an animated bar in the terminal, for running and
+// tuning without the hardware attached. Drawn with ratatui, log to stderr.
+pub struct TuiSink;
+
+#[derive(Debug)]
+struct TuiChannel {
+    sample: Sample,
+    shown: f64,
+}
+
+impl TuiSink {
+    pub fn spawn() -> anyhow::Result<ThreadedSink> {
+        let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+        terminal.clear()?;
+        spawn_sink("tui", move |rx| Self::run(terminal, rx))
+    }
+
+    fn run(mut terminal: Terminal<CrosstermBackend<io::Stdout>>, rx: mpsc::Receiver<Frame>) {
+        let frame_time = time::Duration::from_millis(1000 / TUI_FPS);
+        let title = format!(
+            "perf_vumeter v{} on {}",
+            env!("CARGO_PKG_VERSION"),
+            hostname()
+        );
+        let mut channels: BTreeMap<u8, TuiChannel> = BTreeMap::new();
+
+        loop {
+            match rx.recv_timeout(frame_time) {
+                Ok(frame) => {
+                    for (ch, sample) in frame {
+                        channels
+                            .entry(ch)
+                            .and_modify(|c| c.sample = sample)
+                            .or_insert(TuiChannel { sample, shown: 0.0 });
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => return,
+            }
+            for c in channels.values_mut() {
+                let target = c.sample.value.clamp(0.0, 255.0);
+                c.shown += (target - c.shown) * TUI_EASING;
+            }
+
+            if terminal
+                .draw(|f| Self::render(f, &title, &channels))
+                .is_err()
+            {
+                return;
+            }
+        }
+    }
+
+    // "ch  source          [bar] gauge raw"
+    fn render(f: &mut ratatui::Frame, title: &str, channels: &BTreeMap<u8, TuiChannel>) {
+        let [head, body] =
+            Layout::vertical([Constraint::Length(2), Constraint::Min(0)]).areas(f.area());
+        f.render_widget(
+            Paragraph::new(title).style(Style::default().add_modifier(Modifier::BOLD)),
+            head,
+        );
+        let rows = channels.len().min(body.height as usize);
+        // left below the bars, a hidden one would stay hidden after the exit
+        let cursor = (body.y + rows as u16).min(body.bottom().saturating_sub(1));
+        f.set_cursor_position((0, cursor));
+        for (i, (ch, c)) in channels.iter().take(rows).enumerate() {
+            let row = Rect {
+                y: body.y + i as u16,
+                height: 1,
+                ..body
+            };
+            let [label, bar, value] = Layout::horizontal([
+                Constraint::Length(21),
+                Constraint::Min(10),
+                Constraint::Length(14),
+            ])
+            .areas(row);
+            let source = match c.sample.source {
+                "" => "-",
+                s => s,
+            };
+            let color = match c.shown {
+                v if v >= TUI_RED_ZONE => Color::Red,
+                v if v >= TUI_YELLOW_ZONE => Color::Yellow,
+                _ => Color::Green,
+            };
+            let raw = c.sample.raw.map(fmt_si).unwrap_or_default();
+            f.render_widget(Line::raw(format!("{ch:>3}  {source:<14.14} [")), label);
+            f.render_widget(
+                Gauge::default()
+                    .gauge_style(Style::default().fg(color))
+                    .ratio(c.shown.clamp(0.0, 255.0) / 255.0)
+                    .label("")
+                    .use_unicode(true),
+                bar,
+            );
+            f.render_widget(
+                Line::raw(format!(
+                    "] {:>3} {raw:>8.8}",
+                    c.sample.value.clamp(0.0, 255.0) as u8
+                )),
+                value,
+            );
+        }
+    }
+}
+
+// EOF
@@ -0,0 +1,124 @@
+// vumeter.rs
+
+use std::time;
+
+use crate::*;
+
+// The metering engine for embedding in other programs: channels fed from
+// StatSources or set directly, through the same pipeline as the daemon's,
+// smoothed and written to a Sink, a meter or any of the other outputs.
+//
+//     let mut vu = VuMeter::new(SerialSink::open("/dev/ttyACM0", SerialConfig::default())?)
+//         .channel(1, CpuStats::new()?, 100.0)
+//         .smoothing(Ballistics { attack: 2000.0, release: 200.0, hold: 0.0 });
+//     loop {
+//         vu.set(2, my_gauge());
+//         vu.tick()?;
+//     }
+pub struct VuMeter {
+    meter: Meter,
+    pipeline: Pipeline,
+    pending: Frame,
+    ballistics: Ballistics,
+    quant: u8,
+    rate: u16,
+}
+
+impl VuMeter {
+    pub fn new<S: Sink + 'static>(sink: S) -> Self {
+        Self {
+            meter: Meter::new(MeterPort::default(), Box::new(sink)),
+            pipeline: Pipeline::new(),
+            pending: Frame::new(),
+            ballistics: Ballistics::default(),
+            quant: 1,
+            rate: 5,
+        }
+    }
+
+    // full_scale is the value of the source that gives a full scale gauge
+    pub fn channel<T: StatSource + 'static>(
+        mut self,
+        channel: u8,
+        source: T,
+        full_scale: f64,
+    ) -> Self {
+        let label = source.name().to_string();
+        self.pipeline.push(ChannelSource::with_source(
+            channel,
+            label,
+            Box::new(source),
+            full_scale,
+        ));
+        self
+    }
+
+    // from the gauge to the needle, e.g. Curve::Log(2.0) for network traffic
+    pub fn curve(mut self, channel: u8, curve: Curve) -> Self {
+        self.pipeline.curve(channel, curve);
+        self
+    }
+
+    // channels flashing above their thresholds
+    pub fn alerts(mut self, specs: &[(u8, AlertSpec)]) -> Self {
+        self.pipeline.alerts(specs);
+        self
+    }
+
+    pub fn smoothing(mut self, ballistics: Ballistics) -> Self {
+        self.ballistics = ballistics;
+        self
+    }
+
+    // only move the needles in steps of this many gauge units
+    pub fn quantize(mut self, step: u8) -> Self {
+        self.quant = step.max(1);
+        self
+    }
+
+    // ticks per second for run()
+    pub fn rate(mut self, rate: u16) -> Self {
+        self.rate = rate.max(1);
+        self
+    }
+
+    // a gauge 0-255 for the channel, shown on the next tick
+    pub fn set(&mut self, channel: u8, gauge: f64) {
+        self.pending
+            .insert(channel, Sample::new(gauge).source("set"));
+    }
+
+    // samples the channel sources, moves the needles towards them and the set
+    // gauges, and returns what was shown. A source that fails shows zero,
+    // only the meter failing is an error
+    pub fn tick(&mut self) -> anyhow::Result<Frame> {
+        let meter = &mut self.meter;
+        let frame = self
+            .pipeline
+            .tick(std::mem::take(&mut self.pending), |frame| {
+                meter.out.frame(frame)
+            });
+        for (channel, sample) in &frame {
+            self.meter
+                .set_vu(*channel, sample.value, self.quant, self.ballistics)?;
+        }
+        self.meter.flush()?;
+        Ok(frame)
+    }
+
+    // ticks until an error
+    pub fn run(&mut self) -> anyhow::Result<()> {
+        let mut ticker = Ticker::new(time::Duration::from_secs(1) / self.rate as u32);
+        loop {
+            ticker.wait();
+            self.tick()?;
+        }
+    }
+
+    // the hello sweep on a meter that shows it
+    pub fn hello(&mut self) -> anyhow::Result<()> {
+        self.meter.hello()
+    }
+}
+
+// EOF
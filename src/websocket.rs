@@ -0,0 +1,138 @@
+// websocket.rs
+
+use std::sync::{Arc, Mutex};
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    thread, time,
+};
+
+use anyhow::anyhow;
+
+use crate::*;
+
+const WS_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+// a client that cannot keep up gets dropped
+const WS_WRITE_TIMEOUT: time::Duration = time::Duration::from_millis(200);
+
+// Streams every frame as a JSON text message to all connected WebSocket clients,
+// for browser dashboards and overlays mirroring the meter.
+pub struct WebSocketSink;
+
+impl WebSocketSink {
+    pub fn spawn<S: AsRef<str>>(listen: S) -> anyhow::Result<ThreadedSink> {
+        let listener = TcpListener::bind(listen.as_ref())?;
+        info!("WebSocket server listening on {}", listen.as_ref());
+        let clients: Arc<Mutex<Vec<TcpStream>>> = Arc::new(Mutex::new(Vec::new()));
+
+        let accepted = clients.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let clients = accepted.clone();
+                // do not let a stalled handshake hold up the others
+                thread::spawn(move || {
+                    let peer = stream
+                        .peer_addr()
+                        .map(|a| a.to_string())
+                        .unwrap_or_default();
+                    match handshake(&stream) {
+                        Ok(()) => {
+                            info!("WebSocket client {peer} connected");
+                            clients.lock().unwrap().push(stream);
+                        }
+                        Err(e) => info!("WebSocket handshake with {peer} failed: {e}"),
+                    }
+                });
+            }
+        });
+
+        spawn_sink("websocket", move |rx| Self::run(clients, rx))
+    }
+
+    fn run(clients: Arc<Mutex<Vec<TcpStream>>>, rx: mpsc::Receiver<Frame>) {
+        while let Ok(frame) = rx.recv() {
+            let mut clients = clients.lock().unwrap();
+            if clients.is_empty() {
+                continue;
+            }
+            let msg = ws_text(&frame_json(&frame));
+            clients.retain_mut(|c| match c.write_all(&msg) {
+                Ok(()) => true,
+                Err(e) => {
+                    info!("WebSocket client dropped: {e}");
+                    false
+                }
+            });
+        }
+    }
+}
+
+fn handshake(stream: &TcpStream) -> anyhow::Result<()> {
+    stream.set_read_timeout(Some(time::Duration::from_secs(5)))?;
+    stream.set_write_timeout(Some(WS_WRITE_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+    let mut key = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("sec-websocket-key") {
+                key = Some(value.trim().to_string());
+            }
+        }
+    }
+    let key = key.ok_or_else(|| anyhow!("Not a WebSocket upgrade request"))?;
+    let accept = base64(&crypto::sha1(format!("{key}{WS_GUID}").as_bytes()));
+    write!(
+        &*stream,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {accept}\r\n\r\n"
+    )?;
+    Ok(())
+}
+
+// unmasked, unfragmented text frame
+fn ws_text(text: &str) -> Vec<u8> {
+    let len = text.len();
+    let mut msg = vec![0x81];
+    match len {
+        0..=125 => msg.push(len as u8),
+        126..=0xffff => {
+            msg.push(126);
+            msg.extend((len as u16).to_be_bytes());
+        }
+        _ => {
+            msg.push(127);
+            msg.extend((len as u64).to_be_bytes());
+        }
+    }
+    msg.extend(text.as_bytes());
+    msg
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let n = chunk.len();
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let v = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= n {
+                out.push(ALPHABET[(v >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+// EOF
@@ -0,0 +1,270 @@
+// windows.rs
+
+use std::{ptr, slice};
+
+use anyhow::bail;
+use windows_sys::Win32::System::Performance::{
+    PdhAddEnglishCounterW, PdhCloseQuery, PdhCollectQueryData, PdhGetFormattedCounterArrayW,
+    PdhOpenQueryW, PDH_FMT_COUNTERVALUE_ITEM_W, PDH_FMT_DOUBLE, PDH_MORE_DATA,
+};
+
+use crate::*;
+
+// The cpu, disk and net sources of Windows from the PDH performance counters,
+// behind the same StatSource as the /proc and /sys readers, see mapping.rs
+
+// One wildcard counter like \Processor(*)\% Processor Time, read as
+// (instance, value) pairs. Rate counters need two collections, the first
+// one is made here.
+struct PdhCounter {
+    path: String,
+    query: isize,
+    counter: isize,
+}
+
+impl PdhCounter {
+    fn new(path: &str) -> anyhow::Result<Self> {
+        let wide = path.encode_utf16().chain([0]).collect::<Vec<u16>>();
+        let mut query = 0;
+        let ret = unsafe { PdhOpenQueryW(ptr::null(), 0, &mut query) };
+        if ret != 0 {
+            bail!("PdhOpenQuery failed: {ret:#x}");
+        }
+        let mut counter = 0;
+        let ret = unsafe { PdhAddEnglishCounterW(query, wide.as_ptr(), 0, &mut counter) };
+        if ret != 0 {
+            unsafe { PdhCloseQuery(query) };
+            bail!("Counter {path}: {ret:#x}");
+        }
+        unsafe { PdhCollectQueryData(query) };
+        Ok(Self {
+            path: path.into(),
+            query,
+            counter,
+        })
+    }
+
+    fn values(&mut self) -> anyhow::Result<Vec<(String, f64)>> {
+        let ret = unsafe { PdhCollectQueryData(self.query) };
+        if ret != 0 {
+            bail!("Counter {}: {ret:#x}", self.path);
+        }
+        let (mut size, mut count) = (0u32, 0u32);
+        let ret = unsafe {
+            PdhGetFormattedCounterArrayW(
+                self.counter,
+                PDH_FMT_DOUBLE,
+                &mut size,
+                &mut count,
+                ptr::null_mut(),
+            )
+        };
+        if ret != PDH_MORE_DATA {
+            bail!("Counter {}: {ret:#x}", self.path);
+        }
+        // the instance names are stored after the items in the same buffer
+        let item_size = std::mem::size_of::<PDH_FMT_COUNTERVALUE_ITEM_W>();
+        let mut buf = vec![0u64; (size as usize).div_ceil(8)];
+        let items = buf.as_mut_ptr() as *mut PDH_FMT_COUNTERVALUE_ITEM_W;
+        let ret = unsafe {
+            PdhGetFormattedCounterArrayW(self.counter, PDH_FMT_DOUBLE, &mut size, &mut count, items)
+        };
+        if ret != 0 || count as usize * item_size > buf.len() * 8 {
+            bail!("Counter {}: {ret:#x}", self.path);
+        }
+        let items = unsafe { slice::from_raw_parts(items, count as usize) };
+        Ok(items
+            .iter()
+            .filter(|i| i.FmtValue.CStatus == 0)
+            .map(|i| {
+                (wide_str(i.szName), unsafe {
+                    i.FmtValue.Anonymous.doubleValue
+                })
+            })
+            .collect())
+    }
+}
+
+impl Drop for PdhCounter {
+    fn drop(&mut self) {
+        unsafe { PdhCloseQuery(self.query) };
+    }
+}
+
+fn wide_str(p: *const u16) -> String {
+    if p.is_null() {
+        return String::new();
+    }
+    let len = (0..).take_while(|i| unsafe { *p.add(*i) } != 0).count();
+    String::from_utf16_lossy(unsafe { slice::from_raw_parts(p, len) })
+}
+
+// busy percentage of each core like CpuStats, only the busy mode exists here
+pub struct WinCpuStats {
+    counter: PdhCounter,
+    n_cpu: usize,
+}
+
+impl WinCpuStats {
+    pub fn new(mode: CpuMode) -> anyhow::Result<Self> {
+        if mode != CpuMode::Busy {
+            bail!("CPU mode {mode:?} is not available on Windows");
+        }
+        let mut counter = PdhCounter::new(r"\Processor(*)\% Processor Time")?;
+        let n_cpu = counter
+            .values()?
+            .iter()
+            .filter(|(i, _)| i != "_Total")
+            .count();
+        Ok(Self { counter, n_cpu })
+    }
+    pub fn n_cpu(&self) -> usize {
+        self.n_cpu
+    }
+    // like CpuStats::cpurates(), [0] is the total and the cores follow busiest first
+    pub fn cpurates(&mut self) -> anyhow::Result<Vec<f64>> {
+        let values = self.counter.values()?;
+        let mut rates = vec![0.0];
+        for (instance, v) in values {
+            match instance.as_str() {
+                "_Total" => rates[0] = v,
+                _ => rates.push(v),
+            }
+        }
+        rates[1..].sort_by(|a, b| b.total_cmp(a));
+        Ok(rates)
+    }
+}
+
+impl StatSource for WinCpuStats {
+    fn sample(&mut self) -> anyhow::Result<f64> {
+        let cpu_rates = self.cpurates()?;
+        let load = CpuStats::weighted_load(&cpu_rates, self.n_cpu());
+        debug!("CPU load: {load:.1} sum: {:.1}", cpu_rates[0]);
+        Ok(load)
+    }
+    fn name(&self) -> &'static str {
+        "cpu"
+    }
+    fn unit(&self) -> &'static str {
+        "%"
+    }
+}
+
+// Network Interface counters summed over the adapters whose names match the
+// interface globs, e.g. --interface 'Intel*'
+pub struct WinIfStats {
+    iface: String,
+    dir: IfCounter,
+    counter: PdhCounter,
+}
+
+impl WinIfStats {
+    pub fn new(iface: &str, dir: IfCounter) -> anyhow::Result<Self> {
+        let name = match dir {
+            IfCounter::Rx => "Bytes Received/sec",
+            IfCounter::Tx => "Bytes Sent/sec",
+            IfCounter::RxErrors => "Packets Received Errors",
+            IfCounter::TxErrors => "Packets Outbound Errors",
+            IfCounter::RxDropped => "Packets Received Discarded",
+            IfCounter::TxDropped => "Packets Outbound Discarded",
+        };
+        let mut stats = Self {
+            iface: iface.into(),
+            dir,
+            counter: PdhCounter::new(&format!(r"\Network Interface(*)\{name}"))?,
+        };
+        stats.rate()?;
+        Ok(stats)
+    }
+    // per second, bits for the byte counters
+    pub fn rate(&mut self) -> anyhow::Result<f64> {
+        let patterns = self.iface.split(',').collect::<Vec<&str>>();
+        let (excl, incl): (Vec<&str>, Vec<&str>) =
+            patterns.iter().partition(|p| p.starts_with('!'));
+        let matching = self
+            .counter
+            .values()?
+            .into_iter()
+            .filter(|(name, _)| incl.is_empty() || incl.iter().any(|p| glob_match(p, name)))
+            .filter(|(name, _)| !excl.iter().any(|p| glob_match(&p[1..], name)))
+            .map(|(_, v)| v)
+            .collect::<Vec<f64>>();
+        // an adapter that is gone shows zero until it comes back
+        let scale = match self.dir {
+            IfCounter::Rx | IfCounter::Tx => 8.0,
+            _ => 1.0,
+        };
+        Ok(scale * matching.iter().sum::<f64>())
+    }
+}
+
+impl StatSource for WinIfStats {
+    fn sample(&mut self) -> anyhow::Result<f64> {
+        self.rate()
+    }
+    fn name(&self) -> &'static str {
+        match self.dir {
+            IfCounter::Rx | IfCounter::Tx => "net",
+            _ => "if_counter",
+        }
+    }
+    fn unit(&self) -> &'static str {
+        match self.dir {
+            IfCounter::Rx | IfCounter::Tx => "bit/s",
+            _ => "/s",
+        }
+    }
+}
+
+// PhysicalDisk bytes per second in 512 byte sectors to match DiskStats,
+// the device is matched against the instance names like "0 C:".
+// Without a device the busiest disk shows.
+pub struct WinDiskStats {
+    device: Option<String>,
+    gauge: DiskGauge,
+    counter: PdhCounter,
+}
+
+impl WinDiskStats {
+    pub fn with_device(device: Option<&str>, gauge: DiskGauge) -> anyhow::Result<Self> {
+        let mut stats = Self {
+            device: device.map(String::from),
+            gauge,
+            counter: PdhCounter::new(r"\PhysicalDisk(*)\Disk Bytes/sec")?,
+        };
+        if stats.diskrates()?.is_empty() {
+            bail!("No disk {} found", device.unwrap_or_default());
+        }
+        Ok(stats)
+    }
+    // busiest first
+    pub fn diskrates(&mut self) -> anyhow::Result<Vec<f64>> {
+        let mut rates = self
+            .counter
+            .values()?
+            .into_iter()
+            .filter(|(name, _)| name != "_Total")
+            .filter(|(name, _)| self.device.as_ref().is_none_or(|d| glob_match(d, name)))
+            .map(|(_, bytes)| bytes / 512.0)
+            .collect::<Vec<f64>>();
+        rates.sort_by(|a, b| b.total_cmp(a));
+        Ok(rates)
+    }
+}
+
+impl StatSource for WinDiskStats {
+    fn sample(&mut self) -> anyhow::Result<f64> {
+        let disk_rates = self.diskrates()?;
+        debug!("DISK rates: {disk_rates:?}");
+        Ok(self.gauge.apply(&disk_rates))
+    }
+    fn name(&self) -> &'static str {
+        "disk"
+    }
+    fn unit(&self) -> &'static str {
+        "sectors/s"
+    }
+}
+
+// EOF
@@ -0,0 +1,92 @@
+// wireguard.rs
+
+use std::{process::Command, time};
+
+use anyhow::{anyhow, bail};
+
+use crate::*;
+
+// Transfer rate of a single WireGuard peer, selected by public key prefix.
+// Counters are read with "wg show <iface> transfer", which talks to both
+// the kernel module over netlink and to userspace implementations over UAPI.
+#[derive(Debug)]
+pub struct WgPeer {
+    pub iface: String,
+    pub peer: String,
+    prev_ts: time::Instant,
+    prev_cnt: (i64, i64),
+}
+
+impl WgPeer {
+    pub fn new<S: AsRef<str>>(iface: S, peer: S) -> anyhow::Result<Self> {
+        let mut wg = Self {
+            iface: iface.as_ref().to_string(),
+            peer: peer.as_ref().to_string(),
+            prev_ts: time::Instant::now(),
+            prev_cnt: (0, 0),
+        };
+        wg.prev_cnt = wg.read_transfer()?;
+        Ok(wg)
+    }
+
+    // rx and tx bitrates
+    pub fn bitrates(&mut self) -> anyhow::Result<(i64, i64)> {
+        let us = self.prev_ts.elapsed().as_micros();
+        self.prev_ts = time::Instant::now();
+        let cnt = self.read_transfer()?;
+        let secs = us as f64 / 1_000_000.0;
+        let rates = (
+            ((8 * (cnt.0 - self.prev_cnt.0)) as f64 / secs) as i64,
+            ((8 * (cnt.1 - self.prev_cnt.1)) as f64 / secs) as i64,
+        );
+        self.prev_cnt = cnt;
+        Ok(rates)
+    }
+
+    // Example output, tab separated:
+    // xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=	3774076	1542708
+    fn read_transfer(&self) -> anyhow::Result<(i64, i64)> {
+        let out = Command::new("wg")
+            .args(["show", &self.iface, "transfer"])
+            .output()?;
+        if !out.status.success() {
+            bail!(
+                "wg exited with {}: {}",
+                out.status,
+                String::from_utf8_lossy(&out.stderr).trim()
+            );
+        }
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        let peers = stdout
+            .lines()
+            .map(|line| line.split_ascii_whitespace().collect::<Vec<&str>>())
+            .filter(|items| items.len() == 3 && items[0].starts_with(&self.peer))
+            .collect::<Vec<_>>();
+        match peers.as_slice() {
+            [items] => Ok((items[1].parse::<i64>()?, items[2].parse::<i64>()?)),
+            [] => Err(anyhow!("No peer {} on {}", self.peer, self.iface)),
+            _ => Err(anyhow!(
+                "Peer prefix {} matches {} peers on {}, give more of the key",
+                self.peer,
+                peers.len(),
+                self.iface
+            )),
+        }
+    }
+}
+
+// the busier direction
+impl StatSource for WgPeer {
+    fn sample(&mut self) -> anyhow::Result<f64> {
+        let (rx, tx) = self.bitrates()?;
+        Ok(rx.max(tx) as f64)
+    }
+    fn name(&self) -> &'static str {
+        "wireguard"
+    }
+    fn unit(&self) -> &'static str {
+        "bit/s"
+    }
+}
+
+// EOF
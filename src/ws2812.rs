@@ -0,0 +1,122 @@
+// ws2812.rs
+
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::Write,
+    os::fd::AsRawFd,
+    sync::mpsc,
+    time,
+};
+
+use crate::*;
+
+// 3 SPI bits per LED bit: 1 = 110, 0 = 100
+const WS2812_SPI_HZ: u32 = 2_400_000;
+// SPI_IOC_WR_MAX_SPEED_HZ
+const SPI_IOC_WR_MAX_SPEED_HZ: std::os::raw::c_ulong = 0x4004_6b04;
+// >50 us low latches the strip
+const WS2812_RESET_BYTES: usize = 32;
+const PEAK_HOLD: time::Duration = time::Duration::from_secs(1);
+const PEAK_FALL_PER_SEC: f64 = 10.0;
+
+#[derive(Clone, Debug)]
+pub struct Ws2812Config {
+    pub spi: String,
+    // meter channels in strip order
+    pub channels: Vec<u8>,
+    pub leds: usize,
+    // the bar is split into equal zones, from the bottom up
+    pub colors: Vec<Rgb>,
+    pub peak_color: Option<Rgb>,
+    pub brightness: u8,
+}
+
+#[derive(Debug, Default)]
+struct PeakDot {
+    pos: f64,
+    held: Option<time::Instant>,
+}
+
+// Renders each channel as a bar of LEDs on a WS2812 strip, driven through spidev
+pub struct Ws2812Sink;
+
+impl Ws2812Sink {
+    pub fn spawn(cfg: Ws2812Config) -> anyhow::Result<ThreadedSink> {
+        let spi = OpenOptions::new().write(true).open(&cfg.spi)?;
+        let mut hz = WS2812_SPI_HZ;
+        sys::sys_ioctl(spi.as_raw_fd(), SPI_IOC_WR_MAX_SPEED_HZ, &mut hz)?;
+        info!(
+            "Driving {} WS2812 LEDs on {}",
+            cfg.leds * cfg.channels.len(),
+            cfg.spi
+        );
+        spawn_sink("ws2812", move |rx| Self::run(cfg, spi, rx))
+    }
+
+    fn run(cfg: Ws2812Config, mut spi: File, rx: mpsc::Receiver<Frame>) {
+        let mut levels: HashMap<u8, f64> = HashMap::new();
+        let mut peaks: HashMap<u8, PeakDot> = HashMap::new();
+        let mut prev_ts = time::Instant::now();
+
+        while let Ok(frame) = rx.recv() {
+            let secs = prev_ts.elapsed().as_secs_f64();
+            prev_ts = time::Instant::now();
+            for (ch, sample) in frame {
+                levels.insert(ch, sample.value.clamp(0.0, 255.0));
+            }
+
+            let mut pixels = Vec::with_capacity(cfg.leds * cfg.channels.len());
+            for ch in &cfg.channels {
+                let lit = levels.get(ch).copied().unwrap_or(0.0) / 256.0 * cfg.leds as f64;
+
+                let peak = peaks.entry(*ch).or_default();
+                if lit >= peak.pos {
+                    *peak = PeakDot {
+                        pos: lit,
+                        held: Some(time::Instant::now()),
+                    };
+                } else if peak.held.is_none_or(|t| t.elapsed() > PEAK_HOLD) {
+                    peak.pos = (peak.pos - PEAK_FALL_PER_SEC * secs).max(lit);
+                    peak.held = None;
+                }
+
+                for i in 0..cfg.leds {
+                    let color = if (i as f64) < lit.round() {
+                        let zone = i * cfg.colors.len() / cfg.leds;
+                        cfg.colors.get(zone).copied().unwrap_or_default()
+                    } else {
+                        match cfg.peak_color {
+                            Some(c) if peak.pos >= 1.0 && i == peak.pos as usize - 1 => c,
+                            _ => Rgb::default(),
+                        }
+                    };
+                    pixels.push(color.scale(cfg.brightness));
+                }
+            }
+
+            if let Err(e) = spi.write_all(&encode(&pixels)) {
+                debug!("WS2812: {e}");
+                count_error("ws2812");
+            }
+        }
+    }
+}
+
+// GRB order, most significant bit first
+fn encode(pixels: &[Rgb]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(pixels.len() * 9 + WS2812_RESET_BYTES);
+    for p in pixels {
+        for byte in [p.1, p.0, p.2] {
+            let mut bits = 0u32;
+            for i in (0..8).rev() {
+                bits = bits << 3 | if byte >> i & 1 == 1 { 0b110 } else { 0b100 };
+            }
+            out.extend(&bits.to_be_bytes()[1..]);
+        }
+    }
+    out.extend([0u8; WS2812_RESET_BYTES]);
+    out
+}
+
+// EOF